@@ -1,3 +1,4 @@
+use super::{allocate_on_node, current_node, free_on_node};
 use std::alloc::{self, alloc_zeroed, Layout};
 use std::fmt;
 use std::mem::size_of;
@@ -12,15 +13,21 @@ unsafe fn allocate_cache_line(size: usize) -> *mut u8 {
 #[derive(Debug)]
 pub struct CacheAligned<T: Sized> {
     ptr: Unique<T>,
+    // the NUMA node of the memory, if it was allocated by `allocate_on_node`
+    node: Option<i32>,
 }
 
 impl<T: Sized> Drop for CacheAligned<T> {
     fn drop(&mut self) {
         unsafe {
-            alloc::dealloc(
-                self.ptr.as_ptr() as *mut u8,
-                Layout::from_size_align(size_of::<T>(), CACHE_LINE_SIZE).unwrap(),
-            );
+            if self.node.is_some() {
+                free_on_node(self.ptr.as_ptr() as *mut u8, size_of::<T>());
+            } else {
+                alloc::dealloc(
+                    self.ptr.as_ptr() as *mut u8,
+                    Layout::from_size_align(size_of::<T>(), CACHE_LINE_SIZE).unwrap(),
+                );
+            }
         }
     }
 }
@@ -45,9 +52,39 @@ impl<T: Sized> CacheAligned<T> {
             ptr::write(alloc, src);
             CacheAligned {
                 ptr: Unique::new(alloc).unwrap(),
+                node: None,
             }
         }
     }
+
+    /// Allocate on NUMA `node`. As memory is placed in whole pages, this is meant for values which are used by one
+    /// core for a long time, like port queues. Falls back to `allocate` if NUMA is not available.
+    pub fn allocate_on_node(src: T, node: i32) -> CacheAligned<T> {
+        unsafe {
+            let alloc = allocate_on_node(size_of::<T>(), node) as *mut T;
+            if alloc.is_null() {
+                return CacheAligned::allocate(src);
+            }
+            ptr::write(alloc, src);
+            CacheAligned {
+                ptr: Unique::new(alloc).unwrap(),
+                node: Some(node),
+            }
+        }
+    }
+
+    /// Allocate on the NUMA node of the calling thread, see `current_node`.
+    pub fn allocate_local(src: T) -> CacheAligned<T> {
+        match current_node() {
+            Some(node) => CacheAligned::allocate_on_node(src, node),
+            None => CacheAligned::allocate(src),
+        }
+    }
+
+    /// The NUMA node the value was placed on, `None` if it was allocated without regard to NUMA.
+    pub fn node(&self) -> Option<i32> {
+        self.node
+    }
 }
 
 impl<T: Sized> Clone for CacheAligned<T>
@@ -56,10 +93,16 @@ where
 {
     fn clone(&self) -> CacheAligned<T> {
         unsafe {
-            let alloc = allocate_cache_line(size_of::<T>()) as *mut T;
+            let mut node = self.node;
+            let mut alloc = node.map_or(ptr::null_mut(), |node| allocate_on_node(size_of::<T>(), node)) as *mut T;
+            if alloc.is_null() {
+                node = None;
+                alloc = allocate_cache_line(size_of::<T>()) as *mut T;
+            }
             ptr::copy(self.ptr.as_ptr() as *const T, alloc, 1);
             CacheAligned {
                 ptr: Unique::new(alloc).unwrap(),
+                node,
             }
         }
     }
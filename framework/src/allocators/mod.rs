@@ -1,2 +1,4 @@
 pub use self::cache_aligned::*;
+pub use self::numa::*;
 mod cache_aligned;
+mod numa;
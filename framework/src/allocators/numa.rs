@@ -0,0 +1,109 @@
+use interface::dpdk::get_domain;
+use libc::c_void;
+use native::libnuma;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// True if the system supports NUMA, otherwise memory is never placed on a particular node.
+pub fn numa_available() -> bool {
+    unsafe { libnuma::numa_available() != -1 }
+}
+
+/// The NUMA node the calling thread runs on, if the thread was initialized by `init_thread` or `init_system`.
+///
+/// Pages are placed on the node of the thread which first touches them, so flow tables and other state which a
+/// pipeline creates on its pinned core are already local; `allocate_on_node` is only needed for memory which is
+/// allocated elsewhere, e.g. the queues of a port.
+pub fn current_node() -> Option<i32> {
+    match get_domain() {
+        -1 => None,
+        node => Some(node),
+    }
+}
+
+/// Allocate `size` zeroed bytes on NUMA `node`, in whole pages. Returns null if NUMA is not available or there is
+/// no memory left on the node. The memory must be released with `free_on_node`.
+pub fn allocate_on_node(size: usize, node: i32) -> *mut u8 {
+    if size == 0 || !numa_available() {
+        return ptr::null_mut();
+    }
+    unsafe { libnuma::numa_alloc_onnode(size, node) as *mut u8 }
+}
+
+/// Release memory of `allocate_on_node`.
+///
+/// # Safety
+/// `ptr` must have been returned by `allocate_on_node` for `size` bytes and not been released before.
+pub unsafe fn free_on_node(ptr: *mut u8, size: usize) {
+    libnuma::numa_free(ptr as *mut c_void, size)
+}
+
+/// The NUMA nodes of cores and PCI devices, as found in sysfs. This does not need DPDK, so it can be used to check a
+/// configuration before the system is initialized; once a port is initialized, `PmdPort::numa_node` is the node DPDK
+/// uses for it.
+#[derive(Clone, Debug)]
+pub struct NumaTopology {
+    sysfs: PathBuf,
+}
+
+impl Default for NumaTopology {
+    fn default() -> NumaTopology {
+        NumaTopology::from_sysfs("/sys")
+    }
+}
+
+impl NumaTopology {
+    /// The topology of the system.
+    pub fn new() -> NumaTopology {
+        Default::default()
+    }
+
+    /// The topology found in a sysfs tree mounted at `root`.
+    pub fn from_sysfs<P: AsRef<Path>>(root: P) -> NumaTopology {
+        NumaTopology {
+            sysfs: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The numbers of the `nodeN` entries of `dir`.
+    fn node_entries(dir: &Path) -> Vec<i32> {
+        let mut nodes: Vec<i32> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| {
+                        let name = entry.file_name();
+                        let name = name.to_string_lossy();
+                        name.strip_prefix("node").and_then(|node| node.parse().ok())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        nodes.sort_unstable();
+        nodes
+    }
+
+    /// The NUMA nodes of the system, empty if the system does not report any.
+    pub fn nodes(&self) -> Vec<i32> {
+        NumaTopology::node_entries(&self.sysfs.join("devices/system/node"))
+    }
+
+    /// The NUMA node of a core.
+    pub fn node_of_core(&self, core: i32) -> Option<i32> {
+        NumaTopology::node_entries(&self.sysfs.join(format!("devices/system/cpu/cpu{}", core)))
+            .first()
+            .cloned()
+    }
+
+    /// The NUMA node of a PCI device like `0000:01:00.0`. Virtual devices and devices without a known node have none.
+    pub fn node_of_device(&self, pci: &str) -> Option<i32> {
+        if pci.is_empty() || pci.contains('/') {
+            return None;
+        }
+        fs::read_to_string(self.sysfs.join("bus/pci/devices").join(pci).join("numa_node"))
+            .ok()
+            .and_then(|node| node.trim().parse().ok())
+            .filter(|node| *node >= 0)
+    }
+}
@@ -142,6 +142,7 @@ pub enum ErrorKind {
     ConfigParseError(String),
     TryFromNetSpecError,
     IoError(String),
+    UnsupportedOffload(u64),
 }
 
 impl From<AddrParseError> for ErrorKind {
@@ -1,9 +1,15 @@
 extern crate getopts;
 use self::getopts::{Matches, Options};
 
-use super::{read_configuration, NetbricksConfiguration, PortConfiguration};
+use super::{
+    check_numa_placement, check_port_pairing, parse_override, LayeredConfiguration, NetbricksConfiguration,
+    ValidationError,
+};
+use allocators::NumaTopology;
 //use common::print_error;
 use std::collections::HashMap;
+use toml::map::Map;
+use toml::Value;
 
 use std::env;
 use std::process;
@@ -22,73 +28,51 @@ pub fn basic_opts() -> Options {
     opts.optopt("f", "configuration", "Configuration file", "path");
     opts.optmulti("", "vdev", "Virtual device to create", "vdev_name");
     opts.optflag("i", "interactive", "run interactively");
+    opts.optflag("", "check-config", "check the configuration and exit without starting");
+    opts.optmulti(
+        "",
+        "set",
+        "override a configuration value, e.g. ports[0].rxd=512",
+        "path=value",
+    );
+    opts.optflag(
+        "",
+        "print-config",
+        "print the merged configuration and exit without starting",
+    );
     opts
 }
 
-/// Read the commonly used configuration flags parsed by `basic_opts()` into
-/// a `NetbricksConfiguration`. Some flags may cause side effects -- for example, the
-/// help flag will print usage information and then exit the process.
-pub fn read_matches(matches: &Matches, opts: &Options) -> NetbricksConfiguration {
-    if matches.opt_present("h") {
-        let program = env::args().next().unwrap();
-        print!("{}", opts.usage(&format!("Usage: {} [options]", program)));
-        process::exit(0)
+fn exit_with_errors(source: &str, errors: &[ValidationError]) -> ! {
+    debug!("error reading configuration");
+    for error in errors {
+        println!("{}: {}", source, error);
     }
+    process::exit(1)
+}
 
-    let configuration = if matches.opt_present("f") {
-        let config_file = matches.opt_str("f").unwrap();
-        debug!("config file is: {}", config_file);
-        match read_configuration(&config_file[..]) {
-            Ok(cfg) => cfg,
-            Err(ref e) => {
-                debug!("error reading configuration");
-                println!("{:?}", e);
-                process::exit(1);
-            }
-        }
-    } else {
-        let name = matches.opt_str("n").unwrap_or_else(|| String::from("recv"));
-        NetbricksConfiguration::new_with_name(&name[..])
-    };
-
-    let configuration = if matches.opt_present("m") {
-        NetbricksConfiguration {
-            primary_core: matches
-                .opt_str("m")
-                .unwrap()
-                .parse()
-                .expect("Could not parse master core"),
-            strict: true,
-            ..configuration
-        }
-    } else {
-        configuration
-    };
-
-    let configuration = if matches.opt_present("secondary") {
-        NetbricksConfiguration {
-            secondary: true,
-            ..configuration
-        }
-    } else {
-        configuration
-    };
-
-    let configuration = if matches.opt_present("primary") {
-        NetbricksConfiguration {
-            secondary: false,
-            ..configuration
-        }
-    } else {
-        configuration
-    };
-
-    let mut configuration = if matches.opt_present("c") {
+/// Set the values given by flags over `layers`.
+fn apply_flags(matches: &Matches, layers: &mut LayeredConfiguration) -> Vec<ValidationError> {
+    let mut settings = Vec::new();
+    if let Some(name) = matches.opt_str("n") {
+        settings.push(("name".to_string(), Value::String(name), "--name"));
+    }
+    if let Some(master) = matches.opt_str("m") {
+        settings.push(("master_core".to_string(), parse_override(&master), "--master"));
+        settings.push(("strict".to_string(), Value::Boolean(true), "--master"));
+    }
+    if matches.opt_present("secondary") {
+        settings.push(("secondary".to_string(), Value::Boolean(true), "--secondary"));
+    }
+    if matches.opt_present("primary") {
+        settings.push(("secondary".to_string(), Value::Boolean(false), "--primary"));
+    }
+    if matches.opt_present("c") {
         let cores_str = matches.opt_strs("c");
 
         let mut cores: Vec<i32> = cores_str
             .iter()
-            .map(|n: &String| n.parse().ok().expect(&format!("Core cannot be parsed {}", n)))
+            .map(|n: &String| n.parse().unwrap_or_else(|_| panic!("Core cannot be parsed {}", n)))
             .collect();
 
         debug!("cores = {:?}", cores);
@@ -97,28 +81,96 @@ pub fn read_matches(matches: &Matches, opts: &Options) -> NetbricksConfiguration
 
         debug!("cores_for_port = {:?}", cores_for_port);
 
-        let ports_to_activate: Vec<_> = cores_for_port.keys().collect();
-
-        debug!("ports_to_activate = {:?}", ports_to_activate);
-
-        let mut ports = Vec::with_capacity(ports_to_activate.len());
+        let ports: Vec<_> = cores_for_port
+            .into_iter()
+            .map(|(port, cores)| {
+                let mut table = Map::new();
+                table.insert("name".to_string(), Value::String(port));
+                table.insert("cores".to_string(), core_list(&cores));
+                Value::Table(table)
+            })
+            .collect();
+        cores.dedup();
+        settings.push(("cores".to_string(), core_list(&cores), "--core"));
+        settings.push(("ports".to_string(), Value::Array(ports), "--port"));
+    }
+    if matches.opt_present("vdev") {
+        let vdevs = matches.opt_strs("vdev").into_iter().map(Value::String).collect();
+        settings.push(("vdev".to_string(), Value::Array(vdevs), "--vdev"));
+    }
 
-        for port in &ports_to_activate {
-            let cores = cores_for_port.get(*port).unwrap();
-            ports.push(PortConfiguration::new_with_queues(*port, cores, cores))
+    let mut errors = Vec::new();
+    for setting in matches.opt_strs("set") {
+        match setting.find('=') {
+            Some(equals) => settings.push((
+                setting[..equals].trim().to_string(),
+                parse_override(setting[equals + 1..].trim()),
+                "--set",
+            )),
+            None => errors.push(ValidationError::new(setting.clone(), "expected --set path=value")),
         }
-        cores.dedup();
-        NetbricksConfiguration {
-            cores: cores,
-            ports: ports,
-            ..configuration
+    }
+    for (path, value, flag) in settings {
+        if let Err(error) = layers.set(&path, value, flag) {
+            errors.push(error);
         }
+    }
+    errors
+}
+
+fn core_list(cores: &[i32]) -> Value {
+    Value::Array(cores.iter().map(|core| Value::Integer(i64::from(*core))).collect())
+}
+
+/// Read the commonly used configuration flags parsed by `basic_opts()` into
+/// a `NetbricksConfiguration`. The flags are applied over the configuration file and the
+/// environment variables, see `LayeredConfiguration`. Some flags may cause side effects -- for
+/// example, the help flag will print usage information and then exit the process, `--check-config`
+/// prints the problems of the configuration and exits and `--print-config` prints the merged
+/// configuration and exits.
+pub fn read_matches(matches: &Matches, opts: &Options) -> NetbricksConfiguration {
+    if matches.opt_present("h") {
+        let program = env::args().next().unwrap();
+        print!("{}", opts.usage(&format!("Usage: {} [options]", program)));
+        process::exit(0)
+    }
+
+    let source = matches.opt_str("f").unwrap_or_else(|| String::from("configuration"));
+    let mut layers = if matches.opt_present("f") {
+        debug!("config file is: {}", source);
+        LayeredConfiguration::from_file(&source[..]).unwrap_or_else(|errors| exit_with_errors(&source, &errors))
     } else {
-        configuration
+        LayeredConfiguration::with_name("recv")
     };
 
-    if matches.opt_present("vdev") {
-        configuration.vdevs = matches.opt_strs("vdev");
+    if let Err(errors) = layers.apply_environment() {
+        exit_with_errors(&source, &errors);
+    }
+    let environment = layers.overrides().len();
+    let errors = apply_flags(matches, &mut layers);
+    if !errors.is_empty() {
+        exit_with_errors(&source, &errors);
+    }
+
+    if matches.opt_present("print-config") {
+        print!("{}", layers);
+    }
+    let mut configuration = layers
+        .configuration()
+        .unwrap_or_else(|errors| exit_with_errors(&source, &errors));
+    configuration.flag_overrides = layers.overrides()[environment..].to_vec();
+    if matches.opt_present("print-config") {
+        process::exit(0);
+    }
+    if matches.opt_present("check-config") {
+        for warning in check_port_pairing(&configuration)
+            .into_iter()
+            .chain(check_numa_placement(&configuration, &NumaTopology::new()))
+        {
+            println!("{}: warning: {}", source, warning);
+        }
+        println!("Configuration is valid:\n{}", configuration);
+        process::exit(0);
     }
 
     info!("Going to start with configuration:\n{}", configuration);
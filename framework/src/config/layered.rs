@@ -0,0 +1,325 @@
+//! Layered configurations, where each layer overrides values of the previous ones:
+//!
+//! 1. the defaults of `ConfigurationFile`,
+//! 2. a TOML configuration file,
+//! 3. environment variables starting with `E2D2_`,
+//! 4. command line flags, see `read_matches`.
+//!
+//! The name of an environment variable is the path of the value it sets, in upper case and with `_` between keys
+//! and array indices, e.g. `E2D2_PORTS_0_RXD=512` sets `netbricks.ports[0].rxd`, `E2D2_POOL_SIZE` sets
+//! `netbricks.pool_size` and `E2D2_PIPELINE_0_RX_PORT` sets `pipeline[0].rx_port`. Keys of nested tables are
+//! separated by `__`, e.g. `E2D2_PORTS_0_FDIR__MODE`. Values are read as TOML values, e.g. `[1, 2]` or `true`,
+//! anything else is taken as a string, so `E2D2_PORTS_0_NAME=0000:01:00.0` needs no quotes. An index one past the
+//! end of an array appends an element, so whole ports can be declared in the environment.
+use super::validate::TomlLocations;
+use super::{validate_configuration_from_str, NetbricksConfiguration, ValidationError};
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use toml;
+use toml::map::Map;
+use toml::Value;
+
+/// Prefix of the environment variables which override configuration values.
+pub const ENVIRONMENT_PREFIX: &str = "E2D2_";
+
+/// A value set over the configuration file, by an environment variable or a command line flag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigurationOverride {
+    /// Path of the value, e.g. `netbricks.ports[0].rxd`.
+    pub path: String,
+    pub value: Value,
+    /// The environment variable or flag which set the value.
+    pub source: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn format_path(segments: &[Segment]) -> String {
+    let mut path = String::new();
+    for segment in segments {
+        match *segment {
+            Segment::Key(ref key) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+        }
+    }
+    path
+}
+
+/// Parse a path like `netbricks.ports[0].rxd`, paths which do not start with `netbricks` or `pipeline` are in the
+/// `netbricks` table, e.g. `ports[0].rxd`.
+fn parse_path(path: &str) -> Result<Vec<Segment>, ValidationError> {
+    let invalid = || ValidationError::new(path, "invalid configuration path");
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let mut indices = part.split('[');
+        let key = indices.next().unwrap();
+        if key.is_empty() {
+            return Err(invalid());
+        }
+        segments.push(Segment::Key(key.to_string()));
+        for index in indices {
+            match index.strip_suffix(']').and_then(|index| index.parse().ok()) {
+                Some(index) => segments.push(Segment::Index(index)),
+                None => return Err(invalid()),
+            }
+        }
+    }
+    match segments[0] {
+        Segment::Key(ref key) if key == "netbricks" || key == "pipeline" => (),
+        _ => segments.insert(0, Segment::Key("netbricks".to_string())),
+    }
+    Ok(segments)
+}
+
+/// The path set by an environment variable, without `ENVIRONMENT_PREFIX`, e.g. `ports[0].rxd` for `PORTS_0_RXD`.
+fn environment_path(name: &str) -> String {
+    let mut path = String::new();
+    for table in name.split("__") {
+        let mut key: Vec<String> = Vec::new();
+        for token in table.split('_') {
+            if let Ok(index) = token.parse::<usize>() {
+                if !key.is_empty() {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(&key.join("_"));
+                    key.clear();
+                }
+                path.push_str(&format!("[{}]", index));
+            } else {
+                key.push(token.to_lowercase());
+            }
+        }
+        if !key.is_empty() {
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(&key.join("_"));
+        }
+    }
+    path
+}
+
+/// Read an overriding value, which is a TOML value or else a string.
+pub fn parse_override(value: &str) -> Value {
+    match toml::from_str::<Map<String, Value>>(&format!("value = {}", value)) {
+        Ok(mut table) if table.len() == 1 => table.remove("value").unwrap(),
+        _ => Value::String(value.to_string()),
+    }
+}
+
+fn empty_like(next: &Segment) -> Value {
+    match *next {
+        Segment::Key(_) => Value::Table(Map::new()),
+        Segment::Index(_) => Value::Array(Vec::new()),
+    }
+}
+
+/// The value at `segment` of `parent`, created as `empty` if it is missing.
+fn child<'a>(parent: &'a mut Value, segment: &Segment, empty: Value) -> Result<&'a mut Value, String> {
+    match (parent, segment) {
+        (Value::Table(table), Segment::Key(key)) => Ok(table.entry(key.clone()).or_insert(empty)),
+        (Value::Array(array), &Segment::Index(index)) => {
+            if index == array.len() {
+                array.push(empty);
+            }
+            let len = array.len();
+            array
+                .get_mut(index)
+                .ok_or_else(|| format!("index {} is past the end of an array of {} elements", index, len))
+        }
+        (_, &Segment::Key(_)) => Err("is not a table".to_string()),
+        (_, &Segment::Index(_)) => Err("is not an array".to_string()),
+    }
+}
+
+/// A configuration assembled from the layers described in the module documentation. Its `Display` is the merged TOML
+/// document, preceded by the values which were overridden.
+pub struct LayeredConfiguration {
+    source: String,
+    document: Value,
+    overrides: Vec<ConfigurationOverride>,
+}
+
+impl Default for LayeredConfiguration {
+    fn default() -> LayeredConfiguration {
+        LayeredConfiguration {
+            source: String::new(),
+            document: Value::Table(Map::new()),
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl LayeredConfiguration {
+    /// A configuration of the defaults with a name, useful when initializing through arguments.
+    pub fn with_name(name: &str) -> LayeredConfiguration {
+        let mut netbricks = Map::new();
+        netbricks.insert("name".to_string(), Value::String(name.to_string()));
+        let mut document = Map::new();
+        document.insert("netbricks".to_string(), Value::Table(netbricks));
+        LayeredConfiguration {
+            document: Value::Table(document),
+            ..Default::default()
+        }
+    }
+
+    /// Start from a configuration in TOML format. The configuration is only parsed here, it is checked by
+    /// `configuration` once all layers are applied.
+    pub fn from_toml(configuration: &str) -> Result<LayeredConfiguration, Vec<ValidationError>> {
+        let document = toml::from_str::<Map<String, Value>>(configuration)
+            .map_err(|error| vec![TomlLocations::scan(configuration).parse_error(&error)])?;
+        Ok(LayeredConfiguration {
+            source: configuration.to_string(),
+            document: Value::Table(document),
+            overrides: Vec::new(),
+        })
+    }
+
+    /// Start from a configuration file, see `from_toml`.
+    pub fn from_file(filename: &str) -> Result<LayeredConfiguration, Vec<ValidationError>> {
+        let mut configuration = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut configuration))
+            .map_err(|e| vec![ValidationError::new("", format!("cannot read {}: {}", filename, e))])?;
+        LayeredConfiguration::from_toml(&configuration)
+    }
+
+    /// Set the value at `path`, see `parse_path`. Missing tables and arrays on the way are created.
+    pub fn set(&mut self, path: &str, value: Value, source: &str) -> Result<(), ValidationError> {
+        let segments = parse_path(path)?;
+        self.set_segments(&segments, value, source)
+    }
+
+    fn set_segments(&mut self, segments: &[Segment], value: Value, source: &str) -> Result<(), ValidationError> {
+        let path = format_path(segments);
+        let error = |at: &[Segment], message: String| {
+            ValidationError::new(format_path(at), format!("{} (set by {})", message, source))
+        };
+        let mut current = &mut self.document;
+        for (i, segment) in segments.iter().enumerate() {
+            let empty = match segments.get(i + 1) {
+                Some(next) => empty_like(next),
+                None => value.clone(),
+            };
+            current = child(current, segment, empty).map_err(|message| error(&segments[..i + 1], message))?;
+        }
+        *current = value.clone();
+        debug!("{} = {} from {}", path, value, source);
+        self.overrides.push(ConfigurationOverride {
+            path,
+            value,
+            source: source.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Apply the environment variables starting with `ENVIRONMENT_PREFIX`.
+    pub fn apply_environment(&mut self) -> Result<(), Vec<ValidationError>> {
+        self.apply_variables(env::vars())
+    }
+
+    /// Apply the variables starting with `ENVIRONMENT_PREFIX` of `variables`. They are applied in the order of their
+    /// paths, so that arrays are extended one element at a time.
+    pub fn apply_variables<I: IntoIterator<Item = (String, String)>>(
+        &mut self,
+        variables: I,
+    ) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let mut settings = Vec::new();
+        for (name, value) in variables {
+            if !name.starts_with(ENVIRONMENT_PREFIX) {
+                continue;
+            }
+            match parse_path(&environment_path(&name[ENVIRONMENT_PREFIX.len()..])) {
+                Ok(segments) => settings.push((segments, name, value)),
+                Err(mut error) => {
+                    error.message = format!("{} (set by {})", error.message, name);
+                    errors.push(error)
+                }
+            }
+        }
+        settings.sort();
+        for (segments, name, value) in settings {
+            if let Err(error) = self.set_segments(&segments, parse_override(&value), &name) {
+                errors.push(error);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Set the values of `overrides` again, e.g. those of another `LayeredConfiguration`.
+    pub fn apply_overrides(&mut self, overrides: &[ConfigurationOverride]) -> Result<(), Vec<ValidationError>> {
+        let errors: Vec<_> = overrides
+            .iter()
+            .filter_map(|o| self.set(&o.path, o.value.clone(), &o.source).err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The values set over the configuration file, in the order they were set.
+    pub fn overrides(&self) -> &[ConfigurationOverride] {
+        &self.overrides
+    }
+
+    /// The merged configuration in TOML format.
+    pub fn to_toml(&self) -> Result<String, ValidationError> {
+        toml::to_string(&self.document).map_err(|e| ValidationError::new("", e.to_string()))
+    }
+
+    /// Read and check the merged configuration, see `validate_configuration_from_str`. Errors are located in the
+    /// configuration file, or name the environment variable or flag which set the value.
+    pub fn configuration(&self) -> Result<NetbricksConfiguration, Vec<ValidationError>> {
+        let merged = self.to_toml().map_err(|error| vec![error])?;
+        validate_configuration_from_str(&merged).map_err(|mut errors| {
+            let file = TomlLocations::scan(&self.source);
+            for error in &mut errors {
+                let source = self.overrides.iter().rev().find(|o| {
+                    error.path.starts_with(&o.path[..]) && {
+                        let rest = &error.path[o.path.len()..];
+                        rest.is_empty() || rest.starts_with('.') || rest.starts_with('[')
+                    }
+                });
+                match source {
+                    Some(o) => {
+                        error.line = None;
+                        error.message = format!("{} (set by {})", error.message, o.source);
+                    }
+                    None => file.locate(error),
+                }
+            }
+            errors
+        })
+    }
+}
+
+impl fmt::Display for LayeredConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for o in &self.overrides {
+            writeln!(f, "# {} = {} from {}", o.path, o.value, o.source)?;
+        }
+        match self.to_toml() {
+            Ok(merged) => write!(f, "{}", merged),
+            Err(error) => writeln!(f, "# {}", error),
+        }
+    }
+}
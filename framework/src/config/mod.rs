@@ -1,11 +1,21 @@
 pub use self::config_reader::*;
 pub use self::flag_reader::*;
-use interface::{FlowSteeringMode, NetSpec};
+pub use self::layered::*;
+pub use self::reload::*;
+pub use self::schema::*;
+pub use self::validate::*;
+use interface::{FlowSteeringMode, NetSpec, TxBufferConf};
 use native::zcsi::RteFdirConf;
 use std::fmt;
+use std::net::SocketAddr;
+use toml::Value;
 
 mod config_reader;
 mod flag_reader;
+mod layered;
+mod reload;
+mod schema;
+mod validate;
 
 #[derive(Clone)]
 /// `NetBricks` control configuration. In theory all applications create one of these, either through the use of
@@ -34,6 +44,13 @@ pub struct NetbricksConfiguration {
     pub cache_size: u32,
     /// number of mbufs in the mbuf pool, should be (2**N - 1) for some positive integral N, default 65535
     pub mbuf_cnt: u32,
+    /// Pipelines declared in `[[pipeline]]` sections, see `NetBricksContext::install_pipelines`.
+    pub pipelines: Vec<PipelineConfiguration>,
+    /// Address on which the metrics are served, see `NetBricksContext::start_metrics`.
+    pub metrics: Option<SocketAddr>,
+    /// Values set by command line flags, they are applied again when the configuration file is reloaded, see
+    /// `NetBricksContext::reload_from_file`.
+    pub flag_overrides: Vec<ConfigurationOverride>,
 }
 
 /// Create an empty `NetbricksConfiguration`, useful when initializing through arguments.
@@ -50,6 +67,9 @@ impl Default for NetbricksConfiguration {
             ports: vec![],
             vdevs: vec![],
             mbuf_cnt: DEFAULT_MBUF_CNT,
+            pipelines: vec![],
+            metrics: None,
+            flag_overrides: vec![],
         }
     }
 }
@@ -91,10 +111,56 @@ impl fmt::Display for NetbricksConfiguration {
         for core in &self.cores {
             write!(f, "\t{}", core)?
         }
-        write!(f, "\n")
+        writeln!(f)?;
+        writeln!(f, "Pipelines:")?;
+        for pipeline in &self.pipelines {
+            writeln!(f, "\t{}", pipeline)?
+        }
+        if let Some(metrics) = self.metrics {
+            writeln!(f, "Metrics: {}", metrics)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A pipeline declared in a `[[pipeline]]` section: packets received from `rx_port` run through the chain of
+/// `operators` and are sent out of `tx_port`. One instance of the pipeline runs on each of its `cores`.
+pub struct PipelineConfiguration {
+    pub name: String,
+    pub rx_port: String,
+    /// Defaults to `rx_port`.
+    pub tx_port: String,
+    /// Cores on which the pipeline runs, each must have a queue of `rx_port`. Defaults to all cores with a queue of
+    /// `rx_port`.
+    pub cores: Vec<i32>,
+    pub operators: Vec<OperatorConfiguration>,
+}
+
+impl fmt::Display for PipelineConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cores: Vec<_> = self.cores.iter().map(|c| c.to_string()).collect();
+        let operators: Vec<_> = self.operators.iter().map(|o| o.kind.clone()).collect();
+        write!(
+            f,
+            "Pipeline {}, RX: {}, TX: {}, Cores: [ {} ], Operators: [ {} ]",
+            self.name,
+            self.rx_port,
+            self.tx_port,
+            cores.join(" "),
+            operators.join(" -> ")
+        )
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+/// One operator of a pipeline: the name under which its factory is registered in an `OperatorRegistry` and its
+/// parameters, a TOML table holding all other keys of the operator section.
+pub struct OperatorConfiguration {
+    pub kind: String,
+    pub params: Value,
+}
+
 #[derive(Deserialize, Clone, Copy, PartialEq)]
 pub enum DriverType {
     Unknown = 0,
@@ -123,6 +189,8 @@ pub struct PortConfiguration {
     pub loopback: bool,
     pub tso: bool,
     pub csum: bool,
+    /// Request insertion of VLAN tags by the NIC, see `TxOffload::vlan_tci`.
+    pub vlan_insert: bool,
     /// name of associated kernel network interface (may also be virtio i/f)
     pub kni: Option<String>,
     /// cores on which kni kernel threads should run (in case of multi-threading kni kernel module)
@@ -131,6 +199,8 @@ pub struct PortConfiguration {
     pub flow_steering: Option<FlowSteeringMode>,
     pub driver: DriverType,
     pub net_spec: Option<NetSpec>,
+    /// Limit and drop policy for packets buffered by `PortQueueTxBuffered` when the NIC does not accept them.
+    pub tx_buffer: TxBufferConf,
 }
 
 impl Default for PortConfiguration {
@@ -144,12 +214,14 @@ impl Default for PortConfiguration {
             loopback: false,
             tso: false,
             csum: false,
+            vlan_insert: false,
             k_cores: vec![],
             kni: None,
             fdir_conf: None,
             flow_steering: None,
             driver: DriverType::Unknown,
             net_spec: None,
+            tx_buffer: TxBufferConf::default(),
         }
     }
 }
@@ -179,7 +251,7 @@ impl fmt::Display for PortConfiguration {
         let tx_queue_str = tx_queues_str_vec.join(" ");
         write!(
             f,
-            "Port {}, RXQ_Count: {}, RX_Queues: [ {} ], TXQ_Count: {}, TX_Queues: [ {} ], RXD: {}, TXD: {}, Loopback: {}, ChecksumOffload: {}",
+            "Port {}, RXQ_Count: {}, RX_Queues: [ {} ], TXQ_Count: {}, TX_Queues: [ {} ], RXD: {}, TXD: {}, Loopback: {}, ChecksumOffload: {}, TxBuffer: {}",
             self.name,
             self.rx_queues.len(),
             rx_queue_str,
@@ -189,6 +261,7 @@ impl fmt::Display for PortConfiguration {
             self.txd,
             self.loopback,
             self.csum,
+            self.tx_buffer,
         )
     }
 }
@@ -0,0 +1,287 @@
+use super::{NetbricksConfiguration, PipelineConfiguration, PortConfiguration};
+use common::errors;
+use common::errors::ErrorKind;
+use interface::NetSpec;
+use libc;
+use native::zcsi::RteFdirConf;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by `request_reload` and the SIGHUP handler of `install_reload_signal_handler`.
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Ask the main thread to reload the configuration.
+pub fn request_reload() {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// True once after each reload request.
+#[inline]
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+extern "C" fn on_reload_signal(_signal: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Let SIGHUP request a reload of the configuration instead of terminating the process.
+pub fn install_reload_signal_handler() -> errors::Result<()> {
+    let handler = on_reload_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGHUP, handler) } == libc::SIG_ERR {
+        return Err(ErrorKind::RunTimeError(String::from(
+            "failed to install handler for SIGHUP",
+        )));
+    }
+    Ok(())
+}
+
+/// A change between two configurations which can be applied to a running system, see
+/// `NetBricksContext::reload`.
+#[derive(Clone, Debug)]
+pub enum ConfigChange {
+    AddPipeline(PipelineConfiguration),
+    RemovePipeline(String),
+    /// Pipelines are rebuilt when their declaration changed or when one of their ports changed.
+    RebuildPipeline(PipelineConfiguration),
+    SetNetSpec {
+        port: String,
+        net_spec: Option<NetSpec>,
+    },
+    /// Poll the queues of the port on other cores, the number of queues stays the same. The tx queue of a queue pair
+    /// moves with its rx queue.
+    MoveQueues {
+        port: String,
+        rx_queues: Vec<i32>,
+        tx_queues: Vec<i32>,
+    },
+}
+
+impl fmt::Display for ConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigChange::AddPipeline(ref pipeline) => write!(f, "add pipeline {}", pipeline.name),
+            ConfigChange::RemovePipeline(ref name) => write!(f, "remove pipeline {}", name),
+            ConfigChange::RebuildPipeline(ref pipeline) => write!(f, "rebuild pipeline {}", pipeline.name),
+            ConfigChange::SetNetSpec { ref port, ref net_spec } => write!(f, "set {:?} on port {}", net_spec, port),
+            ConfigChange::MoveQueues {
+                ref port,
+                ref rx_queues,
+                ref tx_queues,
+            } => write!(
+                f,
+                "move queues of port {} to rx cores {:?} and tx cores {:?}",
+                port, rx_queues, tx_queues
+            ),
+        }
+    }
+}
+
+/// Collects the names of fields which changed but cannot be changed live.
+struct Rejected(Vec<String>);
+
+impl Rejected {
+    fn check<T: PartialEq>(&mut self, what: &str, running: &T, new: &T) {
+        if running != new {
+            self.0.push(format!("{} cannot be changed", what));
+        }
+    }
+}
+
+/// The fields of the flow director configuration which are read from the configuration.
+fn same_fdir(running: &Option<RteFdirConf>, new: &Option<RteFdirConf>) -> bool {
+    match (running, new) {
+        (None, None) => true,
+        (Some(a), Some(b)) => {
+            let (am, bm) = (&a.mask, &b.mask);
+            a.mode as i32 == b.mode as i32
+                && a.pballoc as i32 == b.pballoc as i32
+                && am.ipv4_mask.src_ip == bm.ipv4_mask.src_ip
+                && am.ipv4_mask.dst_ip == bm.ipv4_mask.dst_ip
+                && am.ipv4_mask.tos == bm.ipv4_mask.tos
+                && am.ipv4_mask.ttl == bm.ipv4_mask.ttl
+                && am.ipv4_mask.proto == bm.ipv4_mask.proto
+                && am.src_port_mask == bm.src_port_mask
+                && am.dst_port_mask == bm.dst_port_mask
+        }
+        _ => false,
+    }
+}
+
+#[inline]
+fn base_name(port: &str) -> &str {
+    port.split(',').next().unwrap()
+}
+
+/// Ports whose queues are tied to cores by the driver, e.g. to the kernel threads of a KNI.
+fn queues_are_pinned(port: &PortConfiguration) -> bool {
+    port.kni.is_some()
+        || ["kni:", "bess:", "ovs:"]
+            .iter()
+            .any(|prefix| port.name.starts_with(prefix))
+}
+
+/// Cores with a scheduler in a system started from `configuration`.
+fn scheduled_cores(configuration: &NetbricksConfiguration) -> HashSet<i32> {
+    let mut cores: HashSet<i32> = configuration.cores.iter().cloned().collect();
+    if !configuration.strict {
+        for port in &configuration.ports {
+            cores.extend(port.rx_queues.iter());
+        }
+    }
+    cores
+}
+
+fn diff_port(
+    running: &PortConfiguration,
+    new: &PortConfiguration,
+    cores: &HashSet<i32>,
+    rejected: &mut Rejected,
+    changes: &mut Vec<ConfigChange>,
+) {
+    let name = &running.name;
+    rejected.check(&format!("rxd of port {}", name), &running.rxd, &new.rxd);
+    rejected.check(&format!("txd of port {}", name), &running.txd, &new.txd);
+    rejected.check(&format!("loopback of port {}", name), &running.loopback, &new.loopback);
+    rejected.check(&format!("tso of port {}", name), &running.tso, &new.tso);
+    rejected.check(&format!("checksum of port {}", name), &running.csum, &new.csum);
+    rejected.check(
+        &format!("vlan_insert of port {}", name),
+        &running.vlan_insert,
+        &new.vlan_insert,
+    );
+    rejected.check(&format!("kni of port {}", name), &running.kni, &new.kni);
+    rejected.check(&format!("k_cores of port {}", name), &running.k_cores, &new.k_cores);
+    rejected.check(&format!("driver of port {}", name), &running.driver, &new.driver);
+    rejected.check(
+        &format!("tx buffer of port {}", name),
+        &running.tx_buffer,
+        &new.tx_buffer,
+    );
+    // the flow director rules are programmed by the application when it starts
+    rejected.check(
+        &format!("flow_steering of port {}", name),
+        &running.flow_steering,
+        &new.flow_steering,
+    );
+    if !same_fdir(&running.fdir_conf, &new.fdir_conf) {
+        rejected.0.push(format!("fdir of port {} cannot be changed", name));
+    }
+
+    if running.rx_queues.len() != new.rx_queues.len() || running.tx_queues.len() != new.tx_queues.len() {
+        rejected
+            .0
+            .push(format!("number of queues of port {} cannot be changed", name));
+    } else if running.rx_queues != new.rx_queues || running.tx_queues != new.tx_queues {
+        if queues_are_pinned(running) {
+            rejected
+                .0
+                .push(format!("queues of port {} are bound to their cores", name));
+        } else if new.tx_queues.iter().zip(&new.rx_queues).any(|(tx, rx)| tx != rx) {
+            // tx queue i is used by the core polling rx queue i
+            rejected
+                .0
+                .push(format!("tx queues of port {} can only move with their rx queues", name));
+        } else if let Some(core) = new
+            .rx_queues
+            .iter()
+            .chain(new.tx_queues.iter())
+            .find(|core| !cores.contains(core))
+        {
+            rejected.0.push(format!(
+                "queues of port {} cannot be moved to core {} without a scheduler",
+                name, core
+            ));
+        } else {
+            changes.push(ConfigChange::MoveQueues {
+                port: name.clone(),
+                rx_queues: new.rx_queues.clone(),
+                tx_queues: new.tx_queues.clone(),
+            });
+        }
+    }
+
+    if running.net_spec != new.net_spec {
+        changes.push(ConfigChange::SetNetSpec {
+            port: name.clone(),
+            net_spec: new.net_spec.clone(),
+        });
+    }
+}
+
+/// The changes turning the `running` configuration into the `new` one. Port changes come first, then the removed,
+/// rebuilt and added pipelines. If anything changed which cannot be changed while the system is running, e.g. the
+/// mempool or the number of queues of a port, the error names all such changes.
+pub fn diff_configurations(
+    running: &NetbricksConfiguration,
+    new: &NetbricksConfiguration,
+) -> errors::Result<Vec<ConfigChange>> {
+    let mut rejected = Rejected(Vec::new());
+    rejected.check("name", &running.name, &new.name);
+    rejected.check("secondary", &running.secondary, &new.secondary);
+    rejected.check("vdev", &running.vdevs, &new.vdevs);
+    rejected.check("master_core", &running.primary_core, &new.primary_core);
+    rejected.check("cores", &running.cores, &new.cores);
+    rejected.check("strict", &running.strict, &new.strict);
+    rejected.check("pool_size", &running.pool_size, &new.pool_size);
+    rejected.check("cache_size", &running.cache_size, &new.cache_size);
+    rejected.check("mbuf_cnt", &running.mbuf_cnt, &new.mbuf_cnt);
+    rejected.check("metrics", &running.metrics, &new.metrics);
+
+    let mut changes = Vec::new();
+    let cores = scheduled_cores(running);
+    for port in &running.ports {
+        match new.ports.iter().find(|p| p.name == port.name) {
+            Some(new_port) => diff_port(port, new_port, &cores, &mut rejected, &mut changes),
+            None => rejected.0.push(format!("port {} cannot be removed", port.name)),
+        }
+    }
+    for port in new
+        .ports
+        .iter()
+        .filter(|p| !running.ports.iter().any(|r| r.name == p.name))
+    {
+        rejected.0.push(format!("port {} cannot be added", port.name));
+    }
+
+    if !rejected.0.is_empty() {
+        return Err(ErrorKind::ConfigurationError(format!(
+            "configuration cannot be reloaded: {}",
+            rejected.0.join(", ")
+        )));
+    }
+
+    let changed_ports: HashSet<String> = changes
+        .iter()
+        .filter_map(|change| match *change {
+            ConfigChange::SetNetSpec { ref port, .. } | ConfigChange::MoveQueues { ref port, .. } => {
+                Some(base_name(port).to_string())
+            }
+            _ => None,
+        })
+        .collect();
+    let mut rebuilt = Vec::new();
+    let mut added = Vec::new();
+    for pipeline in &running.pipelines {
+        if !new.pipelines.iter().any(|p| p.name == pipeline.name) {
+            changes.push(ConfigChange::RemovePipeline(pipeline.name.clone()));
+        }
+    }
+    for pipeline in &new.pipelines {
+        match running.pipelines.iter().find(|p| p.name == pipeline.name) {
+            Some(running_pipeline) => {
+                if running_pipeline != pipeline
+                    || changed_ports.contains(&pipeline.rx_port)
+                    || changed_ports.contains(&pipeline.tx_port)
+                {
+                    rebuilt.push(ConfigChange::RebuildPipeline(pipeline.clone()));
+                }
+            }
+            None => added.push(ConfigChange::AddPipeline(pipeline.clone())),
+        }
+    }
+    changes.append(&mut rebuilt);
+    changes.append(&mut added);
+    Ok(changes)
+}
@@ -0,0 +1,344 @@
+//! The layout of a configuration file. Each section is read into one of these structs, keys which are not declared
+//! here are rejected. A minimal file:
+//!
+//! ```toml
+//! [netbricks]
+//! name = "fwd"
+//! pool_size = 2048
+//! ports = [
+//!     { name = "0000:01:00.0", cores = [1, 2], ipnet = "10.0.0.1/24" },
+//!     { name = "kni:0", k_cores = [3] },
+//! ]
+//!
+//! [[pipeline]]
+//! name = "forward"
+//! rx_port = "0000:01:00.0"
+//!
+//! [[pipeline.operator]]
+//! kind = "macswap"
+//! ```
+use super::{DriverType, DEFAULT_CACHE_SIZE, DEFAULT_MBUF_CNT, DEFAULT_NAME, DEFAULT_POOL_SIZE, NUM_RXD, NUM_TXD};
+use eui48::MacAddress;
+use interface::{FlowSteeringMode, TxDropPolicy};
+use ipnet::Ipv4Net;
+use native::zcsi::{RteFdirMode, RteFdirPballocType};
+use serde::de::{self, Deserializer, Expected, SeqAccess, Unexpected, Visitor};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use toml::map::Map;
+use toml::Value;
+
+/// A whole configuration file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigurationFile {
+    pub netbricks: NetbricksSection,
+    /// `[[pipeline]]` sections.
+    #[serde(default)]
+    pub pipeline: Vec<PipelineSection>,
+}
+
+/// The `[netbricks]` section.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetbricksSection {
+    /// Passed on to DPDK, must be unique per DPDK application. Defaults to `zcsi`.
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default)]
+    pub secondary: bool,
+    /// Core of the main thread, an integer or a string holding one. Defaults to 0.
+    #[serde(default, deserialize_with = "core_number")]
+    pub master_core: i32,
+    /// Cores for schedulers, the cores of port queues are added unless `strict` is set.
+    #[serde(default)]
+    pub cores: Vec<i32>,
+    /// Require that all cores of port queues are listed in `cores`.
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default = "default_pool_size")]
+    pub pool_size: u32,
+    #[serde(default = "default_cache_size")]
+    pub cache_size: u32,
+    #[serde(default = "default_mbuf_cnt")]
+    pub mbuf_cnt: u32,
+    #[serde(default)]
+    pub ports: Vec<PortSection>,
+    /// Virtual devices created by DPDK.
+    #[serde(default)]
+    pub vdev: Vec<String>,
+    /// Address of the Prometheus metrics endpoint, e.g. `"127.0.0.1:9100"`. No metrics are served if not set.
+    #[serde(default)]
+    pub metrics: Option<SocketAddr>,
+}
+
+/// An entry of `ports` in the `[netbricks]` section.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortSection {
+    /// PCI address, or `dpdk:`, `bess:`, `ovs:`, `kni:` or `virtio:` followed by the device, optionally followed by
+    /// `,key=value` arguments.
+    pub name: String,
+    /// Cores polling one rx and one tx queue each, excludes `rx_cores` and `tx_cores`. A single core or a list.
+    #[serde(default, deserialize_with = "optional_core_list")]
+    pub cores: Option<Vec<i32>>,
+    /// Core of each rx queue.
+    #[serde(default, deserialize_with = "optional_core_list")]
+    pub rx_cores: Option<Vec<i32>>,
+    /// Core of each tx queue.
+    #[serde(default, deserialize_with = "optional_core_list")]
+    pub tx_cores: Option<Vec<i32>>,
+    #[serde(default = "default_rxd")]
+    pub rxd: u16,
+    #[serde(default = "default_txd")]
+    pub txd: u16,
+    #[serde(default)]
+    pub loopback: bool,
+    #[serde(default)]
+    pub tso: bool,
+    #[serde(default)]
+    pub checksum: bool,
+    #[serde(default)]
+    pub vlan_insert: bool,
+    /// Name of the `kni:` or `virtio:` port associated with this port.
+    #[serde(default)]
+    pub kni: Option<String>,
+    /// Cores of the kernel threads of a `kni:` port.
+    #[serde(default, deserialize_with = "core_list")]
+    pub k_cores: Vec<i32>,
+    #[serde(default)]
+    pub fdir: Option<FdirSection>,
+    /// `"Ip"` or `"Port"`.
+    #[serde(default)]
+    pub flow_steering: Option<FlowSteeringMode>,
+    #[serde(default)]
+    pub driver: Option<DriverType>,
+    /// Address and prefix of the port, e.g. `"10.0.0.1/24"`.
+    #[serde(default, deserialize_with = "ipv4_net")]
+    pub ipnet: Option<Ipv4Net>,
+    /// e.g. `"02:00:00:00:00:01"`.
+    #[serde(default, deserialize_with = "mac_address")]
+    pub mac: Option<MacAddress>,
+    /// Network namespace of the port.
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// Limit of the tx buffer in packets, excludes `tx_buffer_bytes`. Unbounded by default.
+    #[serde(default)]
+    pub tx_buffer_packets: Option<usize>,
+    /// Limit of the tx buffer in bytes.
+    #[serde(default)]
+    pub tx_buffer_bytes: Option<usize>,
+    /// `"tail"` (the default) or `"head"`.
+    #[serde(default, deserialize_with = "drop_policy")]
+    pub tx_drop_policy: Option<TxDropPolicy>,
+}
+
+/// The `fdir` table of a port, masks are hexadecimal strings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FdirSection {
+    pub mode: RteFdirMode,
+    /// Not supported by X710 NICs.
+    #[serde(default)]
+    pub pballoc: Option<RteFdirPballocType>,
+    #[serde(default)]
+    pub ipv4_mask: Option<Ipv4MaskSection>,
+    #[serde(default, deserialize_with = "hex_u16")]
+    pub src_port_mask: u16,
+    #[serde(default, deserialize_with = "hex_u16")]
+    pub dst_port_mask: u16,
+}
+
+/// The `ipv4_mask` table of `fdir`, addresses are dotted quads or hexadecimal strings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ipv4MaskSection {
+    #[serde(default, deserialize_with = "ipv4_mask")]
+    pub src_ip: u32,
+    #[serde(default, deserialize_with = "ipv4_mask")]
+    pub dst_ip: u32,
+    #[serde(default, deserialize_with = "hex_u8")]
+    pub tos: u8,
+    #[serde(default, deserialize_with = "hex_u8")]
+    pub ttl: u8,
+    #[serde(default, deserialize_with = "hex_u8")]
+    pub proto: u8,
+}
+
+/// A `[[pipeline]]` section.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineSection {
+    /// Defaults to `pipeline-<index>`.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub rx_port: String,
+    /// Defaults to `rx_port`.
+    #[serde(default)]
+    pub tx_port: Option<String>,
+    /// Defaults to all cores with a queue of `rx_port`.
+    #[serde(default)]
+    pub cores: Option<Vec<i32>>,
+    /// `[[pipeline.operator]]` sections.
+    #[serde(default)]
+    pub operator: Vec<OperatorSection>,
+}
+
+/// A `[[pipeline.operator]]` section. All keys besides `kind` are parameters of the operator, they are checked by its
+/// factory.
+#[derive(Deserialize)]
+pub struct OperatorSection {
+    pub kind: String,
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+}
+
+fn default_name() -> String {
+    String::from(DEFAULT_NAME)
+}
+
+fn default_pool_size() -> u32 {
+    DEFAULT_POOL_SIZE
+}
+
+fn default_cache_size() -> u32 {
+    DEFAULT_CACHE_SIZE
+}
+
+fn default_mbuf_cnt() -> u32 {
+    DEFAULT_MBUF_CNT
+}
+
+fn default_rxd() -> u16 {
+    NUM_RXD
+}
+
+fn default_txd() -> u16 {
+    NUM_TXD
+}
+
+/// A core number, cores are counted from 0.
+fn core<E: de::Error>(core: i64, expected: &dyn Expected) -> Result<i32, E> {
+    if core < 0 || core > i64::from(i32::MAX) {
+        return Err(E::invalid_value(Unexpected::Signed(core), expected));
+    }
+    Ok(core as i32)
+}
+
+fn core_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    struct CoreVisitor;
+
+    impl<'de> Visitor<'de> for CoreVisitor {
+        type Value = i32;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a core number")
+        }
+
+        fn visit_i64<E: de::Error>(self, number: i64) -> Result<i32, E> {
+            core(number, &self)
+        }
+
+        fn visit_u64<E: de::Error>(self, number: u64) -> Result<i32, E> {
+            core(number.min(i64::MAX as u64) as i64, &self)
+        }
+
+        fn visit_str<E: de::Error>(self, number: &str) -> Result<i32, E> {
+            match number.parse::<i64>() {
+                Ok(number) => core(number, &self),
+                Err(_) => Err(E::invalid_value(Unexpected::Str(number), &self)),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(CoreVisitor)
+}
+
+/// A single core or a list of cores.
+fn core_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<i32>, D::Error> {
+    struct CoresVisitor;
+
+    impl<'de> Visitor<'de> for CoresVisitor {
+        type Value = Vec<i32>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a core number or a list of core numbers")
+        }
+
+        fn visit_i64<E: de::Error>(self, number: i64) -> Result<Vec<i32>, E> {
+            core(number, &self).map(|core| vec![core])
+        }
+
+        fn visit_u64<E: de::Error>(self, number: u64) -> Result<Vec<i32>, E> {
+            self.visit_i64(number.min(i64::MAX as u64) as i64)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<i32>, A::Error> {
+            let mut cores = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(number) = seq.next_element::<i64>()? {
+                cores.push(core(number, &self)?);
+            }
+            Ok(cores)
+        }
+    }
+
+    deserializer.deserialize_any(CoresVisitor)
+}
+
+fn optional_core_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<i32>>, D::Error> {
+    core_list(deserializer).map(Some)
+}
+
+fn ipv4_net<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Ipv4Net>, D::Error> {
+    let net = <String as de::Deserialize>::deserialize(deserializer)?;
+    net.parse::<Ipv4Net>()
+        .map(Some)
+        .map_err(|_| de::Error::invalid_value(Unexpected::Str(&net), &"an IPv4 address with prefix length"))
+}
+
+fn mac_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<MacAddress>, D::Error> {
+    let mac = <String as de::Deserialize>::deserialize(deserializer)?;
+    mac.parse::<MacAddress>()
+        .map(Some)
+        .map_err(|_| de::Error::invalid_value(Unexpected::Str(&mac), &"a MAC address"))
+}
+
+fn drop_policy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<TxDropPolicy>, D::Error> {
+    let policy = <String as de::Deserialize>::deserialize(deserializer)?;
+    match &policy[..] {
+        "tail" => Ok(Some(TxDropPolicy::TailDrop)),
+        "head" => Ok(Some(TxDropPolicy::HeadDrop)),
+        _ => Err(de::Error::invalid_value(
+            Unexpected::Str(&policy),
+            &"\"tail\" or \"head\"",
+        )),
+    }
+}
+
+fn hex<'de, D: Deserializer<'de>>(deserializer: D, max: u32) -> Result<u32, D::Error> {
+    let hex = <String as de::Deserialize>::deserialize(deserializer)?;
+    match u32::from_str_radix(&hex, 16) {
+        Ok(value) if value <= max => Ok(value),
+        _ => {
+            let expected = format!("a hexadecimal number up to {:x}", max);
+            Err(de::Error::invalid_value(Unexpected::Str(&hex), &expected.as_str()))
+        }
+    }
+}
+
+fn hex_u8<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    hex(deserializer, u32::from(u8::MAX)).map(|value| value as u8)
+}
+
+fn hex_u16<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    hex(deserializer, u32::from(u16::MAX)).map(|value| value as u16)
+}
+
+fn ipv4_mask<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let mask = <String as de::Deserialize>::deserialize(deserializer)?;
+    match mask.parse::<Ipv4Addr>() {
+        Ok(address) => Ok(u32::from(address)),
+        Err(_) => u32::from_str_radix(&mask, 16)
+            .map_err(|_| de::Error::invalid_value(Unexpected::Str(&mask), &"an IPv4 address or a hexadecimal number")),
+    }
+}
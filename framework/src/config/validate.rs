@@ -0,0 +1,386 @@
+use super::config_reader::configuration_from_file;
+use super::{ConfigurationFile, NetbricksConfiguration, PortConfiguration};
+use allocators::NumaTopology;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use toml;
+use toml::de::{DeTable, DeValue};
+
+/// Cores are selected by a 64 bit mask.
+const MAX_CORE: i32 = 63;
+
+/// A problem in a configuration, located by the TOML path of the offending key, e.g. `netbricks.ports[1].rxd`, and
+/// its line if the configuration was read from a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationError {
+    pub path: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new<P: Into<String>, M: Into<String>>(path: P, message: M) -> ValidationError {
+        ValidationError {
+            path: path.into(),
+            line: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// The positions of the keys, tables and array elements of a TOML document, under the paths used by
+/// `ValidationError`. They are taken from the spans of the `toml` parser, nothing is located in a document which
+/// does not parse.
+pub(crate) struct TomlLocations<'a> {
+    source: &'a str,
+    keys: Vec<(String, usize)>,
+}
+
+impl<'a> TomlLocations<'a> {
+    pub(crate) fn scan(source: &'a str) -> TomlLocations<'a> {
+        let mut locations = TomlLocations {
+            source,
+            keys: Vec::new(),
+        };
+        if let Ok(document) = DeTable::parse(source) {
+            locations.add_table("", document.get_ref());
+        }
+        locations
+    }
+
+    fn add_table(&mut self, path: &str, table: &DeTable) {
+        for (key, value) in table.iter() {
+            let path = if path.is_empty() {
+                key.get_ref().to_string()
+            } else {
+                format!("{}.{}", path, key.get_ref())
+            };
+            self.keys.push((path.clone(), key.span().start));
+            self.add_value(&path, value.get_ref());
+        }
+    }
+
+    fn add_value(&mut self, path: &str, value: &DeValue) {
+        match *value {
+            DeValue::Table(ref table) => self.add_table(path, table),
+            DeValue::Array(ref array) => {
+                for (i, element) in array.into_iter().enumerate() {
+                    let path = format!("{}[{}]", path, i);
+                    self.keys.push((path.clone(), element.span().start));
+                    self.add_value(&path, element.get_ref());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn line_at(&self, offset: usize) -> usize {
+        let offset = offset.min(self.source.len());
+        self.source.as_bytes()[..offset].iter().filter(|c| **c == b'\n').count() + 1
+    }
+
+    /// The innermost key, table or element which starts at or before `offset`.
+    fn path_at(&self, offset: usize) -> String {
+        self.keys
+            .iter()
+            .filter(|&&(_, start)| start <= offset)
+            .max_by_key(|&&(_, start)| start)
+            .map_or_else(String::new, |(path, _)| path.clone())
+    }
+
+    /// The line of `path`, or of the closest enclosing key if `path` does not appear in the document, e.g. because
+    /// its value is a default.
+    fn line_of(&self, path: &str) -> Option<usize> {
+        let mut path = path;
+        loop {
+            if let Some(&(_, start)) = self.keys.iter().find(|(p, _)| p == path) {
+                return Some(self.line_at(start));
+            }
+            match path.rfind(['.', '[']) {
+                Some(end) => path = &path[..end],
+                None => return None,
+            }
+        }
+    }
+
+    pub(crate) fn locate(&self, error: &mut ValidationError) {
+        error.line = self.line_of(&error.path);
+    }
+
+    /// An error of the `toml` parser, located by its span.
+    pub(crate) fn parse_error(&self, error: &toml::de::Error) -> ValidationError {
+        let offset = error.span().map_or(0, |span| span.start);
+        ValidationError {
+            path: self.path_at(offset),
+            line: Some(self.line_at(offset)),
+            message: error.message().to_string(),
+        }
+    }
+}
+
+fn is_kni_or_virtio(name: &str) -> bool {
+    name.starts_with("kni:") || name.starts_with("virtio:")
+}
+
+fn base_name(name: &str) -> &str {
+    name.split(',').next().unwrap()
+}
+
+fn check_core(core: i32, path: String, errors: &mut Vec<ValidationError>) -> bool {
+    if !(0..=MAX_CORE).contains(&core) {
+        errors.push(ValidationError::new(
+            path,
+            format!("core {} is not between 0 and {}", core, MAX_CORE),
+        ));
+        return false;
+    }
+    true
+}
+
+fn check_port_cores(
+    index: usize,
+    port: &PortConfiguration,
+    configuration: &NetbricksConfiguration,
+) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let path = format!("netbricks.ports[{}]", index);
+    for &(key, cores) in &[
+        ("rx_cores", &port.rx_queues),
+        ("tx_cores", &port.tx_queues),
+        ("k_cores", &port.k_cores),
+    ] {
+        for (i, core) in cores.iter().enumerate() {
+            let valid = check_core(*core, format!("{}.{}[{}]", path, key, i), &mut errors);
+            if valid && configuration.strict && key != "k_cores" && !configuration.cores.contains(core) {
+                errors.push(ValidationError::new(
+                    format!("{}.{}[{}]", path, key, i),
+                    format!("core {} is not listed in netbricks.cores, which is strict", core),
+                ));
+            }
+        }
+    }
+
+    if (port.name.starts_with("bess:") || port.name.starts_with("ovs:")) && port.rx_queues.is_empty() {
+        errors.push(ValidationError::new(
+            path.clone(),
+            format!("port {} needs a core", port.name),
+        ));
+    }
+    errors
+}
+
+fn check_kni(configuration: &NetbricksConfiguration) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let mut associated: HashMap<&str, usize> = HashMap::new();
+    for (i, port) in configuration.ports.iter().enumerate() {
+        let kni = match port.kni {
+            Some(ref kni) => kni,
+            None => continue,
+        };
+        let path = format!("netbricks.ports[{}].kni", i);
+        if is_kni_or_virtio(&port.name) {
+            errors.push(ValidationError::new(
+                path,
+                "kni and virtio ports cannot have an associated kni port",
+            ));
+        } else if !is_kni_or_virtio(kni) {
+            errors.push(ValidationError::new(
+                path,
+                format!("{} is not a kni or virtio port", kni),
+            ));
+        } else if !configuration.ports.iter().any(|p| base_name(&p.name) == kni) {
+            errors.push(ValidationError::new(
+                path,
+                format!("kni port {} is not declared in netbricks.ports", kni),
+            ));
+        } else if let Some(other) = associated.insert(kni, i) {
+            errors.push(ValidationError::new(
+                path,
+                format!("kni port {} is already associated with netbricks.ports[{}]", kni, other),
+            ));
+        }
+    }
+    errors
+}
+
+fn check_pipelines(configuration: &NetbricksConfiguration) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let mut names = HashSet::new();
+    for (i, pipeline) in configuration.pipelines.iter().enumerate() {
+        let path = format!("pipeline[{}]", i);
+        if !names.insert(&pipeline.name) {
+            errors.push(ValidationError::new(
+                format!("{}.name", path),
+                format!("pipeline {} appears twice", pipeline.name),
+            ));
+        }
+        let port_of = |name: &str| configuration.ports.iter().find(|p| base_name(&p.name) == name);
+        let rx_port = port_of(&pipeline.rx_port);
+        if rx_port.is_none() {
+            errors.push(ValidationError::new(
+                format!("{}.rx_port", path),
+                format!("unknown port {}", pipeline.rx_port),
+            ));
+        }
+        if pipeline.tx_port != pipeline.rx_port && port_of(&pipeline.tx_port).is_none() {
+            errors.push(ValidationError::new(
+                format!("{}.tx_port", path),
+                format!("unknown port {}", pipeline.tx_port),
+            ));
+        }
+        if let Some(rx_port) = rx_port {
+            for (c, core) in pipeline.cores.iter().enumerate() {
+                if !rx_port.rx_queues.contains(core) {
+                    errors.push(ValidationError::new(
+                        format!("{}.cores[{}]", path, c),
+                        format!("port {} has no queue on core {}", pipeline.rx_port, core),
+                    ));
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// Cross-check the parts of a configuration: cores, queues of ports and cores, duplicate ports, the associations of
+/// ports with kni ports and the ports and cores of pipelines. Also used for configurations which were not read from
+/// a file.
+pub fn check_configuration(configuration: &NetbricksConfiguration) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    check_core(
+        configuration.primary_core,
+        "netbricks.master_core".to_string(),
+        &mut errors,
+    );
+    for (i, core) in configuration.cores.iter().enumerate() {
+        check_core(*core, format!("netbricks.cores[{}]", i), &mut errors);
+    }
+
+    let mut names = HashMap::new();
+    for (i, port) in configuration.ports.iter().enumerate() {
+        if let Some(other) = names.insert(base_name(&port.name), i) {
+            errors.push(ValidationError::new(
+                format!("netbricks.ports[{}].name", i),
+                format!(
+                    "port {} is already declared as netbricks.ports[{}]",
+                    base_name(&port.name),
+                    other
+                ),
+            ));
+        }
+        errors.append(&mut check_port_cores(i, port, configuration));
+    }
+    errors.append(&mut check_kni(configuration));
+    errors.append(&mut check_pipelines(configuration));
+    errors
+}
+
+/// Find ports whose queues or kni port cannot be fully used: rx queues without a paired tx queue, e.g. `rx_cores`
+/// without `tx_cores`, and kni ports which no port declares as its `kni`. Such configurations were always accepted,
+/// so they are reported as warnings.
+pub fn check_port_pairing(configuration: &NetbricksConfiguration) -> Vec<ValidationError> {
+    let mut warnings = Vec::new();
+    for (i, port) in configuration.ports.iter().enumerate() {
+        // rx queue i is paired with tx queue i, the queues of kni and virtio ports are those of their associated port
+        if !is_kni_or_virtio(&port.name) && port.tx_queues.len() < port.rx_queues.len() {
+            warnings.push(ValidationError::new(
+                format!("netbricks.ports[{}].tx_cores", i),
+                format!(
+                    "port {} has {} rx queues but only {} tx queues",
+                    port.name,
+                    port.rx_queues.len(),
+                    port.tx_queues.len()
+                ),
+            ));
+        }
+        let name = base_name(&port.name);
+        if port.name.starts_with("kni:")
+            && !configuration
+                .ports
+                .iter()
+                .any(|p| p.kni.as_ref().is_some_and(|kni| kni == name))
+        {
+            warnings.push(ValidationError::new(
+                format!("netbricks.ports[{}].name", i),
+                format!(
+                    "kni port {} is not associated with a port, set kni = \"{}\" on the port it belongs to",
+                    port.name, name
+                ),
+            ));
+        }
+    }
+    warnings
+}
+
+/// Find cores which poll the queues of a port on another NUMA node. These are not errors, but traffic crossing sockets
+/// costs throughput, so they are reported as warnings. Ports and cores of unknown node, like virtual ports, are skipped.
+pub fn check_numa_placement(configuration: &NetbricksConfiguration, topology: &NumaTopology) -> Vec<ValidationError> {
+    let mut warnings = Vec::new();
+    for (i, port) in configuration.ports.iter().enumerate() {
+        let device = base_name(&port.name);
+        let node = match topology.node_of_device(device) {
+            Some(node) => node,
+            None => continue,
+        };
+        let mut remote = HashSet::new();
+        for &(key, cores) in &[("rx_cores", &port.rx_queues), ("tx_cores", &port.tx_queues)] {
+            for (j, core) in cores.iter().enumerate() {
+                match topology.node_of_core(*core) {
+                    Some(core_node) if core_node != node && remote.insert(*core) => {
+                        warnings.push(ValidationError::new(
+                            format!("netbricks.ports[{}].{}[{}]", i, key, j),
+                            format!(
+                                "core {} on numa node {} polls port {} on numa node {}",
+                                core, core_node, device, node
+                            ),
+                        ))
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// Read and check a configuration in TOML format. All errors found are returned, each with the line it refers to.
+pub fn validate_configuration_from_str(configuration: &str) -> Result<NetbricksConfiguration, Vec<ValidationError>> {
+    let locations = TomlLocations::scan(configuration);
+    let file =
+        toml::from_str::<ConfigurationFile>(configuration).map_err(|error| vec![locations.parse_error(&error)])?;
+    let mut errors = Vec::new();
+    let parsed = configuration_from_file(file, &mut errors);
+    errors.append(&mut check_configuration(&parsed));
+    if errors.is_empty() {
+        Ok(parsed)
+    } else {
+        for error in &mut errors {
+            locations.locate(error);
+        }
+        Err(errors)
+    }
+}
+
+/// Read and check a configuration file, see `validate_configuration_from_str`.
+pub fn validate_configuration(filename: &str) -> Result<NetbricksConfiguration, Vec<ValidationError>> {
+    let mut configuration = String::new();
+    File::open(filename)
+        .and_then(|mut f| f.read_to_string(&mut configuration))
+        .map_err(|e| vec![ValidationError::new("", format!("cannot read {}: {}", filename, e))])?;
+    validate_configuration_from_str(&configuration)
+}
@@ -0,0 +1,247 @@
+use futures::{Async, AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+/// Default limit of the length of one message.
+pub const DEFAULT_MAX_FRAME: usize = 1 << 20;
+
+/// Bytes read from the stream at once.
+const READ_CHUNK: usize = 4096;
+
+/// Splits a byte stream into messages and turns messages into bytes.
+pub trait Codec {
+    type In;
+    type Out;
+
+    /// Remove the first complete message from `buf`, if there is one.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<Self::In>>;
+
+    /// Append the bytes of `msg` to `buf`.
+    fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()>;
+}
+
+fn too_long(len: usize, max_len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("message of {} bytes exceeds the limit of {} bytes", len, max_len),
+    )
+}
+
+/// Messages preceded by their length as a 32 bit big-endian integer.
+#[derive(Clone, Copy, Debug)]
+pub struct LengthDelimited {
+    max_len: usize,
+}
+
+impl LengthDelimited {
+    pub fn new(max_len: usize) -> LengthDelimited {
+        LengthDelimited { max_len }
+    }
+}
+
+impl Default for LengthDelimited {
+    fn default() -> LengthDelimited {
+        LengthDelimited::new(DEFAULT_MAX_FRAME)
+    }
+}
+
+impl Codec for LengthDelimited {
+    type In = Vec<u8>;
+    type Out = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+        let len = (buf[0] as usize) << 24 | (buf[1] as usize) << 16 | (buf[2] as usize) << 8 | buf[3] as usize;
+        if len > self.max_len {
+            return Err(too_long(len, self.max_len));
+        }
+        if buf.len() < 4 + len {
+            return Ok(None);
+        }
+        let msg = buf[4..4 + len].to_vec();
+        buf.drain(..4 + len);
+        Ok(Some(msg))
+    }
+
+    fn encode(&mut self, msg: Vec<u8>, buf: &mut Vec<u8>) -> io::Result<()> {
+        if msg.len() > self.max_len {
+            return Err(too_long(msg.len(), self.max_len));
+        }
+        let len = msg.len() as u32;
+        buf.extend_from_slice(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]);
+        buf.extend_from_slice(&msg);
+        Ok(())
+    }
+}
+
+/// JSON documents, one per line. Received messages are deserialized into `In`, sent ones are serialized from `Out`.
+/// Empty lines are skipped.
+#[derive(Debug)]
+pub struct JsonLines<In, Out> {
+    max_len: usize,
+    phantom: PhantomData<fn(Out) -> In>,
+}
+
+impl<In, Out> JsonLines<In, Out> {
+    pub fn new(max_len: usize) -> JsonLines<In, Out> {
+        JsonLines {
+            max_len,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<In, Out> Default for JsonLines<In, Out> {
+    fn default() -> JsonLines<In, Out> {
+        JsonLines::new(DEFAULT_MAX_FRAME)
+    }
+}
+
+impl<In, Out> Clone for JsonLines<In, Out> {
+    fn clone(&self) -> JsonLines<In, Out> {
+        JsonLines::new(self.max_len)
+    }
+}
+
+impl<In: DeserializeOwned, Out: Serialize> Codec for JsonLines<In, Out> {
+    type In = In;
+    type Out = Out;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<In>> {
+        loop {
+            let end = match buf.iter().position(|b| *b == b'\n') {
+                Some(end) => end,
+                None if buf.len() > self.max_len => return Err(too_long(buf.len(), self.max_len)),
+                None => return Ok(None),
+            };
+            if end > self.max_len {
+                return Err(too_long(end, self.max_len));
+            }
+            let line: Vec<u8> = buf.drain(..end + 1).collect();
+            if line.iter().all(|b| b.is_ascii_whitespace()) {
+                continue;
+            }
+            return serde_json::from_slice(&line[..end])
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+    }
+
+    fn encode(&mut self, msg: Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        serde_json::to_writer(&mut *buf, &msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        buf.push(b'\n');
+        Ok(())
+    }
+}
+
+/// The messages of a non-blocking byte stream, e.g. a `tokio_core::net::TcpStream`: a `Stream` of the received
+/// messages and a `Sink` for the messages to send. It must be polled within a task, which is woken up when the
+/// stream becomes readable or writable again.
+pub struct Framed<S, C> {
+    stream: S,
+    codec: C,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<S: Read + Write, C: Codec> Framed<S, C> {
+    pub fn new(stream: S, codec: C) -> Framed<S, C> {
+        Framed {
+            stream,
+            codec,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            eof: false,
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Answer each message with the message `handler` resolves to, in the order the messages arrived. Resolves
+    /// when the peer closed the stream and all answers are sent.
+    pub fn respond<F, R>(self, handler: F) -> impl Future<Item = (), Error = io::Error>
+    where
+        F: FnMut(C::In) -> R,
+        R: IntoFuture<Item = C::Out, Error = io::Error>,
+    {
+        let (sink, stream) = self.split();
+        stream.and_then(handler).forward(sink).map(|_| ())
+    }
+}
+
+impl<S: Read + Write, C: Codec> Stream for Framed<S, C> {
+    type Item = C::In;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<C::In>, io::Error> {
+        loop {
+            if let Some(msg) = self.codec.decode(&mut self.read_buf)? {
+                return Ok(Async::Ready(Some(msg)));
+            }
+            if self.eof {
+                return if self.read_buf.is_empty() {
+                    Ok(Async::Ready(None))
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "stream closed within a message",
+                    ))
+                };
+            }
+            let mut chunk = [0u8; READ_CHUNK];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.read_buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<S: Read + Write, C: Codec> Sink for Framed<S, C> {
+    type SinkItem = C::Out;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, msg: C::Out) -> StartSend<C::Out, io::Error> {
+        // the messages queued while the stream is not writable are limited to about one read chunk
+        if self.write_buf.len() >= READ_CHUNK {
+            self.poll_complete()?;
+            if self.write_buf.len() >= READ_CHUNK {
+                return Ok(AsyncSink::NotReady(msg));
+            }
+        }
+        self.codec.encode(msg, &mut self.write_buf)?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        while !self.write_buf.is_empty() {
+            match self.stream.write(&self.write_buf) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "stream closed")),
+                Ok(n) => {
+                    self.write_buf.drain(..n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.stream.flush()?;
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        self.poll_complete()
+    }
+}
@@ -17,12 +17,12 @@ impl PollHandle {
     // }
 
     pub fn schedule_read<Fd: AsFd>(&self, fd: &Fd, token: Token) {
-        let event = EpollEvent::new(
+        let mut event = EpollEvent::new(
             EpollFlags::EPOLLIN | EpollFlags::EPOLLET | EpollFlags::EPOLLONESHOT,
             token,
         );
         // epoll_ctl(self.epoll_fd, EpollOp::EpollCtlMod, fd, &mut event).unwrap();
-        self.epoll.add(fd, event).expect("Epoll::add failed");
+        self.epoll.modify(fd, &mut event).expect("Epoll.modify failed");
     }
 
     //pub fn schedule_write<Fd: AsRawFd>(&self, file: &Fd, token: Token) {
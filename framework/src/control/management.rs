@@ -0,0 +1,411 @@
+use super::codec::{Framed, JsonLines};
+use super::runtime::SchedulerChannel;
+use super::tcp::TcpControlServer;
+use common::{errors, ErrorKind};
+use config::ConfigChange;
+use futures::sync::oneshot;
+use futures::{future, Future};
+use interface::PmdPort;
+use scheduler::{NetBricksContext, StandaloneScheduler};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use tokio_core::net::TcpStream;
+use utils::FiveTupleV4;
+use uuid::Uuid;
+
+/// Commands of the management protocol, sent as JSON objects with the command name in the field `command`, one
+/// per line, e.g. `{"command":"set_task_state","core":1,"task":"<uuid>","enabled":true}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ManagementRequest {
+    ListCores,
+    ListTasks,
+    SetTaskState {
+        core: i32,
+        task: String,
+        enabled: bool,
+    },
+    GetPerformance,
+    ListPorts,
+    GetPortStats {
+        port: String,
+    },
+    ListFlowTables,
+    DumpFlowTable {
+        table: String,
+    },
+    /// Reload the configuration from `path`, by default from the file the NF was started with.
+    Reload {
+        path: Option<String>,
+    },
+    Shutdown,
+}
+
+/// Answers to `ManagementRequest`s, one per request, tagged with the field `result`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ManagementResponse {
+    Cores {
+        cores: Vec<i32>,
+    },
+    Tasks {
+        tasks: Vec<TaskInfo>,
+    },
+    TaskState {
+        core: i32,
+        task: String,
+        enabled: bool,
+        previous: bool,
+    },
+    Performance {
+        tasks: Vec<TaskPerformance>,
+    },
+    Ports {
+        ports: Vec<PortInfo>,
+    },
+    PortStats {
+        port: String,
+        queues: Vec<QueueStats>,
+    },
+    FlowTables {
+        tables: Vec<String>,
+    },
+    FlowTable {
+        table: String,
+        flows: Vec<FlowEntry>,
+    },
+    /// The applied changes of a reload.
+    Reloaded {
+        changes: Vec<String>,
+    },
+    ShuttingDown,
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaskInfo {
+    pub core: i32,
+    pub uuid: String,
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// The `PerformanceData` of a task.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaskPerformance {
+    pub core: i32,
+    pub uuid: String,
+    pub name: String,
+    pub cycles: u64,
+    pub count: u64,
+    pub queue_len: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PortInfo {
+    pub name: String,
+    pub port_id: u16,
+    pub rxqs: u16,
+    pub txqs: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub queue: u16,
+    pub rx: usize,
+    pub tx: usize,
+    pub queued: usize,
+    pub dropped: usize,
+    pub max_q_len: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FlowEntry {
+    pub src: String,
+    pub dst: String,
+    pub proto: u8,
+    pub value: String,
+}
+
+impl FlowEntry {
+    pub fn new<T: Debug>(flow: &FiveTupleV4, value: &T) -> FlowEntry {
+        FlowEntry {
+            src: flow.src_socket_addr().to_string(),
+            dst: flow.dst_socket_addr().to_string(),
+            proto: flow.proto,
+            value: format!("{:?}", value),
+        }
+    }
+}
+
+/// The entries of a flow table, e.g. of `MergeableStoreCP::iter`.
+pub fn flow_entries<'a, T, I>(flows: I) -> Vec<FlowEntry>
+where
+    T: Debug + 'a,
+    I: IntoIterator<Item = (&'a FiveTupleV4, &'a T)>,
+{
+    flows
+        .into_iter()
+        .map(|(flow, value)| FlowEntry::new(flow, value))
+        .collect()
+}
+
+/// A reload requested by a client, see `ManagementServer::on_reload`.
+pub struct ReloadRequest {
+    pub path: Option<String>,
+    reply: oneshot::Sender<Result<Vec<String>, String>>,
+}
+
+impl ReloadRequest {
+    /// Answer the client with the outcome of the reload.
+    pub fn reply(self, result: errors::Result<Vec<ConfigChange>>) {
+        let result = result
+            .map(|changes| changes.iter().map(|change| change.to_string()).collect())
+            .map_err(|e| e.to_string());
+        let _ = self.reply.send(result);
+    }
+}
+
+type FlowTableDump = Box<dyn FnMut() -> Vec<FlowEntry> + Send>;
+type ShutdownHandler = Box<dyn FnMut() + Send>;
+type Reply = Box<dyn Future<Item = ManagementResponse, Error = io::Error>>;
+
+/// Lets external tools inspect and steer a running NF: list cores, tasks and ports, enable or disable tasks, read
+/// task performance and port statistics, dump registered flow tables, reload the configuration and request shutdown.
+/// Serve it on a `ControlRuntime`, the protocol is `JsonLines` of `ManagementRequest`s and `ManagementResponse`s.
+pub struct ManagementServer {
+    schedulers: SchedulerChannel,
+    ports: BTreeMap<String, Arc<PmdPort>>,
+    flow_tables: BTreeMap<String, FlowTableDump>,
+    reload: Option<Sender<ReloadRequest>>,
+    shutdown: Option<ShutdownHandler>,
+}
+
+impl ManagementServer {
+    pub fn new(context: &NetBricksContext) -> ManagementServer {
+        let mut server = ManagementServer::with_schedulers(context.scheduler_channel());
+        server.ports = context
+            .ports
+            .iter()
+            .map(|(name, port)| (name.clone(), port.clone()))
+            .collect();
+        server
+    }
+
+    /// A server without ports, e.g. for schedulers which were not started by a `NetBricksContext`.
+    pub fn with_schedulers(schedulers: SchedulerChannel) -> ManagementServer {
+        ManagementServer {
+            schedulers,
+            ports: BTreeMap::new(),
+            flow_tables: BTreeMap::new(),
+            reload: None,
+            shutdown: None,
+        }
+    }
+
+    /// Make a flow table available as `name`. `dump` is called on the control core for every dump, so the table has
+    /// to be shared with the data plane, e.g. a `MergeableStoreCP` behind a mutex which is synced before the dump.
+    pub fn flow_table<F>(mut self, name: &str, dump: F) -> ManagementServer
+    where
+        F: FnMut() -> Vec<FlowEntry> + Send + 'static,
+    {
+        self.flow_tables.insert(name.to_string(), Box::new(dump));
+        self
+    }
+
+    /// Forward reload requests to `requests`, they are answered by the thread owning the `NetBricksContext`, see
+    /// `NetBricksContext::handle_reload_requests`. Without it reload requests are refused.
+    pub fn on_reload(mut self, requests: Sender<ReloadRequest>) -> ManagementServer {
+        self.reload = Some(requests);
+        self
+    }
+
+    /// Called on the control core when a client requests shutdown, it must not block. Without a handler shutdown
+    /// requests are refused.
+    pub fn on_shutdown<F>(mut self, handler: F) -> ManagementServer
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.shutdown = Some(Box::new(handler));
+        self
+    }
+
+    /// Serve the clients of `server`, the returned future has to be spawned on the handle the server was bound with.
+    pub fn serve(self, server: TcpControlServer) -> impl Future<Item = (), Error = ()> {
+        let state = Rc::new(RefCell::new(self));
+        let codec: JsonLines<ManagementRequest, ManagementResponse> = JsonLines::default();
+        server.serve(codec, move |peer, connection| {
+            debug!("management: connection from {}", peer);
+            serve_connection(state.clone(), connection)
+        })
+    }
+
+    fn handle(&mut self, request: ManagementRequest) -> Reply {
+        match request {
+            ManagementRequest::ListCores => reply(ManagementResponse::Cores {
+                cores: self.schedulers.cores(),
+            }),
+            ManagementRequest::ListTasks => Box::new(
+                on_cores(&self.schedulers, |core, scheduler| {
+                    scheduler
+                        .tasks()
+                        .iter()
+                        .map(|task| TaskInfo {
+                            core,
+                            uuid: task.uuid.to_string(),
+                            name: task.name.clone(),
+                            enabled: task.is_ready(),
+                        })
+                        .collect()
+                })
+                .then(|tasks| Ok(tasks.map_or_else(error, |tasks| ManagementResponse::Tasks { tasks }))),
+            ),
+            ManagementRequest::SetTaskState { core, task, enabled } => {
+                let uuid = match Uuid::parse_str(&task) {
+                    Ok(uuid) => uuid,
+                    Err(_) => return reply(error(format!("invalid task {}", task))),
+                };
+                Box::new(
+                    self.schedulers
+                        .query(core, move |scheduler| scheduler.set_task_state(&uuid, enabled))
+                        .then(move |previous| {
+                            Ok(match previous {
+                                Ok(Some(previous)) => ManagementResponse::TaskState {
+                                    core,
+                                    task,
+                                    enabled,
+                                    previous,
+                                },
+                                Ok(None) => error(format!("no task {} on core {}", task, core)),
+                                Err(e) => error(e),
+                            })
+                        }),
+                )
+            }
+            ManagementRequest::GetPerformance => Box::new(
+                on_cores(&self.schedulers, |core, scheduler| {
+                    scheduler
+                        .tasks()
+                        .iter()
+                        .map(|task| TaskPerformance {
+                            core,
+                            uuid: task.uuid.to_string(),
+                            name: task.name.clone(),
+                            cycles: task.cycles,
+                            count: task.count,
+                            queue_len: task.queue_len,
+                        })
+                        .collect()
+                })
+                .then(|tasks| Ok(tasks.map_or_else(error, |tasks| ManagementResponse::Performance { tasks }))),
+            ),
+            ManagementRequest::ListPorts => reply(ManagementResponse::Ports {
+                ports: self
+                    .ports
+                    .iter()
+                    .map(|(name, port)| PortInfo {
+                        name: name.clone(),
+                        port_id: port.port_id(),
+                        rxqs: port.rxqs(),
+                        txqs: port.txqs(),
+                    })
+                    .collect(),
+            }),
+            ManagementRequest::GetPortStats { port } => reply(match self.ports.get(&port) {
+                Some(pmd) => ManagementResponse::PortStats {
+                    queues: port_stats(pmd),
+                    port,
+                },
+                None => error(format!("no port {}", port)),
+            }),
+            ManagementRequest::ListFlowTables => reply(ManagementResponse::FlowTables {
+                tables: self.flow_tables.keys().cloned().collect(),
+            }),
+            ManagementRequest::DumpFlowTable { table } => reply(match self.flow_tables.get_mut(&table) {
+                Some(dump) => ManagementResponse::FlowTable { flows: dump(), table },
+                None => error(format!("no flow table {}", table)),
+            }),
+            ManagementRequest::Reload { path } => match self.reload {
+                Some(ref requests) => {
+                    let (sender, result) = oneshot::channel();
+                    if requests.send(ReloadRequest { path, reply: sender }).is_err() {
+                        return reply(error("reload is not available anymore"));
+                    }
+                    Box::new(result.then(|result| {
+                        Ok(match result {
+                            Ok(Ok(changes)) => ManagementResponse::Reloaded { changes },
+                            Ok(Err(message)) => ManagementResponse::Error { message },
+                            Err(_) => error("reload request was not answered"),
+                        })
+                    }))
+                }
+                None => reply(error("reload is not enabled")),
+            },
+            ManagementRequest::Shutdown => reply(match self.shutdown {
+                Some(ref mut handler) => {
+                    info!("management: shutdown requested");
+                    handler();
+                    ManagementResponse::ShuttingDown
+                }
+                None => error("shutdown is not enabled"),
+            }),
+        }
+    }
+}
+
+fn serve_connection(
+    state: Rc<RefCell<ManagementServer>>,
+    connection: Framed<TcpStream, JsonLines<ManagementRequest, ManagementResponse>>,
+) -> impl Future<Item = (), Error = io::Error> {
+    connection.respond(move |request| state.borrow_mut().handle(request))
+}
+
+#[inline]
+fn reply(response: ManagementResponse) -> Reply {
+    Box::new(future::ok(response))
+}
+
+fn error<E: ToString>(e: E) -> ManagementResponse {
+    ManagementResponse::Error { message: e.to_string() }
+}
+
+/// Runs `f` on all schedulers and concatenates the results.
+fn on_cores<T, F>(schedulers: &SchedulerChannel, f: F) -> impl Future<Item = Vec<T>, Error = ErrorKind>
+where
+    T: Send + 'static,
+    F: Fn(i32, &mut StandaloneScheduler) -> Vec<T> + Clone + Send + 'static,
+{
+    let queries: Vec<_> = schedulers
+        .cores()
+        .into_iter()
+        .map(|core| {
+            let f = f.clone();
+            schedulers.query(core, move |scheduler| f(core, scheduler))
+        })
+        .collect();
+    future::join_all(queries).map(|results| results.into_iter().flatten().collect())
+}
+
+fn port_stats(port: &PmdPort) -> Vec<QueueStats> {
+    (0..port.rxqs())
+        .map(|queue| {
+            let (rx, tx) = (port.rx_queue_stats(queue), port.tx_queue_stats(queue));
+            QueueStats {
+                queue,
+                rx: rx.stats.load(Ordering::Relaxed),
+                tx: tx.stats.load(Ordering::Relaxed),
+                queued: tx.queued.load(Ordering::Relaxed),
+                dropped: tx.dropped.load(Ordering::Relaxed),
+                max_q_len: rx.get_max_q_len(),
+            }
+        })
+        .collect()
+}
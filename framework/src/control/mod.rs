@@ -1,10 +1,16 @@
+pub use self::codec::*;
 #[cfg(target_os = "linux")]
 pub use self::epoll::*;
+pub use self::management::*;
+pub use self::runtime::*;
 use std::os::fd::AsFd;
 
+mod codec;
 #[cfg(target_os = "linux")]
 #[path = "linux/epoll.rs"]
 mod epoll;
+mod management;
+mod runtime;
 #[cfg(feature = "sctp")]
 pub mod sctp;
 pub mod tcp;
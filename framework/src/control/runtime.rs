@@ -0,0 +1,153 @@
+use common::{errors, ErrorKind};
+use futures::sync::oneshot;
+use futures::Future;
+use interface::dpdk::init_thread;
+use scheduler::{SchedulerCommand, StandaloneScheduler};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, SyncSender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use tokio_core::reactor::{Core, Handle, Remote};
+
+/// An event loop on a dedicated control core, running the control plane agents, e.g. `TcpControlServer`s. The data
+/// plane is reached through a `SchedulerChannel`.
+pub struct ControlRuntime {
+    core: i32,
+    remote: Remote,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ControlRuntime {
+    /// Start the event loop in a thread pinned to `core`. `setup` is called on that thread with the handle of the
+    /// loop to bind listeners and spawn agents, its error is returned.
+    pub fn start<F>(core: i32, setup: F) -> errors::Result<ControlRuntime>
+    where
+        F: FnOnce(&Handle) -> errors::Result<()> + Send + 'static,
+    {
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let (ready_sender, ready) = channel::<errors::Result<Remote>>();
+        let thread = thread::Builder::new().name(format!("ctrl-{}", core)).spawn(move || {
+            init_thread(core, core);
+            let mut reactor = match Core::new() {
+                Ok(reactor) => reactor,
+                Err(e) => {
+                    let _ = ready_sender.send(Err(e.into()));
+                    return;
+                }
+            };
+            if let Err(e) = setup(&reactor.handle()) {
+                let _ = ready_sender.send(Err(e));
+                return;
+            }
+            let _ = ready_sender.send(Ok(reactor.remote()));
+            // also returns if the runtime is dropped without stop
+            let _ = reactor.run(stopped);
+            debug!("control runtime on core {} stopped", core);
+        })?;
+        let remote = match ready.recv() {
+            Ok(remote) => remote,
+            Err(_) => Err(ErrorKind::RunTimeError(format!(
+                "control thread on core {} exited during setup",
+                core
+            ))),
+        };
+        match remote {
+            Ok(remote) => Ok(ControlRuntime {
+                core,
+                remote,
+                shutdown: Some(shutdown),
+                thread: Some(thread),
+            }),
+            Err(e) => {
+                let _ = thread.join();
+                Err(e)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn core(&self) -> i32 {
+        self.core
+    }
+
+    /// Spawn further agents from other threads.
+    #[inline]
+    pub fn remote(&self) -> &Remote {
+        &self.remote
+    }
+
+    /// Stop the event loop, the agents which are still running are dropped.
+    pub fn stop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("control runtime on core {} panicked", self.core);
+            }
+        }
+    }
+}
+
+impl Drop for ControlRuntime {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Typed access of control plane agents to the schedulers, see `NetBricksContext::scheduler_channel`.
+#[derive(Clone)]
+pub struct SchedulerChannel {
+    channels: HashMap<i32, SyncSender<SchedulerCommand>>,
+}
+
+impl SchedulerChannel {
+    pub fn new(channels: HashMap<i32, SyncSender<SchedulerCommand>>) -> SchedulerChannel {
+        SchedulerChannel { channels }
+    }
+
+    pub fn cores(&self) -> Vec<i32> {
+        let mut cores: Vec<_> = self.channels.keys().cloned().collect();
+        cores.sort();
+        cores
+    }
+
+    /// Send `command` to the scheduler on `core`. Blocks until the scheduler picks the command up, which it does
+    /// once per round over its tasks, so agents on an event loop use `query` instead.
+    pub fn send(&self, core: i32, command: SchedulerCommand) -> errors::Result<()> {
+        match self.channels.get(&core) {
+            Some(channel) => channel
+                .send(command)
+                .map_err(|_| ErrorKind::NoRunningSchedulerOnCore(core)),
+            None => Err(ErrorKind::NoRunningSchedulerOnCore(core)),
+        }
+    }
+
+    /// Run `f` on the scheduler thread of `core` between two tasks and resolve to its result. The command is handed
+    /// over by a helper thread, the calling event loop is not blocked until the scheduler picks it up.
+    pub fn query<T, F>(&self, core: i32, f: F) -> impl Future<Item = T, Error = ErrorKind>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut StandaloneScheduler) -> T + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        // `Run` takes an `Fn`, the query is taken out on the first call
+        let query = Mutex::new(Some((f, sender)));
+        let command = SchedulerCommand::Run(Box::new(move |scheduler| {
+            if let Some((f, sender)) = query.lock().unwrap().take() {
+                let _ = sender.send(f(scheduler));
+            }
+        }));
+        // if the command cannot be delivered it is dropped with the sender of the result, which fails the query
+        if let Some(channel) = self.channels.get(&core).cloned() {
+            let spawned = thread::Builder::new().name(format!("query-{}", core)).spawn(move || {
+                let _ = channel.send(command);
+            });
+            if let Err(e) = spawned {
+                error!("could not start a thread to query core {}: {}", core, e);
+            }
+        }
+        receiver.map_err(move |_| ErrorKind::NoRunningSchedulerOnCore(core))
+    }
+}
@@ -1,130 +1,81 @@
-use super::{Available, PollScheduler, Token, HUP, READ, WRITE};
-use fnv::FnvHasher;
-/// TCP connection.
+use super::codec::{Codec, Framed};
+use common::errors;
+use futures::{future, Future, IntoFuture, Stream};
+/// TCP control connections, each served by an agent future on the control runtime.
 use net2::TcpBuilder;
-use scheduler::Executable;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Handle, Timeout};
 
-use std::collections::HashMap;
-use std::hash::BuildHasherDefault;
-use std::marker::PhantomData;
-use std::net::*;
+/// Listen backlog used when the caller has no reason to choose another one.
+pub const DEFAULT_BACKLOG: i32 = 1024;
 
-pub trait TcpControlAgent {
-    fn new(address: SocketAddr, stream: TcpStream) -> Self;
-    fn handle_read_ready(&mut self) -> bool;
-    fn handle_write_ready(&mut self) -> bool;
-    fn handle_hup(&mut self) -> bool;
-}
+/// Pause after a failed accept, e.g. when we ran out of file descriptors, so we do not spin on the listener.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
-type FnvHash = BuildHasherDefault<FnvHasher>;
-pub struct TcpControlServer<T: TcpControlAgent> {
+pub struct TcpControlServer {
     listener: TcpListener,
-    scheduler: PollScheduler,
-    next_token: Token,
-    listener_token: Token,
-    phantom_t: PhantomData<T>,
-    connections: HashMap<Token, T, FnvHash>,
-}
-
-impl<T: TcpControlAgent> Executable for TcpControlServer<T> {
-    fn execute(&mut self) -> (u32, i32) {
-        self.schedule();
-        (1u32, 0)
-    }
-
-    //    #[inline]
-    //    fn dependencies(&mut self) -> Vec<usize> {
-    //        vec![]
-    //    }
+    handle: Handle,
 }
 
-impl<T: TcpControlAgent> TcpControlServer<T> {
-    pub fn new(address: SocketAddr) -> TcpControlServer<T> {
+impl TcpControlServer {
+    pub fn bind(address: SocketAddr, backlog: i32, handle: &Handle) -> errors::Result<TcpControlServer> {
         let socket = match address {
-            SocketAddr::V4(_) => TcpBuilder::new_v4(),
-            SocketAddr::V6(_) => TcpBuilder::new_v6(),
-        }
-        .unwrap();
-        let _ = socket.reuse_address(true).unwrap();
-        // TODO: Change 1024 to a parameter
-        let listener = socket.bind(address).unwrap().listen(1024).unwrap();
-        listener.set_nonblocking(true).unwrap();
-        let scheduler = PollScheduler::new();
-        let listener_token = 0;
-        let handle = scheduler.poll_handle();
-        handle.new_io_port(&listener, listener_token);
-        handle.schedule_read(&listener, listener_token);
-        TcpControlServer {
+            SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+        };
+        socket.reuse_address(true)?;
+        let listener = socket.bind(address)?.listen(backlog)?;
+        let listener = TcpListener::from_listener(listener, &address, handle)?;
+        Ok(TcpControlServer {
             listener,
-            scheduler,
-            next_token: listener_token + 1,
-            listener_token,
-            phantom_t: PhantomData,
-            connections: HashMap::with_capacity_and_hasher(32, Default::default()),
-        }
-    }
-
-    pub fn schedule(&mut self) {
-        match self.scheduler.get_token_noblock() {
-            Some((token, avail)) if token == self.listener_token => {
-                self.accept_connection(avail);
-            }
-            Some((token, available)) => {
-                self.handle_data(token, available);
-            }
-            _ => {}
-        }
+            handle: handle.clone(),
+        })
     }
 
-    #[cfg_attr(feature = "dev", allow(single_match))]
-    fn accept_connection(&mut self, available: Available) {
-        if available & READ != 0 {
-            // Make sure we have something to accept
-            match self.listener.accept() {
-                Ok((stream, addr)) => {
-                    let token = self.next_token;
-                    self.next_token += 1;
-                    stream.set_nonblocking(true).unwrap();
-                    //let stream_fd = stream.as_fd();
-                    //let scheduler = IOScheduler::new(self.scheduler.poll_handle(), stream_fd.clone(), token);
-                    self.connections.insert(token, T::new(addr, stream));
-                    // Add to some sort of hashmap.
-                }
-                Err(_) => {
-                    // TODO: Record
-                }
-            }
-        } else {
-            // TODO: Report something.
-        }
-        self.scheduler
-            .poll_handle()
-            .schedule_read(&self.listener, self.listener_token);
+    pub fn local_addr(&self) -> errors::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
     }
 
-    fn handle_data(&mut self, token: Token, available: Available) {
-        let preserve = {
-            match self.connections.get_mut(&token) {
-                Some(connection) => {
-                    if available & READ != 0 {
-                        connection.handle_read_ready()
-                    } else if available & WRITE != 0 {
-                        connection.handle_write_ready()
-                    } else if available & HUP != 0 {
-                        connection.handle_hup()
-                    } else {
-                        true
+    /// Accept connections and serve each with the future returned by `agent`, which gets the peer address and the
+    /// connection framed by `codec`. Connections are served concurrently, the errors of agents and of accept are
+    /// logged. The returned future runs forever, spawn it on the handle the server was bound with.
+    pub fn serve<C, F, R>(self, codec: C, mut agent: F) -> impl Future<Item = (), Error = ()>
+    where
+        C: Codec + Clone + 'static,
+        F: FnMut(SocketAddr, Framed<TcpStream, C>) -> R + 'static,
+        R: IntoFuture<Item = (), Error = io::Error>,
+        R::Future: 'static,
+    {
+        let handle = self.handle.clone();
+        let backoff_handle = self.handle;
+        let local = self.listener.local_addr().ok();
+        self.listener
+            .incoming()
+            .then(
+                move |accepted| -> Box<dyn Future<Item = Option<(TcpStream, SocketAddr)>, Error = ()>> {
+                    match accepted {
+                        Ok(connection) => Box::new(future::ok(Some(connection))),
+                        Err(e) => {
+                            warn!("control: failed to accept connection on {:?}: {}", local, e);
+                            match Timeout::new(ACCEPT_BACKOFF, &backoff_handle) {
+                                Ok(timeout) => Box::new(timeout.then(|_| Ok(None))),
+                                Err(_) => Box::new(future::ok(None)),
+                            }
+                        }
                     }
-                }
-                None => {
-                    // TODO: Record
-                    true
-                }
-            }
-        };
-
-        if !preserve {
-            self.connections.remove(&token);
-        }
+                },
+            )
+            .filter_map(|connection| connection)
+            .for_each(move |(stream, peer)| {
+                debug!("control: connection from {}", peer);
+                let connection = agent(peer, Framed::new(stream, codec.clone()))
+                    .into_future()
+                    .map_err(move |e| warn!("control: connection from {} failed: {}", peer, e));
+                handle.spawn(connection);
+                Ok(())
+            })
     }
 }
@@ -20,6 +20,10 @@ pub struct ArpIpv4Header {
 
 const HDR_SIZE: usize = 28;
 
+pub const ARP_HW_TYPE_ETHERNET: u16 = 1;
+pub const ARP_OP_REQUEST: u16 = 1;
+pub const ARP_OP_REPLY: u16 = 2;
+
 impl EndOffset for ArpIpv4Header {
     #[inline]
     fn offset(&self) -> usize {
@@ -84,4 +88,55 @@ impl ArpIpv4Header {
     pub fn sender_ip_addr(&self) -> Ipv4Addr {
         Ipv4Addr::from(self.sender_proto_addr())
     }
+
+    /// An ARP request or reply for IPv4 over Ethernet.
+    pub fn new(
+        operation: u16,
+        sender_hw_addr: MacAddress,
+        sender_ip_addr: Ipv4Addr,
+        target_hw_addr: MacAddress,
+        target_ip_addr: Ipv4Addr,
+    ) -> ArpIpv4Header {
+        let mut arp = ArpIpv4Header {
+            hw_type: u16::to_be(ARP_HW_TYPE_ETHERNET),
+            proto_etype: u16::to_be(0x0800),
+            hw_addr_len: 6,
+            proto_addr_len: 4,
+            ..Default::default()
+        };
+        arp.set_operation(operation);
+        arp.set_sender_hw_addr(sender_hw_addr);
+        arp.set_sender_ip_addr(sender_ip_addr);
+        arp.set_target_hw_addr(target_hw_addr);
+        arp.set_target_ip_addr(target_ip_addr);
+        arp
+    }
+    /// True for ARP of IPv4 over Ethernet, the only variant handled by the `ArpService`.
+    #[inline]
+    pub fn is_ipv4_over_ethernet(&self) -> bool {
+        self.hw_type() == ARP_HW_TYPE_ETHERNET
+            && self.proto_etype() == 0x0800
+            && self.hw_addr_len == 6
+            && self.proto_addr_len == 4
+    }
+    #[inline]
+    pub fn set_operation(&mut self, operation: u16) {
+        self.operation = u16::to_be(operation)
+    }
+    #[inline]
+    pub fn set_sender_hw_addr(&mut self, mac: MacAddress) {
+        self.sender_hw_addr = mac
+    }
+    #[inline]
+    pub fn set_target_hw_addr(&mut self, mac: MacAddress) {
+        self.target_hw_addr = mac
+    }
+    #[inline]
+    pub fn set_sender_ip_addr(&mut self, ip: Ipv4Addr) {
+        self.sender_proto_addr = u32::to_be(u32::from(ip))
+    }
+    #[inline]
+    pub fn set_target_ip_addr(&mut self, ip: Ipv4Addr) {
+        self.target_proto_addr = u32::to_be(u32::from(ip))
+    }
 }
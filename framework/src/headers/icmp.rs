@@ -0,0 +1,150 @@
+use super::{EndOffset, HeaderKind};
+use std::default::Default;
+use std::fmt;
+use std::slice;
+use utils::checksum;
+
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_DEST_UNREACH: u8 = 3;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+pub const ICMP_TIME_EXCEEDED: u8 = 11;
+
+/// codes of ICMP_DEST_UNREACH
+pub const ICMP_NET_UNREACH: u8 = 0;
+pub const ICMP_HOST_UNREACH: u8 = 1;
+pub const ICMP_PROT_UNREACH: u8 = 2;
+pub const ICMP_PORT_UNREACH: u8 = 3;
+pub const ICMP_FRAG_NEEDED: u8 = 4;
+pub const ICMP_NET_PROHIBITED: u8 = 9;
+pub const ICMP_HOST_PROHIBITED: u8 = 10;
+pub const ICMP_ADMIN_PROHIBITED: u8 = 13;
+
+/// codes of ICMP_TIME_EXCEEDED
+pub const ICMP_EXC_TTL: u8 = 0;
+pub const ICMP_EXC_FRAGTIME: u8 = 1;
+
+/// ICMPv4 header (RFC 792). The meaning of the last four bytes (`rest`) depends on the type.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, packed)]
+pub struct IcmpHeader {
+    icmp_type: u8,
+    code: u8,
+    csum: u16,
+    rest: u32,
+}
+
+const HDR_SIZE: usize = 8;
+
+impl fmt::Display for IcmpHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "type: {} code: {} checksum: {}",
+            self.icmp_type(),
+            self.code(),
+            self.checksum()
+        )
+    }
+}
+
+impl EndOffset for IcmpHeader {
+    #[inline]
+    fn offset(&self) -> usize {
+        HDR_SIZE
+    }
+
+    #[inline]
+    fn size() -> usize {
+        HDR_SIZE
+    }
+
+    #[inline]
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    #[inline]
+    fn header_kind(&self) -> HeaderKind {
+        HeaderKind::Icmp
+    }
+}
+
+impl IcmpHeader {
+    #[inline]
+    pub fn new() -> IcmpHeader {
+        Default::default()
+    }
+
+    #[inline]
+    pub fn icmp_type(&self) -> u8 {
+        self.icmp_type
+    }
+
+    #[inline]
+    pub fn set_icmp_type(&mut self, icmp_type: u8) {
+        self.icmp_type = icmp_type;
+    }
+
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    #[inline]
+    pub fn set_code(&mut self, code: u8) {
+        self.code = code;
+    }
+
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        u16::from_be(self.csum)
+    }
+
+    #[inline]
+    pub fn set_checksum(&mut self, csum: u16) {
+        self.csum = u16::to_be(csum);
+    }
+
+    #[inline]
+    pub fn rest(&self) -> u32 {
+        u32::from_be(self.rest)
+    }
+
+    #[inline]
+    pub fn set_rest(&mut self, rest: u32) {
+        self.rest = u32::to_be(rest);
+    }
+
+    /// identifier of echo request and reply
+    #[inline]
+    pub fn identifier(&self) -> u16 {
+        (self.rest() >> 16) as u16
+    }
+
+    /// sequence number of echo request and reply
+    #[inline]
+    pub fn sequence(&self) -> u16 {
+        self.rest() as u16
+    }
+
+    /// next-hop MTU of fragmentation needed (RFC 1191)
+    #[inline]
+    pub fn next_hop_mtu(&self) -> u16 {
+        self.rest() as u16
+    }
+
+    #[inline]
+    pub fn set_next_hop_mtu(&mut self, mtu: u16) {
+        self.set_rest(mtu as u32);
+    }
+
+    /// Calculates the checksum over the header and the following `payload_len` bytes, which must be contiguous
+    /// with the header.
+    #[inline]
+    pub fn update_checksum(&mut self, payload_len: usize) {
+        unsafe {
+            let bytes = slice::from_raw_parts((self as *const IcmpHeader) as *const u8, HDR_SIZE + payload_len);
+            self.set_checksum(checksum(bytes, 1));
+        }
+    }
+}
@@ -141,12 +141,19 @@ impl IpHeader {
         self.ttl_to_csum = blanked | ((protocol as u32) << 8);
     }
 
+    /// The header checksum as it is stored, i.e. in network byte order, see `csum_host`.
     #[inline]
     pub fn csum(&self) -> u16 {
         let ttlpcsum = self.ttl_to_csum;
         ((ttlpcsum & 0xffff0000) >> 16) as u16
     }
 
+    /// The header checksum in host byte order, like the value taken by `set_csum`.
+    #[inline]
+    pub fn csum_host(&self) -> u16 {
+        u16::from_be(self.csum())
+    }
+
     #[inline]
     pub fn set_csum(&mut self, csum: u16) {
         let ttlpcsum = self.ttl_to_csum;
@@ -181,9 +188,7 @@ impl IpHeader {
 
     #[inline]
     pub fn fragment_offset(&self) -> u16 {
-        let id_flag_fragment = self.id_to_foffset;
-        let flag_fragment = (id_flag_fragment & 0xffff) as u16;
-        u16::from_be(flag_fragment & !0xe >> 3)
+        (u32::from_be(self.id_to_foffset) & 0x1fff) as u16
     }
 
     #[inline]
@@ -1,20 +1,25 @@
 use std::fmt;
+use utils::update_checksum_incremental;
 
 pub use self::arp::*;
+pub use self::icmp::*;
 pub use self::ip::*;
 pub use self::mac::*;
+pub use self::mpls::*;
 pub use self::null_header::*;
 pub use self::tcp::*;
 pub use self::udp::*;
 
 mod arp;
+mod icmp;
 mod ip;
 mod mac;
+mod mpls;
 mod null_header;
 mod tcp;
 mod udp;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum HeaderKind {
     Null,
     Mac,
@@ -22,6 +27,8 @@ pub enum HeaderKind {
     Ip,
     Tcp,
     Udp,
+    Icmp,
+    Mpls,
 }
 
 /// A trait implemented by all headers, used for reading them from a mbuf.
@@ -48,6 +55,8 @@ pub enum Header<'a> {
     Ip(&'a mut IpHeader),
     Tcp(&'a mut TcpHeader),
     Udp(&'a mut UdpHeader),
+    Icmp(&'a mut IcmpHeader),
+    Mpls(&'a mut MplsHeader),
 }
 
 ///as Header contains mutable references, we can only clone Header::Null
@@ -68,10 +77,65 @@ impl<'a> Header<'a> {
                 HeaderKind::Tcp => Header::Tcp(&mut *(ptr as *mut TcpHeader)),
                 HeaderKind::Udp => Header::Udp(&mut *(ptr as *mut UdpHeader)),
                 HeaderKind::ArpIpv4 => Header::ArpIpv4(&mut *(ptr as *mut ArpIpv4Header)),
+                HeaderKind::Icmp => Header::Icmp(&mut *(ptr as *mut IcmpHeader)),
+                HeaderKind::Mpls => Header::Mpls(&mut *(ptr as *mut MplsHeader)),
             }
         }
     }
 
+    /// A header of `kind` at `ptr`.
+    ///
+    /// # Safety
+    /// `ptr` must point to a header of `kind` which is valid for reads and writes for the lifetime `'a`.
+    pub unsafe fn from_kind(kind: HeaderKind, ptr: *mut u8) -> Header<'a> {
+        match kind {
+            HeaderKind::Null => Header::Null,
+            HeaderKind::Mac => Header::Mac(&mut *(ptr as *mut MacHeader)),
+            HeaderKind::Ip => Header::Ip(&mut *(ptr as *mut IpHeader)),
+            HeaderKind::Tcp => Header::Tcp(&mut *(ptr as *mut TcpHeader)),
+            HeaderKind::Udp => Header::Udp(&mut *(ptr as *mut UdpHeader)),
+            HeaderKind::ArpIpv4 => Header::ArpIpv4(&mut *(ptr as *mut ArpIpv4Header)),
+            HeaderKind::Icmp => Header::Icmp(&mut *(ptr as *mut IcmpHeader)),
+            HeaderKind::Mpls => Header::Mpls(&mut *(ptr as *mut MplsHeader)),
+        }
+    }
+
+    /// Sets the next-protocol field of this header, i.e. the Ethertype of a MAC header or the protocol of an IPv4
+    /// header, so that it announces a header of kind `next`. The IPv4 header checksum is updated. Returns false
+    /// if this header has no such field or `next` cannot be announced by it.
+    pub fn set_next_header(&mut self, next: HeaderKind) -> bool {
+        match *self {
+            Header::Mac(ref mut mac) => match next {
+                HeaderKind::Ip => mac.set_etype(0x0800),
+                HeaderKind::ArpIpv4 => mac.set_etype(0x0806),
+                HeaderKind::Mpls => {
+                    if mac.etype() != ETYPE_MPLS_MULTICAST {
+                        mac.set_etype(ETYPE_MPLS_UNICAST)
+                    }
+                }
+                _ => return false,
+            },
+            Header::Ip(ref mut ip) => {
+                let protocol = match next {
+                    HeaderKind::Icmp => 1,
+                    HeaderKind::Ip => 4,
+                    HeaderKind::Tcp => 6,
+                    HeaderKind::Udp => 17,
+                    HeaderKind::Mpls => 137,
+                    _ => return false,
+                };
+                let ttl = (ip.ttl() as u16) << 8;
+                let csum = update_checksum_incremental(ip.csum_host(), ttl | ip.protocol() as u16, ttl | protocol as u16);
+                ip.set_protocol(protocol);
+                ip.set_csum(csum);
+            }
+            // the S bit marks the last label, whatever follows it
+            Header::Mpls(ref mut mpls) => mpls.set_bottom_of_stack(next != HeaderKind::Mpls),
+            _ => return false,
+        }
+        true
+    }
+
     #[inline]
     pub fn as_mac_mut(&mut self) -> Option<&mut MacHeader> {
         match self {
@@ -112,6 +176,22 @@ impl<'a> Header<'a> {
         }
     }
 
+    #[inline]
+    pub fn as_icmp_mut(&mut self) -> Option<&mut IcmpHeader> {
+        match self {
+            Header::Icmp(p) => Some(&mut **p),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_mpls_mut(&mut self) -> Option<&mut MplsHeader> {
+        match self {
+            Header::Mpls(p) => Some(&mut **p),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn as_mac(&self) -> Option<&MacHeader> {
         match self {
@@ -152,6 +232,22 @@ impl<'a> Header<'a> {
         }
     }
 
+    #[inline]
+    pub fn as_icmp(&self) -> Option<&IcmpHeader> {
+        match self {
+            Header::Icmp(p) => Some(&**p),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn as_mpls(&self) -> Option<&MplsHeader> {
+        match self {
+            Header::Mpls(p) => Some(&**p),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn kind(&self) -> HeaderKind {
         match self {
@@ -161,6 +257,8 @@ impl<'a> Header<'a> {
             Header::Tcp(_) => HeaderKind::Tcp,
             Header::Udp(_) => HeaderKind::Udp,
             Header::ArpIpv4(_) => HeaderKind::ArpIpv4,
+            Header::Icmp(_) => HeaderKind::Icmp,
+            Header::Mpls(_) => HeaderKind::Mpls,
         }
     }
 
@@ -173,6 +271,8 @@ impl<'a> Header<'a> {
             Header::Tcp(_) => Some(self.as_tcp().unwrap().offset()),
             Header::Udp(_) => Some(self.as_udp().unwrap().offset()),
             Header::ArpIpv4(_) => Some(self.as_arpipv4().unwrap().offset()),
+            Header::Icmp(_) => Some(self.as_icmp().unwrap().offset()),
+            Header::Mpls(_) => Some(self.as_mpls().unwrap().offset()),
         }
     }
 
@@ -185,6 +285,8 @@ impl<'a> Header<'a> {
             Header::Tcp(p) => Some(*p as *mut TcpHeader as *mut u8),
             Header::Udp(p) => Some(*p as *mut UdpHeader as *mut u8),
             Header::ArpIpv4(p) => Some(*p as *mut ArpIpv4Header as *mut u8),
+            Header::Icmp(p) => Some(*p as *mut IcmpHeader as *mut u8),
+            Header::Mpls(p) => Some(*p as *mut MplsHeader as *mut u8),
         }
     }
 
@@ -197,6 +299,8 @@ impl<'a> Header<'a> {
             Header::Tcp(p) => Some(*p as *const TcpHeader as *const u8),
             Header::Udp(p) => Some(*p as *const UdpHeader as *const u8),
             Header::ArpIpv4(p) => Some(*p as *const ArpIpv4Header as *const u8),
+            Header::Icmp(p) => Some(*p as *const IcmpHeader as *const u8),
+            Header::Mpls(p) => Some(*p as *const MplsHeader as *const u8),
         }
     }
 }
@@ -210,6 +314,8 @@ impl<'a> fmt::Display for Header<'a> {
             Header::Tcp(_) => write!(f, "{ }", self.as_tcp().unwrap()),
             Header::Udp(_) => write!(f, "{:?}", self.as_udp().unwrap()),
             Header::ArpIpv4(_) => write!(f, "{:?}", self.as_arpipv4().unwrap()),
+            Header::Icmp(_) => write!(f, "{}", self.as_icmp().unwrap()),
+            Header::Mpls(_) => write!(f, "{}", self.as_mpls().unwrap()),
         }
     }
 }
@@ -0,0 +1,127 @@
+use super::{EndOffset, HeaderKind};
+use std::default::Default;
+use std::fmt;
+
+pub const ETYPE_MPLS_UNICAST: u16 = 0x8847;
+pub const ETYPE_MPLS_MULTICAST: u16 = 0x8848;
+
+/// reserved labels (RFC 3032)
+pub const MPLS_LABEL_IPV4_EXPLICIT_NULL: u32 = 0;
+pub const MPLS_LABEL_ROUTER_ALERT: u32 = 1;
+pub const MPLS_LABEL_IPV6_EXPLICIT_NULL: u32 = 2;
+pub const MPLS_LABEL_IMPLICIT_NULL: u32 = 3;
+
+/// largest label value
+pub const MPLS_LABEL_MAX: u32 = 0xfffff;
+
+/// One entry of an MPLS label stack (RFC 3032): 20 bits label, 3 bits traffic class, bottom of stack flag and TTL.
+/// Each entry of a label stack is a header of its own in the header stack of a `Pdu`.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, packed)]
+pub struct MplsHeader {
+    entry: u32,
+}
+
+const HDR_SIZE: usize = 4;
+
+impl fmt::Display for MplsHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "label: {} tc: {} s: {} ttl: {}",
+            self.label(),
+            self.tc(),
+            self.bottom_of_stack(),
+            self.ttl()
+        )
+    }
+}
+
+impl EndOffset for MplsHeader {
+    #[inline]
+    fn offset(&self) -> usize {
+        HDR_SIZE
+    }
+
+    #[inline]
+    fn size() -> usize {
+        HDR_SIZE
+    }
+
+    #[inline]
+    fn payload_size(&self, hint: usize) -> usize {
+        hint - self.offset()
+    }
+
+    #[inline]
+    fn header_kind(&self) -> HeaderKind {
+        HeaderKind::Mpls
+    }
+}
+
+impl MplsHeader {
+    #[inline]
+    pub fn new() -> MplsHeader {
+        Default::default()
+    }
+
+    #[inline]
+    fn entry(&self) -> u32 {
+        u32::from_be(self.entry)
+    }
+
+    #[inline]
+    fn set_entry(&mut self, entry: u32) {
+        self.entry = u32::to_be(entry);
+    }
+
+    #[inline]
+    pub fn label(&self) -> u32 {
+        self.entry() >> 12
+    }
+
+    #[inline]
+    pub fn set_label(&mut self, label: u32) {
+        let entry = (self.entry() & 0xfff) | ((label & MPLS_LABEL_MAX) << 12);
+        self.set_entry(entry);
+    }
+
+    /// traffic class, formerly EXP
+    #[inline]
+    pub fn tc(&self) -> u8 {
+        ((self.entry() >> 9) & 0x7) as u8
+    }
+
+    #[inline]
+    pub fn set_tc(&mut self, tc: u8) {
+        let entry = (self.entry() & !0xe00) | (((tc & 0x7) as u32) << 9);
+        self.set_entry(entry);
+    }
+
+    /// the S bit, set in the last entry of the label stack
+    #[inline]
+    pub fn bottom_of_stack(&self) -> bool {
+        self.entry() & 0x100 != 0
+    }
+
+    #[inline]
+    pub fn set_bottom_of_stack(&mut self, bos: bool) {
+        let entry = if bos {
+            self.entry() | 0x100
+        } else {
+            self.entry() & !0x100
+        };
+        self.set_entry(entry);
+    }
+
+    #[inline]
+    pub fn ttl(&self) -> u8 {
+        self.entry() as u8
+    }
+
+    #[inline]
+    pub fn set_ttl(&mut self, ttl: u8) {
+        let entry = (self.entry() & !0xff) | ttl as u32;
+        self.set_entry(entry);
+    }
+}
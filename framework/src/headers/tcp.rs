@@ -1,4 +1,5 @@
 use super::{EndOffset, HeaderKind};
+use std::cmp;
 use std::default::Default;
 use std::fmt;
 use utils::update_checksum_incremental;
@@ -389,3 +390,59 @@ impl TcpHeader {
         self.urgent = u16::to_be(urgent);
     }
 }
+
+pub const TCP_OPT_END: u8 = 0;
+pub const TCP_OPT_NOP: u8 = 1;
+pub const TCP_OPT_MSS: u8 = 2;
+pub const TCP_OPT_WINDOW_SCALE: u8 = 3;
+
+/// Largest shift count of the window scale option (RFC 7323).
+pub const TCP_MAX_WINDOW_SCALE: u8 = 14;
+
+/// The options of a TCP header which are used by the `TcpStack`, others are skipped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+}
+
+impl TcpOptions {
+    /// Parse the options behind a TCP header. Parsing stops at a malformed option.
+    pub fn parse(bytes: &[u8]) -> TcpOptions {
+        let mut options = TcpOptions::default();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                TCP_OPT_END => break,
+                TCP_OPT_NOP => i += 1,
+                kind => {
+                    let len = match bytes.get(i + 1) {
+                        Some(&len) if len >= 2 && i + len as usize <= bytes.len() => len as usize,
+                        _ => break,
+                    };
+                    match (kind, len) {
+                        (TCP_OPT_MSS, 4) => options.mss = Some((bytes[i + 2] as u16) << 8 | bytes[i + 3] as u16),
+                        (TCP_OPT_WINDOW_SCALE, 3) => {
+                            options.window_scale = Some(cmp::min(bytes[i + 2], TCP_MAX_WINDOW_SCALE))
+                        }
+                        _ => (),
+                    }
+                    i += len;
+                }
+            }
+        }
+        options
+    }
+
+    /// The options as they are written behind a TCP header, padded to a multiple of 4 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8);
+        if let Some(mss) = self.mss {
+            bytes.extend_from_slice(&[TCP_OPT_MSS, 4, (mss >> 8) as u8, mss as u8]);
+        }
+        if let Some(shift) = self.window_scale {
+            bytes.extend_from_slice(&[TCP_OPT_NOP, TCP_OPT_WINDOW_SCALE, 3, shift]);
+        }
+        bytes
+    }
+}
@@ -0,0 +1,199 @@
+//! Generation of ICMPv4 messages: errors about forwarded packets (RFC 792, RFC 1812) and echo replies. See
+//! `Batch::icmp_error`, `Batch::decrement_ttl`, `Batch::check_mtu` and `Batch::answer_echo`.
+use common::errors;
+use common::errors::ErrorKind;
+use headers::*;
+use interface::{PacketBuilder, Pdu, PmdPort};
+use std::cmp;
+use std::net::Ipv4Addr;
+use utils::update_checksum_incremental;
+
+/// Number of bytes of the original datagram behind its IP header which are quoted in an ICMP error.
+pub const ICMP_QUOTE_LEN: usize = 8;
+
+/// TTL of generated ICMP messages.
+pub const ICMP_TTL: u8 = 64;
+
+/// don't fragment flag, see `IpHeader::flags`
+const IP_FLAG_DF: u8 = 0x2;
+
+/// Decides whether an ICMP error is sent for a packet. The function may modify the packet, e.g. decrement its TTL.
+pub type IcmpCheckFn = Box<dyn FnMut(&mut Pdu) -> Option<IcmpError>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IcmpError {
+    /// time to live exceeded in transit
+    TtlExceeded,
+    /// destination unreachable with the given code, e.g. `ICMP_HOST_UNREACH`
+    Unreachable(u8),
+    /// fragmentation needed and don't fragment set, with the MTU of the next hop
+    FragmentationNeeded(u16),
+}
+
+impl IcmpError {
+    #[inline]
+    fn header(&self) -> IcmpHeader {
+        let mut icmp = IcmpHeader::new();
+        match *self {
+            IcmpError::TtlExceeded => {
+                icmp.set_icmp_type(ICMP_TIME_EXCEEDED);
+                icmp.set_code(ICMP_EXC_TTL);
+            }
+            IcmpError::Unreachable(code) => {
+                icmp.set_icmp_type(ICMP_DEST_UNREACH);
+                icmp.set_code(code);
+            }
+            IcmpError::FragmentationNeeded(mtu) => {
+                icmp.set_icmp_type(ICMP_DEST_UNREACH);
+                icmp.set_code(ICMP_FRAG_NEEDED);
+                icmp.set_next_hop_mtu(mtu);
+            }
+        }
+        icmp
+    }
+}
+
+/// The source address for ICMP messages of a port, which is the address of the `NetSpec` of the port.
+pub fn port_address(port: &PmdPort) -> errors::Result<Ipv4Addr> {
+    port.net_spec()
+        .and_then(|spec| spec.ip_net)
+        .map(|ip_net| ip_net.addr())
+        .ok_or_else(|| ErrorKind::ConfigurationError(format!("no ip_net for ICMP on port {}", port.name())))
+}
+
+/// Index of the outermost IPv4 header in the header stack of `pdu`.
+#[inline]
+pub fn ip_index(pdu: &Pdu) -> Option<usize> {
+    let headers = pdu.headers();
+    (0..headers.count()).find(|i| headers.get(*i).as_ip().is_some())
+}
+
+#[inline]
+fn is_unicast(addr: Ipv4Addr) -> bool {
+    !(addr.is_unspecified() || addr.is_broadcast() || addr.is_multicast())
+}
+
+/// Whether an ICMP error may be sent about the IPv4 header at `ip_index` of `pdu` (RFC 1812 4.3.2.7): not
+/// about ICMP errors, non-initial fragments, and datagrams from or to non-unicast addresses.
+pub fn may_send_error(pdu: &Pdu, ip_index: usize) -> bool {
+    let headers = pdu.headers();
+    let ip = match headers.get(ip_index).as_ip() {
+        Some(ip) => ip,
+        None => return false,
+    };
+    if ip.fragment_offset() != 0 || !is_unicast(Ipv4Addr::from(ip.src())) || !is_unicast(Ipv4Addr::from(ip.dst())) {
+        return false;
+    }
+    if ip.protocol() == 1 && ip_index + 1 < headers.count() {
+        if let Some(icmp) = headers.get(ip_index + 1).as_icmp() {
+            return match icmp.icmp_type() {
+                ICMP_ECHO_REPLY | ICMP_ECHO_REQUEST => true,
+                // other informational messages are obsolete, no errors about errors
+                _ => false,
+            };
+        }
+    }
+    true
+}
+
+/// Builds the ICMP `error` about `pdu`, from `src` to the source of `pdu`. The message quotes the IP header of
+/// `pdu` and the first `ICMP_QUOTE_LEN` bytes behind it. It is addressed back to the MAC address from which
+/// `pdu` was received. Returns None if no error may be sent about `pdu` or if no mbuf is available.
+pub fn icmp_error(pdu: &Pdu, error: IcmpError, src: Ipv4Addr) -> Option<Pdu<'static>> {
+    let ip_ix = ip_index(pdu)?;
+    if ip_ix == 0 || !may_send_error(pdu, ip_ix) {
+        return None;
+    }
+    let headers = pdu.headers();
+    let rx_mac = headers.get(0).as_mac()?;
+    let rx_ip = headers.ip(ip_ix);
+    let available = pdu.payload_size(ip_ix - 1);
+    let quote_len = cmp::min(
+        rx_ip.offset() + ICMP_QUOTE_LEN,
+        cmp::min(rx_ip.length() as usize, available),
+    );
+    let quote = unsafe { ::std::slice::from_raw_parts(headers.get(ip_ix).as_ptr_u8()?, quote_len) };
+
+    let mut mac = MacHeader::new();
+    mac.set_dmac(&rx_mac.src);
+    mac.set_smac(&rx_mac.dst);
+    let mut ip = IpHeader::new();
+    ip.set_ttl(ICMP_TTL);
+    ip.set_src(u32::from(src));
+    ip.set_dst(rx_ip.src());
+    PacketBuilder::new()
+        .mac(mac)
+        .ipv4(ip)
+        .icmp(error.header())
+        .payload(quote)
+        .build()
+        .ok()
+}
+
+/// Turns an ICMP echo request for `addr` into the echo reply in place. Returns false and leaves the packet
+/// unchanged if it is not such a request.
+pub fn echo_reply(pdu: &mut Pdu, addr: Ipv4Addr) -> bool {
+    let ip_ix = match ip_index(pdu) {
+        Some(i) if i > 0 && i + 1 < pdu.headers().count() => i,
+        _ => return false,
+    };
+    {
+        let headers = pdu.headers();
+        let is_request = headers
+            .get(ip_ix + 1)
+            .as_icmp()
+            .is_some_and(|icmp| icmp.icmp_type() == ICMP_ECHO_REQUEST);
+        if !is_request || headers.ip(ip_ix).dst() != u32::from(addr) || headers.get(0).as_mac().is_none() {
+            return false;
+        }
+    }
+    let headers = pdu.headers_mut();
+    headers.mac_mut(0).swap_addresses();
+    {
+        let ip = headers.ip_mut(ip_ix);
+        let src = ip.src();
+        ip.set_src(ip.dst());
+        ip.set_dst(src);
+        ip.set_ttl(ICMP_TTL);
+        ip.update_checksum();
+    }
+    let icmp = headers.icmp_mut(ip_ix + 1);
+    let code = icmp.code() as u16;
+    let csum = update_checksum_incremental(
+        icmp.checksum(),
+        (ICMP_ECHO_REQUEST as u16) << 8 | code,
+        (ICMP_ECHO_REPLY as u16) << 8 | code,
+    );
+    icmp.set_icmp_type(ICMP_ECHO_REPLY);
+    icmp.set_checksum(csum);
+    true
+}
+
+/// Decrements the TTL of IPv4 packets. Packets whose TTL would drop to zero are reported as `TtlExceeded`.
+pub fn ttl_check() -> IcmpCheckFn {
+    Box::new(|pdu| {
+        let ip_ix = ip_index(pdu)?;
+        let ip = pdu.headers_mut().ip_mut(ip_ix);
+        let ttl = ip.ttl();
+        if ttl <= 1 {
+            Some(IcmpError::TtlExceeded)
+        } else {
+            ip.set_ttl(ttl - 1);
+            ip.update_checksum();
+            None
+        }
+    })
+}
+
+/// Reports IPv4 packets longer than `mtu` with the don't fragment flag set as `FragmentationNeeded`.
+pub fn mtu_check(mtu: u16) -> IcmpCheckFn {
+    Box::new(move |pdu| {
+        let ip_ix = ip_index(pdu)?;
+        let ip = pdu.headers().ip(ip_ix);
+        if ip.length() > mtu && ip.flags() & IP_FLAG_DF != 0 {
+            Some(IcmpError::FragmentationNeeded(mtu))
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,326 @@
+use common::errors;
+use common::errors::ErrorKind;
+use headers::*;
+use interface::Pdu;
+use native::zcsi::mbuf_free;
+use std::mem;
+use std::ptr;
+use std::slice;
+use utils::{checksum, ipv4_checksum, ipv4_pseudo_header_sum};
+
+/// A header of a packet built by the `PacketBuilder`.
+#[derive(Clone, Copy, Debug)]
+pub enum Layer {
+    Mac(MacHeader),
+    ArpIpv4(ArpIpv4Header),
+    Ip(IpHeader),
+    Tcp(TcpHeader),
+    Udp(UdpHeader),
+    Icmp(IcmpHeader),
+    Mpls(MplsHeader),
+}
+
+impl Layer {
+    #[inline]
+    pub fn kind(&self) -> HeaderKind {
+        match *self {
+            Layer::Mac(_) => HeaderKind::Mac,
+            Layer::ArpIpv4(_) => HeaderKind::ArpIpv4,
+            Layer::Ip(_) => HeaderKind::Ip,
+            Layer::Tcp(_) => HeaderKind::Tcp,
+            Layer::Udp(_) => HeaderKind::Udp,
+            Layer::Icmp(_) => HeaderKind::Icmp,
+            Layer::Mpls(_) => HeaderKind::Mpls,
+        }
+    }
+
+    /// The header as it is written into the packet.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            match *self {
+                Layer::Mac(ref h) => bytes_of(h),
+                Layer::ArpIpv4(ref h) => bytes_of(h),
+                Layer::Ip(ref h) => bytes_of(h),
+                Layer::Tcp(ref h) => bytes_of(h),
+                Layer::Udp(ref h) => bytes_of(h),
+                Layer::Icmp(ref h) => bytes_of(h),
+                Layer::Mpls(ref h) => bytes_of(h),
+            }
+        }
+    }
+
+    #[inline]
+    pub fn header_len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    #[inline]
+    fn push_to(&self, pdu: &mut Pdu) -> bool {
+        match *self {
+            Layer::Mac(ref h) => pdu.push_header(h),
+            Layer::ArpIpv4(ref h) => pdu.push_header(h),
+            Layer::Ip(ref h) => pdu.push_header(h),
+            Layer::Tcp(ref h) => pdu.push_header(h),
+            Layer::Udp(ref h) => pdu.push_header(h),
+            Layer::Icmp(ref h) => pdu.push_header(h),
+            Layer::Mpls(ref h) => pdu.push_header(h),
+        }
+    }
+}
+
+#[inline]
+unsafe fn bytes_of<T>(header: &T) -> &[u8] {
+    slice::from_raw_parts(header as *const T as *const u8, mem::size_of::<T>())
+}
+
+/// Constructs packets from typed headers and a payload. The headers are written once, in front of the payload,
+/// and the builder fills in what follows from the stacking of the headers: Ethertypes, IP version, header length,
+/// protocol and total length, UDP length, TCP data offset and all checksums. Only the last header may carry
+/// options, if it is a TCP header. The builder can be kept as a template and build any number of packets.
+///
+/// With `checksum_offload` the IPv4 header checksum and the TCP or UDP checksum directly behind the outermost
+/// IPv4 header are left to the NIC, all other checksums are still calculated.
+#[derive(Clone, Debug, Default)]
+pub struct PacketBuilder<'a> {
+    layers: Vec<Layer>,
+    tcp_options: &'a [u8],
+    payload: &'a [u8],
+    offload: bool,
+}
+
+impl<'a> PacketBuilder<'a> {
+    pub fn new() -> PacketBuilder<'a> {
+        Default::default()
+    }
+
+    pub fn push(mut self, layer: Layer) -> PacketBuilder<'a> {
+        self.layers.push(layer);
+        self
+    }
+
+    pub fn mac(self, mac: MacHeader) -> PacketBuilder<'a> {
+        self.push(Layer::Mac(mac))
+    }
+
+    pub fn arp(self, arp: ArpIpv4Header) -> PacketBuilder<'a> {
+        self.push(Layer::ArpIpv4(arp))
+    }
+
+    pub fn ipv4(self, ip: IpHeader) -> PacketBuilder<'a> {
+        self.push(Layer::Ip(ip))
+    }
+
+    pub fn tcp(self, tcp: TcpHeader) -> PacketBuilder<'a> {
+        self.push(Layer::Tcp(tcp))
+    }
+
+    pub fn udp(self, udp: UdpHeader) -> PacketBuilder<'a> {
+        self.push(Layer::Udp(udp))
+    }
+
+    pub fn icmp(self, icmp: IcmpHeader) -> PacketBuilder<'a> {
+        self.push(Layer::Icmp(icmp))
+    }
+
+    /// Add a label stack entry, the bottom of stack flag is set by the builder.
+    pub fn mpls(self, mpls: MplsHeader) -> PacketBuilder<'a> {
+        self.push(Layer::Mpls(mpls))
+    }
+
+    /// Options of the TCP header, which must be the last header. Their length must be a multiple of 4 bytes, see
+    /// `TcpOptions::to_bytes`.
+    pub fn tcp_options(mut self, options: &'a [u8]) -> PacketBuilder<'a> {
+        self.tcp_options = options;
+        self
+    }
+
+    pub fn payload(mut self, payload: &'a [u8]) -> PacketBuilder<'a> {
+        self.payload = payload;
+        self
+    }
+
+    pub fn checksum_offload(mut self, offload: bool) -> PacketBuilder<'a> {
+        self.offload = offload;
+        self
+    }
+
+    /// Length of the packet.
+    pub fn len(&self) -> usize {
+        self.layers.iter().map(|l| l.header_len()).sum::<usize>() + self.tcp_options.len() + self.payload.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Index of the outermost IPv4 header, if checksums are offloaded.
+    fn offloaded_ip(&self) -> Option<usize> {
+        if self.offload {
+            self.layers.iter().position(|l| l.kind() == HeaderKind::Ip)
+        } else {
+            None
+        }
+    }
+
+    /// The headers as they are written into the packet, with all derived fields filled in.
+    pub fn headers(&self) -> Vec<Layer> {
+        let mut layers = self.layers.clone();
+        let offloaded_ip = self.offloaded_ip();
+        let mut remaining = self.len();
+        for i in 0..layers.len() {
+            let next = layers.get(i + 1).map(|l| l.kind());
+            match layers[i] {
+                Layer::Mac(ref mut mac) => match next {
+                    Some(HeaderKind::Ip) => mac.set_etype(0x0800),
+                    Some(HeaderKind::ArpIpv4) => mac.set_etype(0x0806),
+                    Some(HeaderKind::Mpls) => mac.set_etype(ETYPE_MPLS_UNICAST),
+                    _ => (),
+                },
+                Layer::ArpIpv4(ref mut arp) => {
+                    arp.hw_addr_len = 6;
+                    arp.proto_addr_len = 4;
+                }
+                Layer::Ip(ref mut ip) => {
+                    ip.set_version(4);
+                    ip.set_ihl(5);
+                    if ip.ttl() == 0 {
+                        ip.set_ttl(64);
+                    }
+                    match next {
+                        Some(HeaderKind::Icmp) => ip.set_protocol(1),
+                        Some(HeaderKind::Tcp) => ip.set_protocol(6),
+                        Some(HeaderKind::Udp) => ip.set_protocol(17),
+                        Some(HeaderKind::Mpls) => ip.set_protocol(137),
+                        _ => (),
+                    }
+                    ip.set_length(remaining as u16);
+                    ip.set_csum(0);
+                    if offloaded_ip != Some(i) {
+                        ip.update_checksum();
+                    }
+                }
+                Layer::Tcp(ref mut tcp) => {
+                    let options = if next.is_none() { self.tcp_options.len() / 4 } else { 0 };
+                    tcp.set_data_offset(5 + options as u8);
+                }
+                Layer::Udp(ref mut udp) => udp.set_length(remaining as u16),
+                Layer::Icmp(_) => (),
+                Layer::Mpls(ref mut mpls) => mpls.set_bottom_of_stack(next != Some(HeaderKind::Mpls)),
+            }
+            remaining -= layers[i].header_len();
+        }
+
+        // inner checksums first, outer checksums cover them
+        for i in (0..layers.len()).rev() {
+            let kind = layers[i].kind();
+            if kind != HeaderKind::Tcp && kind != HeaderKind::Udp && kind != HeaderKind::Icmp {
+                continue;
+            }
+            let mut covered: Vec<u8> = layers[i..].iter().flat_map(|l| l.as_bytes().iter().cloned()).collect();
+            covered.extend_from_slice(self.tcp_options);
+            let ip_addrs = layers[..i].iter().rev().find_map(|l| match *l {
+                Layer::Ip(ref ip) => Some((ip.src(), ip.dst())),
+                _ => None,
+            });
+            let offloaded = offloaded_ip.is_some_and(|ip| ip + 1 == i);
+            let l4_len = covered.len() + self.payload.len();
+            match layers[i] {
+                Layer::Tcp(ref mut tcp) => {
+                    tcp.set_checksum(0);
+                    if let Some((src, dst)) = ip_addrs {
+                        let csum = if offloaded {
+                            ipv4_pseudo_header_sum(src, dst, 6, l4_len)
+                        } else {
+                            ipv4_checksum(covered.as_mut_ptr(), covered.len(), 8, self.payload, src, dst, 6)
+                        };
+                        tcp.set_checksum(csum);
+                    }
+                }
+                Layer::Udp(ref mut udp) => {
+                    udp.set_checksum(0);
+                    if let Some((src, dst)) = ip_addrs {
+                        let csum = if offloaded {
+                            ipv4_pseudo_header_sum(src, dst, 17, l4_len)
+                        } else {
+                            match ipv4_checksum(covered.as_mut_ptr(), covered.len(), 3, self.payload, src, dst, 17) {
+                                0 => 0xffff,
+                                csum => csum,
+                            }
+                        };
+                        udp.set_checksum(csum);
+                    }
+                }
+                Layer::Icmp(ref mut icmp) => {
+                    covered.extend_from_slice(self.payload);
+                    icmp.set_checksum(checksum(&covered, 1));
+                }
+                _ => (),
+            }
+        }
+        layers
+    }
+
+    /// The complete packet as bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        for layer in self.headers() {
+            bytes.extend_from_slice(layer.as_bytes());
+        }
+        bytes.extend_from_slice(self.tcp_options);
+        bytes.extend_from_slice(self.payload);
+        bytes
+    }
+
+    /// Writes the packet into `pdu`, which must be empty. The payload is chained into further segments if it does
+    /// not fit into the first one.
+    pub fn write(&self, pdu: &mut Pdu) -> errors::Result<()> {
+        let options = self.tcp_options.len();
+        if options > 0 {
+            let last_is_tcp = self.layers.last().is_some_and(|l| l.kind() == HeaderKind::Tcp);
+            if !last_is_tcp || !options.is_multiple_of(4) || options > 40 {
+                return Err(ErrorKind::BadSize(options, "TCP options".to_string()));
+            }
+        }
+        let layers = self.headers();
+        for layer in &layers {
+            if !layer.push_to(pdu) {
+                return Err(ErrorKind::FailedAllocation);
+            }
+        }
+        if options > 0 {
+            // the data offset of the TCP header already reserved the space of the options
+            let last = pdu.headers().count() - 1;
+            unsafe {
+                let tcp = pdu.headers_mut().tcp_mut(last) as *mut TcpHeader as *mut u8;
+                ptr::copy_nonoverlapping(self.tcp_options.as_ptr(), tcp.add(mem::size_of::<TcpHeader>()), options);
+            }
+        }
+        if !self.payload.is_empty() {
+            pdu.append(self.payload)?;
+        }
+        if let Some(ip) = self.offloaded_ip() {
+            let l2_len: usize = layers[..ip].iter().map(|l| l.header_len()).sum();
+            pdu.set_l2_len(l2_len as u64);
+            pdu.set_l3_len(layers[ip].header_len() as u64);
+            match layers.get(ip + 1).map(|l| l.kind()) {
+                Some(HeaderKind::Tcp) => pdu.set_tcp_ipv4_checksum_tx_offload(),
+                Some(HeaderKind::Udp) => pdu.set_udp_ipv4_checksum_tx_offload(),
+                _ => pdu.set_ipv4_checksum_tx_offload(),
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocates a new packet and writes it.
+    pub fn build(&self) -> errors::Result<Pdu<'static>> {
+        let mut pdu = Pdu::new_pdu().ok_or(ErrorKind::FailedAllocation)?;
+        match self.write(&mut pdu) {
+            Ok(()) => Ok(pdu),
+            Err(e) => {
+                unsafe { mbuf_free(pdu.get_mbuf()) };
+                Err(e)
+            }
+        }
+    }
+}
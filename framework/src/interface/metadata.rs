@@ -0,0 +1,130 @@
+use common::errors;
+use common::errors::ErrorKind;
+use interface::dpdk::METADATA_SLOTS;
+use native::zcsi::MBuf;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::sync::Mutex;
+
+/// Size of a metadata slot in bytes, see mempool.c
+pub const METADATA_SLOT_SIZE: usize = 8;
+
+lazy_static! {
+    // name, first slot and number of slots of each allocated metadata field
+    static ref METADATA_LAYOUT: Mutex<Vec<(String, usize, usize)>> = Mutex::new(Vec::with_capacity(METADATA_SLOTS as usize));
+}
+
+/// Types which can be stored in metadata fields. Fields are read without knowing whether they were written for the
+/// packet, e.g. the slots of a recycled mbuf hold what its previous packet stored there. Hence any bit pattern must be
+/// a valid value of the type.
+///
+/// # Safety
+/// Implement this only for types without invalid bit patterns, i.e. not for `bool`, `char`, enums, references or
+/// types containing them.
+pub unsafe trait MetadataValue: Copy {}
+
+macro_rules! metadata_value {
+    ($($t:ty),*) => {
+        $(unsafe impl MetadataValue for $t {})*
+    };
+}
+
+metadata_value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+unsafe impl<T: MetadataValue, const N: usize> MetadataValue for [T; N] {}
+
+/// A typed key for a per-packet metadata field, stored in the metadata slots following the mbuf.
+/// Keys are allocated once, typically when an operator is created, and can then be used with `Pdu::metadata`
+/// and `Pdu::set_metadata` on any packet. Since packets keep their mbuf on `MpscQueue` hops and in `group_by`,
+/// metadata set before such a hop is available after it. A field which was not set for the packet holds an
+/// arbitrary value.
+pub struct MetadataKey<T: MetadataValue> {
+    slot: usize,
+    phantom: PhantomData<T>,
+}
+
+impl<T: MetadataValue> Clone for MetadataKey<T> {
+    fn clone(&self) -> MetadataKey<T> {
+        *self
+    }
+}
+
+impl<T: MetadataValue> Copy for MetadataKey<T> {}
+
+impl<T: MetadataValue> fmt::Debug for MetadataKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MetadataKey(slot= {}, slots= {})",
+            self.slot,
+            MetadataKey::<T>::slots()
+        )
+    }
+}
+
+impl<T: MetadataValue> MetadataKey<T> {
+    /// Number of slots needed by T.
+    #[inline]
+    pub fn slots() -> usize {
+        mem::size_of::<T>().div_ceil(METADATA_SLOT_SIZE)
+    }
+
+    /// Allocate the slots for a metadata field named `name`. Fails if the slots configured for the mempool
+    /// are exhausted or if T requires an alignment larger than a slot.
+    pub fn allocate(name: &str) -> errors::Result<MetadataKey<T>> {
+        if mem::align_of::<T>() > METADATA_SLOT_SIZE {
+            return Err(ErrorKind::ConfigurationError(format!(
+                "metadata field {} requires alignment {}",
+                name,
+                mem::align_of::<T>()
+            )));
+        }
+        let slots = MetadataKey::<T>::slots();
+        let mut layout = METADATA_LAYOUT.lock().unwrap();
+        let next = layout.last().map(|(_, slot, n)| slot + n).unwrap_or(0);
+        if next + slots > METADATA_SLOTS as usize {
+            error!(
+                "cannot allocate {} metadata slots for {}, {} of {} slots in use",
+                slots, name, next, METADATA_SLOTS
+            );
+            return Err(ErrorKind::MetadataTooLarge);
+        }
+        layout.push((name.to_string(), next, slots));
+        debug!("allocated metadata slots {}..{} for {}", next, next + slots, name);
+        Ok(MetadataKey {
+            slot: next,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The first slot used by this key.
+    #[inline]
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// Read the field from the metadata of `mbuf`.
+    ///
+    /// # Safety
+    /// `mbuf` must point to a valid mbuf of a mempool with metadata slots.
+    #[inline]
+    pub unsafe fn read(&self, mbuf: *mut MBuf) -> T {
+        ptr::read(MBuf::metadata_as::<T>(mbuf, self.slot))
+    }
+
+    /// Write the field into the metadata of `mbuf`.
+    ///
+    /// # Safety
+    /// `mbuf` must point to a valid mbuf of a mempool with metadata slots.
+    #[inline]
+    pub unsafe fn write(&self, mbuf: *mut MBuf, value: T) {
+        ptr::write(MBuf::mut_metadata_as::<T>(mbuf, self.slot), value)
+    }
+}
+
+/// The allocated metadata fields as (name, first slot, number of slots).
+pub fn metadata_layout() -> Vec<(String, usize, usize)> {
+    METADATA_LAYOUT.lock().unwrap().clone()
+}
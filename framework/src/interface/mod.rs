@@ -1,6 +1,12 @@
+pub use self::builder::*;
+pub use self::metadata::*;
+pub use self::offload::*;
 pub use self::pdu::*;
 pub use self::port::*;
 pub mod dpdk;
+mod builder;
+mod metadata;
+mod offload;
 mod pdu;
 mod port;
 use common::errors;
@@ -10,11 +16,28 @@ use native::zcsi::MBuf;
 pub trait PacketRx {
     fn recv(&self, pkts: &mut [*mut MBuf]) -> errors::Result<(u32, i32)>; // (packets received, queue length (if >=0))
     fn queued(&self) -> usize;
+    /// Packets which arrived but were not received yet and still have to be processed, e.g. in a queue between two
+    /// pipeline stages. Packets in NIC rings are not counted, they stay there once receiving is stopped.
+    fn pending(&self) -> usize {
+        0
+    }
 }
 
 /// Generic trait for objects that can send packets.
 pub trait PacketTx {
     fn send(&mut self, pkts: &mut [*mut MBuf]) -> errors::Result<u32>;
+    /// True if the transmitter cannot keep up and upstream should stop producing packets for a while.
+    fn backpressure(&self) -> bool {
+        false
+    }
+    /// Give transmitters which hold packets back a chance to send them, also when no new packets arrive.
+    fn flush(&mut self) -> errors::Result<u32> {
+        Ok(0)
+    }
+    /// Packets held back by the transmitter which were not sent yet.
+    fn pending(&self) -> usize {
+        0
+    }
 }
 
 pub trait PacketRxTx: PacketRx + PacketTx {}
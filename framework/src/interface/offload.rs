@@ -0,0 +1,533 @@
+use common::errors;
+use common::errors::ErrorKind;
+use native::zcsi::rte_ethdev_api::{
+    DEV_RX_OFFLOAD_IPV4_CKSUM, DEV_RX_OFFLOAD_OUTER_IPV4_CKSUM, DEV_RX_OFFLOAD_TCP_CKSUM, DEV_RX_OFFLOAD_UDP_CKSUM,
+    DEV_TX_OFFLOAD_GENEVE_TNL_TSO, DEV_TX_OFFLOAD_GRE_TNL_TSO, DEV_TX_OFFLOAD_IPIP_TNL_TSO, DEV_TX_OFFLOAD_IPV4_CKSUM,
+    DEV_TX_OFFLOAD_IP_TNL_TSO, DEV_TX_OFFLOAD_MULTI_SEGS, DEV_TX_OFFLOAD_OUTER_IPV4_CKSUM, DEV_TX_OFFLOAD_SCTP_CKSUM,
+    DEV_TX_OFFLOAD_TCP_CKSUM, DEV_TX_OFFLOAD_TCP_TSO, DEV_TX_OFFLOAD_UDP_CKSUM, DEV_TX_OFFLOAD_UDP_TNL_TSO,
+    DEV_TX_OFFLOAD_VLAN_INSERT, DEV_TX_OFFLOAD_VXLAN_TNL_TSO, PKT_TX_IPV4, PKT_TX_IPV6, PKT_TX_IP_CKSUM,
+    PKT_TX_L4_MASK, PKT_TX_OFFLOAD_MASK, PKT_TX_OUTER_IPV4, PKT_TX_OUTER_IPV6, PKT_TX_OUTER_IP_CKSUM,
+    PKT_TX_SCTP_CKSUM, PKT_TX_TCP_CKSUM, PKT_TX_TCP_SEG, PKT_TX_TUNNEL_GENEVE, PKT_TX_TUNNEL_GRE, PKT_TX_TUNNEL_IPIP,
+    PKT_TX_TUNNEL_MASK, PKT_TX_TUNNEL_MPLSINUDP, PKT_TX_TUNNEL_UDP, PKT_TX_TUNNEL_VXLAN, PKT_TX_UDP_CKSUM, PKT_TX_VLAN,
+};
+use native::zcsi::{mbuf_alloc, mbuf_free, MBuf};
+use std::cmp;
+use std::ops::Range;
+use std::ptr;
+use utils::{checksum, finalize_checksum};
+
+/// Rx and tx offloads of a port, as masks of DEV_RX_OFFLOAD_* and DEV_TX_OFFLOAD_* flags. Used both for the
+/// capabilities of a device and for the offloads enabled on a port.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Offloads {
+    pub rx: u64,
+    pub tx: u64,
+}
+
+impl Offloads {
+    /// The offloads requested by a port configuration: IP, TCP and UDP checksums (inner and outer) for `csum`, TCP
+    /// segmentation, also of tunneled packets, for `tso` and insertion of VLAN tags for `vlan_insert`.
+    pub fn requested(tso: bool, csum: bool, vlan_insert: bool) -> Offloads {
+        let mut offloads = Offloads::default();
+        if csum {
+            offloads.rx |= (DEV_RX_OFFLOAD_IPV4_CKSUM
+                | DEV_RX_OFFLOAD_UDP_CKSUM
+                | DEV_RX_OFFLOAD_TCP_CKSUM
+                | DEV_RX_OFFLOAD_OUTER_IPV4_CKSUM) as u64;
+            offloads.tx |= (DEV_TX_OFFLOAD_IPV4_CKSUM
+                | DEV_TX_OFFLOAD_UDP_CKSUM
+                | DEV_TX_OFFLOAD_TCP_CKSUM
+                | DEV_TX_OFFLOAD_OUTER_IPV4_CKSUM) as u64;
+        }
+        if tso {
+            offloads.tx |= (DEV_TX_OFFLOAD_TCP_TSO
+                | DEV_TX_OFFLOAD_VXLAN_TNL_TSO
+                | DEV_TX_OFFLOAD_GRE_TNL_TSO
+                | DEV_TX_OFFLOAD_IPIP_TNL_TSO
+                | DEV_TX_OFFLOAD_GENEVE_TNL_TSO
+                | DEV_TX_OFFLOAD_MULTI_SEGS) as u64;
+        }
+        if vlan_insert {
+            offloads.tx |= DEV_TX_OFFLOAD_VLAN_INSERT as u64;
+        }
+        offloads
+    }
+
+    /// The requested offloads which are among the `capabilities` of a device.
+    #[inline]
+    pub fn negotiate(&self, capabilities: &Offloads) -> Offloads {
+        Offloads {
+            rx: self.rx & capabilities.rx,
+            tx: self.tx & capabilities.tx,
+        }
+    }
+
+    /// True if all DEV_RX_OFFLOAD_* `flags` are set.
+    #[inline]
+    pub fn has_rx(&self, flags: u32) -> bool {
+        self.rx & flags as u64 == flags as u64
+    }
+
+    /// True if all DEV_TX_OFFLOAD_* `flags` are set.
+    #[inline]
+    pub fn has_tx(&self, flags: u32) -> bool {
+        self.tx & flags as u64 == flags as u64
+    }
+}
+
+/// The DEV_TX_OFFLOAD_* flags a port needs for sending a packet with the PKT_TX_* flags `ol_flags`.
+pub fn tx_offloads_for(ol_flags: u64) -> u64 {
+    let mut needed = 0u32;
+    if ol_flags & PKT_TX_IP_CKSUM != 0 {
+        needed |= DEV_TX_OFFLOAD_IPV4_CKSUM;
+    }
+    match ol_flags & PKT_TX_L4_MASK {
+        PKT_TX_TCP_CKSUM => needed |= DEV_TX_OFFLOAD_TCP_CKSUM,
+        PKT_TX_UDP_CKSUM => needed |= DEV_TX_OFFLOAD_UDP_CKSUM,
+        PKT_TX_SCTP_CKSUM => needed |= DEV_TX_OFFLOAD_SCTP_CKSUM,
+        _ => (),
+    }
+    if ol_flags & PKT_TX_TCP_SEG != 0 {
+        needed |= match ol_flags & PKT_TX_TUNNEL_MASK {
+            0 => DEV_TX_OFFLOAD_TCP_TSO,
+            PKT_TX_TUNNEL_VXLAN => DEV_TX_OFFLOAD_VXLAN_TNL_TSO,
+            PKT_TX_TUNNEL_GRE => DEV_TX_OFFLOAD_GRE_TNL_TSO,
+            PKT_TX_TUNNEL_IPIP => DEV_TX_OFFLOAD_IPIP_TNL_TSO,
+            PKT_TX_TUNNEL_GENEVE => DEV_TX_OFFLOAD_GENEVE_TNL_TSO,
+            PKT_TX_TUNNEL_UDP => DEV_TX_OFFLOAD_UDP_TNL_TSO,
+            _ => DEV_TX_OFFLOAD_IP_TNL_TSO,
+        };
+    }
+    if ol_flags & PKT_TX_VLAN != 0 {
+        needed |= DEV_TX_OFFLOAD_VLAN_INSERT;
+    }
+    if ol_flags & PKT_TX_OUTER_IP_CKSUM != 0 {
+        needed |= DEV_TX_OFFLOAD_OUTER_IPV4_CKSUM;
+    }
+    needed as u64
+}
+
+/// True if `mbuf` requests tx offloads which are not among the enabled `tx_offloads` of the port.
+#[inline]
+pub fn needs_tx_fallback(mbuf: &MBuf, tx_offloads: u64) -> bool {
+    mbuf.ol_flags & PKT_TX_OFFLOAD_MASK != 0 && tx_offloads_for(mbuf.ol_flags) & !tx_offloads != 0
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum L4Checksum {
+    None,
+    Tcp,
+    Udp,
+}
+
+/// Tunnel types for the offloads of encapsulated packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tunnel {
+    Vxlan,
+    Gre,
+    IpIp,
+    Geneve,
+    MplsInUdp,
+}
+
+/// Outer headers of a tunneled packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TunnelOffload {
+    pub tunnel: Tunnel,
+    pub outer_l2_len: u16,
+    pub outer_l3_len: u16,
+    pub outer_ip: IpVersion,
+    pub outer_ip_checksum: bool,
+}
+
+/// The tx offloads requested for a packet, see `Pdu::set_tx_offload`. Lengths are in bytes. For a tunneled packet
+/// `l2_len` counts from the end of the outer L3 header, i.e. it covers the tunnel headers and the inner MAC header,
+/// as DPDK expects it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxOffload {
+    pub ip: IpVersion,
+    pub l2_len: u16,
+    pub l3_len: u16,
+    pub l4_len: u16,
+    pub ip_checksum: bool,
+    pub l4_checksum: L4Checksum,
+    /// maximum segment size for TCP segmentation, 0 for none
+    pub tso_segsz: u16,
+    pub vlan_tci: Option<u16>,
+    pub tunnel: Option<TunnelOffload>,
+}
+
+impl TxOffload {
+    /// No offloads for a packet with the given IP version and header lengths.
+    pub fn new(ip: IpVersion, l2_len: u16, l3_len: u16) -> TxOffload {
+        TxOffload {
+            ip,
+            l2_len,
+            l3_len,
+            l4_len: 0,
+            ip_checksum: false,
+            l4_checksum: L4Checksum::None,
+            tso_segsz: 0,
+            vlan_tci: None,
+            tunnel: None,
+        }
+    }
+
+    /// The PKT_TX_* flags for this request.
+    pub fn ol_flags(&self) -> u64 {
+        let mut flags = match self.ip {
+            IpVersion::V4 => PKT_TX_IPV4,
+            IpVersion::V6 => PKT_TX_IPV6,
+        };
+        if self.ip_checksum && self.ip == IpVersion::V4 {
+            flags |= PKT_TX_IP_CKSUM;
+        }
+        match self.l4_checksum {
+            L4Checksum::Tcp => flags |= PKT_TX_TCP_CKSUM,
+            L4Checksum::Udp => flags |= PKT_TX_UDP_CKSUM,
+            L4Checksum::None => (),
+        }
+        if self.tso_segsz > 0 {
+            flags |= PKT_TX_TCP_SEG | PKT_TX_TCP_CKSUM;
+        }
+        if self.vlan_tci.is_some() {
+            flags |= PKT_TX_VLAN;
+        }
+        if let Some(ref t) = self.tunnel {
+            flags |= match t.tunnel {
+                Tunnel::Vxlan => PKT_TX_TUNNEL_VXLAN,
+                Tunnel::Gre => PKT_TX_TUNNEL_GRE,
+                Tunnel::IpIp => PKT_TX_TUNNEL_IPIP,
+                Tunnel::Geneve => PKT_TX_TUNNEL_GENEVE,
+                Tunnel::MplsInUdp => PKT_TX_TUNNEL_MPLSINUDP,
+            };
+            flags |= match t.outer_ip {
+                IpVersion::V4 => PKT_TX_OUTER_IPV4,
+                IpVersion::V6 => PKT_TX_OUTER_IPV6,
+            };
+            if t.outer_ip_checksum && t.outer_ip == IpVersion::V4 {
+                flags |= PKT_TX_OUTER_IP_CKSUM;
+            }
+        }
+        flags
+    }
+
+    /// Write the header lengths and the PKT_TX_* flags into `mbuf`, replacing any previous tx offload request.
+    pub fn write_to(&self, mbuf: &mut MBuf) {
+        mbuf.ol_flags = (mbuf.ol_flags & !PKT_TX_OFFLOAD_MASK) | self.ol_flags();
+        mbuf.set_l2_len(self.l2_len as u64);
+        mbuf.set_l3_len(self.l3_len as u64);
+        mbuf.set_l4_len(self.l4_len as u64);
+        mbuf.set_tso_segsz(self.tso_segsz as u64);
+        mbuf.vlan_tci = self.vlan_tci.unwrap_or(0);
+        let (outer_l2_len, outer_l3_len) = self
+            .tunnel
+            .map_or((0, 0), |t| (t.outer_l2_len as u64, t.outer_l3_len as u64));
+        mbuf.set_outer_l2_len(outer_l2_len);
+        mbuf.set_outer_l3_len(outer_l3_len);
+    }
+}
+
+/// PKT_TX_* flags which request work from the NIC, as opposed to flags which only describe the packet
+const TX_REQUESTS: u64 = PKT_TX_IP_CKSUM | PKT_TX_L4_MASK | PKT_TX_TCP_SEG | PKT_TX_VLAN | PKT_TX_OUTER_IP_CKSUM;
+
+const TX_DESCRIPTIONS: u64 = PKT_TX_IPV4 | PKT_TX_IPV6 | PKT_TX_OUTER_IPV4 | PKT_TX_OUTER_IPV6 | PKT_TX_TUNNEL_MASK;
+
+/// Do the tx offloads requested by `mbuf` which are not among the enabled `tx_offloads` of the port in software.
+/// The packets to send are appended to `out`: either `mbuf` itself, or, if the TCP segmentation is done in
+/// software, the segments of `mbuf`. In the latter case `mbuf` is left unchanged and must be freed by the caller
+/// once the segments are sent. Segmentation of tunneled packets is not supported in software.
+///
+/// # Safety
+/// `mbuf` must point to a valid mbuf, which is not accessed by anyone else during the call.
+pub unsafe fn tx_fallback(mbuf: *mut MBuf, tx_offloads: u64, out: &mut Vec<*mut MBuf>) -> errors::Result<()> {
+    let m = &mut *mbuf;
+    let missing = tx_offloads_for(m.ol_flags) & !tx_offloads;
+    let tso = (DEV_TX_OFFLOAD_TCP_TSO
+        | DEV_TX_OFFLOAD_VXLAN_TNL_TSO
+        | DEV_TX_OFFLOAD_GRE_TNL_TSO
+        | DEV_TX_OFFLOAD_IPIP_TNL_TSO
+        | DEV_TX_OFFLOAD_GENEVE_TNL_TSO
+        | DEV_TX_OFFLOAD_UDP_TNL_TSO
+        | DEV_TX_OFFLOAD_IP_TNL_TSO) as u64;
+    if missing & tso != 0 {
+        if m.ol_flags & PKT_TX_TUNNEL_MASK != 0 {
+            return Err(ErrorKind::UnsupportedOffload(missing & tso));
+        }
+        let hdr_len = (m.l2_len() + m.l3_len() + m.l4_len()) as usize;
+        if l3_end(m, m.l2_len() as usize) > hdr_len + m.tso_segsz() as usize {
+            return segment_tcp(m, tx_offloads, out);
+        }
+        // fits into a single segment
+        m.ol_flags &= !PKT_TX_TCP_SEG;
+        m.set_tso_segsz(0);
+    }
+    offload_in_software(m, tx_offloads)?;
+    out.push(mbuf);
+    Ok(())
+}
+
+/// Checksums and VLAN insertion for the requests of `m` which `tx_offloads` does not cover.
+fn offload_in_software(m: &mut MBuf, tx_offloads: u64) -> errors::Result<()> {
+    let missing = tx_offloads_for(m.ol_flags) & !tx_offloads;
+    if missing == 0 {
+        return Ok(());
+    }
+    let tunneled = m.ol_flags & (PKT_TX_TUNNEL_MASK | PKT_TX_OUTER_IPV4 | PKT_TX_OUTER_IPV6) != 0;
+    let outer_l3 = m.outer_l2_len() as usize;
+    let l3 = if tunneled {
+        outer_l3 + m.outer_l3_len() as usize
+    } else {
+        0
+    } + m.l2_len() as usize;
+    let l4 = l3 + m.l3_len() as usize;
+
+    if missing & DEV_TX_OFFLOAD_OUTER_IPV4_CKSUM as u64 != 0 {
+        ipv4_header_checksum(m, outer_l3, m.outer_l3_len() as usize);
+        m.ol_flags &= !PKT_TX_OUTER_IP_CKSUM;
+    }
+    if missing & DEV_TX_OFFLOAD_IPV4_CKSUM as u64 != 0 {
+        ipv4_header_checksum(m, l3, m.l3_len() as usize);
+        m.ol_flags &= !PKT_TX_IP_CKSUM;
+    }
+    let l4_csum = (DEV_TX_OFFLOAD_TCP_CKSUM | DEV_TX_OFFLOAD_UDP_CKSUM) as u64;
+    if missing & l4_csum != 0 {
+        let ipv6 = m.ol_flags & PKT_TX_IPV6 != 0;
+        let udp = m.ol_flags & PKT_TX_L4_MASK == PKT_TX_UDP_CKSUM;
+        l4_checksum(m, l3, l4, ipv6, udp);
+        m.ol_flags &= !PKT_TX_L4_MASK;
+    }
+    if missing & DEV_TX_OFFLOAD_SCTP_CKSUM as u64 != 0 {
+        return Err(ErrorKind::UnsupportedOffload(DEV_TX_OFFLOAD_SCTP_CKSUM as u64));
+    }
+    if missing & DEV_TX_OFFLOAD_VLAN_INSERT as u64 != 0 {
+        insert_vlan_tag(m, tunneled)?;
+        m.ol_flags &= !PKT_TX_VLAN;
+    }
+    if m.ol_flags & TX_REQUESTS == 0 {
+        m.ol_flags &= !TX_DESCRIPTIONS;
+    }
+    Ok(())
+}
+
+#[inline]
+fn read_u16(m: &MBuf, offset: usize) -> u16 {
+    let mut b = [0u8; 2];
+    m.read_bytes(offset, &mut b);
+    (b[0] as u16) << 8 | b[1] as u16
+}
+
+#[inline]
+fn write_u16(m: &mut MBuf, offset: usize, val: u16) {
+    m.write_bytes(offset, &[(val >> 8) as u8, val as u8]);
+}
+
+/// End of the IP packet beginning at `l3`, excluding any Ethernet padding.
+#[inline]
+fn l3_end(m: &MBuf, l3: usize) -> usize {
+    if m.ol_flags & PKT_TX_IPV6 != 0 {
+        l3 + 40 + read_u16(m, l3 + 4) as usize
+    } else {
+        l3 + read_u16(m, l3 + 2) as usize
+    }
+}
+
+/// Sum of the big endian 16 bit words of `len` bytes beginning at `offset`, across segments.
+fn sum_words(m: &MBuf, offset: usize, len: usize) -> u64 {
+    let mut sum = 0u64;
+    let mut odd = false;
+    let mut left = len;
+    for s in m.segments_from(offset) {
+        let mut data = &s[..cmp::min(s.len(), left)];
+        left -= data.len();
+        if odd && !data.is_empty() {
+            sum += data[0] as u64;
+            data = &data[1..];
+            odd = false;
+        }
+        for w in data.chunks(2) {
+            if w.len() == 2 {
+                sum += (w[0] as u64) << 8 | w[1] as u64;
+            } else {
+                sum += (w[0] as u64) << 8;
+                odd = true;
+            }
+        }
+        if left == 0 {
+            break;
+        }
+    }
+    sum
+}
+
+#[inline]
+fn fold(mut sum: u64) -> u32 {
+    while sum >> 32 != 0 {
+        sum = (sum >> 32) + (sum & 0xffff_ffff);
+    }
+    sum as u32
+}
+
+fn ipv4_header_checksum(m: &mut MBuf, l3: usize, l3_len: usize) {
+    write_u16(m, l3 + 10, 0);
+    let csum = finalize_checksum(fold(sum_words(m, l3, l3_len)));
+    write_u16(m, l3 + 10, csum);
+}
+
+fn l4_checksum(m: &mut MBuf, l3: usize, l4: usize, ipv6: bool, udp: bool) {
+    let l4_len = l3_end(m, l3) - l4;
+    let proto = if udp { 17 } else { 6 };
+    let pseudo = if ipv6 {
+        sum_words(m, l3 + 8, 32)
+    } else {
+        sum_words(m, l3 + 12, 8)
+    } + proto
+        + l4_len as u64;
+    let field = l4 + if udp { 6 } else { 16 };
+    write_u16(m, field, 0);
+    let mut csum = finalize_checksum(fold(pseudo + sum_words(m, l4, l4_len)));
+    if udp && csum == 0 {
+        csum = 0xffff;
+    }
+    write_u16(m, field, csum);
+}
+
+/// Insert an 802.1Q tag with the `vlan_tci` of `m` behind the (outer) MAC addresses.
+fn insert_vlan_tag(m: &mut MBuf, tunneled: bool) -> errors::Result<()> {
+    if m.data_len() < 12 || m.add_data_beginning(4) != 4 {
+        return Err(ErrorKind::UnsupportedOffload(DEV_TX_OFFLOAD_VLAN_INSERT as u64));
+    }
+    let tci = m.vlan_tci;
+    unsafe {
+        ptr::copy(m.data_address(4), m.data_address(0), 12);
+    }
+    m.write_bytes(12, &[0x81, 0x00, (tci >> 8) as u8, tci as u8]);
+    if tunneled {
+        let len = m.outer_l2_len();
+        m.set_outer_l2_len(len + 4);
+    } else {
+        let len = m.l2_len();
+        m.set_l2_len(len + 4);
+    }
+    Ok(())
+}
+
+/// Adapt the copied headers of a TCP segment: `headers` holds the L2, IP and TCP headers of the original packet,
+/// the IP header begins at `l3`. The `index`-th segment carries the bytes `payload` of the original payload. The
+/// IPv4 checksum is recomputed.
+pub fn tcp_segment_headers(
+    headers: &mut [u8],
+    l3: usize,
+    l3_len: usize,
+    ipv6: bool,
+    index: usize,
+    payload: Range<usize>,
+    last: bool,
+) {
+    let set_u16 = |h: &mut [u8], at: usize, val: u16| {
+        h[at] = (val >> 8) as u8;
+        h[at + 1] = val as u8;
+    };
+    let ip_len = headers.len() - l3 + payload.len();
+    if ipv6 {
+        set_u16(headers, l3 + 4, (ip_len - 40) as u16);
+    } else {
+        set_u16(headers, l3 + 2, ip_len as u16);
+        let id = ((headers[l3 + 4] as u16) << 8 | headers[l3 + 5] as u16).wrapping_add(index as u16);
+        set_u16(headers, l3 + 4, id);
+        set_u16(headers, l3 + 10, 0);
+        let csum = checksum(&headers[l3..l3 + l3_len], 5);
+        set_u16(headers, l3 + 10, csum);
+    }
+    let l4 = l3 + l3_len;
+    let seq = (headers[l4 + 4] as u32) << 24
+        | (headers[l4 + 5] as u32) << 16
+        | (headers[l4 + 6] as u32) << 8
+        | headers[l4 + 7] as u32;
+    let seq = seq.wrapping_add(payload.start as u32);
+    headers[l4 + 4..l4 + 8].copy_from_slice(&[(seq >> 24) as u8, (seq >> 16) as u8, (seq >> 8) as u8, seq as u8]);
+    // CWR only in the first, FIN and PSH only in the last segment
+    if index > 0 {
+        headers[l4 + 13] &= !0x80;
+    }
+    if !last {
+        headers[l4 + 13] &= !0x09;
+    }
+}
+
+/// Split the TCP packet `m` into segments of at most `tso_segsz` payload bytes.
+fn segment_tcp(m: &MBuf, tx_offloads: u64, out: &mut Vec<*mut MBuf>) -> errors::Result<()> {
+    let l3 = m.l2_len() as usize;
+    let l3_len = m.l3_len() as usize;
+    let hdr_len = l3 + l3_len + m.l4_len() as usize;
+    let ipv6 = m.ol_flags & PKT_TX_IPV6 != 0;
+    let payload_len = l3_end(m, l3) - hdr_len;
+    let mss = m.tso_segsz() as usize;
+    let mut original = vec![0u8; hdr_len];
+    if m.read_bytes(0, &mut original) < hdr_len {
+        return Err(ErrorKind::BadSize(hdr_len, "TCP segmentation".to_string()));
+    }
+    let flags = (m.ol_flags & !(PKT_TX_TCP_SEG | PKT_TX_L4_MASK)) | PKT_TX_TCP_CKSUM;
+    let first = out.len();
+    let mut seq_offset = 0;
+    let mut index = 0;
+    while seq_offset < payload_len {
+        let len = cmp::min(mss, payload_len - seq_offset);
+        let seg = unsafe { mbuf_alloc() };
+        if seg.is_null() {
+            discard(out, first);
+            return Err(ErrorKind::FailedAllocation);
+        }
+        out.push(seg);
+        let s = unsafe { &mut *seg };
+        let mut headers = original.clone();
+        tcp_segment_headers(
+            &mut headers,
+            l3,
+            l3_len,
+            ipv6,
+            index,
+            seq_offset..seq_offset + len,
+            seq_offset + len == payload_len,
+        );
+        let mut appended = s.append_bytes(&headers);
+        let mut left = len;
+        for data in m.segments_from(hdr_len + seq_offset) {
+            let n = cmp::min(data.len(), left);
+            appended += s.append_bytes(&data[..n]);
+            left -= n;
+            if left == 0 {
+                break;
+            }
+        }
+        if appended < hdr_len + len {
+            discard(out, first);
+            return Err(ErrorKind::FailedAllocation);
+        }
+        s.ol_flags = flags;
+        s.vlan_tci = m.vlan_tci;
+        s.set_l2_len(l3 as u64);
+        s.set_l3_len(l3_len as u64);
+        s.set_l4_len(m.l4_len());
+        if let Err(e) = offload_in_software(s, tx_offloads) {
+            discard(out, first);
+            return Err(e);
+        }
+        seq_offset += len;
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Free the packets of `out` beginning at `first`.
+fn discard(out: &mut Vec<*mut MBuf>, first: usize) {
+    for seg in out.drain(first..) {
+        unsafe { mbuf_free(seg) };
+    }
+}
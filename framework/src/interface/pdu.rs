@@ -7,12 +7,15 @@ use std::slice;
 
 use common::errors;
 use common::errors::ErrorKind;
-use headers::{ArpIpv4Header, EndOffset, Header, IpHeader, MacHeader, TcpHeader, UdpHeader};
-use native::zcsi::MBuf;
-use native::zcsi::{mbuf_alloc, mbuf_alloc_bulk, validate_tx_offload};
+use headers::{ArpIpv4Header, EndOffset, Header, IcmpHeader, IpHeader, MacHeader, MplsHeader, TcpHeader, UdpHeader};
+use headers::{ETYPE_MPLS_MULTICAST, ETYPE_MPLS_UNICAST};
+use interface::dpdk::METADATA_SLOTS;
+use interface::{IpVersion, L4Checksum, MetadataKey, MetadataValue, Tunnel, TunnelOffload, TxOffload};
+use native::zcsi::{mbuf_alloc, mbuf_alloc_bulk, mbuf_clone, mbuf_free, validate_tx_offload};
+use native::zcsi::{MBuf, Segments};
 use utils::ipv4_checksum;
 
-const MAX_HEADERS: usize = 5;
+const MAX_HEADERS: usize = 8;
 
 #[derive(Clone, Debug)]
 pub struct HeaderStack<'a> {
@@ -26,7 +29,16 @@ impl<'a> HeaderStack<'a> {
     #[inline]
     pub fn new() -> HeaderStack<'a> {
         HeaderStack {
-            stack: [Header::Null, Header::Null, Header::Null, Header::Null, Header::Null],
+            stack: [
+                Header::Null,
+                Header::Null,
+                Header::Null,
+                Header::Null,
+                Header::Null,
+                Header::Null,
+                Header::Null,
+                Header::Null,
+            ],
             hc: 0,
         }
     }
@@ -42,6 +54,23 @@ impl<'a> HeaderStack<'a> {
         self.hc
     }
 
+    /// Insert `h` at position `at`, the headers from `at` on move up by one.
+    #[inline]
+    pub fn insert(&mut self, at: usize, h: Header<'a>) {
+        self.stack[at..self.hc + 1].rotate_right(1);
+        self.stack[at] = h;
+        self.hc += 1;
+    }
+
+    /// Remove the header at position `at`, the headers behind it move down by one.
+    #[inline]
+    pub fn remove(&mut self, at: usize) -> Header<'a> {
+        let h = mem::replace(&mut self.stack[at], Header::Null);
+        self.stack[at..self.hc].rotate_left(1);
+        self.hc -= 1;
+        h
+    }
+
     #[inline]
     pub fn get(&self, which: usize) -> &Header<'a> {
         &self.stack[which]
@@ -77,6 +106,16 @@ impl<'a> HeaderStack<'a> {
         self.stack[which].as_arpipv4_mut().unwrap()
     }
 
+    #[inline]
+    pub fn icmp_mut(&mut self, which: usize) -> &mut IcmpHeader {
+        self.stack[which].as_icmp_mut().unwrap()
+    }
+
+    #[inline]
+    pub fn mpls_mut(&mut self, which: usize) -> &mut MplsHeader {
+        self.stack[which].as_mpls_mut().unwrap()
+    }
+
     #[inline]
     pub fn tcp(&self, which: usize) -> &TcpHeader {
         self.stack[which].as_tcp().unwrap()
@@ -96,6 +135,16 @@ impl<'a> HeaderStack<'a> {
     pub fn arp(&self, which: usize) -> &ArpIpv4Header {
         self.stack[which].as_arpipv4().unwrap()
     }
+
+    #[inline]
+    pub fn icmp(&self, which: usize) -> &IcmpHeader {
+        self.stack[which].as_icmp().unwrap()
+    }
+
+    #[inline]
+    pub fn mpls(&self, which: usize) -> &MplsHeader {
+        self.stack[which].as_mpls().unwrap()
+    }
 }
 
 impl<'a> fmt::Display for HeaderStack<'a> {
@@ -196,6 +245,7 @@ impl<'a> Pdu<'a> {
     pub unsafe fn copy_use_mbuf(&self, mbuf: *mut MBuf) -> Pdu {
         assert!(!mbuf.is_null());
         (*self.mbuf).copy_to(mbuf.as_mut().unwrap());
+        (*self.mbuf).copy_metadata_to(mbuf.as_mut().unwrap(), METADATA_SLOTS as usize);
         Pdu::pdu_from_mbuf_no_increment(mbuf)
     }
 
@@ -214,6 +264,72 @@ impl<'a> Pdu<'a> {
         Pdu::pdu_from_mbuf(self.mbuf)
     }
 
+    /// Duplicate the packet without copying its data, using indirect mbufs which are attached to the data of this
+    /// packet. Use `duplicate_with_private_headers` if the headers of the duplicate are to be modified.
+    ///
+    /// # Safety
+    /// The data is shared by both packets, it must not be modified through either of them, e.g. by `headers_mut`,
+    /// as long as both exist.
+    pub unsafe fn duplicate(&self) -> errors::Result<Pdu<'static>> {
+        let mbuf = mbuf_clone(self.mbuf);
+        if mbuf.is_null() {
+            return Err(ErrorKind::FailedAllocation);
+        }
+        (*self.mbuf).copy_metadata_to(&mut *mbuf, METADATA_SLOTS as usize);
+        Ok(Pdu::pdu_from_mbuf_no_increment(mbuf))
+    }
+
+    /// Duplicate the packet with a private copy of the parsed headers in a new direct mbuf. The payload behind
+    /// the headers is shared with this packet through indirect mbufs and must be treated as read-only, whereas
+    /// the headers of both packets can be modified independently.
+    pub fn duplicate_with_private_headers(&self) -> errors::Result<Pdu<'static>> {
+        let hdr_len = match self.header_stack.count() {
+            0 => 0,
+            n => self.payload_offset(n - 1),
+        };
+        unsafe {
+            let head = mbuf_alloc();
+            if head.is_null() {
+                return Err(ErrorKind::FailedAllocation);
+            }
+            if (*head).add_data_end(hdr_len) != hdr_len {
+                mbuf_free(head);
+                return Err(ErrorKind::BadSize(
+                    hdr_len,
+                    "headers exceed the tailroom of a new mbuf".to_string(),
+                ));
+            }
+            ptr::copy_nonoverlapping((*self.mbuf).data_address(0), (*head).data_address(0), hdr_len);
+            (*self.mbuf).copy_metadata_to(&mut *head, METADATA_SLOTS as usize);
+            (*head).port = (*self.mbuf).port;
+            if self.pkt_len() > hdr_len {
+                let mut tail = mbuf_clone(self.mbuf);
+                if tail.is_null() {
+                    mbuf_free(head);
+                    return Err(ErrorKind::FailedAllocation);
+                }
+                if (*tail).remove_data_beginning(hdr_len) != hdr_len {
+                    // the headers are not within the first segment
+                    mbuf_free(tail);
+                    mbuf_free(head);
+                    return Err(ErrorKind::BadOffset(hdr_len));
+                }
+                if (*tail).data_len() == 0 {
+                    // the first segment contained only headers, drop its empty clone
+                    let next = (*tail).next;
+                    (*next).pkt_len = (*tail).pkt_len;
+                    (*next).nb_segs = (*tail).nb_segs - 1;
+                    (*tail).next = ptr::null_mut();
+                    (*tail).nb_segs = 1;
+                    mbuf_free(tail);
+                    tail = next;
+                }
+                (*head).chain(&mut *tail);
+            }
+            Ok(Pdu::pdu_from_mbuf_no_increment(head))
+        }
+    }
+
     /// same as clone, but without increment of mbuf ref count
     #[inline]
     pub fn clone_without_ref_counting(&mut self) -> Pdu {
@@ -233,6 +349,14 @@ impl<'a> Pdu<'a> {
         }
     }
 
+    #[inline]
+    fn parse_icmp(&mut self, offset: usize) {
+        let hdr = unsafe { (*self.mbuf).data_address(offset) as *mut IcmpHeader };
+        unsafe {
+            self.header_stack.push(Header::Icmp(&mut *hdr));
+        }
+    }
+
     #[inline]
     fn parse_ipv4(&mut self, offset: usize) {
         let hdr = unsafe { (*self.mbuf).data_address(offset) as *mut IpHeader };
@@ -248,13 +372,38 @@ impl<'a> Pdu<'a> {
             ip_protocol = ip.protocol();
             ip_offset = ip.offset();
         }
+        if self.header_stack.count() == MAX_HEADERS {
+            return;
+        }
         match ip_protocol {
-            6 => {
-                if self.data_len() >= ip_length as usize + offset {
-                    self.parse_tcp(offset + ip_offset);
+            1 if self.data_len() >= ip_length as usize + offset => self.parse_icmp(offset + ip_offset),
+            6 if self.data_len() >= ip_length as usize + offset => self.parse_tcp(offset + ip_offset),
+            _ => {}
+        }
+    }
+
+    /// parses the label stack and an IPv4 packet behind it, as far as the header stack has room
+    #[inline]
+    fn parse_mpls(&mut self, offset: usize) {
+        let l = self.data_len();
+        let mut offset = offset;
+        while l >= offset + MplsHeader::size() && self.header_stack.count() < MAX_HEADERS {
+            let hdr = unsafe { (*self.mbuf).data_address(offset) as *mut MplsHeader };
+            let bos = unsafe {
+                self.header_stack.push(Header::Mpls(&mut *hdr));
+                (*hdr).bottom_of_stack()
+            };
+            offset += MplsHeader::size();
+            if bos {
+                // there is no next-protocol field, IPv4 is recognized by its version
+                if l >= offset + IpHeader::size()
+                    && self.header_stack.count() < MAX_HEADERS
+                    && unsafe { *(*self.mbuf).data_address(offset) >> 4 } == 4
+                {
+                    self.parse_ipv4(offset);
                 }
+                return;
             }
-            _ => {}
         }
     }
 
@@ -296,6 +445,7 @@ impl<'a> Pdu<'a> {
                     self.parse_ipv4(mac.offset());
                 }
             }
+            ETYPE_MPLS_UNICAST | ETYPE_MPLS_MULTICAST => self.parse_mpls(mac.offset()),
             0x86DD => {} // IPv6
             0x0806 => {
                 if l >= mac.offset() + ArpIpv4Header::size() {
@@ -307,6 +457,13 @@ impl<'a> Pdu<'a> {
         self.header_stack.count()
     }
 
+    /// Clear the header stack and parse the packet again, e.g. after headers were changed in place.
+    #[inline]
+    pub fn reparse(&mut self) -> usize {
+        self.header_stack = HeaderStack::new();
+        self.parse()
+    }
+
     /// Get the mbuf reference by this packet.
     ///
     /// # Safety
@@ -325,11 +482,67 @@ impl<'a> Pdu<'a> {
     }
 
     // this includes ethernet padding if it is present, sta
+    // for chained mbufs this is the length of the first segment only, see pkt_len
     #[inline]
     pub fn data_len(&self) -> usize {
         unsafe { (*self.mbuf).data_len() }
     }
 
+    /// Length of the packet across all segments.
+    #[inline]
+    pub fn pkt_len(&self) -> usize {
+        unsafe { (*self.mbuf).pkt_len() }
+    }
+
+    #[inline]
+    pub fn nb_segs(&self) -> usize {
+        unsafe { (*self.mbuf).nb_segs() }
+    }
+
+    #[inline]
+    pub fn is_contiguous(&self) -> bool {
+        unsafe { (*self.mbuf).is_contiguous() }
+    }
+
+    /// Iterate over the data of all segments of the packet.
+    #[inline]
+    pub fn segments(&self) -> Segments<'_> {
+        unsafe { (*self.mbuf).segments() }
+    }
+
+    /// Copy the data of all segments into the first segment. Headers stay valid, as the first segment is not moved.
+    pub fn linearize(&mut self) -> errors::Result<()> {
+        if unsafe { (*self.mbuf).linearize() } {
+            Ok(())
+        } else {
+            Err(ErrorKind::BadSize(
+                self.pkt_len(),
+                "packet does not fit into a single segment".to_string(),
+            ))
+        }
+    }
+
+    /// Append `data` at the end of the packet, new segments are chained as needed.
+    pub fn append(&mut self, data: &[u8]) -> errors::Result<()> {
+        if unsafe { (*self.mbuf).append_bytes(data) } == data.len() {
+            Ok(())
+        } else {
+            Err(ErrorKind::FailedAllocation)
+        }
+    }
+
+    /// Add `len` bytes in front of the packet. If the headroom is too small, the current data of the first
+    /// segment is moved into a new second segment. The header stack is cleared, as the new bytes are not a valid
+    /// header yet; use `parse` after writing them.
+    pub fn prepend(&mut self, len: usize) -> errors::Result<()> {
+        if unsafe { (*self.mbuf).prepend(len) } {
+            self.header_stack = HeaderStack::new();
+            Ok(())
+        } else {
+            Err(ErrorKind::FailedAllocation)
+        }
+    }
+
     #[inline]
     pub fn get_tailroom(&self) -> usize {
         unsafe { (*self.mbuf).pkt_tailroom() }
@@ -360,6 +573,8 @@ impl<'a> Pdu<'a> {
                 Header::ArpIpv4(ref mut p) => {
                     ptr::copy_nonoverlapping(hdr.as_arpipv4().unwrap() as *const ArpIpv4Header, *p, 1)
                 }
+                Header::Icmp(ref mut p) => ptr::copy_nonoverlapping(hdr.as_icmp().unwrap() as *const IcmpHeader, *p, 1),
+                Header::Mpls(ref mut p) => ptr::copy_nonoverlapping(hdr.as_mpls().unwrap() as *const MplsHeader, *p, 1),
             };
         }
     }
@@ -371,7 +586,7 @@ impl<'a> Pdu<'a> {
     /// Append a header to the header stack of a packet
     pub fn push_header<T: EndOffset>(&mut self, header: &T) -> bool {
         let size = header.offset();
-        let added = unsafe { (*self.mbuf).add_data_end(size) };
+        let added = unsafe { (*self.mbuf).add_data_end_first_segment(size) };
         if added < size {
             return false;
         };
@@ -412,6 +627,93 @@ impl<'a> Pdu<'a> {
         }
     }
 
+    /// Insert `header` in front of the header at position `at` of the header stack, `at` equal to the number of
+    /// headers appends it like `push_header`. The bytes in front of the new header are moved into the headroom of
+    /// the mbuf, if it is too small the bytes behind are moved into the tailroom of the first segment. The
+    /// next-protocol field of the preceding header is set to announce the new header, and the one of the new
+    /// header to announce the header behind it. Length fields and checksums of outer headers are not changed.
+    pub fn insert_header<T: EndOffset>(&mut self, at: usize, header: &T) -> errors::Result<()> {
+        let count = self.header_stack.count();
+        if at > count {
+            return Err(ErrorKind::BadOffset(at));
+        }
+        if count == MAX_HEADERS {
+            return Err(ErrorKind::BadSize(count, "header stack is full".to_string()));
+        }
+        if at == count {
+            if !self.push_header(header) {
+                return Err(ErrorKind::FailedAllocation);
+            }
+            self.announce(at);
+            return Ok(());
+        }
+        let size = header.offset();
+        let prefix = if at == 0 { 0 } else { self.payload_offset(at - 1) };
+        let dst = unsafe {
+            let mbuf = &mut *self.mbuf;
+            if mbuf.add_data_beginning(size) == size {
+                ptr::copy(mbuf.data_address(size), mbuf.data_address(0), prefix);
+                self.relocate_headers(0..at, -(size as isize));
+            } else {
+                let behind = mbuf.data_len() - prefix;
+                if mbuf.add_data_end_first_segment(size) < size {
+                    return Err(ErrorKind::FailedAllocation);
+                }
+                ptr::copy(mbuf.data_address(prefix), mbuf.data_address(prefix + size), behind);
+                self.relocate_headers(at..count, size as isize);
+            }
+            let dst = mbuf.data_address(prefix) as *mut T;
+            ptr::copy_nonoverlapping(header as *const T, dst, 1);
+            dst
+        };
+        self.header_stack.insert(at, Header::new(dst));
+        self.announce(at);
+        self.announce(at + 1);
+        Ok(())
+    }
+
+    /// Remove the header at position `at` of the header stack. The bytes in front of it are moved up, so that the
+    /// removed bytes become headroom of the mbuf. The next-protocol field of the preceding header is set to
+    /// announce the header which now follows it. Length fields and checksums of outer headers are not changed.
+    pub fn remove_header(&mut self, at: usize) -> errors::Result<()> {
+        if at >= self.header_stack.count() {
+            return Err(ErrorKind::BadOffset(at));
+        }
+        let size = self.header_stack.get(at).offset().unwrap();
+        let prefix = if at == 0 { 0 } else { self.payload_offset(at - 1) };
+        unsafe {
+            let mbuf = &mut *self.mbuf;
+            ptr::copy(mbuf.data_address(0), mbuf.data_address(size), prefix);
+            mbuf.remove_data_beginning(size);
+        }
+        self.relocate_headers(0..at, size as isize);
+        self.header_stack.remove(at);
+        if at < self.header_stack.count() {
+            self.announce(at);
+        }
+        Ok(())
+    }
+
+    /// Move the pointers of the headers in `range` by `delta` bytes.
+    #[inline]
+    fn relocate_headers(&mut self, range: Range<usize>, delta: isize) {
+        for i in range {
+            let h = self.header_stack.get_mut(i);
+            let kind = h.kind();
+            let ptr = h.as_ptr_u8_mut().unwrap();
+            *h = unsafe { Header::from_kind(kind, ptr.offset(delta)) };
+        }
+    }
+
+    /// Let the header in front of the header at `which` announce it in its next-protocol field.
+    #[inline]
+    fn announce(&mut self, which: usize) {
+        if which > 0 && which < self.header_stack.count() {
+            let next = self.header_stack.get(which).kind();
+            self.header_stack.get_mut(which - 1).set_next_header(next);
+        }
+    }
+
     #[inline]
     pub fn set_tcp_ipv4_checksum_tx_offload(&mut self) {
         unsafe {
@@ -419,6 +721,20 @@ impl<'a> Pdu<'a> {
         }
     }
 
+    #[inline]
+    pub fn set_udp_ipv4_checksum_tx_offload(&mut self) {
+        unsafe {
+            (*self.mbuf).set_udp_ipv4_checksum_tx_offload();
+        }
+    }
+
+    #[inline]
+    pub fn set_ipv4_checksum_tx_offload(&mut self) {
+        unsafe {
+            (*self.mbuf).set_ipv4_checksum_tx_offload();
+        }
+    }
+
     #[inline]
     pub fn ipv4_checksum_tx_offload(&self) -> bool {
         unsafe { (*self.mbuf).ipv4_checksum_tx_offload() }
@@ -486,6 +802,53 @@ impl<'a> Pdu<'a> {
         unsafe { validate_tx_offload(self.mbuf) }
     }
 
+    /// Request the tx offloads `offload`: sets the header lengths and the PKT_TX_* flags of the mbuf. Offloads which
+    /// are not enabled on the port are done in software when the packet is sent.
+    #[inline]
+    pub fn set_tx_offload(&mut self, offload: &TxOffload) {
+        unsafe { offload.write_to(&mut *self.mbuf) }
+    }
+
+    /// Checksum offloads for the innermost IPv4 header of the header stack and the TCP or UDP header behind it,
+    /// with the header lengths taken from the header stack. IP in IP is described as tunnel with checksum offload
+    /// for the outer header. Returns None if there is no IPv4 header. TSO or VLAN insertion may be added to the
+    /// result before it is passed to `set_tx_offload`.
+    pub fn tx_offload_from_headers(&self) -> Option<TxOffload> {
+        let headers = &self.header_stack;
+        let ips: Vec<usize> = (0..headers.count())
+            .filter(|i| headers.get(*i).as_ip().is_some())
+            .collect();
+        let inner = *ips.last()?;
+        let start = unsafe { (*self.mbuf).data_address(0) } as usize;
+        let offset = |i: usize| (headers.get(i).as_ptr_u8().unwrap() as usize - start) as u16;
+        let ip = headers.ip(inner);
+        let mut offload = TxOffload::new(IpVersion::V4, offset(inner), ip.ihl() as u16 * 4);
+        offload.ip_checksum = true;
+        match ip.protocol() {
+            6 if inner + 1 < headers.count() => {
+                offload.l4_len = headers.tcp(inner + 1).data_offset() as u16 * 4;
+                offload.l4_checksum = L4Checksum::Tcp;
+            }
+            17 => {
+                offload.l4_len = 8;
+                offload.l4_checksum = L4Checksum::Udp;
+            }
+            _ => (),
+        }
+        if ips.len() > 1 && ips[ips.len() - 2] + 1 == inner {
+            let outer = inner - 1;
+            offload.l2_len = 0;
+            offload.tunnel = Some(TunnelOffload {
+                tunnel: Tunnel::IpIp,
+                outer_l2_len: offset(outer),
+                outer_l3_len: headers.ip(outer).ihl() as u16 * 4,
+                outer_ip: IpVersion::V4,
+                outer_ip_checksum: true,
+            });
+        }
+        Some(offload)
+    }
+
     #[inline]
     pub fn trim_payload_size(&mut self, trim_by: usize) -> usize {
         unsafe { (*self.mbuf).remove_data_end(trim_by) }
@@ -556,16 +919,41 @@ impl<'a> Pdu<'a> {
         }
     }
 
-    /// may include padding
     #[inline]
-    pub fn payload_size(&self, which: usize) -> usize {
+    fn payload_offset(&self, which: usize) -> usize {
         // sum up the header offsets
-        let sum = self
-            .header_stack
+        self.header_stack
             .get_slice(0..which as usize + 1)
             .iter()
-            .fold(0, |sum, value| sum + value.offset().unwrap());
-        self.data_len() - sum
+            .fold(0, |sum, value| sum + value.offset().unwrap())
+    }
+
+    /// may include padding, for chained mbufs this is the part of the payload in the first segment
+    #[inline]
+    pub fn payload_size(&self, which: usize) -> usize {
+        self.data_len() - self.payload_offset(which)
+    }
+
+    /// Size of the payload across all segments.
+    #[inline]
+    pub fn total_payload_size(&self, which: usize) -> usize {
+        self.pkt_len() - self.payload_offset(which)
+    }
+
+    /// Iterate over the payload behind header `which` across all segments.
+    #[inline]
+    pub fn payload_segments(&self, which: usize) -> Segments<'_> {
+        unsafe { (*self.mbuf).segments_from(self.payload_offset(which)) }
+    }
+
+    /// The complete payload as one slice, if it is not split across segments.
+    #[inline]
+    pub fn contiguous_payload(&self, which: usize) -> Option<&[u8]> {
+        if self.is_contiguous() {
+            Some(self.get_payload(which))
+        } else {
+            None
+        }
     }
 
     #[inline]
@@ -577,7 +965,7 @@ impl<'a> Pdu<'a> {
             let payload_size = self.payload_size(which);
             let should_copy = if payload_size < copy_len {
                 let increment = copy_len - payload_size;
-                payload_size + self.increase_payload_size(increment)
+                payload_size + unsafe { (*self.mbuf).add_data_end_first_segment(increment) }
             } else {
                 copy_len
             };
@@ -615,6 +1003,22 @@ impl<'a> Pdu<'a> {
     pub fn port_id(&self) -> u16 {
         unsafe { (*self.mbuf).port }
     }
+
+    /// Read the metadata field `key` of this packet, an arbitrary value if the field was not set.
+    #[inline]
+    pub fn metadata<T: MetadataValue>(&self, key: MetadataKey<T>) -> T {
+        unsafe { key.read(self.mbuf) }
+    }
+
+    #[inline]
+    pub fn set_metadata<T: MetadataValue>(&mut self, key: MetadataKey<T>, value: T) {
+        unsafe { key.write(self.mbuf, value) }
+    }
+
+    #[inline]
+    pub fn metadata_mut<T: MetadataValue>(&mut self, key: MetadataKey<T>) -> &mut T {
+        unsafe { &mut *MBuf::mut_metadata_as::<T>(self.mbuf, key.slot()) }
+    }
 }
 
 #[inline]
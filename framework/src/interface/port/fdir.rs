@@ -12,7 +12,7 @@ pub struct L4Flow {
     pub port: u16,
 }
 
-#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
 pub enum FlowSteeringMode {
     // Port is default
     Port,
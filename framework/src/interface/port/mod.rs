@@ -6,16 +6,37 @@ use allocators::*;
 use common::*;
 use interface::{PacketRx, PacketTx};
 use native::zcsi::MBuf;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 pub mod fdir;
 mod phy_port;
 mod virt_port;
 
+/// Set while receiving from ports is stopped, see `stop_rx`.
+static RX_STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Stop receiving on all port queues of the process, e.g. to drain the pipelines before shutdown. Packets arriving
+/// afterwards stay in the NIC rings.
+pub fn stop_rx() {
+    RX_STOPPED.store(true, Ordering::SeqCst);
+}
+
+/// Resume receiving after `stop_rx`.
+pub fn start_rx() {
+    RX_STOPPED.store(false, Ordering::SeqCst);
+}
+
+#[inline]
+pub fn rx_stopped() -> bool {
+    RX_STOPPED.load(Ordering::Relaxed)
+}
+
 /// Statistics for PMD port.
 pub struct PortStats {
     pub stats: AtomicUsize,
     pub queued: AtomicUsize,
+    /// packets dropped because a tx buffer was full or the NIC rejected them
+    pub dropped: AtomicUsize,
     pub q_len: AtomicUsize,
     pub max_q_len: AtomicUsize,
     pub cycles: AtomicU64,
@@ -28,6 +49,7 @@ impl PortStats {
         CacheAligned::allocate(PortStats {
             stats: AtomicUsize::new(0),
             queued: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
             q_len: AtomicUsize::new(1),
             max_q_len: AtomicUsize::new(1),
             cycles: AtomicU64::new(0),
@@ -43,6 +65,9 @@ impl PortStats {
     pub fn cycles(&self) -> u64 {
         self.cycles.load(Ordering::Relaxed)
     }
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
 
     pub fn set_q_len(&self, len: usize) -> usize {
         let q_max = self.get_max_q_len();
@@ -63,6 +88,11 @@ impl<T: PacketRx> PacketRx for CacheAligned<T> {
     fn queued(&self) -> usize {
         T::queued(&self)
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        T::pending(self)
+    }
 }
 
 impl<T: PacketTx> PacketTx for CacheAligned<T> {
@@ -70,4 +100,19 @@ impl<T: PacketTx> PacketTx for CacheAligned<T> {
     fn send(&mut self, pkts: &mut [*mut MBuf]) -> errors::Result<u32> {
         T::send(&mut *self, pkts)
     }
+
+    #[inline]
+    fn backpressure(&self) -> bool {
+        T::backpressure(self)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> errors::Result<u32> {
+        T::flush(&mut *self)
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        T::pending(self)
+    }
 }
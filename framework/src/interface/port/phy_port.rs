@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 use super::super::{PacketRx, PacketTx};
-use super::PortStats;
+use super::{rx_stopped, PortStats};
 use allocators::*;
 use common::errors;
 use common::errors::ErrorKind;
@@ -8,17 +8,21 @@ use config::{DriverType, PortConfiguration, NUM_RXD, NUM_TXD};
 use eui48::MacAddress;
 use interface::port::fdir::FlowSteeringMode;
 use interface::PortType::Physical;
+use interface::{needs_tx_fallback, tx_fallback, Offloads};
 use ipnet::Ipv4Net;
 use libc::if_indextoname;
 use native::zcsi::rte_ethdev_api::{
-    rte_eth_dev_info, rte_eth_dev_info_get, rte_eth_dev_rx_offload_name, rte_eth_dev_tx_offload_name,
-    rte_eth_macaddr_get, rte_eth_rx_mq_mode_ETH_MQ_RX_NONE, rte_eth_rx_mq_mode_ETH_MQ_RX_RSS, rte_ether_addr, rte_flow,
+    rte_eth_dev_info, rte_eth_dev_info_get, rte_eth_dev_rx_offload_name, rte_eth_dev_socket_id,
+    rte_eth_dev_tx_offload_name, rte_eth_macaddr_get, rte_eth_rx_mq_mode_ETH_MQ_RX_NONE,
+    rte_eth_rx_mq_mode_ETH_MQ_RX_RSS, rte_eth_xstat, rte_eth_xstat_name, rte_eth_xstats_get, rte_eth_xstats_get_names,
+    rte_ether_addr, rte_flow, DEV_TX_OFFLOAD_IPV4_CKSUM, DEV_TX_OFFLOAD_TCP_CKSUM, DEV_TX_OFFLOAD_UDP_CKSUM,
 };
 use native::zcsi::rte_ethdev_api::{RTE_ETH_FLOW_MAX, RTE_ETH_FLOW_UNKNOWN};
 use native::zcsi::{
     add_tcp_flow, attach_device, eth_rx_burst, eth_rx_queue_count, eth_tx_burst, eth_tx_prepare, init_bess_eth_ring,
-    init_ovs_eth_ring, init_pmd_port, kni_alloc, kni_get_name, max_rxqs, max_txqs, num_pmd_ports, rss_flow_name,
-    rte_kni_rx_burst, rte_kni_tx_burst, KniPortParams, MBuf, RteFdirConf, RteFlowError, RteKni,
+    init_ovs_eth_ring, init_pmd_port, kni_alloc, kni_get_name, max_rxqs, max_txqs, mbuf_free, mbuf_free_bulk,
+    num_pmd_ports, rss_flow_name, rte_kni_rx_burst, rte_kni_tx_burst, KniPortParams, MBuf, RteFdirConf, RteFlowError,
+    RteKni,
 };
 use regex::Regex;
 use std::arch::x86_64::_rdtsc;
@@ -34,7 +38,7 @@ use std::ptr::Unique;
 use std::rc::Rc;
 use std::string::ToString;
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use utils::FiveTupleV4;
 
 /// A DPDK based PMD port. Send and receive should not be called directly on this structure but on the port queue
@@ -64,7 +68,7 @@ impl fmt::Display for PortType {
     }
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug, PartialEq)]
 pub struct NetSpec {
     pub mac: Option<MacAddress>,
     pub ip_net: Option<Ipv4Net>,
@@ -77,6 +81,10 @@ pub struct PmdPort {
     kni_name: Option<String>,
     port_type: PortType,
     csumoffload: bool,
+    // rx and tx offload capabilities of the device
+    offload_capa: Offloads,
+    // offloads enabled on the port
+    offloads: Offloads,
     port: u16,
     // id of an associated port, if any
     associated_dpdk_port_id: Option<u16>,
@@ -95,7 +103,9 @@ pub struct PmdPort {
     stats_tx: Vec<Arc<CacheAligned<PortStats>>>,
     fdir_conf: Option<RteFdirConf>,
     flow_steering_mode: Option<FlowSteeringMode>,
-    net_spec: Option<NetSpec>,
+    // may be changed by a configuration reload while the port is in use
+    net_spec: RwLock<Option<NetSpec>>,
+    tx_buffer: TxBufferConf,
 }
 
 impl fmt::Display for PmdPort {
@@ -115,6 +125,8 @@ impl Default for PmdPort {
             kni_name: None,
             port_type: PortType::Null,
             csumoffload: false,
+            offload_capa: Offloads::default(),
+            offloads: Offloads::default(),
             port: 0,
             associated_dpdk_port_id: None,
             //must use Unique because raw ptr does not implement Send
@@ -132,7 +144,8 @@ impl Default for PmdPort {
             stats_tx: vec![Arc::new(PortStats::new())],
             fdir_conf: None,
             flow_steering_mode: None,
-            net_spec: None,
+            net_spec: RwLock::new(None),
+            tx_buffer: TxBufferConf::default(),
         }
     }
 }
@@ -173,6 +186,50 @@ impl Hash for CacheAligned<PortQueue> {
     }
 }
 
+/// Limit for the packets buffered by a `PortQueueTxBuffered` when the NIC does not accept them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxBufferLimit {
+    Unbounded,
+    /// maximum number of buffered packets
+    Packets(usize),
+    /// maximum number of buffered bytes (sum of pkt_len)
+    Bytes(usize),
+}
+
+/// Which packets are dropped when the tx buffer is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxDropPolicy {
+    /// drop the fresh packets which do not fit into the buffer anymore
+    TailDrop,
+    /// drop the oldest buffered packets to make room for the fresh ones
+    HeadDrop,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TxBufferConf {
+    pub limit: TxBufferLimit,
+    pub policy: TxDropPolicy,
+}
+
+impl Default for TxBufferConf {
+    fn default() -> TxBufferConf {
+        TxBufferConf {
+            limit: TxBufferLimit::Unbounded,
+            policy: TxDropPolicy::TailDrop,
+        }
+    }
+}
+
+impl fmt::Display for TxBufferConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.limit {
+            TxBufferLimit::Unbounded => write!(f, "unbounded"),
+            TxBufferLimit::Packets(n) => write!(f, "{} packets, {:?}", n, self.policy),
+            TxBufferLimit::Bytes(n) => write!(f, "{} bytes, {:?}", n, self.policy),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct PortQueueTxBuffered {
     pub port_queue: PortQueue,
@@ -184,19 +241,46 @@ struct TxQueue {
     tx_buffer: VecDeque<Vec<*mut MBuf>>,
     ///total no of MBufs in the queue
     tx_queue_len: usize,
+    ///fill level of the queue in the unit of the limit (packets or bytes)
+    tx_queue_fill: usize,
+    limit: usize,
+    conf: TxBufferConf,
 }
 
 impl TxQueue {
-    fn with_capacity(capacity: usize) -> TxQueue {
+    fn with_capacity(capacity: usize, conf: TxBufferConf) -> TxQueue {
         TxQueue {
             tx_buffer: VecDeque::with_capacity(capacity),
             tx_queue_len: 0,
+            tx_queue_fill: 0,
+            limit: match conf.limit {
+                TxBufferLimit::Unbounded => usize::MAX,
+                TxBufferLimit::Packets(n) | TxBufferLimit::Bytes(n) => n,
+            },
+            conf,
+        }
+    }
+
+    #[inline]
+    fn size_of(&self, pkt: *mut MBuf) -> usize {
+        match self.conf.limit {
+            TxBufferLimit::Bytes(_) => unsafe { (*pkt).pkt_len() },
+            _ => 1,
+        }
+    }
+
+    #[inline]
+    fn size_of_batch(&self, pkts: &[*mut MBuf]) -> usize {
+        match self.conf.limit {
+            TxBufferLimit::Bytes(_) => pkts.iter().map(|p| self.size_of(*p)).sum(),
+            _ => pkts.len(),
         }
     }
 
     #[inline]
     fn push_back(&mut self, pkts: Vec<*mut MBuf>) {
         let len = pkts.len();
+        self.tx_queue_fill += self.size_of_batch(&pkts);
         self.tx_buffer.push_back(pkts);
         self.tx_queue_len += len;
     }
@@ -204,6 +288,7 @@ impl TxQueue {
     #[inline]
     fn push_front(&mut self, pkts: Vec<*mut MBuf>) {
         let len = pkts.len();
+        self.tx_queue_fill += self.size_of_batch(&pkts);
         self.tx_buffer.push_front(pkts);
         self.tx_queue_len += len;
     }
@@ -211,12 +296,63 @@ impl TxQueue {
     #[inline]
     fn pop_front(&mut self) -> Option<Vec<*mut MBuf>> {
         let r = self.tx_buffer.pop_front();
-        if r.is_some() {
-            self.tx_queue_len -= r.as_ref().unwrap().len();
+        if let Some(ref pkts) = r {
+            self.tx_queue_len -= pkts.len();
+            self.tx_queue_fill -= self.size_of_batch(pkts);
         }
         r
     }
 
+    /// Appends `pkts` to the queue and applies the drop policy if the limit is exceeded.
+    /// Packets which are dropped are moved to `dropped`, returns the number of fresh packets kept in the queue.
+    fn enqueue(&mut self, pkts: &[*mut MBuf], dropped: &mut Vec<*mut MBuf>) -> usize {
+        match self.conf.policy {
+            TxDropPolicy::TailDrop => {
+                let mut fill = self.tx_queue_fill;
+                let mut accepted = 0;
+                for pkt in pkts {
+                    let size = self.size_of(*pkt);
+                    if fill + size > self.limit {
+                        break;
+                    }
+                    fill += size;
+                    accepted += 1;
+                }
+                if accepted > 0 {
+                    self.push_back(pkts[..accepted].to_vec());
+                }
+                dropped.extend_from_slice(&pkts[accepted..]);
+                accepted
+            }
+            TxDropPolicy::HeadDrop => {
+                let buffered = self.tx_queue_len;
+                let n_dropped = dropped.len();
+                self.push_back(pkts.to_vec());
+                while self.tx_queue_fill > self.limit {
+                    let mut oldest = self.pop_front().unwrap();
+                    let mut n = 0;
+                    let mut fill = self.tx_queue_fill + self.size_of_batch(&oldest);
+                    while n < oldest.len() && fill > self.limit {
+                        fill -= self.size_of(oldest[n]);
+                        n += 1;
+                    }
+                    dropped.extend(oldest.drain(..n));
+                    if !oldest.is_empty() {
+                        self.push_front(oldest);
+                    }
+                }
+                // the drops are taken from the head, fresh packets are only lost once all older ones are gone
+                pkts.len() - (dropped.len() - n_dropped).saturating_sub(buffered)
+            }
+        }
+    }
+
+    /// True when the queue is filled to 7/8 of its limit, upstream should stop producing packets.
+    #[inline]
+    fn is_congested(&self) -> bool {
+        self.tx_queue_fill >= self.limit - self.limit / 8
+    }
+
     #[inline]
     fn len(&self) -> usize {
         self.tx_queue_len
@@ -263,25 +399,107 @@ impl fmt::Display for PortQueue {
 
 /// Represents a single RX/TX queue pair for a port. This is what is needed to send or receive traffic.
 impl PortQueue {
+    /// Sends `to_send` packets of `pkts` and returns the number of packets of `pkts` which the caller no longer owns.
+    /// These are followed by the packets which could not be sent. If a packet segmented in software could only be
+    /// sent in part, it no longer belongs to the caller, its unsent segments are appended to `segments`.
     #[inline]
-    fn try_send(&mut self, pkts: &mut [*mut MBuf], to_send: u32) -> u32 {
-        let sent = if self.port.is_native_kni() {
-            unsafe { rte_kni_tx_burst(self.port.kni.unwrap().as_ptr(), pkts.as_mut_ptr(), to_send) }
+    fn try_send(&self, pkts: &mut [*mut MBuf], to_send: u32, segments: &mut Vec<*mut MBuf>) -> u32 {
+        let tx_offloads = self.port.offloads.tx;
+        if pkts[..to_send as usize]
+            .iter()
+            .any(|p| needs_tx_fallback(unsafe { &**p }, tx_offloads))
+        {
+            self.send_with_fallback(pkts, to_send, segments)
         } else {
-            if self.csum_offload() {
-                let nb_prep = unsafe { eth_tx_prepare(self.port_id, self.txq, pkts.as_mut_ptr(), to_send as u16) };
-                assert_eq!(nb_prep, to_send as u16);
+            self.tx_burst(pkts, to_send)
+        }
+    }
+
+    /// Hands the packets to the NIC and counts the sent ones. A packet rejected by the tx preparation of the NIC
+    /// is dropped, it is included in the returned number of packets which the caller no longer owns.
+    #[inline]
+    fn tx_burst(&self, pkts: &mut [*mut MBuf], to_send: u32) -> u32 {
+        let (sent, consumed) = if self.port.is_native_kni() {
+            let sent = unsafe { rte_kni_tx_burst(self.port.kni.unwrap().as_ptr(), pkts.as_mut_ptr(), to_send) };
+            (sent, sent)
+        } else {
+            let to_burst = if self.port.offloads.tx != 0 {
+                unsafe { eth_tx_prepare(self.port_id, self.txq, pkts.as_mut_ptr(), to_send as u16) as u32 }
+            } else {
+                to_send
+            };
+            let sent = unsafe { eth_tx_burst(self.port_id, self.txq, pkts.as_mut_ptr(), to_burst as u16) as u32 };
+            if sent == to_burst && to_burst < to_send {
+                warn!("port {}: dropping packet, rejected by tx prepare", self.port_id);
+                self.discard(&mut pkts[to_burst as usize..to_burst as usize + 1]);
+                (sent, sent + 1)
+            } else {
+                (sent, sent)
             }
-            unsafe { eth_tx_burst(self.port_id, self.txq, pkts.as_mut_ptr(), to_send as u16) as u32 }
         };
         let update = self.stats_tx.stats.load(Ordering::Relaxed) + sent as usize;
         self.stats_tx.stats.store(update, Ordering::Relaxed);
-        sent
+        consumed
+    }
+
+    /// Frees packets which cannot be sent and counts them as dropped.
+    fn discard(&self, pkts: &mut [*mut MBuf]) {
+        let update = self.stats_tx.dropped.load(Ordering::Relaxed) + pkts.len();
+        self.stats_tx.dropped.store(update, Ordering::Relaxed);
+        unsafe { mbuf_free_bulk(pkts.as_mut_ptr(), pkts.len() as i32) };
+    }
+
+    /// Send packets which request tx offloads not enabled on the port, doing these offloads in software. Packets for
+    /// which the software offload fails are dropped. Returns the number of packets of `pkts` which the caller no
+    /// longer owns, the unsent segments of a packet segmented in software which was sent in part are appended to
+    /// `segments`.
+    fn send_with_fallback(&self, pkts: &mut [*mut MBuf], to_send: u32, segments: &mut Vec<*mut MBuf>) -> u32 {
+        let tx_offloads = self.port.offloads.tx;
+        let mut burst = Vec::with_capacity(to_send as usize);
+        // for each packet the range of its entries in burst and whether these are copies
+        let mut entries = Vec::with_capacity(to_send as usize);
+        for &pkt in &pkts[..to_send as usize] {
+            let start = burst.len();
+            if let Err(e) = unsafe { tx_fallback(pkt, tx_offloads, &mut burst) } {
+                warn!("port {}: dropping packet, tx offload failed: {}", self.port_id, e);
+            }
+            let copied = burst.len() != start + 1 || burst[start] != pkt;
+            entries.push((start..burst.len(), copied));
+        }
+        let n = if burst.is_empty() {
+            0
+        } else {
+            let len = burst.len() as u32;
+            self.tx_burst(&mut burst, len) as usize
+        };
+        let mut consumed = 0;
+        for (i, (range, copied)) in entries.into_iter().enumerate() {
+            if range.start < n || range.end == n {
+                // at least partly sent, or dropped before the first unsent packet
+                if range.end > n {
+                    segments.extend_from_slice(&burst[n..range.end]);
+                }
+                if copied {
+                    unsafe { mbuf_free(pkts[i]) };
+                }
+                consumed += 1;
+            } else if copied && !range.is_empty() {
+                // the segments are created anew on the next attempt
+                let mut unsent = burst[range].to_vec();
+                unsafe { mbuf_free_bulk(unsent.as_mut_ptr(), unsent.len() as i32) };
+            }
+        }
+        consumed
     }
 
     #[inline]
     fn send_queue(&mut self, pkts: &mut [*mut MBuf], to_send: u32) -> errors::Result<u32> {
-        let sent = self.try_send(pkts, to_send);
+        let mut segments = Vec::new();
+        let sent = self.try_send(pkts, to_send, &mut segments);
+        // without a tx buffer the rest of a packet sent in part is lost
+        if !segments.is_empty() {
+            self.discard(&mut segments);
+        }
         Ok(sent)
     }
 
@@ -362,6 +580,9 @@ impl PacketRx for PortQueue {
     /// called).
     #[inline]
     fn recv(&self, pkts: &mut [*mut MBuf]) -> errors::Result<(u32, i32)> {
+        if rx_stopped() {
+            return Ok((0, 0));
+        }
         let len = pkts.len() as u16;
         Ok((self.recv_queue(pkts, len)?, self.stats_rx.get_q_len() as i32))
     }
@@ -396,13 +617,22 @@ fn i32_from_bool(x: bool) -> i32 {
 }
 
 impl PortQueueTxBuffered {
-    fn queue(&mut self, pkts: &mut [*mut MBuf]) {
-        let len = pkts.len();
-        let mut pkt_vec = Vec::with_capacity(len);
-        pkt_vec.extend_from_slice(pkts);
-        self.tx_queue.borrow_mut().push_back(pkt_vec);
+    fn queue(&self, pkts: &mut [*mut MBuf]) {
+        if pkts.is_empty() {
+            return;
+        }
+        let mut dropped = Vec::new();
+        let len = self.tx_queue.borrow_mut().enqueue(pkts, &mut dropped);
         let update = self.port_queue.stats_tx.queued.load(Ordering::Relaxed) + len;
         self.port_queue.stats_tx.queued.store(update, Ordering::Relaxed);
+        if !dropped.is_empty() {
+            let update = self.port_queue.stats_tx.dropped.load(Ordering::Relaxed) + dropped.len();
+            self.port_queue.stats_tx.dropped.store(update, Ordering::Relaxed);
+            unsafe {
+                mbuf_free_bulk(dropped.as_mut_ptr(), dropped.len() as i32);
+            }
+            trace!("txq={}: dropped {} packets", self.port_queue.txq, dropped.len());
+        }
         trace!("qlen= {}", self.tx_queue_len());
     }
 
@@ -421,13 +651,21 @@ impl PortQueueTxBuffered {
         RefCell::borrow(&self.tx_queue).is_empty()
     }
 
+    /// Limit and drop policy of the tx buffer.
     #[inline]
-    fn send_queue(&mut self, pkts: &mut [*mut MBuf], to_send: u32) -> errors::Result<u32> {
+    pub fn tx_buffer_conf(&self) -> TxBufferConf {
+        RefCell::borrow(&self.tx_queue).conf
+    }
+
+    #[inline]
+    fn send_queue(&self, pkts: &mut [*mut MBuf], to_send: u32) -> errors::Result<u32> {
         let stamp = unsafe { _rdtsc() };
         if self.tx_queue_is_empty() {
-            let sent = self.port_queue.try_send(pkts, to_send);
-            if sent < to_send {
-                self.queue(&mut pkts[sent as usize..to_send as usize]);
+            let mut unsent = Vec::new();
+            let sent = self.port_queue.try_send(pkts, to_send, &mut unsent);
+            if sent < to_send || !unsent.is_empty() {
+                unsent.extend_from_slice(&pkts[sent as usize..to_send as usize]);
+                self.queue(&mut unsent);
                 trace!(
                     "txq={}, {}: sent {} of {} fresh packets, queued remaining, tx q len = {}, batches = {}",
                     self.port_queue.txq,
@@ -444,7 +682,8 @@ impl PortQueueTxBuffered {
                 //let tx_q_len= self.tx_queue_len();
                 let mut queued_batch = self.tx_queue.borrow_mut().pop_front().unwrap();
                 let len = queued_batch.len();
-                let sent = self.port_queue.try_send(&mut queued_batch[..], len as u32) as usize;
+                let mut unsent = Vec::new();
+                let sent = self.port_queue.try_send(&mut queued_batch[..], len as u32, &mut unsent) as usize;
                 trace!(
                     "txq={}, {}: sent {} of {} queued packets, tx q len = {}, batches= {}",
                     self.port_queue.txq,
@@ -455,10 +694,10 @@ impl PortQueueTxBuffered {
                     self.tx_batches()
                 );
                 //assert!(sent <= tx_q_len);
-                if sent < len {
-                    let mut pkt_vec = Vec::with_capacity(len - sent);
-                    pkt_vec.extend_from_slice(&queued_batch[sent..len]);
-                    self.tx_queue.borrow_mut().push_front(pkt_vec);
+                if sent < len || !unsent.is_empty() {
+                    // the unsent segments of a packet sent in part go first
+                    unsent.extend_from_slice(&queued_batch[sent..len]);
+                    self.tx_queue.borrow_mut().push_front(unsent);
                     self.queue(&mut pkts[0..to_send as usize]);
                     trace!(
                         "txq={}, {}: queuing full fresh {} packets, tx q len= {}, batches= {}",
@@ -471,9 +710,11 @@ impl PortQueueTxBuffered {
                     break;
                 }
                 if self.tx_queue_is_empty() {
-                    let sent = self.port_queue.try_send(pkts, to_send);
-                    if sent < to_send {
-                        self.queue(&mut pkts[sent as usize..to_send as usize]);
+                    let mut unsent = Vec::new();
+                    let sent = self.port_queue.try_send(pkts, to_send, &mut unsent);
+                    if sent < to_send || !unsent.is_empty() {
+                        unsent.extend_from_slice(&pkts[sent as usize..to_send as usize]);
+                        self.queue(&mut unsent);
                         trace!(
                             "txq={}, {}: queuing remaining fresh {} packets, tx q len= {}, batches= {}",
                             self.port_queue.txq,
@@ -510,6 +751,32 @@ impl PacketTx for PortQueueTxBuffered {
         let len = pkts.len();
         self.send_queue(pkts, len as u32)
     }
+
+    /// A congested tx buffer is flushed before the signal is given, so that it drains while upstream, e.g. a
+    /// `MergeBatchAuto`, holds back and does not send.
+    #[inline]
+    fn backpressure(&self) -> bool {
+        if RefCell::borrow(&self.tx_queue).is_congested() {
+            // sending from the buffer does not fail, packets which cannot be sent stay in it
+            let _ = self.send_queue(&mut [], 0);
+        }
+        RefCell::borrow(&self.tx_queue).is_congested()
+    }
+
+    /// Try to send the packets in the tx buffer.
+    #[inline]
+    fn flush(&mut self) -> errors::Result<u32> {
+        if self.tx_queue_is_empty() {
+            Ok(0)
+        } else {
+            self.send_queue(&mut [], 0)
+        }
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.tx_queue_len()
+    }
 }
 
 impl PacketRx for PortQueueTxBuffered {
@@ -552,6 +819,14 @@ impl PmdPort {
         self.port
     }
 
+    /// The NUMA node of the device, `None` for virtual devices and if the node is not known.
+    pub fn numa_node(&self) -> Option<i32> {
+        match unsafe { rte_eth_dev_socket_id(self.port) } {
+            node if node >= 0 => Some(node),
+            _ => None,
+        }
+    }
+
     #[inline]
     pub fn name(&self) -> &String {
         &self.name
@@ -583,22 +858,24 @@ impl PmdPort {
     }
 
     #[inline]
-    pub fn net_spec(&self) -> &Option<NetSpec> {
-        &self.net_spec
+    pub fn net_spec(&self) -> Option<NetSpec> {
+        self.net_spec.read().unwrap().clone()
+    }
+
+    /// Change the addresses of the port, e.g. on a configuration reload. Operators which were created with the
+    /// previous addresses keep using them.
+    pub fn set_net_spec(&self, net_spec: Option<NetSpec>) {
+        *self.net_spec.write().unwrap() = net_spec;
     }
 
     #[inline]
     pub fn ip_addr(&self) -> Option<Ipv4Addr> {
-        if self.net_spec.is_some() {
-            let spec = self.net_spec.as_ref().unwrap();
-            if spec.ip_net.is_some() {
-                Some(spec.ip_net.unwrap().addr())
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+        self.net_spec
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|spec| spec.ip_net)
+            .map(|ip_net| ip_net.addr())
     }
 
     #[inline]
@@ -618,11 +895,42 @@ impl PmdPort {
         self.driver
     }
 
+    /// Limit and drop policy for the tx buffers of `PortQueueTxBuffered` queues of this port.
+    #[inline]
+    pub fn tx_buffer(&self) -> TxBufferConf {
+        self.tx_buffer
+    }
+
     #[inline]
     pub fn csum_offload(&self) -> bool {
         self.csumoffload
     }
 
+    /// Rx and tx offloads the device supports.
+    #[inline]
+    pub fn offload_capabilities(&self) -> Offloads {
+        self.offload_capa
+    }
+
+    /// Rx and tx offloads enabled on this port: the requested offloads which the device supports. Packets requesting
+    /// other tx offloads are completed in software when sent.
+    #[inline]
+    pub fn offloads(&self) -> Offloads {
+        self.offloads
+    }
+
+    /// Query the rx and tx offload capabilities of the device `port`.
+    pub fn query_offload_capabilities(port: u16) -> Offloads {
+        let mut dev_info = rte_eth_dev_info::new_null();
+        unsafe {
+            rte_eth_dev_info_get(port, &mut dev_info as *mut rte_eth_dev_info);
+        }
+        Offloads {
+            rx: dev_info.rx_offload_capa,
+            tx: dev_info.tx_offload_capa,
+        }
+    }
+
     #[inline]
     pub fn get_tcp_dst_port_mask(&self) -> u16 {
         if self.fdir_conf.is_some() {
@@ -661,6 +969,15 @@ impl PmdPort {
         self.kni.unwrap().as_ptr()
     }
 
+    /// Queues are placed on the NUMA node of their port, so they are local to cores which poll the port from the
+    /// same socket, see `check_numa_placement`.
+    fn allocate_queue<T>(&self, queue: T) -> CacheAligned<T> {
+        match self.numa_node() {
+            Some(node) => CacheAligned::allocate_on_node(queue, node),
+            None => CacheAligned::allocate(queue),
+        }
+    }
+
     pub fn new_queue_pair(port: &Arc<PmdPort>, rxq: u16, txq: u16) -> errors::Result<CacheAligned<PortQueue>> {
         if rxq > port.rxqs {
             Err(ErrorKind::BadRxQueue(port.port, rxq).into())
@@ -671,7 +988,7 @@ impl PmdPort {
                 "allocating PortQueue type= {}, port_id= {}, rxq= {}, txq= {}",
                 port.port_type, port.port, rxq, txq
             );
-            Ok(CacheAligned::allocate(PortQueue {
+            Ok(port.allocate_queue(PortQueue {
                 port: port.clone(),
                 port_id: port.port,
                 txq,
@@ -696,7 +1013,7 @@ impl PmdPort {
                 "allocating PortQueueTxBuffered port_id= {}, rxq= {}, txq= {}",
                 port.port, rxq, txq
             );
-            Ok(CacheAligned::allocate(PortQueueTxBuffered {
+            Ok(port.allocate_queue(PortQueueTxBuffered {
                 port_queue: PortQueue {
                     port: port.clone(),
                     port_id: port.port,
@@ -705,7 +1022,7 @@ impl PmdPort {
                     stats_rx: port.stats_rx[rxq as usize].clone(),
                     stats_tx: port.stats_tx[txq as usize].clone(),
                 },
-                tx_queue: Rc::new(RefCell::new(TxQueue::with_capacity(4096))),
+                tx_queue: Rc::new(RefCell::new(TxQueue::with_capacity(4096, port.tx_buffer))),
             }))
         }
     }
@@ -772,6 +1089,42 @@ impl PmdPort {
         }
     }
 
+    /// Get the `PortStats` of rx queue `queue`, which is below `rxqs()`.
+    pub fn rx_queue_stats(&self, queue: u16) -> Arc<CacheAligned<PortStats>> {
+        self.stats_rx[queue as usize].clone()
+    }
+
+    /// Get the `PortStats` of tx queue `queue`, which is below `txqs()`.
+    pub fn tx_queue_stats(&self, queue: u16) -> Arc<CacheAligned<PortStats>> {
+        self.stats_tx[queue as usize].clone()
+    }
+
+    /// Read the extended statistics of the DPDK device as (name, value) pairs.
+    pub fn xstats(&self) -> errors::Result<Vec<(String, u64)>> {
+        let n = unsafe { rte_eth_xstats_get_names(self.port, ptr::null_mut(), 0) };
+        if n < 0 {
+            return Err(ErrorKind::RunTimeError(format!(
+                "cannot read xstats names of port {}, error {}",
+                self.port, n
+            )));
+        }
+        let mut names = vec![rte_eth_xstat_name { name: [0; 64] }; n as usize];
+        let mut values = vec![rte_eth_xstat { id: 0, value: 0 }; n as usize];
+        let n_names = unsafe { rte_eth_xstats_get_names(self.port, names.as_mut_ptr(), n as u32) };
+        let n_values = unsafe { rte_eth_xstats_get(self.port, values.as_mut_ptr(), n as u32) };
+        if n_names < 0 || n_values < 0 || n_names > n || n_values > n {
+            return Err(ErrorKind::RunTimeError(format!(
+                "cannot read xstats of port {}",
+                self.port
+            )));
+        }
+        Ok(values[..n_values as usize]
+            .iter()
+            .filter(|x| (x.id as i32) < n_names)
+            .map(|x| (names[x.id as usize].to_str().unwrap_or("invalid").to_string(), x.value))
+            .collect())
+    }
+
     pub fn print_soft_statistics(&self) {
         println!(
             "{0:>3} | {1: >20} | {2: >20} | {3: >20} | {4: >20} | {5: >20} | {6: >20} |",
@@ -892,18 +1245,28 @@ impl PmdPort {
         nrxd: u16,
         ntxd: u16,
         loopback: bool,
-        tso: bool,
-        csumoffload: bool,
+        offloads: Offloads,
         driver: DriverType,
         port_type: PortType,
         fdir_conf: Option<&RteFdirConf>,
         flow_steering_mode: Option<FlowSteeringMode>,
         net_spec: Option<NetSpec>,
         associated_dpdk_port_id: Option<u16>,
+        tx_buffer: TxBufferConf,
     ) -> errors::Result<Arc<PmdPort>> {
         let loopbackv = i32_from_bool(loopback);
-        let tsov = i32_from_bool(tso);
-        let csumoffloadv = i32_from_bool(csumoffload);
+        let offload_capa = PmdPort::query_offload_capabilities(port);
+        let enabled = offloads.negotiate(&offload_capa);
+        if enabled != offloads {
+            info!(
+                "port {}: offloads rx= {:#x} tx= {:#x} not supported by the device, using software fallback",
+                port,
+                offloads.rx & !enabled.rx,
+                offloads.tx & !enabled.tx,
+            );
+        }
+        let csumoffload =
+            enabled.has_tx(DEV_TX_OFFLOAD_IPV4_CKSUM | DEV_TX_OFFLOAD_UDP_CKSUM | DEV_TX_OFFLOAD_TCP_CKSUM);
         let max_txqs = unsafe { max_txqs(port) };
         let max_rxqs = unsafe { max_rxqs(port) };
         assert!(max_rxqs >= 0);
@@ -935,8 +1298,8 @@ impl PmdPort {
                     nrxd,
                     ntxd,
                     loopbackv,
-                    tsov,
-                    csumoffloadv,
+                    enabled.rx,
+                    enabled.tx,
                     rx_mq_mode,
                     if fdir_conf.is_some() {
                         fdir_conf.unwrap() as *const RteFdirConf
@@ -960,6 +1323,8 @@ impl PmdPort {
                     n_rx_desc: nrxd,
                     n_tx_desc: ntxd,
                     csumoffload,
+                    offload_capa,
+                    offloads: enabled,
                     driver,
                     stats_rx: (0..actual_rxqs).map(|_| Arc::new(PortStats::new())).collect(),
                     stats_tx: (0..actual_txqs).map(|_| Arc::new(PortStats::new())).collect(),
@@ -969,8 +1334,9 @@ impl PmdPort {
                         None
                     },
                     flow_steering_mode,
-                    net_spec,
+                    net_spec: RwLock::new(net_spec),
                     associated_dpdk_port_id,
+                    tx_buffer,
                 }))
             } else {
                 Err(ErrorKind::FailedToInitializePort(port).into())
@@ -1029,6 +1395,7 @@ impl PmdPort {
         rx_cores: &[i32],
         tx_cores: &[i32],
         net_spec: Option<NetSpec>,
+        tx_buffer: TxBufferConf,
     ) -> errors::Result<Arc<PmdPort>> {
         let associated_dpdk_port_id = kni_port_params.associated_dpdk_port_id;
         let p_kni_port_params: *mut KniPortParams = Box::into_raw(kni_port_params);
@@ -1049,8 +1416,9 @@ impl PmdPort {
                     stats_tx: (0..tx_cores.len()).map(|_| Arc::new(PortStats::new())).collect(),
                     rxqs: rx_cores.len() as u16,
                     txqs: tx_cores.len() as u16,
-                    net_spec,
+                    net_spec: RwLock::new(net_spec),
                     associated_dpdk_port_id: Some(associated_dpdk_port_id),
+                    tx_buffer,
                     ..Default::default()
                 }))
             } else {
@@ -1069,14 +1437,14 @@ impl PmdPort {
         nrxd: u16,
         ntxd: u16,
         loopback: bool,
-        tso: bool,
-        csumoffload: bool,
+        offloads: Offloads,
         driver: DriverType,
         port_type: PortType,
         fdir_conf: Option<&RteFdirConf>,
         flow_steering_mode: Option<FlowSteeringMode>,
         net_spec: Option<NetSpec>,
         associated_dpdk_port_id: Option<u16>,
+        tx_buffer: TxBufferConf,
     ) -> errors::Result<Arc<PmdPort>> {
         let cannonical_spec = PmdPort::cannonicalize_pci(spec);
         debug!("attach_pmd_device, port = {:?}", cannonical_spec);
@@ -1104,26 +1472,27 @@ impl PmdPort {
                 nrxd,
                 ntxd,
                 loopback,
-                tso,
-                csumoffload,
+                offloads,
                 driver,
                 port_type,
                 fdir_conf,
                 flow_steering_mode,
                 net_spec,
                 associated_dpdk_port_id,
+                tx_buffer,
             )
         } else {
             Err(ErrorKind::BadDev(String::from(spec)).into())
         }
     }
 
-    fn null_port() -> errors::Result<Arc<PmdPort>> {
+    fn null_port(tx_buffer: TxBufferConf) -> errors::Result<Arc<PmdPort>> {
         Ok(Arc::new(PmdPort {
             name: String::from("NullPort"),
             kni_name: None,
             port_type: PortType::Null,
             port: 0,
+            tx_buffer,
             ..Default::default()
         }))
     }
@@ -1147,8 +1516,7 @@ impl PmdPort {
         let nrxd = port_config.rxd;
         let ntxd = port_config.txd;
         let loopback = port_config.loopback;
-        let tso = port_config.tso;
-        let csumoffload = port_config.csum;
+        let offloads = Offloads::requested(port_config.tso, port_config.csum, port_config.vlan_insert);
         let driver = port_config.driver;
         let fdir_conf = port_config.fdir_conf.as_ref();
         let kni = port_config.kni.clone();
@@ -1236,14 +1604,14 @@ impl PmdPort {
                     nrxd,
                     ntxd,
                     loopback,
-                    tso,
-                    csumoffload,
+                    offloads,
                     driver,
                     port_type,
                     fdir_conf,
                     port_config.flow_steering,
                     port_config.net_spec.clone(),
                     associated_port.map_or(None, |p| Some(p.port_id())),
+                    port_config.tx_buffer,
                 )
             }
             "kni" => {
@@ -1266,10 +1634,11 @@ impl PmdPort {
                         rx_cores,
                         tx_cores,
                         port_config.net_spec.clone(),
+                        port_config.tx_buffer,
                     )
                 }
             }
-            "null" => PmdPort::null_port(),
+            "null" => PmdPort::null_port(port_config.tx_buffer),
             _ => PmdPort::new_dpdk_port(
                 name,
                 kni,
@@ -1280,14 +1649,14 @@ impl PmdPort {
                 nrxd,
                 ntxd,
                 loopback,
-                tso,
-                csumoffload,
+                offloads,
                 driver,
                 PortType::Physical,
                 fdir_conf,
                 port_config.flow_steering,
                 None,
                 associated_port.map_or(None, |p| Some(p.port_id())),
+                port_config.tx_buffer,
             ),
         }
     }
@@ -1308,12 +1677,14 @@ impl PmdPort {
             loopback: false,
             tso: false,
             csum: false,
+            vlan_insert: false,
             k_cores: vec![],
             fdir_conf: None,
             flow_steering: None,
             kni: None,
             driver: DriverType::Unknown,
             net_spec: None,
+            tx_buffer: TxBufferConf::default(),
         };
         PmdPort::new_port_from_configuration(&config, None)
     }
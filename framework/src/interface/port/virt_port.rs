@@ -1,5 +1,5 @@
 use super::super::{PacketRx, PacketTx};
-use super::PortStats;
+use super::{rx_stopped, PortStats};
 use allocators::*;
 use common::*;
 use native::zcsi::{mbuf_alloc_bulk, mbuf_free_bulk, MBuf};
@@ -42,6 +42,9 @@ impl PacketRx for VirtualQueue {
     /// called).
     #[inline]
     fn recv(&self, pkts: &mut [*mut MBuf]) -> errors::Result<(u32, i32)> {
+        if rx_stopped() {
+            return Ok((0, 0));
+        }
         let len = pkts.len() as i32;
         let status = unsafe { mbuf_alloc_bulk(pkts.as_mut_ptr(), len as u32) };
         let alloced = if status == 0 { len } else { 0 };
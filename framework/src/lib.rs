@@ -8,11 +8,14 @@
 #![cfg_attr(feature = "dev", deny(warnings))]
 extern crate byteorder;
 extern crate fnv;
+extern crate futures;
 extern crate ipnet;
 extern crate libc;
 extern crate net2;
 extern crate regex;
 extern crate separator;
+extern crate serde_json;
+extern crate tokio_core;
 extern crate twox_hash;
 
 #[macro_use]
@@ -50,11 +53,18 @@ pub mod common;
 pub mod config;
 pub mod control;
 pub mod headers;
+pub mod icmp;
 pub mod interface;
+pub mod metrics;
+pub mod mpls;
 pub mod native;
+pub mod neighbor;
 pub mod operators;
+pub mod pipeline;
+pub mod qos;
 pub mod queues;
 pub mod scheduler;
 pub mod shared_state;
 pub mod state;
+pub mod tcp;
 pub mod utils;
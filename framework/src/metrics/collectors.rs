@@ -0,0 +1,136 @@
+use super::{labels, MetricsRegistry, LABEL_CORE, LABEL_PORT, LABEL_QUEUE, LABEL_TASK};
+use common::errors;
+use control::SchedulerChannel;
+use futures::{future, Future, Stream};
+use interface::PmdPort;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Interval};
+use uuid::Uuid;
+
+/// Mirror the software statistics (`PortStats`) of all queues of a port into the registry.
+pub fn collect_port_stats(registry: &MetricsRegistry, port: &PmdPort) {
+    for q in 0..port.rxqs() {
+        let rx = port.rx_queue_stats(q);
+        let queue = q.to_string();
+        let l = labels(&[(LABEL_PORT, port.name()), (LABEL_QUEUE, &queue)]);
+        registry
+            .counter("e2d2_port_rx_packets_total", "Packets received on queue", l.clone())
+            .set(rx.stats.load(Ordering::Relaxed) as u64);
+        registry
+            .gauge(
+                "e2d2_port_rx_max_queue_len",
+                "Maximum observed rx queue length",
+                l.clone(),
+            )
+            .set(rx.get_max_q_len() as i64);
+        registry
+            .counter("e2d2_port_rx_cycles_total", "Cycles spent receiving on queue", l)
+            .set(rx.cycles());
+    }
+    for q in 0..port.txqs() {
+        let tx = port.tx_queue_stats(q);
+        let queue = q.to_string();
+        let l = labels(&[(LABEL_PORT, port.name()), (LABEL_QUEUE, &queue)]);
+        registry
+            .counter("e2d2_port_tx_packets_total", "Packets transmitted on queue", l.clone())
+            .set(tx.stats.load(Ordering::Relaxed) as u64);
+        registry
+            .gauge("e2d2_port_tx_queued", "Packets queued for transmission", l.clone())
+            .set(tx.queued.load(Ordering::Relaxed) as i64);
+        registry
+            .counter(
+                "e2d2_port_tx_dropped_total",
+                "Packets dropped because the tx buffer was full",
+                l.clone(),
+            )
+            .set(tx.dropped() as u64);
+        registry
+            .gauge("e2d2_port_tx_max_queue_len", "Maximum observed tx queue length", l)
+            .set(tx.get_max_q_len() as i64);
+    }
+}
+
+/// Mirror the DPDK extended statistics of a port into the registry. Each xstat becomes a series of
+/// `e2d2_port_xstat` with the label `name`.
+pub fn collect_port_xstats(registry: &MetricsRegistry, port: &PmdPort) {
+    if port.is_native_kni() {
+        return;
+    }
+    match port.xstats() {
+        Ok(xstats) => {
+            for (name, value) in xstats {
+                registry
+                    .counter(
+                        "e2d2_port_xstat",
+                        "DPDK extended device statistics",
+                        labels(&[(LABEL_PORT, port.name()), ("name", &name)]),
+                    )
+                    .set(value);
+            }
+        }
+        Err(e) => debug!("no xstats for port {}: {}", port.name(), e),
+    }
+}
+
+/// Register collectors for software statistics and xstats of `port`, they run on each scrape.
+pub fn register_port_collectors(registry: &MetricsRegistry, port: Arc<PmdPort>) {
+    registry.register_collector(move |r| {
+        collect_port_stats(r, &port);
+        collect_port_xstats(r, &port);
+    });
+}
+
+/// Update the task metrics of a core from the `SchedulerReply::PerformanceData` of its scheduler.
+pub fn collect_performance_data(registry: &MetricsRegistry, core: i32, data: &HashMap<Uuid, (String, u64, u64, u32)>) {
+    let core = core.to_string();
+    for (uuid, (name, cycles, count, queue_len)) in data {
+        let uuid = uuid.to_string();
+        let l = labels(&[(LABEL_CORE, &core), (LABEL_TASK, name), ("uuid", &uuid)]);
+        registry
+            .counter(
+                "e2d2_task_cycles_total",
+                "Cycles used by task while doing work",
+                l.clone(),
+            )
+            .set(*cycles);
+        registry
+            .counter("e2d2_task_packets_total", "Packets processed by task", l.clone())
+            .set(*count);
+        registry
+            .gauge("e2d2_task_max_queue_len", "Maximum queue length observed by task", l)
+            .set(*queue_len as i64);
+    }
+}
+
+/// Refresh the task metrics in `registry` from the performance data of all schedulers every `period`. This is a
+/// control plane agent like the `metrics_server`, spawn it on the handle of a `ControlRuntime`.
+pub fn task_metrics_collector(
+    registry: Arc<MetricsRegistry>,
+    schedulers: SchedulerChannel,
+    period: Duration,
+    handle: &Handle,
+) -> errors::Result<impl Future<Item = (), Error = ()>> {
+    Ok(Interval::new(period, handle)?
+        .map_err(|e| error!("metrics: timer failed: {}", e))
+        .for_each(move |_| {
+            let queries: Vec<_> = schedulers
+                .cores()
+                .into_iter()
+                .map(|core| {
+                    let registry = registry.clone();
+                    schedulers
+                        .query(core, |scheduler| scheduler.performance_data())
+                        .map(move |data| collect_performance_data(&registry, core, &data))
+                })
+                .collect();
+            future::join_all(queries).then(|result| {
+                if let Err(e) = result {
+                    warn!("metrics: collecting task metrics failed: {}", e);
+                }
+                Ok(())
+            })
+        }))
+}
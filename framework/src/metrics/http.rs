@@ -0,0 +1,75 @@
+use super::global_registry;
+use common::errors;
+use control::tcp::{TcpControlServer, DEFAULT_BACKLOG};
+use control::{Codec, Framed};
+use futures::future::{self, Either};
+use futures::{Future, Sink, Stream};
+use std::io;
+use std::net::SocketAddr;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Frames HTTP requests by their head, bodies are not supported. Responses are passed through as bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HttpRequestCodec;
+
+impl Codec for HttpRequestCodec {
+    type In = Vec<u8>;
+    type Out = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => Ok(Some(buf.drain(..end + 4).collect())),
+            None if buf.len() > MAX_REQUEST_SIZE => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "request head too long"))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn encode(&mut self, response: Vec<u8>, buf: &mut Vec<u8>) -> io::Result<()> {
+        buf.extend_from_slice(&response);
+        Ok(())
+    }
+}
+
+fn respond(request: &[u8]) -> Vec<u8> {
+    let (status, body) = if request.starts_with(b"GET ") {
+        ("200 OK", global_registry().render())
+    } else {
+        ("405 Method Not Allowed", String::new())
+    };
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body.as_bytes());
+    response
+}
+
+/// Serves the global metrics registry via HTTP. Any GET request is answered with the Prometheus text exposition,
+/// i.e. the path is ignored. Connections are closed after each response.
+pub fn metrics_agent(
+    address: SocketAddr,
+    connection: Framed<TcpStream, HttpRequestCodec>,
+) -> impl Future<Item = (), Error = io::Error> {
+    connection
+        .into_future()
+        .map_err(|(e, _)| e)
+        .and_then(move |(request, connection)| match request {
+            Some(request) => {
+                debug!("metrics: request from {}", address);
+                Either::A(connection.send(respond(&request)).map(|_| ()))
+            }
+            None => Either::B(future::ok(())),
+        })
+}
+
+/// A control plane agent serving metrics on `address`, spawn it on the handle of a `ControlRuntime`.
+pub fn metrics_server(address: SocketAddr, handle: &Handle) -> errors::Result<impl Future<Item = (), Error = ()>> {
+    Ok(TcpControlServer::bind(address, DEFAULT_BACKLOG, handle)?.serve(HttpRequestCodec, metrics_agent))
+}
@@ -0,0 +1,157 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+/// Number of bits used for the linear sub-buckets within each power of two range. With 7 bits the
+/// relative error of a recorded value is below 1/64.
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKET_COUNT: u64 = 1 << SUB_BUCKET_BITS;
+const SUB_BUCKET_HALF: u64 = SUB_BUCKET_COUNT / 2;
+const BUCKETS: usize = ((64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKET_HALF as usize) + SUB_BUCKET_HALF as usize;
+
+/// The latency histograms of the tasks on a core, keyed by task uuid, with the task name.
+pub type TaskLatencies = HashMap<Uuid, (String, LatencyHistogram)>;
+
+/// A log-linear histogram in the style of HdrHistogram for latencies measured in TSC cycles.
+/// It covers the full u64 range with a bounded relative error and constant memory.
+#[derive(Clone)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    count: u64,
+    min: u64,
+    max: u64,
+    sum: u128,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> LatencyHistogram {
+        LatencyHistogram::new()
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> LatencyHistogram {
+        LatencyHistogram {
+            counts: vec![0; BUCKETS],
+            count: 0,
+            min: u64::MAX,
+            max: 0,
+            sum: 0,
+        }
+    }
+
+    #[inline]
+    fn index_of(value: u64) -> usize {
+        if value < SUB_BUCKET_COUNT {
+            value as usize
+        } else {
+            let msb = 63 - value.leading_zeros();
+            let shift = msb - (SUB_BUCKET_BITS - 1);
+            (shift as u64 * SUB_BUCKET_HALF + (value >> shift)) as usize
+        }
+    }
+
+    /// The highest value which is recorded into bucket `index`.
+    #[inline]
+    fn highest_equivalent(index: usize) -> u64 {
+        let index = index as u64;
+        if index < SUB_BUCKET_COUNT {
+            index
+        } else {
+            let shift = index / SUB_BUCKET_HALF - 1;
+            let m = index - shift * SUB_BUCKET_HALF;
+            ((m + 1) << shift).wrapping_sub(1)
+        }
+    }
+
+    #[inline]
+    pub fn record(&mut self, value: u64) {
+        self.counts[LatencyHistogram::index_of(value)] += 1;
+        self.count += 1;
+        self.sum += value as u128;
+        self.min = cmp::min(self.min, value);
+        self.max = cmp::max(self.max, value);
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (c, o) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *c += *o;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = cmp::min(self.min, other.min);
+        self.max = cmp::max(self.max, other.max);
+    }
+
+    pub fn reset(&mut self) {
+        *self = LatencyHistogram::new();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.min
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// The value below or at which `percentile` (0.0 to 100.0) of all recorded values fall.
+    pub fn value_at_percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let percentile = percentile.clamp(0.0, 100.0);
+        let rank = cmp::max(1, ((percentile / 100.0) * self.count as f64).ceil() as u64);
+        let mut acc = 0u64;
+        for (i, c) in self.counts.iter().enumerate() {
+            acc += *c;
+            if acc >= rank {
+                return cmp::min(LatencyHistogram::highest_equivalent(i), self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Returns (p50, p99, p99.9).
+    pub fn percentiles(&self) -> (u64, u64, u64) {
+        (
+            self.value_at_percentile(50.0),
+            self.value_at_percentile(99.0),
+            self.value_at_percentile(99.9),
+        )
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (p50, p99, p999) = self.percentiles();
+        write!(
+            f,
+            "count= {}, min= {}, mean= {:.1}, p50= {}, p99= {}, p99.9= {}, max= {} (cycles)",
+            self.count(),
+            self.min(),
+            self.mean(),
+            p50,
+            p99,
+            p999,
+            self.max()
+        )
+    }
+}
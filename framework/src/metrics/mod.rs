@@ -0,0 +1,278 @@
+//! A registry for counters, gauges and histograms which can be rendered in the Prometheus text exposition format.
+//! Metric handles are atomics shared by `Arc`, so data plane tasks can update them without taking the registry lock.
+pub use self::collectors::*;
+pub use self::http::*;
+pub use self::latency::{LatencyHistogram, TaskLatencies};
+pub use self::prometheus::*;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+mod collectors;
+mod http;
+mod latency;
+mod prometheus;
+
+/// Label names used throughout the framework.
+pub const LABEL_CORE: &str = "core";
+pub const LABEL_PORT: &str = "port";
+pub const LABEL_QUEUE: &str = "queue";
+pub const LABEL_TASK: &str = "task";
+
+/// Default histogram buckets, suited for latencies measured in nanoseconds.
+pub const DEFAULT_BUCKETS: [f64; 12] = [
+    250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 25000.0, 50000.0, 100000.0, 250000.0, 500000.0, 1000000.0,
+];
+
+lazy_static! {
+    static ref GLOBAL_REGISTRY: Arc<MetricsRegistry> = Arc::new(MetricsRegistry::new());
+}
+
+/// The process wide registry, this is the one which is served by the `metrics_agent`.
+pub fn global_registry() -> Arc<MetricsRegistry> {
+    GLOBAL_REGISTRY.clone()
+}
+
+/// A sorted list of label pairs identifying one time series within a metric family.
+pub type Labels = Vec<(String, String)>;
+
+pub fn labels(pairs: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    labels.sort();
+    labels
+}
+
+/// A monotonically increasing counter.
+#[derive(Default)]
+pub struct Counter {
+    value: AtomicU64,
+}
+
+impl Counter {
+    #[inline]
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    #[inline]
+    pub fn inc_by(&self, v: u64) {
+        self.value.fetch_add(v, Ordering::Relaxed);
+    }
+
+    /// Overwrite the counter, used when mirroring a counter maintained elsewhere (e.g. `PortStats`).
+    #[inline]
+    pub fn set(&self, v: u64) {
+        self.value.store(v, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// A value which can go up and down.
+#[derive(Default)]
+pub struct Gauge {
+    value: AtomicI64,
+}
+
+impl Gauge {
+    #[inline]
+    pub fn set(&self, v: i64) {
+        self.value.store(v, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn add(&self, v: i64) {
+        self.value.fetch_add(v, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn get(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// A histogram with fixed upper bucket bounds. Bucket counts are not cumulative internally,
+/// they are accumulated when rendered.
+pub struct Histogram {
+    bounds: Vec<f64>,
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    // sum is kept as f64 bits
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub fn with_buckets(bounds: &[f64]) -> Histogram {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(|a, b| a.total_cmp(b));
+        Histogram {
+            buckets: (0..bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            bounds,
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    pub fn observe(&self, v: f64) {
+        if let Some(i) = self.bounds.iter().position(|b| v <= *b) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let mut old = self.sum.load(Ordering::Relaxed);
+        loop {
+            let new = (f64::from_bits(old) + v).to_bits();
+            match self
+                .sum
+                .compare_exchange_weak(old, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(x) => old = x,
+            }
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum.load(Ordering::Relaxed))
+    }
+
+    /// Cumulative bucket counts as (upper bound, count) pairs, excluding the +Inf bucket.
+    pub fn cumulative_buckets(&self) -> Vec<(f64, u64)> {
+        let mut acc = 0u64;
+        self.bounds
+            .iter()
+            .zip(self.buckets.iter())
+            .map(|(b, c)| {
+                acc += c.load(Ordering::Relaxed);
+                (*b, acc)
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+#[derive(Clone)]
+pub enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+/// All time series sharing a metric name.
+pub struct MetricFamily {
+    pub name: String,
+    pub help: String,
+    pub metric_type: MetricType,
+    pub series: BTreeMap<Labels, Metric>,
+}
+
+type Collector = Box<dyn Fn(&MetricsRegistry) + Send>;
+
+pub struct MetricsRegistry {
+    families: Mutex<BTreeMap<String, MetricFamily>>,
+    collectors: Mutex<Vec<Collector>>,
+}
+
+impl Default for MetricsRegistry {
+    fn default() -> MetricsRegistry {
+        MetricsRegistry::new()
+    }
+}
+
+impl MetricsRegistry {
+    pub fn new() -> MetricsRegistry {
+        MetricsRegistry {
+            families: Mutex::new(BTreeMap::new()),
+            collectors: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn get_or_create<F>(&self, name: &str, help: &str, metric_type: MetricType, labels: Labels, create: F) -> Metric
+    where
+        F: FnOnce() -> Metric,
+    {
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_string()).or_insert_with(|| MetricFamily {
+            name: name.to_string(),
+            help: help.to_string(),
+            metric_type,
+            series: BTreeMap::new(),
+        });
+        assert_eq!(
+            family.metric_type, metric_type,
+            "metric {} registered with different types",
+            name
+        );
+        family.series.entry(labels).or_insert_with(create).clone()
+    }
+
+    /// Get or create the counter `name` with `labels`.
+    pub fn counter(&self, name: &str, help: &str, labels: Labels) -> Arc<Counter> {
+        match self.get_or_create(name, help, MetricType::Counter, labels, || {
+            Metric::Counter(Arc::new(Counter::default()))
+        }) {
+            Metric::Counter(c) => c,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Get or create the gauge `name` with `labels`.
+    pub fn gauge(&self, name: &str, help: &str, labels: Labels) -> Arc<Gauge> {
+        match self.get_or_create(name, help, MetricType::Gauge, labels, || {
+            Metric::Gauge(Arc::new(Gauge::default()))
+        }) {
+            Metric::Gauge(g) => g,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Get or create the histogram `name` with `labels`. `buckets` is only used when the series is created.
+    pub fn histogram(&self, name: &str, help: &str, labels: Labels, buckets: &[f64]) -> Arc<Histogram> {
+        match self.get_or_create(name, help, MetricType::Histogram, labels, || {
+            Metric::Histogram(Arc::new(Histogram::with_buckets(buckets)))
+        }) {
+            Metric::Histogram(h) => h,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Register a function which refreshes metrics from some other source, it is run before each rendering.
+    pub fn register_collector<F>(&self, collector: F)
+    where
+        F: Fn(&MetricsRegistry) + Send + 'static,
+    {
+        self.collectors.lock().unwrap().push(Box::new(collector));
+    }
+
+    /// Run all registered collectors.
+    pub fn collect(&self) {
+        let collectors = self.collectors.lock().unwrap();
+        for c in collectors.iter() {
+            c(self);
+        }
+    }
+
+    /// Run the collectors and render all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        self.collect();
+        let families = self.families.lock().unwrap();
+        let mut out = String::with_capacity(4096);
+        for family in families.values() {
+            encode_family(&mut out, family);
+        }
+        out
+    }
+}
@@ -0,0 +1,94 @@
+use super::{Labels, Metric, MetricFamily, MetricType};
+use std::fmt::Write;
+
+fn escape_label_value(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('\n', "\\n").replace('"', "\\\"")
+}
+
+fn escape_help(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn format_labels(labels: &Labels, extra: Option<(&str, &str)>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect();
+    if let Some((k, v)) = extra {
+        pairs.push(format!("{}=\"{}\"", k, v));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn format_float(v: f64) -> String {
+    if v.is_infinite() {
+        if v > 0.0 {
+            "+Inf".to_string()
+        } else {
+            "-Inf".to_string()
+        }
+    } else {
+        format!("{}", v)
+    }
+}
+
+/// Append one metric family in the Prometheus text exposition format (version 0.0.4) to `out`.
+pub fn encode_family(out: &mut String, family: &MetricFamily) {
+    if family.series.is_empty() {
+        return;
+    }
+    let type_name = match family.metric_type {
+        MetricType::Counter => "counter",
+        MetricType::Gauge => "gauge",
+        MetricType::Histogram => "histogram",
+    };
+    let _ = writeln!(out, "# HELP {} {}", family.name, escape_help(&family.help));
+    let _ = writeln!(out, "# TYPE {} {}", family.name, type_name);
+    for (labels, metric) in &family.series {
+        match metric {
+            Metric::Counter(c) => {
+                let _ = writeln!(out, "{}{} {}", family.name, format_labels(labels, None), c.get());
+            }
+            Metric::Gauge(g) => {
+                let _ = writeln!(out, "{}{} {}", family.name, format_labels(labels, None), g.get());
+            }
+            Metric::Histogram(h) => {
+                for (bound, count) in h.cumulative_buckets() {
+                    let le = format_float(bound);
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{} {}",
+                        family.name,
+                        format_labels(labels, Some(("le", &le))),
+                        count
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    family.name,
+                    format_labels(labels, Some(("le", "+Inf"))),
+                    h.count()
+                );
+                let _ = writeln!(
+                    out,
+                    "{}_sum{} {}",
+                    family.name,
+                    format_labels(labels, None),
+                    format_float(h.sum())
+                );
+                let _ = writeln!(
+                    out,
+                    "{}_count{} {}",
+                    family.name,
+                    format_labels(labels, None),
+                    h.count()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! MPLS label stack operations and the label forwarding table of a label switching router (RFC 3031). See
+//! `Batch::switch_labels`.
+use common::errors;
+use common::errors::ErrorKind;
+use headers::*;
+use interface::{MetadataKey, Pdu};
+use std::cmp;
+use std::collections::HashMap;
+
+/// Index of the top entry of the label stack in the header stack of `pdu`.
+#[inline]
+pub fn top_label(pdu: &Pdu) -> Option<usize> {
+    let headers = pdu.headers();
+    (0..headers.count()).find(|i| headers.get(*i).as_mpls().is_some())
+}
+
+/// Push a label on top of the label stack of `pdu`. Without a label stack the label is inserted behind the MAC
+/// header and the Ethertype is set accordingly.
+pub fn push_label(pdu: &mut Pdu, label: u32, tc: u8, ttl: u8) -> errors::Result<()> {
+    let at = top_label(pdu).unwrap_or_else(|| cmp::min(1, pdu.headers().count()));
+    let mut mpls = MplsHeader::new();
+    mpls.set_label(label);
+    mpls.set_tc(tc);
+    mpls.set_ttl(ttl);
+    mpls.set_bottom_of_stack(pdu.headers().count() == at || pdu.headers().get(at).as_mpls().is_none());
+    pdu.insert_header(at, &mpls)
+}
+
+/// Pop the top label of `pdu`. If it is the bottom of the stack, the Ethertype of the preceding MAC header is set
+/// for the IPv4 or IPv6 packet behind it.
+pub fn pop_label(pdu: &mut Pdu) -> errors::Result<()> {
+    let top = top_label(pdu).ok_or(ErrorKind::HeaderMismatch)?;
+    let bos = pdu.headers().mpls(top).bottom_of_stack();
+    pdu.remove_header(top)?;
+    if top == pdu.headers().count() {
+        // whatever follows was not parsed, e.g. IPv6 or labels beyond the capacity of the header stack
+        if bos && top > 0 {
+            let etype = match pdu.get_payload(top - 1).first().map(|b| b >> 4) {
+                Some(4) => 0x0800,
+                Some(6) => 0x86DD,
+                _ => return Ok(()),
+            };
+            if let Some(mac) = pdu.headers_mut().get_mut(top - 1).as_mac_mut() {
+                mac.set_etype(etype);
+            }
+        }
+        pdu.reparse();
+    }
+    Ok(())
+}
+
+/// Replace the top label of `pdu`.
+pub fn swap_label(pdu: &mut Pdu, label: u32) -> errors::Result<()> {
+    let top = top_label(pdu).ok_or(ErrorKind::HeaderMismatch)?;
+    pdu.headers_mut().mpls_mut(top).set_label(label);
+    Ok(())
+}
+
+/// The operation applied to the label stack of a packet by an ILM entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LabelOp {
+    /// replace the top label
+    Swap(u32),
+    /// remove the top label, e.g. at the penultimate hop or the egress of an LSP
+    Pop,
+    /// replace the top label with the first label and push the second label on top of it, e.g. to enter a tunnel
+    SwapPush(u32, u32),
+}
+
+/// Next hop label forwarding entry: the label operation and an index of the next hop, which is left to the
+/// pipeline to interpret, e.g. by a `group_by` on the metadata written by the `Ilm`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Nhlfe {
+    pub op: LabelOp,
+    pub next_hop: usize,
+}
+
+/// Incoming label map: maps the top label of received packets to their `Nhlfe`. The TTL is decremented and
+/// copied into the label which becomes the top label (RFC 3032 section 2.4). The TTL of an IP packet behind a
+/// popped bottom label is not touched.
+#[derive(Clone, Debug, Default)]
+pub struct Ilm {
+    entries: HashMap<u32, Nhlfe>,
+    next_hop_key: Option<MetadataKey<usize>>,
+}
+
+impl Ilm {
+    /// The next hop of switched packets is written to the metadata field `next_hop_key`, if given.
+    pub fn new(next_hop_key: Option<MetadataKey<usize>>) -> Ilm {
+        Ilm {
+            entries: HashMap::new(),
+            next_hop_key,
+        }
+    }
+
+    pub fn insert(&mut self, label: u32, nhlfe: Nhlfe) -> Option<Nhlfe> {
+        self.entries.insert(label, nhlfe)
+    }
+
+    pub fn remove(&mut self, label: u32) -> Option<Nhlfe> {
+        self.entries.remove(&label)
+    }
+
+    #[inline]
+    pub fn get(&self, label: u32) -> Option<&Nhlfe> {
+        self.entries.get(&label)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Switch the labeled packet `pdu` with the entry of its top label. Returns the next hop, or None if the
+    /// packet is to be dropped: it has no label stack, its top label is unknown, its TTL expires or the label
+    /// operation fails.
+    pub fn switch(&self, pdu: &mut Pdu) -> Option<usize> {
+        let top = top_label(pdu)?;
+        let (label, tc, ttl) = {
+            let mpls = pdu.headers().mpls(top);
+            (mpls.label(), mpls.tc(), mpls.ttl())
+        };
+        let nhlfe = self.entries.get(&label)?;
+        if ttl <= 1 {
+            return None;
+        }
+        let ttl = ttl - 1;
+        match nhlfe.op {
+            LabelOp::Swap(out) => {
+                let mpls = pdu.headers_mut().mpls_mut(top);
+                mpls.set_label(out);
+                mpls.set_ttl(ttl);
+            }
+            LabelOp::Pop => {
+                pop_label(pdu).ok()?;
+                if let Some(next) = top_label(pdu) {
+                    let mpls = pdu.headers_mut().mpls_mut(next);
+                    let inner_ttl = mpls.ttl();
+                    mpls.set_ttl(cmp::min(ttl, inner_ttl));
+                }
+            }
+            LabelOp::SwapPush(out, push) => {
+                {
+                    let mpls = pdu.headers_mut().mpls_mut(top);
+                    mpls.set_label(out);
+                    mpls.set_ttl(ttl);
+                }
+                push_label(pdu, push, tc, ttl).ok()?;
+            }
+        }
+        if let Some(key) = self.next_hop_key {
+            pdu.set_metadata(key, nhlfe.next_hop);
+        }
+        Some(nhlfe.next_hop)
+    }
+}
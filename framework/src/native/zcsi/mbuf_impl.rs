@@ -1,9 +1,42 @@
-use native::zcsi::rte_mbuf_api::{rte_mbuf, PKT_TX_IPV4, PKT_TX_IP_CKSUM, PKT_TX_TCP_CKSUM};
+use native::zcsi::rte_mbuf_api::{
+    rte_mbuf, PKT_TX_IPV4, PKT_TX_IP_CKSUM, PKT_TX_TCP_CKSUM, PKT_TX_UDP_CKSUM, RTE_PKTMBUF_HEADROOM,
+};
+use native::zcsi::{mbuf_alloc, mbuf_free};
+use std::cmp;
 use std::fmt;
+use std::marker::PhantomData;
 use std::ptr;
+use std::slice;
 
 pub type MBuf = rte_mbuf;
 
+/// Iterator over the data of the segments of a (chained) mbuf, starting at some offset into the packet.
+pub struct Segments<'a> {
+    seg: *const MBuf,
+    skip: usize,
+    phantom: PhantomData<&'a MBuf>,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = &'a [u8];
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a [u8]> {
+        while !self.seg.is_null() {
+            let seg = unsafe { &*self.seg };
+            self.seg = seg.next;
+            if self.skip >= seg.data_len() {
+                self.skip -= seg.data_len();
+            } else {
+                let skip = self.skip;
+                self.skip = 0;
+                return Some(unsafe { slice::from_raw_parts(seg.data_address(skip), seg.data_len() - skip) });
+            }
+        }
+        None
+    }
+}
+
 /* this must be adapted when new RX offloads are added */
 pub const PKT_RX_OFFLOAD_MASK: u32 = (1 << 20) - 1;
 
@@ -35,6 +68,18 @@ impl MBuf {
         (mbuf.offset(1) as *mut usize).offset(slot as isize) as *mut T
     }
 
+    /// Copy the first `slots` metadata slots to `tmb`.
+    #[inline]
+    pub fn copy_metadata_to(&self, tmb: &mut MBuf, slots: usize) {
+        unsafe {
+            ptr::copy_nonoverlapping(
+                MBuf::metadata_as::<usize>(self, 0),
+                MBuf::mut_metadata_as::<usize>(tmb, 0),
+                slots,
+            );
+        }
+    }
+
     #[inline]
     pub fn data_address(&self, offset: usize) -> *mut u8 {
         unsafe { (self.buf_addr as *mut u8).offset(self.data_off as isize + offset as isize) }
@@ -59,6 +104,174 @@ impl MBuf {
         self.pkt_len as usize
     }
 
+    /// Number of segments of the packet, valid in the first segment only.
+    #[inline]
+    pub fn nb_segs(&self) -> usize {
+        self.nb_segs as usize
+    }
+
+    /// True if the packet consists of a single segment.
+    #[inline]
+    pub fn is_contiguous(&self) -> bool {
+        self.next.is_null()
+    }
+
+    #[inline]
+    pub fn next_segment(&self) -> Option<&MBuf> {
+        unsafe { self.next.as_ref() }
+    }
+
+    #[inline]
+    pub fn last_segment(&mut self) -> &mut MBuf {
+        let mut seg: *mut MBuf = self;
+        unsafe {
+            while !(*seg).next.is_null() {
+                seg = (*seg).next;
+            }
+            &mut *seg
+        }
+    }
+
+    /// Iterate over the data of all segments.
+    #[inline]
+    pub fn segments(&self) -> Segments<'_> {
+        self.segments_from(0)
+    }
+
+    /// Iterate over the data of all segments, beginning at `offset` into the packet.
+    #[inline]
+    pub fn segments_from(&self, offset: usize) -> Segments<'_> {
+        Segments {
+            seg: self,
+            skip: offset,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Copy packet data beginning at `offset` into `dst`, across segments. Returns the number of bytes copied.
+    pub fn read_bytes(&self, offset: usize, dst: &mut [u8]) -> usize {
+        let mut copied = 0;
+        for s in self.segments_from(offset) {
+            let n = cmp::min(s.len(), dst.len() - copied);
+            dst[copied..copied + n].copy_from_slice(&s[..n]);
+            copied += n;
+            if copied == dst.len() {
+                break;
+            }
+        }
+        copied
+    }
+
+    /// Overwrite packet data beginning at `offset` with `src`, across segments. Returns the number of bytes written.
+    pub fn write_bytes(&mut self, offset: usize, src: &[u8]) -> usize {
+        let mut written = 0;
+        let mut skip = offset;
+        let mut seg: *mut MBuf = self;
+        while !seg.is_null() && written < src.len() {
+            let s = unsafe { &mut *seg };
+            if skip >= s.data_len() {
+                skip -= s.data_len();
+            } else {
+                let n = cmp::min(s.data_len() - skip, src.len() - written);
+                unsafe { ptr::copy_nonoverlapping(src[written..].as_ptr(), s.data_address(skip), n) };
+                written += n;
+                skip = 0;
+            }
+            seg = s.next;
+        }
+        written
+    }
+
+    /// Append the segment chain `tail` to this packet. `tail` must not be used as a packet on its own afterwards.
+    #[inline]
+    pub fn chain(&mut self, tail: &mut MBuf) {
+        let nb_segs = tail.nb_segs;
+        let pkt_len = tail.pkt_len;
+        self.last_segment().next = tail;
+        self.nb_segs += nb_segs;
+        self.pkt_len += pkt_len;
+    }
+
+    /// Append `data` to the end of the packet, allocating new segments when the tailroom of the last segment is
+    /// exhausted. Returns the number of bytes appended, which is less than `data.len()` if the allocation fails.
+    pub fn append_bytes(&mut self, data: &[u8]) -> usize {
+        let mut appended = 0;
+        while appended < data.len() {
+            let room = self.last_segment().pkt_tailroom();
+            if room == 0 {
+                let seg = unsafe { mbuf_alloc() };
+                if seg.is_null() {
+                    break;
+                }
+                self.chain(unsafe { &mut *seg });
+                continue;
+            }
+            let n = cmp::min(room, data.len() - appended);
+            let last = self.last_segment();
+            unsafe {
+                ptr::copy_nonoverlapping(data[appended..].as_ptr(), last.data_address(last.data_len()), n);
+            }
+            last.data_len += n as u16;
+            self.pkt_len += n as u32;
+            appended += n;
+        }
+        appended
+    }
+
+    /// Add `len` bytes to the beginning of the packet. If the headroom is too small, the data of the first
+    /// segment is moved into a new second segment, so that the first segment (which holds the packet level
+    /// fields and the metadata) is kept. The added bytes then start at the default headroom, or later if they
+    /// would not fit, so that the first segment keeps room on both ends. Returns false if `len` exceeds the
+    /// buffer size or allocation fails.
+    pub fn prepend(&mut self, len: usize) -> bool {
+        if len <= self.pkt_headroom() {
+            return self.add_data_beginning(len) == len;
+        }
+        if len > self.buf_len() {
+            return false;
+        }
+        let seg = unsafe { mbuf_alloc() };
+        if seg.is_null() {
+            return false;
+        }
+        let s = unsafe { &mut *seg };
+        if self.data_len() > s.buf_len() - s.pkt_headroom() {
+            unsafe { mbuf_free(seg) };
+            return false;
+        }
+        unsafe { ptr::copy_nonoverlapping(self.data_address(0), s.data_address(0), self.data_len()) };
+        s.data_len = self.data_len;
+        s.next = self.next;
+        self.next = seg;
+        self.nb_segs += 1;
+        self.data_off = cmp::min(RTE_PKTMBUF_HEADROOM as usize, self.buf_len() - len) as u16;
+        self.data_len = len as u16;
+        self.pkt_len += len as u32;
+        true
+    }
+
+    /// Copy the data of all further segments into the first one and free them. Fails if the data does not fit
+    /// into the first segment, the packet is unchanged in this case.
+    pub fn linearize(&mut self) -> bool {
+        if self.is_contiguous() {
+            return true;
+        }
+        if self.pkt_len() - self.data_len() > self.pkt_tailroom() {
+            return false;
+        }
+        let mut seg = self.next;
+        while !seg.is_null() {
+            let s = unsafe { &mut *seg };
+            unsafe { ptr::copy_nonoverlapping(s.data_address(0), self.data_address(self.data_len()), s.data_len()) };
+            self.data_len += s.data_len;
+            seg = s.next;
+        }
+        unsafe { mbuf_free(self.next) };
+        self.next = ptr::null_mut();
+        self.nb_segs = 1;
+        true
+    }
+
     #[inline]
     fn pkt_headroom(&self) -> usize {
         self.data_off as usize
@@ -83,14 +296,15 @@ impl MBuf {
         }
     }
 
-    /// Add data to the end of a packet buffer. This might fail (i.e., return 0) when no more tailroom is left. We do
-    /// not currently deal with packet with multiple segments.
+    /// Add data to the end of a packet buffer. This might fail (i.e., return 0) when no more tailroom is left in the
+    /// last segment. Use `append_bytes` to extend the packet by further segments.
     #[inline]
     pub fn add_data_end(&mut self, len: usize) -> usize {
-        if len > self.pkt_tailroom() {
+        let last = self.last_segment();
+        if len > last.pkt_tailroom() {
             0
         } else {
-            self.data_len += len as u16;
+            last.data_len += len as u16;
             self.pkt_len += len as u32;
             len
         }
@@ -108,13 +322,51 @@ impl MBuf {
         }
     }
 
+    /// Add data to the end of the first segment, i.e. behind the headers, regardless of further segments.
+    #[inline]
+    pub fn add_data_end_first_segment(&mut self, len: usize) -> usize {
+        if len > self.pkt_tailroom() {
+            0
+        } else {
+            self.data_len += len as u16;
+            self.pkt_len += len as u32;
+            len
+        }
+    }
+
+    /// Remove data from the end of the packet. Segments which become empty are freed.
     #[inline]
     pub fn remove_data_end(&mut self, len: usize) -> usize {
-        if len > self.data_len() {
+        if self.is_contiguous() {
+            if len > self.data_len() {
+                0
+            } else {
+                self.data_len -= len as u16;
+                self.pkt_len -= len as u32;
+                len
+            }
+        } else if len > self.pkt_len() {
             0
         } else {
-            self.data_len -= len as u16;
-            self.pkt_len -= len as u32;
+            let new_len = self.pkt_len() - len;
+            let mut seg: *mut MBuf = self;
+            let mut before = 0;
+            let mut segs = 1;
+            unsafe {
+                // find the segment which holds the new end of the packet, the first segment is always kept
+                while !(*seg).next.is_null() && before + (*seg).data_len() < new_len {
+                    before += (*seg).data_len();
+                    seg = (*seg).next;
+                    segs += 1;
+                }
+                (*seg).data_len = (new_len - before) as u16;
+                if !(*seg).next.is_null() {
+                    mbuf_free((*seg).next);
+                    (*seg).next = ptr::null_mut();
+                }
+            }
+            self.nb_segs = segs;
+            self.pkt_len = new_len as u32;
             len
         }
     }
@@ -139,15 +391,26 @@ impl MBuf {
         self.refcnt = new_value;
     }
 
-    // copy payload and selected fields to target tmb
+    // copy payload and selected fields to target tmb, further segments of a chained mbuf are copied into
+    // newly allocated segments
     #[inline]
     pub fn copy_to(&self, tmb: &mut MBuf) {
         (*tmb).data_len = (*self).data_len;
         (*tmb).data_off = (*self).data_off;
-        (*tmb).pkt_len = (*self).pkt_len;
+        (*tmb).pkt_len = (*self).data_len as u32;
         unsafe {
             ptr::copy_nonoverlapping(self.data_address(0), (*tmb).data_address(0), self.data_len());
         }
+        let mut seg = self.next;
+        while !seg.is_null() {
+            let s = unsafe { &*seg };
+            let data = unsafe { slice::from_raw_parts(s.data_address(0), s.data_len()) };
+            if tmb.append_bytes(data) < data.len() {
+                error!("copy_to: mbuf allocation failed, packet truncated");
+                break;
+            }
+            seg = s.next;
+        }
     }
 
     #[inline]
@@ -166,6 +429,16 @@ impl MBuf {
         self.ol_flags |= PKT_TX_IPV4 | PKT_TX_IP_CKSUM | PKT_TX_TCP_CKSUM;
     }
 
+    #[inline]
+    pub fn set_udp_ipv4_checksum_tx_offload(&mut self) {
+        self.ol_flags |= PKT_TX_IPV4 | PKT_TX_IP_CKSUM | PKT_TX_UDP_CKSUM;
+    }
+
+    #[inline]
+    pub fn set_ipv4_checksum_tx_offload(&mut self) {
+        self.ol_flags |= PKT_TX_IPV4 | PKT_TX_IP_CKSUM;
+    }
+
     #[inline]
     pub fn ipv4_checksum_tx_offload(&mut self) -> bool {
         self.ol_flags & PKT_TX_IPV4 != 0 && self.ol_flags & PKT_TX_IP_CKSUM != 0
@@ -210,6 +483,42 @@ impl MBuf {
             self.__bindgen_anon_3.__bindgen_anon_1.set_l4_len(val);
         }
     }
+
+    #[inline]
+    pub fn tso_segsz(&self) -> u64 {
+        unsafe { self.__bindgen_anon_3.__bindgen_anon_1.tso_segsz() }
+    }
+
+    #[inline]
+    pub fn set_tso_segsz(&mut self, val: u64) {
+        unsafe {
+            self.__bindgen_anon_3.__bindgen_anon_1.set_tso_segsz(val);
+        }
+    }
+
+    #[inline]
+    pub fn outer_l2_len(&self) -> u64 {
+        unsafe { self.__bindgen_anon_3.__bindgen_anon_1.outer_l2_len() }
+    }
+
+    #[inline]
+    pub fn set_outer_l2_len(&mut self, val: u64) {
+        unsafe {
+            self.__bindgen_anon_3.__bindgen_anon_1.set_outer_l2_len(val);
+        }
+    }
+
+    #[inline]
+    pub fn outer_l3_len(&self) -> u64 {
+        unsafe { self.__bindgen_anon_3.__bindgen_anon_1.outer_l3_len() }
+    }
+
+    #[inline]
+    pub fn set_outer_l3_len(&mut self, val: u64) {
+        unsafe {
+            self.__bindgen_anon_3.__bindgen_anon_1.set_outer_l3_len(val);
+        }
+    }
 }
 
 impl fmt::Display for MBuf {
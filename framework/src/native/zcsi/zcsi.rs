@@ -835,8 +835,8 @@ extern "C" {
         nrxd: u16,
         ntxd: u16,
         loopback: i32,
-        tso: i32,
-        csumoffload: i32,
+        rx_offloads: u64,
+        tx_offloads: u64,
         rx_mq_mode: rte_eth_rx_mq_mode,
         fdir_conf_ptr: *const RteFdirConf,
     ) -> i32;
@@ -861,6 +861,7 @@ extern "C" {
     pub fn max_txqs(port: u16) -> i32;
     pub fn mbuf_alloc() -> *mut rte_mbuf;
     pub fn mbuf_free(buf: *mut rte_mbuf);
+    pub fn mbuf_clone(md: *mut rte_mbuf) -> *mut rte_mbuf;
     pub fn mbuf_alloc_bulk(array: *mut *mut rte_mbuf, cnt: u32) -> i32;
     pub fn mbuf_free_bulk(array: *mut *mut rte_mbuf, cnt: i32) -> i32;
     pub fn mbuf_avail_count() -> u32;
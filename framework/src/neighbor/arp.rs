@@ -0,0 +1,175 @@
+use super::cache::{NeighborCache, NeighborConf};
+use common::errors;
+use common::errors::ErrorKind;
+use eui48::MacAddress;
+use headers::{ArpIpv4Header, MacHeader, ARP_OP_REPLY, ARP_OP_REQUEST};
+use interface::{PacketBuilder, Pdu, PmdPort};
+use ipnet::Ipv4Net;
+use native::zcsi::{mbuf_free, MBuf};
+use std::mem;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
+
+/// A packet waiting in the `NeighborCache` for the resolution of its next hop. The packet owns its mbuf, which
+/// is freed when the packet is dropped, e.g. together with the cache, unless it is taken out by `into_mbuf`.
+#[derive(Debug)]
+pub struct PendingPacket(*mut MBuf);
+
+impl PendingPacket {
+    #[inline]
+    pub fn new(mbuf: *mut MBuf) -> PendingPacket {
+        PendingPacket(mbuf)
+    }
+
+    /// Takes the mbuf out of the packet, the caller becomes responsible for freeing it.
+    #[inline]
+    pub fn into_mbuf(self) -> *mut MBuf {
+        let mbuf = self.0;
+        mem::forget(self);
+        mbuf
+    }
+}
+
+impl Drop for PendingPacket {
+    fn drop(&mut self) {
+        unsafe { mbuf_free(self.0) };
+    }
+}
+
+unsafe impl Send for PendingPacket {}
+unsafe impl Sync for PendingPacket {}
+
+pub type ArpCache = NeighborCache<Ipv4Addr, PendingPacket>;
+
+/// ARP service of a port: answers ARP requests for the address of the port, learns neighbors from ARP
+/// traffic and resolves next hops. Clones share the neighbor cache, so that a service can be used by the
+/// pipelines of all cores of the port. Incoming ARP is handled by `Batch::handle_arp`, resolution of outgoing
+/// packets by `Batch::set_dmac_from_neighbor`.
+#[derive(Clone)]
+pub struct ArpService {
+    mac: MacAddress,
+    ip_net: Ipv4Net,
+    gateway: Option<Ipv4Addr>,
+    cache: Arc<RwLock<ArpCache>>,
+}
+
+impl ArpService {
+    /// `gateway` is the next hop for destinations outside of `ip_net`, without a gateway those are unreachable.
+    pub fn new(
+        mac: MacAddress,
+        ip_net: Ipv4Net,
+        gateway: Option<Ipv4Addr>,
+        conf: NeighborConf,
+        tsc_hz: u64,
+    ) -> ArpService {
+        ArpService {
+            mac,
+            ip_net,
+            gateway,
+            cache: Arc::new(RwLock::new(NeighborCache::new(conf, tsc_hz))),
+        }
+    }
+
+    /// Creates the service for the MAC address and IP network of the `NetSpec` of `port`. If the `NetSpec` has
+    /// no MAC address, the MAC address of the port is used.
+    pub fn for_port(
+        port: &PmdPort,
+        gateway: Option<Ipv4Addr>,
+        conf: NeighborConf,
+        tsc_hz: u64,
+    ) -> errors::Result<ArpService> {
+        let net_spec = port.net_spec();
+        let ip_net = net_spec.as_ref().and_then(|spec| spec.ip_net).ok_or_else(|| {
+            ErrorKind::ConfigurationError(format!("ARP service requires an ip_net for port {}", port.name()))
+        })?;
+        let mac = net_spec.and_then(|spec| spec.mac).unwrap_or_else(|| port.mac_address());
+        Ok(ArpService::new(mac, ip_net, gateway, conf, tsc_hz))
+    }
+
+    #[inline]
+    pub fn mac(&self) -> MacAddress {
+        self.mac
+    }
+
+    #[inline]
+    pub fn ip(&self) -> Ipv4Addr {
+        self.ip_net.addr()
+    }
+
+    #[inline]
+    pub fn ip_net(&self) -> Ipv4Net {
+        self.ip_net
+    }
+
+    #[inline]
+    pub fn gateway(&self) -> Option<Ipv4Addr> {
+        self.gateway
+    }
+
+    /// Exclusive access to the neighbor cache, e.g. for adding static neighbors.
+    #[inline]
+    pub fn cache(&self) -> RwLockWriteGuard<'_, ArpCache> {
+        self.cache.write().unwrap()
+    }
+
+    /// The neighbor to which packets for `dst` are sent: `dst` itself if it is on-link, otherwise the gateway.
+    #[inline]
+    pub fn next_hop(&self, dst: Ipv4Addr) -> Option<Ipv4Addr> {
+        if self.ip_net.contains(&dst) {
+            Some(dst)
+        } else {
+            self.gateway
+        }
+    }
+
+    /// Allocates an ARP packet from us to `target_mac`/`target_ip`. Requests are broadcast.
+    pub fn arp_packet(&self, operation: u16, target_mac: MacAddress, target_ip: Ipv4Addr) -> Option<*mut MBuf> {
+        let mut mac = MacHeader::new();
+        mac.set_smac(&self.mac);
+        if operation == ARP_OP_REQUEST {
+            mac.set_dmac(&MacAddress::broadcast());
+        } else {
+            mac.set_dmac(&target_mac);
+        }
+        let arp = ArpIpv4Header::new(operation, self.mac, self.ip(), target_mac, target_ip);
+        PacketBuilder::new()
+            .mac(mac)
+            .arp(arp)
+            .build()
+            .ok()
+            .map(|pdu| unsafe { pdu.get_mbuf() })
+    }
+
+    #[inline]
+    pub fn request(&self, target_ip: Ipv4Addr) -> Option<*mut MBuf> {
+        self.arp_packet(ARP_OP_REQUEST, MacAddress::nil(), target_ip)
+    }
+
+    #[inline]
+    pub fn reply(&self, target_mac: MacAddress, target_ip: Ipv4Addr) -> Option<*mut MBuf> {
+        self.arp_packet(ARP_OP_REPLY, target_mac, target_ip)
+    }
+
+    /// Prepares packets which were waiting for the neighbor at `dmac` for sending and appends them to `out`.
+    pub fn release(&self, pending: &mut Vec<PendingPacket>, dmac: MacAddress, out: &mut Vec<*mut MBuf>) {
+        for packet in pending.drain(..) {
+            let mbuf = packet.into_mbuf();
+            let mut pdu = Pdu::pdu_from_mbuf_no_increment(mbuf);
+            if let Some(mac) = pdu.headers_mut().get_mut(0).as_mac_mut() {
+                mac.set_dmac(&dmac);
+                mac.set_smac(&self.mac);
+            }
+            out.push(mbuf);
+        }
+    }
+
+    /// Runs the timers of the neighbor cache at time `now`. Repeated requests are appended to `out`, packets for
+    /// neighbors which could not be resolved to `dropped`.
+    pub fn poll(&self, now: u64, out: &mut Vec<*mut MBuf>, dropped: &mut Vec<*mut MBuf>) {
+        let mut requests = Vec::new();
+        let mut unresolved = Vec::new();
+        self.cache().expire(now, &mut requests, &mut unresolved);
+        out.extend(requests.into_iter().filter_map(|ip| self.request(ip)));
+        dropped.extend(unresolved.into_iter().map(PendingPacket::into_mbuf));
+    }
+}
@@ -0,0 +1,208 @@
+use eui48::MacAddress;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Timers and limits of a `NeighborCache`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NeighborConf {
+    /// time in milliseconds after which a learned neighbor is forgotten unless it is confirmed again
+    pub reachable_time: u64,
+    /// time in milliseconds between two requests for an unresolved neighbor
+    pub retrans_time: u64,
+    /// number of requests sent for an unresolved neighbor before its pending packets are dropped
+    pub max_requests: u32,
+    /// maximum number of packets buffered per unresolved neighbor, further packets are dropped
+    pub max_pending: usize,
+}
+
+impl Default for NeighborConf {
+    fn default() -> NeighborConf {
+        NeighborConf {
+            reachable_time: 60_000,
+            retrans_time: 1_000,
+            max_requests: 3,
+            max_pending: 16,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NeighborState {
+    /// resolution is in progress
+    Incomplete,
+    /// the neighbor has been learned and did not age out yet
+    Reachable,
+    /// configured neighbor which never ages out
+    Static,
+}
+
+struct Entry<T> {
+    mac: Option<MacAddress>,
+    state: NeighborState,
+    // time stamp of the last confirmation resp. of the next request (Incomplete)
+    stamp: u64,
+    requests: u32,
+    pending: Vec<T>,
+}
+
+/// An aging cache of neighbor link layer addresses, keyed by network address `A`. Packets `T` for unresolved
+/// neighbors are buffered in the cache until the neighbor resolves or resolution fails. Time stamps are TSC
+/// values. The cache itself does not send anything, the caller sends the requests which the cache asks for.
+/// It is used by the `ArpService` for IPv4 and is meant to be shared with NDP for IPv6.
+pub struct NeighborCache<A, T> {
+    entries: HashMap<A, Entry<T>>,
+    conf: NeighborConf,
+    reachable_time: u64,
+    retrans_time: u64,
+}
+
+impl<A, T> NeighborCache<A, T>
+where
+    A: Copy + Eq + Hash,
+{
+    pub fn new(conf: NeighborConf, tsc_hz: u64) -> NeighborCache<A, T> {
+        NeighborCache {
+            entries: HashMap::new(),
+            conf,
+            reachable_time: conf.reachable_time * tsc_hz / 1000,
+            retrans_time: conf.retrans_time * tsc_hz / 1000,
+        }
+    }
+
+    #[inline]
+    pub fn conf(&self) -> &NeighborConf {
+        &self.conf
+    }
+
+    /// Number of entries, including unresolved neighbors.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    pub fn state(&self, addr: &A) -> Option<NeighborState> {
+        self.entries.get(addr).map(|e| e.state)
+    }
+
+    /// Number of packets waiting for the resolution of `addr`.
+    #[inline]
+    pub fn pending(&self, addr: &A) -> usize {
+        self.entries.get(addr).map_or(0, |e| e.pending.len())
+    }
+
+    /// The link layer address of `addr`, if it is resolved and did not age out at time `now`.
+    #[inline]
+    pub fn lookup(&self, addr: &A, now: u64) -> Option<MacAddress> {
+        match self.entries.get(addr) {
+            Some(e) if e.state == NeighborState::Static => e.mac,
+            Some(e) if e.state == NeighborState::Reachable && now.saturating_sub(e.stamp) < self.reachable_time => {
+                e.mac
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds a neighbor which never ages out. Packets pending for it are appended to `released`.
+    pub fn insert_static(&mut self, addr: A, mac: MacAddress, released: &mut Vec<T>) {
+        let entry = self.entries.entry(addr).or_insert_with(|| Entry {
+            mac: None,
+            state: NeighborState::Static,
+            stamp: 0,
+            requests: 0,
+            pending: Vec::new(),
+        });
+        entry.mac = Some(mac);
+        entry.state = NeighborState::Static;
+        released.append(&mut entry.pending);
+    }
+
+    pub fn remove(&mut self, addr: &A, dropped: &mut Vec<T>) -> Option<MacAddress> {
+        self.entries.remove(addr).and_then(|mut e| {
+            dropped.append(&mut e.pending);
+            e.mac
+        })
+    }
+
+    /// Learns that `addr` is at `mac` at time `now`. An existing entry is always updated, a new entry is only
+    /// created if `create` is set, e.g. when we are the target of the request (RFC 826). Packets which were
+    /// waiting for the neighbor are appended to `released`. Returns true if the cache holds the neighbor.
+    pub fn learn(&mut self, addr: A, mac: MacAddress, now: u64, create: bool, released: &mut Vec<T>) -> bool {
+        if !create && !self.entries.contains_key(&addr) {
+            return false;
+        }
+        let entry = self.entries.entry(addr).or_insert_with(|| Entry {
+            mac: None,
+            state: NeighborState::Reachable,
+            stamp: now,
+            requests: 0,
+            pending: Vec::new(),
+        });
+        if entry.state != NeighborState::Static {
+            entry.mac = Some(mac);
+            entry.state = NeighborState::Reachable;
+            entry.stamp = now;
+            entry.requests = 0;
+        }
+        released.append(&mut entry.pending);
+        true
+    }
+
+    /// Buffers `item` until `addr` resolves. Returns Ok(true) if resolution has just been started and the
+    /// caller must send the first request, Ok(false) if resolution is already in progress. If the pending buffer
+    /// of the neighbor is full, the item is handed back. Neighbors which aged out are resolved again.
+    pub fn hold(&mut self, addr: A, item: T, now: u64) -> Result<bool, T> {
+        let retrans_time = self.retrans_time;
+        let max_pending = self.conf.max_pending;
+        let entry = self.entries.entry(addr).or_insert_with(|| Entry {
+            mac: None,
+            state: NeighborState::Incomplete,
+            stamp: 0,
+            requests: 0,
+            pending: Vec::new(),
+        });
+        let start = entry.state != NeighborState::Incomplete || entry.requests == 0;
+        if start {
+            entry.state = NeighborState::Incomplete;
+            entry.stamp = now + retrans_time;
+            entry.requests = 1;
+        }
+        if entry.pending.len() < max_pending {
+            entry.pending.push(item);
+            Ok(start)
+        } else {
+            Err(item)
+        }
+    }
+
+    /// Runs the timers at time `now`: neighbors for which a request is to be repeated are appended to
+    /// `requests`, packets of neighbors which could not be resolved are appended to `dropped` and their entries
+    /// are removed, as are the entries of neighbors which aged out.
+    pub fn expire(&mut self, now: u64, requests: &mut Vec<A>, dropped: &mut Vec<T>) {
+        let reachable_time = self.reachable_time;
+        let retrans_time = self.retrans_time;
+        let max_requests = self.conf.max_requests;
+        self.entries.retain(|addr, e| match e.state {
+            NeighborState::Static => true,
+            NeighborState::Reachable => now.saturating_sub(e.stamp) < reachable_time,
+            NeighborState::Incomplete => {
+                if now < e.stamp {
+                    true
+                } else if e.requests >= max_requests {
+                    dropped.append(&mut e.pending);
+                    false
+                } else {
+                    e.requests += 1;
+                    e.stamp = now + retrans_time;
+                    requests.push(*addr);
+                    true
+                }
+            }
+        });
+    }
+}
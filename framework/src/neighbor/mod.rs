@@ -0,0 +1,7 @@
+//! Neighbor resolution: an aging neighbor cache and the ARP service of a port, which answers ARP requests and
+//! resolves the link layer addresses of next hops. See `Batch::handle_arp` and `Batch::set_dmac_from_neighbor`.
+pub use self::arp::*;
+pub use self::cache::*;
+
+mod arp;
+mod cache;
@@ -0,0 +1,206 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use headers::ARP_OP_REQUEST;
+use interface::{PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+use neighbor::{ArpService, PendingPacket};
+use std::arch::x86_64::_rdtsc;
+use std::net::Ipv4Addr;
+
+/// Handles the ARP packets of the batch with an `ArpService`: neighbors are learned from ARP requests and
+/// replies, requests for the address of the service are answered out of `port`. Packets which were waiting for
+/// a learned neighbor and repeated requests for unresolved neighbors are sent out of `port` as well. ARP packets
+/// are removed from the batch, all other packets pass unchanged.
+pub struct ArpBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    service: ArpService,
+    port: Port,
+    remove: Vec<usize>,
+    out: Vec<*mut MBuf>,
+    dropped_mbufs: Vec<*mut MBuf>,
+    released: Vec<PendingPacket>,
+    applied: bool,
+    pub replies: u64,
+    pub learned: u64,
+    pub dropped: u64,
+}
+
+impl<Port, V> ArpBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, service: ArpService, port: Port) -> ArpBatch<Port, V> {
+        let capacity = parent.capacity() as usize;
+        ArpBatch {
+            parent,
+            service,
+            port,
+            remove: Vec::with_capacity(capacity),
+            out: Vec::with_capacity(capacity),
+            dropped_mbufs: Vec::with_capacity(capacity),
+            released: Vec::new(),
+            applied: false,
+            replies: 0,
+            learned: 0,
+            dropped: 0,
+        }
+    }
+
+    #[inline]
+    pub fn service(&self) -> &ArpService {
+        &self.service
+    }
+
+    fn send_out(&mut self) {
+        if !self.out.is_empty() {
+            let sent = self.port.send(&mut self.out[..]).unwrap_or(0) as usize;
+            self.dropped_mbufs.extend_from_slice(&self.out[sent..]);
+            self.out.clear();
+        }
+        if !self.dropped_mbufs.is_empty() {
+            self.dropped += self.dropped_mbufs.len() as u64;
+            unsafe {
+                mbuf_free_bulk(self.dropped_mbufs.as_mut_ptr(), self.dropped_mbufs.len() as i32);
+            }
+            self.dropped_mbufs.clear();
+        }
+    }
+}
+
+impl<Port, V> Batch for ArpBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued()
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending() + self.port.pending()
+    }
+}
+
+impl<Port, V> Act for ArpBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let mut count = 0;
+        let mut q_len = 0;
+        if !self.applied {
+            q_len = self.parent.act().1;
+            let now = unsafe { _rdtsc() };
+            {
+                let iter = PayloadEnumerator::new(&mut self.parent);
+                while let Some(ParsedDescriptor { index: idx, pdu }) = iter.next(&mut self.parent) {
+                    count += 1;
+                    let arp = match pdu.headers().count() {
+                        n if n > 1 => pdu.headers().get(1).as_arpipv4().cloned(),
+                        _ => None,
+                    };
+                    let arp = match arp {
+                        Some(arp) => arp,
+                        None => continue,
+                    };
+                    self.remove.push(idx);
+                    if !arp.is_ipv4_over_ethernet() {
+                        continue;
+                    }
+                    let sender_ip = arp.sender_ip_addr();
+                    let sender_mac = arp.sender_hw_addr;
+                    let for_us = arp.target_ip_addr() == self.service.ip();
+                    if sender_ip != Ipv4Addr::UNSPECIFIED
+                        && self
+                            .service
+                            .cache()
+                            .learn(sender_ip, sender_mac, now, for_us, &mut self.released)
+                    {
+                        self.learned += 1;
+                        self.service.release(&mut self.released, sender_mac, &mut self.out);
+                    }
+                    if for_us && arp.operation() == ARP_OP_REQUEST {
+                        if let Some(reply) = self.service.reply(sender_mac, sender_ip) {
+                            self.out.push(reply);
+                            self.replies += 1;
+                        }
+                    }
+                }
+            }
+            if !self.remove.is_empty() {
+                self.parent
+                    .drop_packets(&self.remove[..])
+                    .expect("ARP handling was performed incorrectly");
+                self.remove.clear();
+            }
+            self.service.poll(now, &mut self.out, &mut self.dropped_mbufs);
+            self.send_out();
+            self.applied = true;
+        }
+        (count, q_len)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.applied = false;
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<Port, V> BatchIterator for ArpBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
@@ -22,6 +22,11 @@ impl Batch for CompositionBatch {
     fn queued(&self) -> usize {
         self.parent.queued()
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending()
+    }
 }
 
 impl BatchIterator for CompositionBatch {
@@ -86,4 +91,9 @@ impl Executable for CompositionBatch {
         self.done();
         count
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        Batch::pending(self)
+    }
 }
@@ -0,0 +1,161 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use icmp::echo_reply;
+use interface::{PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+use std::net::Ipv4Addr;
+
+/// Answers ICMP echo requests for `addr`: the requests are turned into replies in place, taken out of the batch
+/// and sent out of `port`. All other packets pass unchanged.
+pub struct EchoBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    port: Port,
+    addr: Ipv4Addr,
+    keep: Vec<*mut MBuf>,
+    out: Vec<*mut MBuf>,
+    applied: bool,
+    pub replies: u64,
+    pub dropped: u64,
+}
+
+impl<Port, V> EchoBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, port: Port, addr: Ipv4Addr) -> EchoBatch<Port, V> {
+        let capacity = parent.capacity() as usize;
+        EchoBatch {
+            parent,
+            port,
+            addr,
+            keep: Vec::with_capacity(capacity),
+            out: Vec::with_capacity(capacity),
+            applied: false,
+            replies: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<Port, V> Batch for EchoBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued()
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending() + self.port.pending()
+    }
+}
+
+impl<Port, V> Act for EchoBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        if self.applied {
+            return (self.parent.get_packet_batch().available() as u32, 0);
+        }
+        let pre = self.parent.act();
+        {
+            let iter = PayloadEnumerator::new(&mut self.parent);
+            while let Some(ParsedDescriptor { mut pdu, .. }) = iter.next(&mut self.parent) {
+                if echo_reply(&mut pdu, self.addr) {
+                    self.out.push(unsafe { pdu.get_mbuf() });
+                } else {
+                    self.keep.push(unsafe { pdu.get_mbuf() });
+                }
+            }
+        }
+        if self.out.is_empty() {
+            self.keep.clear();
+            self.applied = true;
+            return (self.parent.get_packet_batch().available() as u32, pre.1);
+        }
+        // the replies are owned by the port now, the batch keeps the other packets
+        let batch = self.parent.get_packet_batch();
+        batch.clear_packets();
+        for mbuf in self.keep.drain(..) {
+            batch.push(mbuf);
+        }
+        let sent = self.port.send(&mut self.out[..]).unwrap_or(0) as usize;
+        self.replies += sent as u64;
+        if sent < self.out.len() {
+            let unsent = &mut self.out[sent..];
+            self.dropped += unsent.len() as u64;
+            unsafe {
+                mbuf_free_bulk(unsent.as_mut_ptr(), unsent.len() as i32);
+            }
+        }
+        self.out.clear();
+        self.applied = true;
+        (batch.available() as u32, pre.1)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.applied = false;
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<Port, V> BatchIterator for EchoBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
@@ -51,6 +51,12 @@ where
         self.parent.done();
         (count, pre.1)
     }
+
+    /// Packets handed to the groups are counted by the receiving side of their queues.
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending()
+    }
 }
 
 #[cfg_attr(feature = "dev", allow(len_without_is_empty))]
@@ -0,0 +1,164 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use icmp::{icmp_error, IcmpCheckFn};
+use interface::{PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+use std::net::Ipv4Addr;
+
+/// Removes the packets for which `check_f` returns an `IcmpError` from the batch and sends the ICMP error
+/// messages, with source address `src`, out of `port` back toward the senders of the packets. No messages are
+/// generated about packets which must not be answered with an ICMP error, see `icmp::may_send_error`.
+pub struct IcmpErrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    port: Port,
+    src: Ipv4Addr,
+    check_f: IcmpCheckFn,
+    remove: Vec<usize>,
+    out: Vec<*mut MBuf>,
+    applied: bool,
+    pub errors: u64,
+    pub dropped: u64,
+}
+
+impl<Port, V> IcmpErrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, port: Port, src: Ipv4Addr, check_f: IcmpCheckFn) -> IcmpErrorBatch<Port, V> {
+        let capacity = parent.capacity() as usize;
+        IcmpErrorBatch {
+            parent,
+            port,
+            src,
+            check_f,
+            remove: Vec::with_capacity(capacity),
+            out: Vec::with_capacity(capacity),
+            applied: false,
+            errors: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<Port, V> Batch for IcmpErrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued()
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending() + self.port.pending()
+    }
+}
+
+impl<Port, V> Act for IcmpErrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let mut count = 0;
+        let mut q_len = 0;
+        if !self.applied {
+            q_len = self.parent.act().1;
+            {
+                let iter = PayloadEnumerator::new(&mut self.parent);
+                while let Some(ParsedDescriptor { index: idx, mut pdu }) = iter.next(&mut self.parent) {
+                    if let Some(error) = (self.check_f)(&mut pdu) {
+                        self.remove.push(idx);
+                        if let Some(msg) = icmp_error(&pdu, error, self.src) {
+                            self.out.push(unsafe { msg.get_mbuf() });
+                        }
+                    }
+                    count += 1;
+                }
+            }
+            if !self.remove.is_empty() {
+                self.parent
+                    .drop_packets(&self.remove[..])
+                    .expect("ICMP error generation was performed incorrectly");
+                self.remove.clear();
+            }
+            if !self.out.is_empty() {
+                let sent = self.port.send(&mut self.out[..]).unwrap_or(0) as usize;
+                self.errors += sent as u64;
+                if sent < self.out.len() {
+                    let unsent = &mut self.out[sent..];
+                    self.dropped += unsent.len() as u64;
+                    unsafe {
+                        mbuf_free_bulk(unsent.as_mut_ptr(), unsent.len() as i32);
+                    }
+                }
+                self.out.clear();
+            }
+            self.applied = true;
+        }
+        (count, q_len)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.applied = false;
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<Port, V> BatchIterator for IcmpErrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
@@ -21,6 +21,7 @@ macro_rules! batch_no_new {
             where
             V:Batch + BatchIterator + Act {
                 fn queued(&self) -> usize { self.parent.queued() }
+                fn pending(&self) -> usize { self.parent.pending() }
         }
     };
     ($name: ident, [ $($parts: ident : $pty: ty),* ]) => {
@@ -46,6 +46,11 @@ impl Batch for MergeBatchTraitObj {
         }
         result
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parents.iter().map(|parent| parent.pending()).sum()
+    }
 }
 
 impl BatchIterator for MergeBatchTraitObj {
@@ -117,6 +122,11 @@ impl Executable for MergeBatchTraitObj {
         self.done();
         count
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        Batch::pending(self)
+    }
 }
 
 pub struct MergeBatch<T: Batch> {
@@ -159,6 +169,11 @@ impl<T: Batch> Batch for MergeBatch<T> {
         }
         result
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parents.iter().map(|parent| parent.pending()).sum()
+    }
 }
 
 impl<T: Batch> BatchIterator for MergeBatch<T> {
@@ -244,6 +259,11 @@ impl<T: Batch> Executable for MergeBatch<T> {
         count
     }
 
+    #[inline]
+    fn pending(&self) -> usize {
+        Batch::pending(self)
+    }
+
     //    #[inline]
     //    fn dependencies(&mut self) -> Vec<usize> {
     //        self.get_task_dependencies()
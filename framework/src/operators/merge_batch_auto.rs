@@ -55,6 +55,7 @@ impl MergeBatchAuto {
         // we must make sure that we round robin through equally sized queues, otherwise we may get stuck on a single queue
         // is there an easier but still efficient algorithm ?
         self.parents.iter().enumerate().for_each(|(i, batch)| {
+            // parents which signal backpressure report no queued packets and are skipped
             let q = batch.queued();
             state[i] = q;
             if q > max_queue.0 {
@@ -105,6 +106,11 @@ impl Batch for MergeBatchAuto {
     fn queued(&self) -> usize {
         self.queue_size
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parents.iter().map(|parent| parent.pending()).sum()
+    }
 }
 
 impl BatchIterator for MergeBatchAuto {
@@ -174,4 +180,9 @@ impl Executable for MergeBatchAuto {
         self.done();
         count
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        Batch::pending(self)
+    }
 }
@@ -0,0 +1,159 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use interface::{PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+
+/// Sends a copy of each packet out of each of its ports, while the packets continue unchanged through the pipeline.
+/// Copies share the payload with the original packet and only get private headers, see
+/// `Pdu::duplicate_with_private_headers`. Packets which cannot be duplicated or sent are not mirrored to that port.
+pub struct MirrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    ports: Vec<Port>,
+    copies: Vec<Vec<*mut MBuf>>,
+    applied: bool,
+    pub mirrored: u64,
+    pub dropped: u64,
+}
+
+impl<Port, V> MirrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, port: Port) -> MirrorBatch<Port, V> {
+        MirrorBatch::with_ports(parent, vec![port])
+    }
+
+    /// Mirror to several ports, e.g. to replicate multicast packets, each port gets its own copy.
+    pub fn with_ports(parent: V, ports: Vec<Port>) -> MirrorBatch<Port, V> {
+        let capacity = parent.capacity() as usize;
+        let copies = ports.iter().map(|_| Vec::with_capacity(capacity)).collect();
+        MirrorBatch {
+            parent,
+            ports,
+            copies,
+            applied: false,
+            mirrored: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<Port, V> Batch for MirrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued()
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending() + self.ports.iter().map(|port| port.pending()).sum::<usize>()
+    }
+}
+
+impl<Port, V> BatchIterator for MirrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
+
+impl<Port, V> Act for MirrorBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let mut count = 0;
+        let mut q_len = 0;
+        if !self.applied {
+            q_len = self.parent.act().1;
+            {
+                let iter = PayloadEnumerator::new(&mut self.parent);
+                while let Some(ParsedDescriptor { pdu, .. }) = iter.next(&mut self.parent) {
+                    for copies in &mut self.copies {
+                        match pdu.duplicate_with_private_headers() {
+                            Ok(copy) => copies.push(unsafe { copy.get_mbuf() }),
+                            Err(_) => self.dropped += 1,
+                        }
+                    }
+                    count += 1
+                }
+            }
+            for (port, copies) in self.ports.iter_mut().zip(self.copies.iter_mut()) {
+                if copies.is_empty() {
+                    continue;
+                }
+                let sent = port.send(&mut copies[..]).unwrap_or(0) as usize;
+                self.mirrored += sent as u64;
+                if sent < copies.len() {
+                    let unsent = &mut copies[sent..];
+                    self.dropped += unsent.len() as u64;
+                    unsafe {
+                        mbuf_free_bulk(unsent.as_mut_ptr(), unsent.len() as i32);
+                    }
+                }
+                copies.clear();
+            }
+            self.applied = true;
+        }
+        (count, q_len)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.applied = false;
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
@@ -1,39 +1,68 @@
 pub use self::act::Act;
+pub use self::arp_batch::ArpBatch;
 pub use self::composition_batch::CompositionBatch;
 pub use self::drop::DropBatch;
+pub use self::echo_batch::EchoBatch;
 pub use self::filter_batch::FilterBatch;
 use self::filter_batch::FilterFn;
 pub use self::group_by::*;
+pub use self::icmp_batch::IcmpErrorBatch;
 pub use self::iterator::BatchIterator;
 pub use self::map_batch::MapBatch;
 use self::map_batch::MapFn;
 pub use self::merge_batch::MergeBatch;
 pub use self::merge_batch::MergeBatchTraitObj;
 pub use self::merge_batch_auto::MergeBatchAuto;
+pub use self::mirror_batch::MirrorBatch;
+pub use self::mpls_batch::MplsBatch;
+pub use self::neighbor_batch::NeighborBatch;
 pub use self::packet_batch::PacketBatch;
+pub use self::police_batch::{ColorActions, PoliceAction, PoliceBatch};
+pub use self::reassemble_batch::ReassembleBatch;
 pub use self::receive_batch::ReceiveBatch;
 pub use self::send_batch::SendBatch;
+pub use self::shape_batch::ShapeBatch;
+pub use self::syn_proxy_batch::SynProxyBatch;
+pub use self::tcp_endpoint_batch::TcpEndpointBatch;
 pub use self::transform_batch::TransformBatch;
 use self::transform_batch::TransformFn;
 
+use icmp::{mtu_check, ttl_check, IcmpCheckFn};
 use interface::*;
+use mpls::Ilm;
+use neighbor::ArpService;
 use scheduler::Scheduler;
+use state::{StreamHandler, TcpReassembler};
+use std::net::Ipv4Addr;
+use tcp::{SynProxy, TcpStack};
+use utils::{FiveTupleV4, Meter, TokenBucket};
 use uuid::Uuid;
 
 #[macro_use]
 mod macros;
 mod act;
+mod arp_batch;
 mod composition_batch;
 mod drop;
+mod echo_batch;
 mod filter_batch;
 mod group_by;
+mod icmp_batch;
 mod iterator;
 mod map_batch;
 mod merge_batch;
 mod merge_batch_auto;
+mod mirror_batch;
+mod mpls_batch;
+mod neighbor_batch;
 mod packet_batch;
+mod police_batch;
+mod reassemble_batch;
 mod receive_batch;
 mod send_batch;
+mod shape_batch;
+mod syn_proxy_batch;
+mod tcp_endpoint_batch;
 mod transform_batch;
 
 /// Merge a vector of batches into one batch. Currently this just round-robins between merged batches, but in the future
@@ -68,8 +97,14 @@ pub fn merge_auto(batches: Vec<Box<dyn Batch>>, policy: SchedulingPolicy) -> Mer
 /// places where a Batch type is required.
 ///
 pub trait Batch: BatchIterator + Act {
+    /// Packets which are ready to be processed. Zero while downstream cannot keep up, e.g. a `SendBatch` reports
+    /// the backpressure of its port this way, so that nothing is pulled from upstream.
     fn queued(&self) -> usize;
 
+    /// Packets held within the pipeline up to this operator which still would be processed, e.g. in queues between
+    /// stages, tx buffers or operator queues. Operators add their own to the ones of their parents.
+    fn pending(&self) -> usize;
+
     /// Send this batch out a particular port and queue.
     fn send<Port: PacketTx>(self, port: Port) -> SendBatch<Port, Self>
     where
@@ -103,6 +138,134 @@ pub trait Batch: BatchIterator + Act {
         FilterBatch::<Self>::new(self, filter_f)
     }
 
+    /// Send a copy of each packet out of `port`, the packets themselves continue through the pipeline.
+    fn mirror<Port: PacketTx>(self, port: Port) -> MirrorBatch<Port, Self>
+    where
+        Self: Sized,
+    {
+        MirrorBatch::<Port, Self>::new(self, port)
+    }
+
+    /// Send a copy of each packet out of each of `ports`, e.g. to replicate multicast packets. The packets
+    /// themselves continue through the pipeline.
+    fn tee<Port: PacketTx>(self, ports: Vec<Port>) -> MirrorBatch<Port, Self>
+    where
+        Self: Sized,
+    {
+        MirrorBatch::<Port, Self>::with_ports(self, ports)
+    }
+
+    /// Meter packets with the meter selected by `key_f` and pass, mark or drop them according to their color.
+    fn police(self, key_f: GroupFnPdu, meters: Vec<Meter>, actions: ColorActions) -> PoliceBatch<Self>
+    where
+        Self: Sized,
+    {
+        PoliceBatch::<Self>::new(self, key_f, meters, actions)
+    }
+
+    /// Queue packets per class selected by `key_f` and release them at the rate of the token bucket of the class.
+    fn shape(self, key_f: GroupFnPdu, buckets: Vec<TokenBucket>, queue_len: usize) -> ShapeBatch<Self>
+    where
+        Self: Sized,
+    {
+        ShapeBatch::<Self>::new(self, key_f, buckets, queue_len)
+    }
+
+    /// Answer ARP requests and learn neighbors with `service`, replies are sent out of `port`. ARP packets are
+    /// removed from the batch.
+    fn handle_arp<Port: PacketTx>(self, service: ArpService, port: Port) -> ArpBatch<Port, Self>
+    where
+        Self: Sized,
+    {
+        ArpBatch::<Port, Self>::new(self, service, port)
+    }
+
+    /// Set the MAC addresses of IPv4 packets for their next hop as resolved by `service`. Packets for unresolved
+    /// next hops are buffered until the neighbor is learned, ARP requests are sent out of `port`.
+    fn set_dmac_from_neighbor<Port: PacketTx>(self, service: ArpService, port: Port) -> NeighborBatch<Port, Self>
+    where
+        Self: Sized,
+    {
+        NeighborBatch::<Port, Self>::new(self, service, port)
+    }
+
+    /// Drop packets for which `check_f` returns an ICMP error and send the error from `src` out of `port`.
+    fn icmp_error<Port: PacketTx>(self, port: Port, src: Ipv4Addr, check_f: IcmpCheckFn) -> IcmpErrorBatch<Port, Self>
+    where
+        Self: Sized,
+    {
+        IcmpErrorBatch::<Port, Self>::new(self, port, src, check_f)
+    }
+
+    /// Decrement the TTL of IPv4 packets, packets with expiring TTL are answered with a time exceeded error.
+    fn decrement_ttl<Port: PacketTx>(self, port: Port, src: Ipv4Addr) -> IcmpErrorBatch<Port, Self>
+    where
+        Self: Sized,
+    {
+        IcmpErrorBatch::<Port, Self>::new(self, port, src, ttl_check())
+    }
+
+    /// Answer IPv4 packets longer than `mtu` with don't fragment set with a fragmentation needed error.
+    fn check_mtu<Port: PacketTx>(self, port: Port, src: Ipv4Addr, mtu: u16) -> IcmpErrorBatch<Port, Self>
+    where
+        Self: Sized,
+    {
+        IcmpErrorBatch::<Port, Self>::new(self, port, src, mtu_check(mtu))
+    }
+
+    /// Answer ICMP echo requests for `addr` out of `port`.
+    fn answer_echo<Port: PacketTx>(self, port: Port, addr: Ipv4Addr) -> EchoBatch<Port, Self>
+    where
+        Self: Sized,
+    {
+        EchoBatch::<Port, Self>::new(self, port, addr)
+    }
+
+    /// Switch labeled packets with the incoming label map `ilm`, see `Ilm::switch`. Packets with unknown labels or
+    /// expiring TTL are dropped.
+    fn switch_labels(self, ilm: Ilm) -> MplsBatch<Self>
+    where
+        Self: Sized,
+    {
+        MplsBatch::<Self>::new(self, ilm)
+    }
+
+    /// Terminate the TCP connections addressed to `stack`, `app` runs on the stack for each batch with the current
+    /// time. Segments are sent out of `port` to next hops resolved by `service`.
+    fn tcp_endpoint<Port, F>(
+        self,
+        stack: TcpStack,
+        service: ArpService,
+        port: Port,
+        app: F,
+    ) -> TcpEndpointBatch<Port, Self, F>
+    where
+        Self: Sized,
+        Port: PacketTx,
+        F: FnMut(&mut TcpStack, u64),
+    {
+        TcpEndpointBatch::<Port, Self, F>::new(self, stack, service, port, app)
+    }
+
+    /// Protect TCP services against SYN floods with `proxy`, which answers the SYNs of clients with SYN cookies. The
+    /// batch has to carry both directions of the protected connections, see `SynProxyBatch`.
+    fn syn_proxy(self, proxy: SynProxy) -> SynProxyBatch<Self>
+    where
+        Self: Sized,
+    {
+        SynProxyBatch::<Self>::new(self, proxy)
+    }
+
+    /// Reassemble the byte streams of the TCP connections of the batch with `reassembler`, see `TcpReassembler`.
+    fn reassemble_tcp<H, F>(self, reassembler: TcpReassembler<H, F>) -> ReassembleBatch<Self, H, F>
+    where
+        Self: Sized,
+        H: StreamHandler,
+        F: FnMut(&FiveTupleV4) -> Option<H>,
+    {
+        ReassembleBatch::<Self, H, F>::new(self, reassembler)
+    }
+
     fn drop(self) -> DropBatch<Self>
     where
         Self: Sized,
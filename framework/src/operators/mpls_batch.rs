@@ -0,0 +1,124 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use interface::{PacketTx, Pdu};
+use mpls::{top_label, Ilm};
+
+/// Switches labeled packets with an incoming label map. Packets with an unknown top label or an expiring TTL are
+/// dropped, packets without label stack pass unchanged.
+pub struct MplsBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    ilm: Ilm,
+    remove: Vec<usize>,
+    pub switched: u64,
+    pub dropped: u64,
+}
+
+impl<V> MplsBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, ilm: Ilm) -> MplsBatch<V> {
+        let capacity = parent.capacity() as usize;
+        MplsBatch {
+            parent,
+            ilm,
+            remove: Vec::with_capacity(capacity),
+            switched: 0,
+            dropped: 0,
+        }
+    }
+
+    #[inline]
+    pub fn ilm_mut(&mut self) -> &mut Ilm {
+        &mut self.ilm
+    }
+}
+
+batch_no_new! {MplsBatch}
+
+impl<V> Act for MplsBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let mut count = 0;
+        let pre = self.parent.act();
+        {
+            let iter = PayloadEnumerator::new(&mut self.parent);
+            while let Some(ParsedDescriptor { index: idx, mut pdu }) = iter.next(&mut self.parent) {
+                if top_label(&pdu).is_some() {
+                    match self.ilm.switch(&mut pdu) {
+                        Some(_) => self.switched += 1,
+                        None => self.remove.push(idx),
+                    }
+                }
+                count += 1;
+            }
+        }
+        if !self.remove.is_empty() {
+            self.dropped += self.remove.len() as u64;
+            self.parent
+                .drop_packets(&self.remove[..])
+                .expect("Label switching was performed incorrectly");
+        }
+        self.remove.clear();
+        (count, pre.1)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<V> BatchIterator for MplsBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
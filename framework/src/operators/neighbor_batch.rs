@@ -0,0 +1,209 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use interface::{PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+use neighbor::{ArpService, PendingPacket};
+use std::arch::x86_64::_rdtsc;
+use std::net::Ipv4Addr;
+
+/// Sets the destination MAC address of IPv4 packets to the address of their next hop, as resolved by an
+/// `ArpService`, and the source MAC address to the address of the service. Packets for unresolved next hops are
+/// taken out of the batch and buffered in the neighbor cache, while ARP requests for the next hop are sent out
+/// of `port`. Once the neighbor is learned, the buffered packets are sent out of the `port` of the
+/// `ArpBatch` which learned it. Packets without next hop or exceeding the buffer are dropped. Packets without
+/// IPv4 header pass unchanged.
+pub struct NeighborBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    service: ArpService,
+    port: Port,
+    keep: Vec<*mut MBuf>,
+    out: Vec<*mut MBuf>,
+    dropped_mbufs: Vec<*mut MBuf>,
+    applied: bool,
+    pub resolved: u64,
+    pub held: u64,
+    pub dropped: u64,
+}
+
+impl<Port, V> NeighborBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, service: ArpService, port: Port) -> NeighborBatch<Port, V> {
+        let capacity = parent.capacity() as usize;
+        NeighborBatch {
+            parent,
+            service,
+            port,
+            keep: Vec::with_capacity(capacity),
+            out: Vec::with_capacity(capacity),
+            dropped_mbufs: Vec::with_capacity(capacity),
+            applied: false,
+            resolved: 0,
+            held: 0,
+            dropped: 0,
+        }
+    }
+
+    #[inline]
+    pub fn service(&self) -> &ArpService {
+        &self.service
+    }
+}
+
+impl<Port, V> Batch for NeighborBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued()
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending() + self.port.pending()
+    }
+}
+
+impl<Port, V> Act for NeighborBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        if self.applied {
+            return (self.parent.get_packet_batch().available() as u32, 0);
+        }
+        let pre = self.parent.act();
+        let now = unsafe { _rdtsc() };
+        {
+            let mut cache = self.service.cache();
+            let iter = PayloadEnumerator::new(&mut self.parent);
+            while let Some(ParsedDescriptor { mut pdu, .. }) = iter.next(&mut self.parent) {
+                let dst = match pdu.headers().count() {
+                    n if n > 1 => pdu.headers().get(1).as_ip().map(|ip| Ipv4Addr::from(ip.dst())),
+                    _ => None,
+                };
+                let dst = match dst {
+                    Some(dst) => dst,
+                    None => {
+                        self.keep.push(unsafe { pdu.get_mbuf() });
+                        continue;
+                    }
+                };
+                let next_hop = match self.service.next_hop(dst) {
+                    Some(next_hop) => next_hop,
+                    None => {
+                        self.dropped_mbufs.push(unsafe { pdu.get_mbuf() });
+                        continue;
+                    }
+                };
+                if let Some(dmac) = cache.lookup(&next_hop, now) {
+                    if let Some(mac) = pdu.headers_mut().get_mut(0).as_mac_mut() {
+                        mac.set_dmac(&dmac);
+                        mac.set_smac(&self.service.mac());
+                    }
+                    self.keep.push(unsafe { pdu.get_mbuf() });
+                    self.resolved += 1;
+                    continue;
+                }
+                match cache.hold(next_hop, PendingPacket::new(unsafe { pdu.get_mbuf() }), now) {
+                    Ok(start) => {
+                        self.held += 1;
+                        if start {
+                            if let Some(request) = self.service.request(next_hop) {
+                                self.out.push(request);
+                            }
+                        }
+                    }
+                    Err(packet) => self.dropped_mbufs.push(packet.into_mbuf()),
+                }
+            }
+        }
+        self.service.poll(now, &mut self.out, &mut self.dropped_mbufs);
+
+        // the batch keeps only the resolved packets and those without IPv4 header
+        let batch = self.parent.get_packet_batch();
+        batch.clear_packets();
+        for mbuf in self.keep.drain(..) {
+            batch.push(mbuf);
+        }
+        if !self.out.is_empty() {
+            let sent = self.port.send(&mut self.out[..]).unwrap_or(0) as usize;
+            self.dropped_mbufs.extend_from_slice(&self.out[sent..]);
+            self.out.clear();
+        }
+        if !self.dropped_mbufs.is_empty() {
+            self.dropped += self.dropped_mbufs.len() as u64;
+            unsafe {
+                mbuf_free_bulk(self.dropped_mbufs.as_mut_ptr(), self.dropped_mbufs.len() as i32);
+            }
+            self.dropped_mbufs.clear();
+        }
+        self.applied = true;
+        (batch.available() as u32, pre.1)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.applied = false;
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<Port, V> BatchIterator for NeighborBatch<Port, V>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
@@ -64,6 +64,23 @@ impl PacketBatch {
         self.array.len()
     }
 
+    /// The mbufs currently held by this batch.
+    #[inline]
+    pub fn mbufs(&self) -> &[*mut MBuf] {
+        &self.array[..]
+    }
+
+    /// Append an mbuf to the batch, the batch takes over the reference. Returns false if the batch is full.
+    #[inline]
+    pub fn push(&mut self, mbuf: *mut MBuf) -> bool {
+        if self.array.len() < self.array.capacity() {
+            self.array.push(mbuf);
+            true
+        } else {
+            false
+        }
+    }
+
     /// Receive packets from a PMD port queue.
     #[inline]
     pub fn recv<Rx: PacketRx>(&mut self, port: &Rx) -> errors::Result<(u32, i32)> {
@@ -292,6 +309,11 @@ impl Batch for PacketBatch {
     fn queued(&self) -> usize {
         self.available()
     }
+
+    /// The batch only holds the packets of the current round.
+    fn pending(&self) -> usize {
+        0
+    }
 }
 
 impl Drop for PacketBatch {
@@ -0,0 +1,193 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use super::GroupFnPdu;
+use common::*;
+use interface::{PacketTx, Pdu};
+use std::arch::x86_64::_rdtsc;
+use utils::{Color, Meter};
+
+/// What happens to a packet of a given color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoliceAction {
+    Pass,
+    /// set the DSCP of the outermost IPv4 header, packets without IPv4 header pass unchanged
+    Mark(u8),
+    Drop,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorActions {
+    pub green: PoliceAction,
+    pub yellow: PoliceAction,
+    pub red: PoliceAction,
+}
+
+impl Default for ColorActions {
+    fn default() -> ColorActions {
+        ColorActions {
+            green: PoliceAction::Pass,
+            yellow: PoliceAction::Pass,
+            red: PoliceAction::Drop,
+        }
+    }
+}
+
+impl ColorActions {
+    #[inline]
+    fn action(&self, color: Color) -> PoliceAction {
+        match color {
+            Color::Green => self.green,
+            Color::Yellow => self.yellow,
+            Color::Red => self.red,
+        }
+    }
+}
+
+/// Meters packets with a three color marker and marks or drops them depending on their color. The meter is
+/// selected per packet by `key_f`, which returns an index into `meters`. Packets with an index beyond the
+/// meters pass unmetered. The metered length is the full packet length (`pkt_len`).
+pub struct PoliceBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    key_f: GroupFnPdu,
+    meters: Vec<Meter>,
+    actions: ColorActions,
+    remove: Vec<usize>,
+    pub green: u64,
+    pub yellow: u64,
+    pub red: u64,
+    pub dropped: u64,
+}
+
+impl<V> PoliceBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, key_f: GroupFnPdu, meters: Vec<Meter>, actions: ColorActions) -> PoliceBatch<V> {
+        let capacity = parent.capacity() as usize;
+        PoliceBatch {
+            parent,
+            key_f,
+            meters,
+            actions,
+            remove: Vec::with_capacity(capacity),
+            green: 0,
+            yellow: 0,
+            red: 0,
+            dropped: 0,
+        }
+    }
+
+    #[inline]
+    pub fn meters_mut(&mut self) -> &mut Vec<Meter> {
+        &mut self.meters
+    }
+}
+
+#[inline]
+fn mark_dscp(pdu: &mut Pdu, dscp: u8) {
+    let headers = pdu.headers_mut();
+    for i in 0..headers.count() {
+        if let Some(ip) = headers.get_mut(i).as_ip_mut() {
+            ip.set_dscp(dscp);
+            ip.update_checksum();
+            return;
+        }
+    }
+}
+
+batch_no_new! {PoliceBatch}
+
+impl<V> Act for PoliceBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let mut count = 0;
+        let pre = self.parent.act();
+        let now = unsafe { _rdtsc() };
+        {
+            let iter = PayloadEnumerator::new(&mut self.parent);
+            while let Some(ParsedDescriptor { index: idx, mut pdu }) = iter.next(&mut self.parent) {
+                let key = (self.key_f)(&mut pdu);
+                if let Some(meter) = self.meters.get_mut(key) {
+                    let color = meter.color(pdu.pkt_len(), now);
+                    match color {
+                        Color::Green => self.green += 1,
+                        Color::Yellow => self.yellow += 1,
+                        Color::Red => self.red += 1,
+                    }
+                    match self.actions.action(color) {
+                        PoliceAction::Pass => (),
+                        PoliceAction::Mark(dscp) => mark_dscp(&mut pdu, dscp),
+                        PoliceAction::Drop => self.remove.push(idx),
+                    }
+                }
+                count += 1;
+            }
+        }
+        if !self.remove.is_empty() {
+            self.dropped += self.remove.len() as u64;
+            self.parent
+                .drop_packets(&self.remove[..])
+                .expect("Policing was performed incorrectly");
+        }
+        self.remove.clear();
+        (count, pre.1)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<V> BatchIterator for PoliceBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
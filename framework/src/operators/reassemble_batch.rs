@@ -0,0 +1,146 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use interface::{PacketTx, Pdu};
+use state::{StreamHandler, TcpReassembler};
+use std::arch::x86_64::_rdtsc;
+use tcp::tcp_segment;
+use utils::FiveTupleV4;
+
+/// Feeds the TCP segments over IPv4 of the batch into a `TcpReassembler`. Packets pass unchanged.
+pub struct ReassembleBatch<V, H, F>
+where
+    V: Batch + BatchIterator + Act,
+    H: StreamHandler,
+    F: FnMut(&FiveTupleV4) -> Option<H>,
+{
+    parent: V,
+    reassembler: TcpReassembler<H, F>,
+    applied: bool,
+}
+
+impl<V, H, F> ReassembleBatch<V, H, F>
+where
+    V: Batch + BatchIterator + Act,
+    H: StreamHandler,
+    F: FnMut(&FiveTupleV4) -> Option<H>,
+{
+    pub fn new(parent: V, reassembler: TcpReassembler<H, F>) -> ReassembleBatch<V, H, F> {
+        ReassembleBatch {
+            parent,
+            reassembler,
+            applied: false,
+        }
+    }
+
+    #[inline]
+    pub fn reassembler(&self) -> &TcpReassembler<H, F> {
+        &self.reassembler
+    }
+
+    #[inline]
+    pub fn reassembler_mut(&mut self) -> &mut TcpReassembler<H, F> {
+        &mut self.reassembler
+    }
+}
+
+impl<V, H, F> Batch for ReassembleBatch<V, H, F>
+where
+    V: Batch + BatchIterator + Act,
+    H: StreamHandler,
+    F: FnMut(&FiveTupleV4) -> Option<H>,
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued()
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending()
+    }
+}
+
+impl<V, H, F> Act for ReassembleBatch<V, H, F>
+where
+    V: Batch + BatchIterator + Act,
+    H: StreamHandler,
+    F: FnMut(&FiveTupleV4) -> Option<H>,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let mut count = 0;
+        let mut q_len = 0;
+        if !self.applied {
+            q_len = self.parent.act().1;
+            let now = unsafe { _rdtsc() };
+            self.reassembler.expire(now);
+            {
+                let iter = PayloadEnumerator::new(&mut self.parent);
+                while let Some(ParsedDescriptor { pdu, .. }) = iter.next(&mut self.parent) {
+                    if let Some((tcp, flow, payload)) = tcp_segment(&pdu) {
+                        self.reassembler.process(&flow, pdu.headers().tcp(tcp), payload, now);
+                    }
+                    count += 1;
+                }
+            }
+            self.applied = true;
+        }
+        (count, q_len)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.applied = false;
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<V, H, F> BatchIterator for ReassembleBatch<V, H, F>
+where
+    V: Batch + BatchIterator + Act,
+    H: StreamHandler,
+    F: FnMut(&FiveTupleV4) -> Option<H>,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
@@ -3,13 +3,16 @@ use super::iterator::*;
 use super::packet_batch::PacketBatch;
 use super::Batch;
 use common::*;
-use interface::{PacketRx, PacketTx, Pdu};
+use interface::{MetadataKey, PacketRx, PacketTx, Pdu};
+use std::arch::x86_64::_rdtsc;
 
 pub struct ReceiveBatch<T: PacketRx> {
     parent: PacketBatch,
     packet_rx: T,
     pub received: u64,
     urgent: bool,
+    // metadata field for the rx time stamp, if any
+    tsc_key: Option<MetadataKey<u64>>,
 }
 
 impl<T: PacketRx> ReceiveBatch<T> {
@@ -19,6 +22,7 @@ impl<T: PacketRx> ReceiveBatch<T> {
             packet_rx,
             received: 0,
             urgent: false,
+            tsc_key: None,
         }
     }
 
@@ -28,6 +32,7 @@ impl<T: PacketRx> ReceiveBatch<T> {
             packet_rx,
             received: 0,
             urgent: false,
+            tsc_key: None,
         }
     }
 
@@ -37,6 +42,7 @@ impl<T: PacketRx> ReceiveBatch<T> {
             packet_rx,
             received: 0,
             urgent: false,
+            tsc_key: None,
         }
     }
 
@@ -44,6 +50,13 @@ impl<T: PacketRx> ReceiveBatch<T> {
         self.urgent = true;
         self
     }
+
+    /// Stamp the TSC at reception into the metadata field `key` of each received packet, so that a
+    /// `SendBatch` with `measure_latency` can determine the latency through the pipeline.
+    pub fn stamp_rx_tsc(mut self, key: MetadataKey<u64>) -> ReceiveBatch<T> {
+        self.tsc_key = Some(key);
+        self
+    }
 }
 
 impl<T: PacketRx> Batch for ReceiveBatch<T> {
@@ -59,6 +72,11 @@ impl<T: PacketRx> Batch for ReceiveBatch<T> {
             self.packet_rx.queued()
         }
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.packet_rx.pending()
+    }
 }
 
 impl<T: PacketRx> BatchIterator for ReceiveBatch<T> {
@@ -82,6 +100,14 @@ impl<T: PacketRx> Act for ReceiveBatch<T> {
             .recv(&self.packet_rx)
             .and_then(|x| {
                 self.received += x.0 as u64;
+                if let Some(key) = self.tsc_key {
+                    if x.0 > 0 {
+                        let tsc = unsafe { _rdtsc() };
+                        for mbuf in self.parent.mbufs() {
+                            unsafe { key.write(*mbuf, tsc) };
+                        }
+                    }
+                }
                 Ok(x)
             })
             .expect("Receive failure")
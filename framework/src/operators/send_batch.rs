@@ -3,8 +3,10 @@ use super::iterator::*;
 use super::packet_batch::PacketBatch;
 use super::Batch;
 use common::*;
-use interface::{PacketTx, Pdu};
+use interface::{MetadataKey, PacketTx, Pdu};
+use metrics::LatencyHistogram;
 use scheduler::Executable;
+use std::arch::x86_64::_rdtsc;
 
 pub struct SendBatch<Port, V>
 where
@@ -13,6 +15,9 @@ where
 {
     port: Port,
     parent: V,
+    // metadata field holding the rx time stamp and the histogram of the latencies, if latency is measured
+    latency: Option<(MetadataKey<u64>, LatencyHistogram)>,
+    latency_scratch: Vec<u64>,
 }
 
 impl<Port, V> SendBatch<Port, V>
@@ -24,8 +29,22 @@ where
         SendBatch {
             port: port,
             parent: parent,
+            latency: None,
+            latency_scratch: Vec::new(),
         }
     }
+
+    /// Record the latency of each sent packet, based on the time stamp written into the metadata field `key`
+    /// by `ReceiveBatch::stamp_rx_tsc`. The histogram can be queried with `SchedulerCommand::GetLatency`.
+    pub fn measure_latency(mut self, key: MetadataKey<u64>) -> SendBatch<Port, V> {
+        self.latency = Some((key, LatencyHistogram::new()));
+        self.latency_scratch = Vec::with_capacity(self.parent.capacity() as usize);
+        self
+    }
+
+    pub fn latency_histogram(&self) -> Option<&LatencyHistogram> {
+        self.latency.as_ref().map(|l| &l.1)
+    }
 }
 
 impl<Port, V> Batch for SendBatch<Port, V>
@@ -33,9 +52,19 @@ where
     Port: PacketTx,
     V: Batch + BatchIterator + Act,
 {
+    /// Reports an empty queue while the port signals backpressure, so that nothing is pulled from upstream.
     #[inline]
     fn queued(&self) -> usize {
-        self.parent.queued()
+        if self.port.backpressure() {
+            0
+        } else {
+            self.parent.queued()
+        }
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending() + self.port.pending()
     }
 }
 
@@ -66,7 +95,25 @@ where
         // debug!("SendBatch.act with port {}", self.port.port_id());
         // First everything is applied
         let mut count: u32 = 0;
+        if self.port.backpressure() {
+            // only try to drain the tx buffer, packets not received stay in the rx rings and do not block the mempool
+            self.port.flush().expect("Flush failed");
+            if self.port.backpressure() {
+                return (0, 0);
+            }
+        }
         let pre = self.parent.act();
+        if let Some((key, _)) = self.latency {
+            let now = unsafe { _rdtsc() };
+            self.latency_scratch.clear();
+            for mbuf in self.parent.get_packet_batch().mbufs() {
+                self.latency_scratch.push(now.wrapping_sub(unsafe { key.read(*mbuf) }));
+            }
+        }
+        if self.parent.get_packet_batch().available() == 0 {
+            // nothing to send, but ports which hold packets back may make progress
+            self.port.flush().expect("Flush failed");
+        }
         self.parent
             .get_packet_batch()
             .send_q(&mut self.port)
@@ -76,6 +123,12 @@ where
                 Ok(x)
             })
             .expect("Send failed");
+        if let Some((_, ref mut histogram)) = self.latency {
+            // unsent packets are dropped, we only account for the sent ones
+            for latency in self.latency_scratch.iter().take(count as usize) {
+                histogram.record(*latency);
+            }
+        }
         self.parent.done();
         (count, pre.1)
     }
@@ -126,6 +179,21 @@ where
         self.act()
     }
 
+    fn latency(&self) -> Option<&LatencyHistogram> {
+        self.latency_histogram()
+    }
+
+    fn reset_latency(&mut self) {
+        if let Some((_, ref mut histogram)) = self.latency {
+            histogram.reset();
+        }
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        Batch::pending(self)
+    }
+
     //    #[inline]
     //    fn dependencies(&mut self) -> Vec<usize> {
     //        self.get_task_dependencies()
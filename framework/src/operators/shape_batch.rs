@@ -0,0 +1,216 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use super::GroupFnPdu;
+use common::*;
+use interface::{PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+use std::arch::x86_64::_rdtsc;
+use std::collections::VecDeque;
+use utils::TokenBucket;
+
+struct ShapingClass {
+    bucket: TokenBucket,
+    queue: VecDeque<*mut MBuf>,
+}
+
+/// Holds packets in a queue per class and releases them when the token bucket of the class permits, i.e. the
+/// packets of a class leave with at most the rate of its bucket. The class is selected per packet by `key_f`,
+/// which returns an index into `buckets`. Packets with an index beyond the buckets pass unshaped. Each class
+/// queue holds up to `queue_len` packets, further packets are dropped.
+pub struct ShapeBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    key_f: GroupFnPdu,
+    classes: Vec<ShapingClass>,
+    queue_len: usize,
+    // class which is served first in the next round, rotates to share the batch capacity between the classes
+    next_class: usize,
+    // packets held in all class queues
+    backlog: usize,
+    bypass: Vec<*mut MBuf>,
+    dropped_mbufs: Vec<*mut MBuf>,
+    pub released: u64,
+    pub dropped: u64,
+}
+
+impl<V> ShapeBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, key_f: GroupFnPdu, buckets: Vec<TokenBucket>, queue_len: usize) -> ShapeBatch<V> {
+        let capacity = parent.capacity() as usize;
+        ShapeBatch {
+            parent,
+            key_f,
+            classes: buckets
+                .into_iter()
+                .map(|bucket| ShapingClass {
+                    bucket,
+                    queue: VecDeque::with_capacity(queue_len),
+                })
+                .collect(),
+            queue_len,
+            next_class: 0,
+            backlog: 0,
+            bypass: Vec::with_capacity(capacity),
+            dropped_mbufs: Vec::with_capacity(capacity),
+            released: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Number of packets waiting in the class queues.
+    #[inline]
+    pub fn backlog(&self) -> usize {
+        self.backlog
+    }
+}
+
+impl<V> Batch for ShapeBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued() + self.backlog
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending() + self.backlog
+    }
+}
+
+impl<V> Act for ShapeBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let pre = self.parent.act();
+        {
+            let iter = PayloadEnumerator::new(&mut self.parent);
+            while let Some(ParsedDescriptor { mut pdu, .. }) = iter.next(&mut self.parent) {
+                let key = (self.key_f)(&mut pdu);
+                let mbuf = unsafe { pdu.get_mbuf() };
+                match self.classes.get_mut(key) {
+                    Some(class) => {
+                        if class.queue.len() < self.queue_len {
+                            class.queue.push_back(mbuf);
+                            self.backlog += 1;
+                        } else {
+                            self.dropped_mbufs.push(mbuf);
+                        }
+                    }
+                    None => self.bypass.push(mbuf),
+                }
+            }
+        }
+        if !self.dropped_mbufs.is_empty() {
+            self.dropped += self.dropped_mbufs.len() as u64;
+            unsafe {
+                mbuf_free_bulk(self.dropped_mbufs.as_mut_ptr(), self.dropped_mbufs.len() as i32);
+            }
+            self.dropped_mbufs.clear();
+        }
+
+        // the packets are now owned by the class queues, refill the batch with the packets due for release
+        let batch = self.parent.get_packet_batch();
+        batch.clear_packets();
+        for mbuf in self.bypass.drain(..) {
+            batch.push(mbuf);
+        }
+        let now = unsafe { _rdtsc() };
+        let n_classes = self.classes.len();
+        let mut released = 0;
+        for i in 0..n_classes {
+            let class = &mut self.classes[(self.next_class + i) % n_classes];
+            while let Some(&mbuf) = class.queue.front() {
+                if batch.available() == batch.capacity() as usize {
+                    break;
+                }
+                if !class.bucket.consume(unsafe { (*mbuf).pkt_len() }, now) {
+                    break;
+                }
+                class.queue.pop_front();
+                batch.push(mbuf);
+                released += 1;
+            }
+        }
+        if n_classes > 0 {
+            self.next_class = (self.next_class + 1) % n_classes;
+        }
+        self.backlog -= released;
+        self.released += released as u64;
+        (batch.available() as u32, pre.1)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<V> BatchIterator for ShapeBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
+
+impl<V> Drop for ShapeBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    fn drop(&mut self) {
+        for class in self.classes.iter_mut() {
+            let (front, back) = class.queue.as_mut_slices();
+            unsafe {
+                mbuf_free_bulk(front.as_mut_ptr(), front.len() as i32);
+                mbuf_free_bulk(back.as_mut_ptr(), back.len() as i32);
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use headers::{IpHeader, TcpHeader, TcpOptions};
+use interface::{update_tcp_checksum_, PacketBuilder, PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+use std::arch::x86_64::_rdtsc;
+use std::mem;
+use tcp::{tcp_options, tcp_segment, Ack, SynProxy, SynProxyAction};
+
+/// TTL of the segments the proxy answers with.
+const REPLY_TTL: u8 = 64;
+
+/// Passes the TCP segments of the batch through a `SynProxy`. Segments are rewritten in place and stay in the
+/// batch, also the answers which go back toward the sender: the batch has to be forwarded by destination
+/// afterwards. The ACKs opening the windows of clients are added to the batch. Dropped segments are removed.
+pub struct SynProxyBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    parent: V,
+    proxy: SynProxy,
+    remove: Vec<usize>,
+    add: Vec<*mut MBuf>,
+    dropped_mbufs: Vec<*mut MBuf>,
+    applied: bool,
+    /// segments which could not be rewritten and window updates which did not fit into the batch
+    pub dropped: u64,
+}
+
+impl<V> SynProxyBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    pub fn new(parent: V, proxy: SynProxy) -> SynProxyBatch<V> {
+        let capacity = parent.capacity() as usize;
+        SynProxyBatch {
+            parent,
+            proxy,
+            remove: Vec::with_capacity(capacity),
+            add: Vec::with_capacity(capacity),
+            dropped_mbufs: Vec::new(),
+            applied: false,
+            dropped: 0,
+        }
+    }
+
+    #[inline]
+    pub fn proxy(&self) -> &SynProxy {
+        &self.proxy
+    }
+
+    #[inline]
+    pub fn proxy_mut(&mut self) -> &mut SynProxy {
+        &mut self.proxy
+    }
+}
+
+fn ack_header(ack: &Ack) -> TcpHeader {
+    let mut tcp = TcpHeader::new();
+    tcp.set_ack_flag();
+    tcp.set_seq_num(ack.seq);
+    tcp.set_ack_num(ack.ack);
+    tcp.set_window_size(ack.window);
+    tcp
+}
+
+fn mss_option(mss: u16) -> Vec<u8> {
+    TcpOptions {
+        mss: Some(mss),
+        window_scale: None,
+    }
+    .to_bytes()
+}
+
+/// Replaces the TCP header at index `tcp` of `pdu` with `header` followed by `options`, and removes the data of
+/// the segment. The ports are kept, the addresses and ports are swapped if the segment is a `reply`. Returns false
+/// if the packet could not be resized.
+fn reshape(pdu: &mut Pdu, tcp: usize, mut header: TcpHeader, options: &[u8], reply: bool) -> bool {
+    if !pdu.is_contiguous() {
+        return false;
+    }
+    let ip = tcp - 1;
+    let len = mem::size_of::<TcpHeader>() + options.len();
+    let segment_len = pdu.get_payload(ip).len();
+    if segment_len < len {
+        if pdu.increase_payload_size(len - segment_len) < len - segment_len {
+            return false;
+        }
+    } else {
+        pdu.trim_payload_size(segment_len - len);
+    }
+    pdu.get_payload_mut(ip)[mem::size_of::<TcpHeader>()..len].copy_from_slice(options);
+
+    let headers = pdu.headers_mut();
+    if reply && headers.get(0).as_mac().is_some() {
+        headers.mac_mut(0).swap_addresses();
+    }
+    let (src, dst) = {
+        let ip = headers.ip_mut(ip);
+        if reply {
+            let src = ip.src();
+            ip.set_src(ip.dst());
+            ip.set_dst(src);
+            ip.set_ttl(REPLY_TTL);
+        }
+        let ip_len = ip.ihl() as usize * 4 + len;
+        ip.set_length(ip_len as u16);
+        ip.update_checksum();
+        (ip.src(), ip.dst())
+    };
+    let tcp = headers.tcp_mut(tcp);
+    if reply {
+        header.set_src_port(tcp.dst_port());
+        header.set_dst_port(tcp.src_port());
+    } else {
+        header.set_src_port(tcp.src_port());
+        header.set_dst_port(tcp.dst_port());
+    }
+    header.set_data_offset((len / 4) as u8);
+    *tcp = header;
+    update_tcp_checksum_(tcp, len, src, dst);
+    true
+}
+
+/// Adds the deltas to the sequence and acknowledgment number of `tcp`, the checksum is updated incrementally.
+#[inline]
+fn translate(tcp: &mut TcpHeader, seq_delta: u32, ack_delta: u32) {
+    if seq_delta != 0 {
+        let old = tcp.seq_num();
+        let new = old.wrapping_add(seq_delta);
+        tcp.set_seq_num(new);
+        tcp.update_checksum_incremental((old >> 16) as u16, (new >> 16) as u16);
+        tcp.update_checksum_incremental(old as u16, new as u16);
+    }
+    if ack_delta != 0 {
+        let old = tcp.ack_num();
+        let new = old.wrapping_add(ack_delta);
+        tcp.set_ack_num(new);
+        tcp.update_checksum_incremental((old >> 16) as u16, (new >> 16) as u16);
+        tcp.update_checksum_incremental(old as u16, new as u16);
+    }
+}
+
+/// A packet with the ACK `ack`, which goes in the direction of the segment at index `tcp` of `pdu`.
+fn ack_packet(pdu: &Pdu, tcp: usize, ack: &Ack) -> Option<*mut MBuf> {
+    let headers = pdu.headers();
+    let mut ip = IpHeader::new();
+    ip.set_src(headers.ip(tcp - 1).src());
+    ip.set_dst(headers.ip(tcp - 1).dst());
+    let mut header = ack_header(ack);
+    header.set_src_port(headers.tcp(tcp).src_port());
+    header.set_dst_port(headers.tcp(tcp).dst_port());
+    let mut builder = PacketBuilder::new();
+    if let Some(mac) = headers.get(0).as_mac() {
+        builder = builder.mac(*mac);
+    }
+    builder
+        .ipv4(ip)
+        .tcp(header)
+        .build()
+        .ok()
+        .map(|pdu| unsafe { pdu.get_mbuf() })
+}
+
+impl<V> Batch for SynProxyBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued()
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending()
+    }
+}
+
+impl<V> Act for SynProxyBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let mut count = 0;
+        let mut q_len = 0;
+        if !self.applied {
+            q_len = self.parent.act().1;
+            let now = unsafe { _rdtsc() };
+            {
+                let iter = PayloadEnumerator::new(&mut self.parent);
+                while let Some(ParsedDescriptor { index: idx, mut pdu }) = iter.next(&mut self.parent) {
+                    count += 1;
+                    let (tcp, header, action) = match tcp_segment(&pdu) {
+                        Some((tcp, flow, _)) => {
+                            let header = *pdu.headers().tcp(tcp);
+                            let action = self.proxy.process(&flow, &header, tcp_options(&pdu, tcp), now);
+                            (tcp, header, action)
+                        }
+                        None => continue,
+                    };
+                    let keep = match action {
+                        SynProxyAction::Pass => true,
+                        SynProxyAction::Drop => false,
+                        SynProxyAction::SynAck { cookie, mss } => {
+                            let mut syn_ack = TcpHeader::new();
+                            syn_ack.set_syn_flag();
+                            syn_ack.set_ack_flag();
+                            syn_ack.set_seq_num(cookie);
+                            syn_ack.set_ack_num(header.seq_num().wrapping_add(1));
+                            // the window of the client opens once the server accepted
+                            syn_ack.set_window_size(0);
+                            reshape(&mut pdu, tcp, syn_ack, &mss_option(mss), true)
+                        }
+                        SynProxyAction::Syn { seq, mss } => {
+                            let mut syn = TcpHeader::new();
+                            syn.set_syn_flag();
+                            syn.set_seq_num(seq);
+                            syn.set_window_size(header.window_size());
+                            reshape(&mut pdu, tcp, syn, &mss_option(mss), false)
+                        }
+                        SynProxyAction::Connected { server, client } => {
+                            match ack_packet(&pdu, tcp, &client) {
+                                Some(mbuf) => self.add.push(mbuf),
+                                None => self.dropped += 1,
+                            }
+                            reshape(&mut pdu, tcp, ack_header(&server), &[], true)
+                        }
+                        SynProxyAction::Translate { seq_delta, ack_delta } => {
+                            translate(pdu.headers_mut().tcp_mut(tcp), seq_delta, ack_delta);
+                            true
+                        }
+                    };
+                    if !keep {
+                        self.remove.push(idx);
+                    }
+                }
+            }
+            if !self.remove.is_empty() {
+                self.parent
+                    .drop_packets(&self.remove[..])
+                    .expect("SYN proxy dropped packets incorrectly");
+                self.remove.clear();
+            }
+            if !self.add.is_empty() {
+                let batch = self.parent.get_packet_batch();
+                for mbuf in self.add.drain(..) {
+                    if !batch.push(mbuf) {
+                        self.dropped_mbufs.push(mbuf);
+                    }
+                }
+            }
+            if !self.dropped_mbufs.is_empty() {
+                self.dropped += self.dropped_mbufs.len() as u64;
+                unsafe {
+                    mbuf_free_bulk(self.dropped_mbufs.as_mut_ptr(), self.dropped_mbufs.len() as i32);
+                }
+                self.dropped_mbufs.clear();
+            }
+            self.applied = true;
+        }
+        (count, q_len)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.applied = false;
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<V> BatchIterator for SynProxyBatch<V>
+where
+    V: Batch + BatchIterator + Act,
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
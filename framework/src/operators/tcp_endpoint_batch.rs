@@ -0,0 +1,240 @@
+use super::act::Act;
+use super::iterator::*;
+use super::packet_batch::PacketBatch;
+use super::Batch;
+use common::*;
+use headers::MacHeader;
+use interface::{PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+use neighbor::{ArpService, PendingPacket};
+use std::arch::x86_64::_rdtsc;
+use std::net::Ipv4Addr;
+use tcp::{tcp_options, tcp_segment, Segment, TcpStack};
+
+/// Terminates the TCP connections addressed to a `TcpStack`: their segments are removed from the batch and passed
+/// to the stack, then `app` runs on the stack, e.g. to accept connections and to exchange data over their sockets.
+/// The segments sent by the stack are resolved with an `ArpService` and sent out of `port`; segments for
+/// unresolved next hops wait in the neighbor cache and are released by `Batch::handle_arp`. All other packets pass
+/// unchanged.
+pub struct TcpEndpointBatch<Port, V, F>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+    F: FnMut(&mut TcpStack, u64),
+{
+    parent: V,
+    stack: TcpStack,
+    service: ArpService,
+    port: Port,
+    app: F,
+    remove: Vec<usize>,
+    segments: Vec<Segment>,
+    out: Vec<*mut MBuf>,
+    dropped_mbufs: Vec<*mut MBuf>,
+    applied: bool,
+    pub dropped: u64,
+}
+
+impl<Port, V, F> TcpEndpointBatch<Port, V, F>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+    F: FnMut(&mut TcpStack, u64),
+{
+    pub fn new(parent: V, stack: TcpStack, service: ArpService, port: Port, app: F) -> TcpEndpointBatch<Port, V, F> {
+        let capacity = parent.capacity() as usize;
+        TcpEndpointBatch {
+            parent,
+            stack,
+            service,
+            port,
+            app,
+            remove: Vec::with_capacity(capacity),
+            segments: Vec::with_capacity(capacity),
+            out: Vec::with_capacity(capacity),
+            dropped_mbufs: Vec::with_capacity(capacity),
+            applied: false,
+            dropped: 0,
+        }
+    }
+
+    #[inline]
+    pub fn stack(&self) -> &TcpStack {
+        &self.stack
+    }
+
+    #[inline]
+    pub fn stack_mut(&mut self) -> &mut TcpStack {
+        &mut self.stack
+    }
+
+    /// Build the packets of the segments of the stack.
+    fn transmit(&mut self, now: u64) {
+        let mut cache = self.service.cache();
+        for segment in self.segments.drain(..) {
+            let dst = Ipv4Addr::from(segment.flow.dst_ip);
+            let next_hop = match self.service.next_hop(dst) {
+                Some(next_hop) => next_hop,
+                None => {
+                    self.dropped += 1;
+                    continue;
+                }
+            };
+            let dmac = cache.lookup(&next_hop, now);
+            let mut mac = MacHeader::new();
+            mac.set_smac(&self.service.mac());
+            if let Some(dmac) = dmac {
+                mac.set_dmac(&dmac);
+            }
+            let mbuf = match segment.packet(mac).build() {
+                Ok(pdu) => unsafe { pdu.get_mbuf() },
+                Err(_) => {
+                    self.dropped += 1;
+                    continue;
+                }
+            };
+            if dmac.is_some() {
+                self.out.push(mbuf);
+                continue;
+            }
+            match cache.hold(next_hop, PendingPacket::new(mbuf), now) {
+                Ok(start) => {
+                    if start {
+                        if let Some(request) = self.service.request(next_hop) {
+                            self.out.push(request);
+                        }
+                    }
+                }
+                Err(packet) => self.dropped_mbufs.push(packet.into_mbuf()),
+            }
+        }
+    }
+
+    fn send_out(&mut self) {
+        if !self.out.is_empty() {
+            let sent = self.port.send(&mut self.out[..]).unwrap_or(0) as usize;
+            self.dropped_mbufs.extend_from_slice(&self.out[sent..]);
+            self.out.clear();
+        }
+        if !self.dropped_mbufs.is_empty() {
+            self.dropped += self.dropped_mbufs.len() as u64;
+            unsafe {
+                mbuf_free_bulk(self.dropped_mbufs.as_mut_ptr(), self.dropped_mbufs.len() as i32);
+            }
+            self.dropped_mbufs.clear();
+        }
+    }
+}
+
+impl<Port, V, F> Batch for TcpEndpointBatch<Port, V, F>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+    F: FnMut(&mut TcpStack, u64),
+{
+    #[inline]
+    fn queued(&self) -> usize {
+        self.parent.queued()
+    }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending() + self.port.pending()
+    }
+}
+
+impl<Port, V, F> Act for TcpEndpointBatch<Port, V, F>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+    F: FnMut(&mut TcpStack, u64),
+{
+    #[inline]
+    fn act(&mut self) -> (u32, i32) {
+        let mut count = 0;
+        let mut q_len = 0;
+        if !self.applied {
+            q_len = self.parent.act().1;
+            let now = unsafe { _rdtsc() };
+            {
+                let addr = u32::from(self.stack.addr());
+                let iter = PayloadEnumerator::new(&mut self.parent);
+                while let Some(ParsedDescriptor { index: idx, pdu }) = iter.next(&mut self.parent) {
+                    count += 1;
+                    if let Some((tcp, flow, payload)) = tcp_segment(&pdu) {
+                        if flow.dst_ip == addr {
+                            let options = tcp_options(&pdu, tcp);
+                            self.stack.receive(&flow, pdu.headers().tcp(tcp), options, payload, now);
+                            self.remove.push(idx);
+                        }
+                    }
+                }
+            }
+            if !self.remove.is_empty() {
+                self.parent
+                    .drop_packets(&self.remove[..])
+                    .expect("TCP endpoint dropped packets incorrectly");
+                self.remove.clear();
+            }
+            (self.app)(&mut self.stack, now);
+            self.stack.poll(now, &mut self.segments);
+            self.transmit(now);
+            self.send_out();
+            self.applied = true;
+        }
+        (count, q_len)
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.applied = false;
+        self.parent.done();
+    }
+
+    #[inline]
+    fn send_q(&mut self, port: &mut dyn PacketTx) -> errors::Result<u32> {
+        self.parent.send_q(port)
+    }
+
+    #[inline]
+    fn capacity(&self) -> i32 {
+        self.parent.capacity()
+    }
+
+    #[inline]
+    fn drop_packets(&mut self, idxes: &[usize]) -> Option<usize> {
+        self.parent.drop_packets(idxes)
+    }
+
+    #[inline]
+    fn drop_packets_all(&mut self) -> Option<usize> {
+        self.parent.drop_packets_all()
+    }
+
+    #[inline]
+    fn clear_packets(&mut self) {
+        self.parent.clear_packets()
+    }
+
+    #[inline]
+    fn get_packet_batch(&mut self) -> &mut PacketBatch {
+        self.parent.get_packet_batch()
+    }
+}
+
+impl<Port, V, F> BatchIterator for TcpEndpointBatch<Port, V, F>
+where
+    Port: PacketTx,
+    V: Batch + BatchIterator + Act,
+    F: FnMut(&mut TcpStack, u64),
+{
+    #[inline]
+    fn start(&mut self) -> usize {
+        self.parent.start()
+    }
+
+    #[inline]
+    fn next_payload(&mut self, idx: usize) -> Option<Pdu<'_>> {
+        self.parent.next_payload(idx)
+    }
+}
@@ -38,6 +38,11 @@ where
     fn queued(&self) -> usize {
         self.parent.queued()
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.parent.pending()
+    }
 }
 
 impl<V> BatchIterator for TransformBatch<V>
@@ -0,0 +1,524 @@
+use super::PipelineInstance;
+use common::errors;
+use common::errors::ErrorKind;
+use eui48::MacAddress;
+use fnv::FnvHasher;
+use interface::Pdu;
+use ipnet::Ipv4Net;
+use operators::*;
+use std::arch::x86_64::_rdtsc;
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use toml::Value;
+use utils::{checksum, tsc_hz, update_checksum_incremental, FiveTupleV4, Ipv4Prefix};
+
+type FnvHash = BuildHasherDefault<FnvHasher>;
+
+fn param_error(key: &str, value: &Value) -> ErrorKind {
+    ErrorKind::ConfigurationError(format!("Could not parse operator parameter {} = {}", key, value))
+}
+
+fn read_ipv4(params: &Value, key: &str) -> errors::Result<Option<Ipv4Addr>> {
+    match params.get(key) {
+        Some(Value::String(s)) => Ok(Some(s.parse::<Ipv4Addr>()?)),
+        Some(v) => Err(param_error(key, v)),
+        None => Ok(None),
+    }
+}
+
+/// Read an IPv4 prefix like "10.0.0.0/8", an address without prefix length is a /32.
+fn read_prefix(params: &Value, key: &str) -> errors::Result<Option<Ipv4Prefix>> {
+    match params.get(key) {
+        Some(Value::String(s)) => {
+            let net = if s.contains('/') {
+                s.parse::<Ipv4Net>()
+                    .map_err(|_| param_error(key, &Value::String(s.clone())))?
+            } else {
+                Ipv4Net::new(s.parse::<Ipv4Addr>()?, 32).unwrap()
+            };
+            Ok(Some(Ipv4Prefix::new(u32::from(net.addr()), net.prefix_len())))
+        }
+        Some(v) => Err(param_error(key, v)),
+        None => Ok(None),
+    }
+}
+
+fn read_port(params: &Value, key: &str) -> errors::Result<Option<u16>> {
+    match params.get(key) {
+        Some(&Value::Integer(port)) if (0..=0xffff).contains(&port) => Ok(Some(port as u16)),
+        Some(v) => Err(param_error(key, v)),
+        None => Ok(None),
+    }
+}
+
+fn read_u64(params: &Value, key: &str) -> errors::Result<Option<u64>> {
+    match params.get(key) {
+        Some(&Value::Integer(n)) if n >= 0 => Ok(Some(n as u64)),
+        Some(v) => Err(param_error(key, v)),
+        None => Ok(None),
+    }
+}
+
+fn read_bool(params: &Value, key: &str) -> errors::Result<Option<bool>> {
+    match params.get(key) {
+        Some(&Value::Boolean(b)) => Ok(Some(b)),
+        Some(v) => Err(param_error(key, v)),
+        None => Ok(None),
+    }
+}
+
+fn read_tables<'a>(params: &'a Value, key: &str) -> errors::Result<&'a [Value]> {
+    match params.get(key) {
+        Some(Value::Array(tables)) => match tables.iter().find(|t| !t.is_table()) {
+            Some(v) => Err(param_error(key, v)),
+            None => Ok(&tables[..]),
+        },
+        Some(v) => Err(param_error(key, v)),
+        None => Ok(&[]),
+    }
+}
+
+/// The IPv4 5-tuple of TCP and UDP packets.
+#[inline]
+fn ipv4_flow(p: &Pdu) -> Option<FiveTupleV4> {
+    let headers = p.headers();
+    (0..headers.count())
+        .filter_map(|i| headers.get(i).as_ip())
+        .next()
+        .and_then(|ip| ip.flow())
+}
+
+/// A rule of the "acl" operator, fields which are None match any packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AclRule {
+    pub src_ip: Option<Ipv4Prefix>,
+    pub dst_ip: Option<Ipv4Prefix>,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    /// match only flows in either direction of which packets were accepted before, or only other flows
+    pub established: Option<bool>,
+    pub drop: bool,
+}
+
+impl AclRule {
+    pub fn matches(&self, flow: &FiveTupleV4, connections: &AclConnections) -> bool {
+        if self.src_ip.is_none_or(|pfx| pfx.in_range(flow.src_ip))
+            && self.dst_ip.is_none_or(|pfx| pfx.in_range(flow.dst_ip))
+            && self.src_port.is_none_or(|port| port == flow.src_port)
+            && self.dst_port.is_none_or(|port| port == flow.dst_port)
+        {
+            match self.established {
+                Some(established) => connections.contains(flow) == established,
+                None => true,
+            }
+        } else {
+            false
+        }
+    }
+}
+
+const ACL_IDLE_TIMEOUT: u64 = 300_000;
+const ACL_MAX_CONNECTIONS: u64 = 65536;
+
+/// Flows of which packets were accepted by an "acl" operator. Flows idle for the idle timeout (ms) are forgotten,
+/// new flows are not recorded while `max_connections` flows are known.
+pub struct AclConnections {
+    idle_timeout: u64,
+    max_connections: usize,
+    last_seen: HashMap<FiveTupleV4, u64, FnvHash>,
+    last_expiry: u64,
+}
+
+impl AclConnections {
+    pub fn new(idle_timeout: u64, max_connections: usize, tsc_hz: u64) -> AclConnections {
+        AclConnections {
+            idle_timeout: (idle_timeout * tsc_hz / 1000).max(1),
+            max_connections,
+            last_seen: HashMap::with_hasher(Default::default()),
+            last_expiry: 0,
+        }
+    }
+
+    /// Whether packets of `flow` or of its reverse flow were accepted.
+    pub fn contains(&self, flow: &FiveTupleV4) -> bool {
+        self.last_seen.contains_key(flow) || self.last_seen.contains_key(&flow.reverse_flow())
+    }
+
+    /// Record that a packet of `flow` was accepted at the TSC time stamp `now`.
+    pub fn insert(&mut self, flow: FiveTupleV4, now: u64) {
+        self.expire(now);
+        if let Some(last_seen) = self.last_seen.get_mut(&flow) {
+            *last_seen = now;
+        } else if let Some(last_seen) = self.last_seen.get_mut(&flow.reverse_flow()) {
+            *last_seen = now;
+        } else if self.last_seen.len() < self.max_connections {
+            self.last_seen.insert(flow, now);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_seen.is_empty()
+    }
+
+    /// Forget the flows idle for the idle timeout, the table is scanned at most four times per timeout.
+    pub fn expire(&mut self, now: u64) {
+        if now.wrapping_sub(self.last_expiry) < self.idle_timeout / 4 {
+            return;
+        }
+        self.last_expiry = now;
+        let idle_timeout = self.idle_timeout;
+        self.last_seen
+            .retain(|_, last_seen| now.saturating_sub(*last_seen) < idle_timeout);
+    }
+}
+
+/// Read the rules of an "acl" operator, e.g.
+/// `rules = [ { src_ip = "10.0.0.0/8", dst_port = 80 }, { established = true }, { drop = true } ]`
+pub fn read_acl_rules(params: &Value) -> errors::Result<Vec<AclRule>> {
+    let mut rules = Vec::new();
+    for rule in read_tables(params, "rules")? {
+        rules.push(AclRule {
+            src_ip: read_prefix(rule, "src_ip")?,
+            dst_ip: read_prefix(rule, "dst_ip")?,
+            src_port: read_port(rule, "src_port")?,
+            dst_port: read_port(rule, "dst_port")?,
+            established: read_bool(rule, "established")?,
+            drop: read_bool(rule, "drop")?.unwrap_or(false),
+        });
+    }
+    Ok(rules)
+}
+
+/// Firewall: TCP and UDP packets over IPv4 pass if the first matching rule accepts them. All other packets are
+/// dropped. Accepted flows are established until they were idle for `idle_timeout` ms, at most `max_connections`
+/// flows are tracked.
+pub fn acl_factory(
+    parent: CompositionBatch,
+    params: &Value,
+    _: &mut PipelineInstance,
+) -> errors::Result<CompositionBatch> {
+    let rules = read_acl_rules(params)?;
+    let idle_timeout = read_u64(params, "idle_timeout")?.unwrap_or(ACL_IDLE_TIMEOUT);
+    let max_connections = read_u64(params, "max_connections")?.unwrap_or(ACL_MAX_CONNECTIONS) as usize;
+    // created with the first packet, the TSC frequency is not known before DPDK is initialized
+    let mut connections = None;
+    Ok(parent
+        .filter(Box::new(move |p| {
+            let flow = match ipv4_flow(p) {
+                Some(flow) => flow,
+                None => return false,
+            };
+            let connections =
+                connections.get_or_insert_with(|| AclConnections::new(idle_timeout, max_connections, tsc_hz()));
+            match rules.iter().find(|rule| rule.matches(&flow, connections)) {
+                Some(rule) if !rule.drop => {
+                    connections.insert(flow, unsafe { _rdtsc() });
+                    true
+                }
+                _ => false,
+            }
+        }))
+        .compose())
+}
+
+/// Longest prefix match of IPv4 addresses.
+#[derive(Clone, Debug)]
+pub struct Lpm<T> {
+    // indexed by prefix length
+    entries: Vec<HashMap<u32, T, FnvHash>>,
+}
+
+impl<T> Default for Lpm<T> {
+    fn default() -> Lpm<T> {
+        Lpm {
+            entries: (0..33).map(|_| Default::default()).collect(),
+        }
+    }
+}
+
+impl<T> Lpm<T> {
+    pub fn new() -> Lpm<T> {
+        Default::default()
+    }
+
+    pub fn insert(&mut self, prefix: Ipv4Prefix, value: T) -> Option<T> {
+        self.entries[prefix.prefix as usize].insert(prefix.ip_address, value)
+    }
+
+    pub fn remove(&mut self, prefix: Ipv4Prefix) -> Option<T> {
+        self.entries[prefix.prefix as usize].remove(&prefix.ip_address)
+    }
+
+    /// The value of the longest prefix containing `ip`.
+    #[inline]
+    pub fn lookup(&self, ip: u32) -> Option<&T> {
+        for len in (0..33).rev() {
+            let table = &self.entries[len];
+            if !table.is_empty() {
+                if let Some(value) = table.get(&Ipv4Prefix::new(ip, len as u8).ip_address) {
+                    return Some(value);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Read the routes of an "lpm" operator, e.g. `routes = [ { prefix = "10.0.0.0/8", mac = "02:00:00:00:00:01" } ]`
+pub fn read_lpm_routes(params: &Value) -> errors::Result<Lpm<MacAddress>> {
+    let mut lpm = Lpm::new();
+    for route in read_tables(params, "routes")? {
+        let prefix = match read_prefix(route, "prefix")? {
+            Some(prefix) => prefix,
+            None => {
+                return Err(ErrorKind::ConfigurationError(format!(
+                    "Route without prefix: {}",
+                    route
+                )))
+            }
+        };
+        let mac = match route.get("mac") {
+            Some(v @ &Value::String(_)) => v
+                .as_str()
+                .unwrap()
+                .parse::<MacAddress>()
+                .map_err(|_| param_error("mac", v))?,
+            _ => return Err(ErrorKind::ConfigurationError(format!("Route without mac: {}", route))),
+        };
+        lpm.insert(prefix, mac);
+    }
+    Ok(lpm)
+}
+
+/// Router: the destination MAC address of IPv4 packets is set to the MAC address of the route with the longest
+/// prefix matching their destination. Packets without route are dropped.
+pub fn lpm_factory(
+    parent: CompositionBatch,
+    params: &Value,
+    _: &mut PipelineInstance,
+) -> errors::Result<CompositionBatch> {
+    let routes = Arc::new(read_lpm_routes(params)?);
+    let lookup = routes.clone();
+    Ok(parent
+        .filter(Box::new(move |p| {
+            p.headers().count() > 1
+                && p.headers()
+                    .get(1)
+                    .as_ip()
+                    .is_some_and(|ip| lookup.lookup(ip.dst()).is_some())
+        }))
+        .transform(Box::new(move |p| {
+            let mac = *routes.lookup(p.headers().ip(1).dst()).unwrap();
+            p.headers_mut().mac_mut(0).set_dmac(&mac);
+        }))
+        .compose())
+}
+
+const NAT_MIN_PORT: u16 = 1024;
+const NAT_MAX_PORT: u16 = 65535;
+const NAT_PORTS: usize = (NAT_MAX_PORT - NAT_MIN_PORT) as usize + 1;
+const NAT_IDLE_TIMEOUT: u64 = 300_000;
+
+/// The port mappings of a "nat" operator. Mappings idle for the idle timeout (ms) are removed and their ports are
+/// reused.
+pub struct NatTable {
+    ip: u32,
+    idle_timeout: u64,
+    // translations of both directions of the mapped flows, with the port of their mapping
+    translations: HashMap<FiveTupleV4, (FiveTupleV4, u16), FnvHash>,
+    // the mapped flow and the time stamp its mapping was last used, indexed by port - NAT_MIN_PORT
+    mappings: Vec<Option<(FiveTupleV4, u64)>>,
+    free_ports: Vec<u16>,
+    last_expiry: u64,
+}
+
+impl NatTable {
+    pub fn new(ip: u32, idle_timeout: u64, tsc_hz: u64) -> NatTable {
+        NatTable {
+            ip,
+            idle_timeout: (idle_timeout * tsc_hz / 1000).max(1),
+            translations: HashMap::with_capacity_and_hasher(2 * NAT_PORTS, Default::default()),
+            mappings: vec![None; NAT_PORTS],
+            free_ports: (NAT_MIN_PORT..=NAT_MAX_PORT).rev().collect(),
+            last_expiry: 0,
+        }
+    }
+
+    /// The translation of `flow` at the TSC time stamp `now`, a mapping is created for an unknown flow. None if the
+    /// flow is unknown and all ports are assigned.
+    pub fn translate(&mut self, flow: &FiveTupleV4, now: u64) -> Option<FiveTupleV4> {
+        self.expire(now);
+        if let Some(&(translated, port)) = self.translations.get(flow) {
+            if let Some((_, ref mut last_seen)) = self.mappings[(port - NAT_MIN_PORT) as usize] {
+                *last_seen = now;
+            }
+            return Some(translated);
+        }
+        let port = self.free_ports.pop()?;
+        let mut outgoing = *flow;
+        outgoing.src_ip = self.ip;
+        outgoing.src_port = port;
+        self.translations.insert(*flow, (outgoing, port));
+        self.translations
+            .insert(outgoing.reverse_flow(), (flow.reverse_flow(), port));
+        self.mappings[(port - NAT_MIN_PORT) as usize] = Some((*flow, now));
+        Some(outgoing)
+    }
+
+    /// The translation of `flow` if it is mapped.
+    pub fn lookup(&self, flow: &FiveTupleV4) -> Option<FiveTupleV4> {
+        self.translations.get(flow).map(|&(translated, _)| translated)
+    }
+
+    /// The number of mapped flows.
+    pub fn len(&self) -> usize {
+        NAT_PORTS - self.free_ports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove the mappings idle for the idle timeout, the table is scanned at most four times per timeout.
+    pub fn expire(&mut self, now: u64) {
+        if now.wrapping_sub(self.last_expiry) < self.idle_timeout / 4 {
+            return;
+        }
+        self.last_expiry = now;
+        for (i, mapping) in self.mappings.iter_mut().enumerate() {
+            if let Some((flow, last_seen)) = *mapping {
+                if now.saturating_sub(last_seen) >= self.idle_timeout {
+                    if let Some((outgoing, _)) = self.translations.remove(&flow) {
+                        self.translations.remove(&outgoing.reverse_flow());
+                    }
+                    self.free_ports.push(NAT_MIN_PORT + i as u16);
+                    *mapping = None;
+                }
+            }
+        }
+    }
+}
+
+/// The folded sum of the addresses and ports of the IPv4 packet `bytes`.
+#[inline]
+fn ipv4_flow_sum(bytes: &[u8], port_start: usize) -> u16 {
+    let mut flow = [0u8; 12];
+    flow[..8].copy_from_slice(&bytes[12..20]);
+    flow[8..].copy_from_slice(&bytes[port_start..port_start + 4]);
+    !checksum(&flow, usize::MAX)
+}
+
+/// Rewrite the flow of the IPv4 packet `bytes` to `flow` and update the IPv4 and the TCP or UDP checksum.
+pub fn ipv4_rewrite_flow(bytes: &mut [u8], flow: &FiveTupleV4) {
+    let port_start = (bytes[0] & 0xf) as usize * 4;
+    let csum_at = match bytes[9] {
+        6 => port_start + 16,
+        17 => port_start + 6,
+        _ => 0,
+    };
+    let old_sum = ipv4_flow_sum(bytes, port_start);
+    flow.ipv4_stamp_flow(bytes);
+    if csum_at > 0 && bytes.len() >= csum_at + 2 {
+        let old_csum = (bytes[csum_at] as u16) << 8 | bytes[csum_at + 1] as u16;
+        // a zero UDP checksum is not computed
+        if bytes[9] == 6 || old_csum != 0 {
+            let csum = update_checksum_incremental(old_csum, old_sum, ipv4_flow_sum(bytes, port_start));
+            bytes[csum_at] = (csum >> 8) as u8;
+            bytes[csum_at + 1] = csum as u8;
+        }
+    }
+}
+
+/// Source NAT: TCP and UDP flows over IPv4 get `ip` and a port of their own as source, packets of the reverse flows
+/// are translated back. Mappings idle for `idle_timeout` ms are removed, while all ports are assigned packets of new
+/// flows are dropped.
+pub fn nat_factory(
+    parent: CompositionBatch,
+    params: &Value,
+    _: &mut PipelineInstance,
+) -> errors::Result<CompositionBatch> {
+    let ip = match read_ipv4(params, "ip")? {
+        Some(ip) => u32::from(ip),
+        None => return Err(ErrorKind::ConfigurationError(String::from("nat operator without ip"))),
+    };
+    let idle_timeout = read_u64(params, "idle_timeout")?.unwrap_or(NAT_IDLE_TIMEOUT);
+    // created with the first packet, the TSC frequency is not known before DPDK is initialized
+    let table = Arc::new(Mutex::new(None::<NatTable>));
+    let mapper = table.clone();
+    Ok(parent
+        .filter(Box::new(move |p| match ipv4_flow(p) {
+            Some(flow) => mapper
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| NatTable::new(ip, idle_timeout, tsc_hz()))
+                .translate(&flow, unsafe { _rdtsc() })
+                .is_some(),
+            None => true,
+        }))
+        .transform(Box::new(move |p| {
+            let flow = match ipv4_flow(p) {
+                Some(flow) => flow,
+                None => return,
+            };
+            let which = match (0..p.headers().count()).find(|i| p.headers().get(*i).as_ip().is_some()) {
+                Some(i) if i > 0 => i - 1,
+                _ => return,
+            };
+            let translated = match table.lock().unwrap().as_ref().and_then(|t| t.lookup(&flow)) {
+                Some(translated) => translated,
+                None => return,
+            };
+            ipv4_rewrite_flow(p.get_payload_mut(which), &translated);
+        }))
+        .compose())
+}
+
+/// Swap the MAC addresses.
+pub fn macswap_factory(
+    parent: CompositionBatch,
+    _: &Value,
+    _: &mut PipelineInstance,
+) -> errors::Result<CompositionBatch> {
+    Ok(parent
+        .transform(Box::new(|p| p.headers_mut().mac_mut(0).swap_addresses()))
+        .compose())
+}
+
+/// Decrement the TTL of IPv4 packets, time exceeded errors from `src` are sent out of the receiving queue.
+pub fn decrement_ttl_factory(
+    parent: CompositionBatch,
+    params: &Value,
+    instance: &mut PipelineInstance,
+) -> errors::Result<CompositionBatch> {
+    match read_ipv4(params, "src")? {
+        Some(src) => Ok(parent.decrement_ttl(instance.rx.clone(), src).compose()),
+        None => Err(ErrorKind::ConfigurationError(String::from(
+            "decrement_ttl operator without src",
+        ))),
+    }
+}
+
+/// Answer ICMP echo requests for `addr` out of the receiving queue.
+pub fn answer_echo_factory(
+    parent: CompositionBatch,
+    params: &Value,
+    instance: &mut PipelineInstance,
+) -> errors::Result<CompositionBatch> {
+    match read_ipv4(params, "addr")? {
+        Some(addr) => Ok(parent.answer_echo(instance.rx.clone(), addr).compose()),
+        None => Err(ErrorKind::ConfigurationError(String::from(
+            "answer_echo operator without addr",
+        ))),
+    }
+}
+
+/// Drop all packets.
+pub fn drop_factory(parent: CompositionBatch, _: &Value, _: &mut PipelineInstance) -> errors::Result<CompositionBatch> {
+    Ok(parent.drop().compose())
+}
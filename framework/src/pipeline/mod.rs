@@ -0,0 +1,127 @@
+//! Pipelines built from their declaration in the configuration, see `PipelineConfiguration` and
+//! `NetBricksContext::install_pipelines`. Each operator of a declared pipeline is created by the factory registered
+//! for its kind in an `OperatorRegistry`.
+pub use self::builtin::*;
+
+use allocators::CacheAligned;
+use common::errors;
+use common::errors::ErrorKind;
+use config::OperatorConfiguration;
+use interface::PortQueue;
+use operators::{Batch, CompositionBatch, ReceiveBatch};
+use scheduler::StandaloneScheduler;
+use std::collections::HashMap;
+use std::sync::mpsc::channel;
+use toml::Value;
+
+mod builtin;
+
+/// The instance of a pipeline an operator is created for. Operators which emit packets of their own, e.g. ICMP
+/// errors, send them out of `rx`.
+pub struct PipelineInstance<'a> {
+    pub name: &'a str,
+    pub core: i32,
+    pub rx: &'a CacheAligned<PortQueue>,
+    pub tx: &'a CacheAligned<PortQueue>,
+    pub scheduler: &'a mut StandaloneScheduler,
+}
+
+/// Creates an operator on top of `parent` from its parameters, a TOML table.
+pub type OperatorFactory =
+    Box<dyn Fn(CompositionBatch, &Value, &mut PipelineInstance) -> errors::Result<CompositionBatch> + Send + Sync>;
+
+/// Maps operator kinds to the factories creating them.
+#[derive(Default)]
+pub struct OperatorRegistry {
+    factories: HashMap<String, OperatorFactory>,
+}
+
+impl OperatorRegistry {
+    /// An empty registry.
+    pub fn new() -> OperatorRegistry {
+        OperatorRegistry {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// A registry with the built-in operators: "acl", "lpm", "nat", "macswap", "decrement_ttl", "answer_echo" and
+    /// "drop".
+    pub fn with_builtins() -> OperatorRegistry {
+        let mut registry = OperatorRegistry::new();
+        registry.register("acl", acl_factory);
+        registry.register("lpm", lpm_factory);
+        registry.register("nat", nat_factory);
+        registry.register("macswap", macswap_factory);
+        registry.register("decrement_ttl", decrement_ttl_factory);
+        registry.register("answer_echo", answer_echo_factory);
+        registry.register("drop", drop_factory);
+        registry
+    }
+
+    /// Register `factory` for operators of `kind`, replacing and returning a factory registered before.
+    pub fn register<F>(&mut self, kind: &str, factory: F) -> Option<OperatorFactory>
+    where
+        F: Fn(CompositionBatch, &Value, &mut PipelineInstance) -> errors::Result<CompositionBatch>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.factories.insert(kind.to_string(), Box::new(factory))
+    }
+
+    #[inline]
+    pub fn contains(&self, kind: &str) -> bool {
+        self.factories.contains_key(kind)
+    }
+
+    /// Check that the `operators` of the pipeline `name` can be built on `core`: their kinds are registered and
+    /// their factories accept the parameters. The operators are built on a scheduler of their own, which is dropped
+    /// together with them.
+    pub fn validate(
+        &self,
+        name: &str,
+        core: i32,
+        rx: &CacheAligned<PortQueue>,
+        tx: &CacheAligned<PortQueue>,
+        operators: &[OperatorConfiguration],
+    ) -> errors::Result<()> {
+        if let Some(operator) = operators.iter().find(|o| !self.contains(&o.kind)) {
+            return Err(ErrorKind::ConfigurationError(format!(
+                "Unknown operator {} in pipeline {}",
+                operator.kind, name
+            )));
+        }
+        let (_, receiver) = channel();
+        let (sender, _) = channel();
+        let mut scheduler = StandaloneScheduler::new_with_channel(core, receiver, sender);
+        let mut instance = PipelineInstance {
+            name,
+            core,
+            rx,
+            tx,
+            scheduler: &mut scheduler,
+        };
+        self.build(ReceiveBatch::new(rx.clone()).compose(), operators, &mut instance)
+            .map(|_| ())
+    }
+
+    /// Chain the `operators` on top of `parent`.
+    pub fn build(
+        &self,
+        parent: CompositionBatch,
+        operators: &[OperatorConfiguration],
+        instance: &mut PipelineInstance,
+    ) -> errors::Result<CompositionBatch> {
+        let mut batch = parent;
+        for operator in operators {
+            let factory = self.factories.get(&operator.kind).ok_or_else(|| {
+                ErrorKind::ConfigurationError(format!(
+                    "Unknown operator {} in pipeline {}",
+                    operator.kind, instance.name
+                ))
+            })?;
+            batch = factory(batch, &operator.params, instance)?;
+        }
+        Ok(batch)
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+
+/// Active queue management of a leaf queue of the `HqosScheduler`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aqm {
+    /// drop arriving packets only when the queue is full
+    TailDrop,
+    /// Random Early Detection on the average queue length in packets, `wq` is the weight of the moving average
+    Red {
+        min_th: usize,
+        max_th: usize,
+        max_p: f64,
+        wq: f64,
+    },
+    /// Controlled Delay (RFC 8289) on the sojourn time of the packets, `target` and `interval` in microseconds
+    CoDel { target: u64, interval: u64 },
+}
+
+struct RedState {
+    min_th: f64,
+    max_th: f64,
+    max_p: f64,
+    wq: f64,
+    avg: f64,
+    // packets since the last drop
+    count: i64,
+    rng: u64,
+}
+
+impl RedState {
+    #[inline]
+    fn random(&mut self) -> f64 {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn drop_on_enqueue(&mut self, q_len: usize) -> bool {
+        self.avg = (1.0 - self.wq) * self.avg + self.wq * q_len as f64;
+        if self.avg < self.min_th {
+            self.count = -1;
+            false
+        } else if self.avg >= self.max_th {
+            self.count = 0;
+            true
+        } else {
+            self.count += 1;
+            let pb = self.max_p * (self.avg - self.min_th) / (self.max_th - self.min_th);
+            let denominator = 1.0 - self.count as f64 * pb;
+            let pa = if denominator <= 0.0 { 1.0 } else { pb / denominator };
+            if self.random() < pa {
+                self.count = 0;
+                true
+            } else {
+                false
+            }
+        }
+    }
+}
+
+struct CoDelState {
+    target: u64,
+    interval: u64,
+    first_above_time: u64,
+    drop_next: u64,
+    count: u32,
+    dropping: bool,
+}
+
+impl CoDelState {
+    #[inline]
+    fn control_law(&self, t: u64) -> u64 {
+        t + (self.interval as f64 / (self.count as f64).sqrt()) as u64
+    }
+
+    #[inline]
+    fn ok_to_drop(&mut self, sojourn: u64, now: u64, remaining: usize) -> bool {
+        if sojourn < self.target || remaining == 0 {
+            self.first_above_time = 0;
+            false
+        } else if self.first_above_time == 0 {
+            self.first_above_time = now + self.interval;
+            false
+        } else {
+            now >= self.first_above_time
+        }
+    }
+}
+
+enum AqmState {
+    TailDrop,
+    Red(RedState),
+    CoDel(CoDelState),
+}
+
+/// A leaf queue of the `HqosScheduler`, holding packets with their length and enqueue time stamp.
+pub(crate) struct AqmQueue<T> {
+    queue: VecDeque<(T, usize, u64)>,
+    limit: usize,
+    state: AqmState,
+}
+
+impl<T> AqmQueue<T> {
+    pub fn new(limit: usize, aqm: Aqm, tsc_hz: u64, seed: u64) -> AqmQueue<T> {
+        let state = match aqm {
+            Aqm::TailDrop => AqmState::TailDrop,
+            Aqm::Red {
+                min_th,
+                max_th,
+                max_p,
+                wq,
+            } => AqmState::Red(RedState {
+                min_th: min_th as f64,
+                max_th: max_th as f64,
+                max_p,
+                wq,
+                avg: 0.0,
+                count: -1,
+                rng: seed | 1,
+            }),
+            Aqm::CoDel { target, interval } => AqmState::CoDel(CoDelState {
+                target: target * tsc_hz / 1_000_000,
+                interval: interval * tsc_hz / 1_000_000,
+                first_above_time: 0,
+                drop_next: 0,
+                count: 0,
+                dropping: false,
+            }),
+        };
+        AqmQueue {
+            queue: VecDeque::with_capacity(limit),
+            limit,
+            state,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Enqueues a packet of `len` bytes, hands it back if it is dropped.
+    pub fn enqueue(&mut self, item: T, len: usize, now: u64) -> Result<(), T> {
+        if self.queue.len() >= self.limit {
+            return Err(item);
+        }
+        let q_len = self.queue.len();
+        if let AqmState::Red(ref mut red) = self.state {
+            if red.drop_on_enqueue(q_len) {
+                return Err(item);
+            }
+        }
+        self.queue.push_back((item, len, now));
+        Ok(())
+    }
+
+    /// Dequeues the next packet. Packets dropped by the AQM on dequeue are appended to `dropped`.
+    pub fn dequeue(&mut self, now: u64, dropped: &mut Vec<T>) -> Option<(T, usize)> {
+        match self.state {
+            AqmState::CoDel(ref mut codel) => {
+                let mut next = self.queue.pop_front();
+                loop {
+                    let (item, len, stamp) = match next {
+                        Some(p) => p,
+                        None => {
+                            codel.dropping = false;
+                            return None;
+                        }
+                    };
+                    let ok_to_drop = codel.ok_to_drop(now.saturating_sub(stamp), now, self.queue.len());
+                    if codel.dropping {
+                        if !ok_to_drop {
+                            codel.dropping = false;
+                        } else if now >= codel.drop_next {
+                            dropped.push(item);
+                            codel.count += 1;
+                            codel.drop_next = codel.control_law(codel.drop_next);
+                            next = self.queue.pop_front();
+                            continue;
+                        }
+                    } else if ok_to_drop {
+                        dropped.push(item);
+                        codel.dropping = true;
+                        let delta = codel.count.saturating_sub(2);
+                        codel.count = if delta > 0 && now.saturating_sub(codel.drop_next) < 16 * codel.interval {
+                            delta
+                        } else {
+                            1
+                        };
+                        codel.drop_next = codel.control_law(now);
+                        next = self.queue.pop_front();
+                        continue;
+                    }
+                    return Some((item, len));
+                }
+            }
+            _ => self.queue.pop_front().map(|(item, len, _)| (item, len)),
+        }
+    }
+
+    /// Removes all packets from the queue.
+    pub fn drain(&mut self, dropped: &mut Vec<T>) {
+        dropped.extend(self.queue.drain(..).map(|(item, _, _)| item));
+    }
+}
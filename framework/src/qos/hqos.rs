@@ -0,0 +1,423 @@
+use super::aqm::{Aqm, AqmQueue};
+use std::cmp;
+use std::mem;
+
+/// A rate limit, `rate` in bytes per second and `burst` in bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub rate: u64,
+    pub burst: u64,
+}
+
+/// A subscriber group below the port.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroupConf {
+    pub weight: u32,
+    pub rate: Option<Rate>,
+}
+
+/// A subscriber, belonging to the subscriber group with index `group`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubscriberConf {
+    pub group: usize,
+    pub weight: u32,
+    pub rate: Option<Rate>,
+}
+
+/// A traffic class, each subscriber has one queue per traffic class. Classes with a lower `priority` value are
+/// served strictly before those with a higher value, classes with the same priority share by `weight`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClassConf {
+    pub priority: u8,
+    pub weight: u32,
+    pub queue_len: usize,
+    pub aqm: Aqm,
+    pub rate: Option<Rate>,
+}
+
+/// The hierarchy of an `HqosScheduler`: port, subscriber groups, subscribers and traffic classes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HqosConf {
+    pub port_rate: Option<Rate>,
+    pub groups: Vec<GroupConf>,
+    pub subscribers: Vec<SubscriberConf>,
+    pub classes: Vec<ClassConf>,
+    /// bytes granted per unit of weight in each round of the deficit round robin
+    pub quantum: u32,
+}
+
+impl Default for HqosConf {
+    fn default() -> HqosConf {
+        HqosConf {
+            port_rate: None,
+            groups: vec![],
+            subscribers: vec![],
+            classes: vec![],
+            quantum: 1514,
+        }
+    }
+}
+
+/// Counters of a leaf queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueueStats {
+    pub enqueued: u64,
+    pub dequeued: u64,
+    pub dropped: u64,
+    pub len: usize,
+}
+
+/// A token bucket which may run into debt by one packet: a node conforms as long as it has positive credit,
+/// so the length of the next packet need not be known when selecting a node.
+struct RateLimit {
+    rate: i128,
+    burst: i128,
+    credit: i128,
+    tsc_hz: i128,
+    last: u64,
+}
+
+impl RateLimit {
+    fn new(rate: &Rate, tsc_hz: u64) -> RateLimit {
+        let burst = rate.burst as i128 * tsc_hz as i128;
+        RateLimit {
+            rate: rate.rate as i128,
+            burst,
+            credit: burst,
+            tsc_hz: tsc_hz as i128,
+            last: 0,
+        }
+    }
+
+    #[inline]
+    fn conforms(&mut self, now: u64) -> bool {
+        if now > self.last {
+            self.credit = cmp::min(self.burst, self.credit + (now - self.last) as i128 * self.rate);
+            self.last = now;
+        }
+        self.credit > 0
+    }
+
+    #[inline]
+    fn charge(&mut self, len: usize) {
+        self.credit -= len as i128 * self.tsc_hz;
+    }
+}
+
+struct Node {
+    limit: Option<RateLimit>,
+    quantum: i64,
+    deficit: i64,
+    // packets queued below this node
+    backlog: usize,
+}
+
+impl Node {
+    fn new(weight: u32, quantum: u32, rate: &Option<Rate>, tsc_hz: u64) -> Node {
+        let quantum = cmp::max(1, weight as i64) * quantum as i64;
+        Node {
+            limit: rate.as_ref().map(|r| RateLimit::new(r, tsc_hz)),
+            quantum,
+            deficit: quantum,
+            backlog: 0,
+        }
+    }
+
+    #[inline]
+    fn eligible(&mut self, now: u64) -> bool {
+        self.backlog > 0 && self.limit.as_mut().is_none_or(|l| l.conforms(now))
+    }
+
+    /// Accounts a packet of `len` bytes sent by this node, returns true if the round robin should move on.
+    #[inline]
+    fn charge(&mut self, len: usize) -> bool {
+        if let Some(ref mut l) = self.limit {
+            l.charge(len);
+        }
+        self.deficit -= len as i64;
+        if self.backlog == 0 {
+            self.deficit = self.quantum;
+            true
+        } else if self.deficit <= 0 {
+            self.deficit += self.quantum;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Deficit round robin position within a list of children.
+#[derive(Clone, Copy, Default)]
+struct RoundRobin {
+    next: usize,
+}
+
+impl RoundRobin {
+    /// Positions of the children in the order in which they are tried.
+    #[inline]
+    fn order(&self, n: usize) -> impl Iterator<Item = usize> {
+        let next = self.next;
+        (0..n).map(move |i| (next + i) % n)
+    }
+
+    #[inline]
+    fn served(&mut self, pos: usize, n: usize, move_on: bool) {
+        self.next = if move_on { (pos + 1) % n } else { pos };
+    }
+}
+
+/// Path through the hierarchy selected for the next packet, with the positions within the round robins.
+struct Selection {
+    group: usize,
+    member_pos: usize,
+    subscriber: usize,
+    level: usize,
+    class_pos: usize,
+    class: usize,
+}
+
+/// A hierarchical QoS scheduler with the levels port, subscriber group, subscriber and traffic class. Groups and
+/// subscribers are served by deficit round robin according to their weights, traffic classes by strict priority
+/// and deficit round robin within the same priority. Each node may have a rate limit and each leaf queue an AQM.
+/// Time stamps are TSC values, packets are opaque items with a length.
+pub struct HqosScheduler<T> {
+    port: Node,
+    port_rr: RoundRobin,
+    groups: Vec<Node>,
+    // subscribers of each group and the round robin among them
+    group_members: Vec<Vec<usize>>,
+    group_rr: Vec<RoundRobin>,
+    subscribers: Vec<Node>,
+    subscriber_group: Vec<Option<usize>>,
+    // class indices per priority level, in ascending priority values
+    levels: Vec<Vec<usize>>,
+    // round robin per subscriber and priority level
+    level_rr: Vec<RoundRobin>,
+    // one node and queue per subscriber and class, index subscriber * n_classes + class
+    classes: Vec<Node>,
+    queues: Vec<AqmQueue<T>>,
+    stats: Vec<QueueStats>,
+    n_classes: usize,
+    // packets dropped by the AQM while dequeuing, to be freed by the owner
+    dropped: Vec<T>,
+}
+
+impl<T> HqosScheduler<T> {
+    pub fn new(conf: &HqosConf, tsc_hz: u64) -> HqosScheduler<T> {
+        let mut group_members = vec![Vec::new(); conf.groups.len()];
+        let mut subscriber_group = Vec::with_capacity(conf.subscribers.len());
+        for (s, sub) in conf.subscribers.iter().enumerate() {
+            if sub.group < group_members.len() {
+                group_members[sub.group].push(s);
+                subscriber_group.push(Some(sub.group));
+            } else {
+                warn!("subscriber {} refers to unknown group {}, ignored", s, sub.group);
+                subscriber_group.push(None);
+            }
+        }
+        let mut priorities: Vec<u8> = conf.classes.iter().map(|c| c.priority).collect();
+        priorities.sort();
+        priorities.dedup();
+        let levels: Vec<Vec<usize>> = priorities
+            .iter()
+            .map(|p| {
+                (0..conf.classes.len())
+                    .filter(|c| conf.classes[*c].priority == *p)
+                    .collect()
+            })
+            .collect();
+        let n_classes = conf.classes.len();
+        let n_leafs = conf.subscribers.len() * n_classes;
+        let mut classes = Vec::with_capacity(n_leafs);
+        let mut queues = Vec::with_capacity(n_leafs);
+        for s in 0..conf.subscribers.len() {
+            for (c, class) in conf.classes.iter().enumerate() {
+                classes.push(Node::new(class.weight, conf.quantum, &class.rate, tsc_hz));
+                queues.push(AqmQueue::new(
+                    class.queue_len,
+                    class.aqm,
+                    tsc_hz,
+                    ((s * n_classes + c + 1) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+                ));
+            }
+        }
+        HqosScheduler {
+            port: Node::new(1, conf.quantum, &conf.port_rate, tsc_hz),
+            port_rr: RoundRobin::default(),
+            groups: conf
+                .groups
+                .iter()
+                .map(|g| Node::new(g.weight, conf.quantum, &g.rate, tsc_hz))
+                .collect(),
+            group_rr: vec![RoundRobin::default(); conf.groups.len()],
+            group_members,
+            subscribers: conf
+                .subscribers
+                .iter()
+                .map(|s| Node::new(s.weight, conf.quantum, &s.rate, tsc_hz))
+                .collect(),
+            subscriber_group,
+            level_rr: vec![RoundRobin::default(); conf.subscribers.len() * levels.len()],
+            levels,
+            classes,
+            queues,
+            stats: vec![QueueStats::default(); n_leafs],
+            n_classes,
+            dropped: Vec::new(),
+        }
+    }
+
+    /// Number of packets held by the scheduler.
+    #[inline]
+    pub fn backlog(&self) -> usize {
+        self.port.backlog
+    }
+
+    pub fn queue_stats(&self, subscriber: usize, class: usize) -> Option<QueueStats> {
+        if class < self.n_classes {
+            self.stats.get(subscriber * self.n_classes + class).map(|s| QueueStats {
+                len: self.queues[subscriber * self.n_classes + class].len(),
+                ..*s
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Packets dropped by the AQM since the last call, the caller becomes their owner.
+    #[inline]
+    pub fn take_dropped(&mut self) -> Vec<T> {
+        mem::take(&mut self.dropped)
+    }
+
+    /// Enqueues a packet of `len` bytes for `class` of `subscriber` at time `now`, hands it back if it is dropped.
+    pub fn enqueue(&mut self, subscriber: usize, class: usize, item: T, len: usize, now: u64) -> Result<(), T> {
+        let group = match self.subscriber_group.get(subscriber) {
+            Some(&Some(group)) if class < self.n_classes => group,
+            _ => return Err(item),
+        };
+        let leaf = subscriber * self.n_classes + class;
+        match self.queues[leaf].enqueue(item, len, now) {
+            Ok(()) => {
+                self.stats[leaf].enqueued += 1;
+                self.classes[leaf].backlog += 1;
+                self.subscribers[subscriber].backlog += 1;
+                self.groups[group].backlog += 1;
+                self.port.backlog += 1;
+                Ok(())
+            }
+            Err(item) => {
+                self.stats[leaf].dropped += 1;
+                Err(item)
+            }
+        }
+    }
+
+    #[inline]
+    fn sub_backlog(&mut self, group: usize, subscriber: usize, class: usize, n: usize) {
+        self.classes[subscriber * self.n_classes + class].backlog -= n;
+        self.subscribers[subscriber].backlog -= n;
+        self.groups[group].backlog -= n;
+        self.port.backlog -= n;
+    }
+
+    /// Selects the next class of `subscriber` by strict priority and round robin, returns (level, position, class).
+    fn select_class(&mut self, subscriber: usize, now: u64) -> Option<(usize, usize, usize)> {
+        for (level, members) in self.levels.iter().enumerate() {
+            let rr = self.level_rr[subscriber * self.levels.len() + level];
+            for pos in rr.order(members.len()) {
+                let class = members[pos];
+                if self.classes[subscriber * self.n_classes + class].eligible(now) {
+                    return Some((level, pos, class));
+                }
+            }
+        }
+        None
+    }
+
+    /// Selects group, subscriber and class of the next packet.
+    fn select(&mut self, now: u64) -> Option<Selection> {
+        if !self.port.eligible(now) {
+            return None;
+        }
+        let n_groups = self.groups.len();
+        for g_pos in self.port_rr.order(n_groups) {
+            if !self.groups[g_pos].eligible(now) {
+                continue;
+            }
+            let n_members = self.group_members[g_pos].len();
+            for s_pos in self.group_rr[g_pos].order(n_members) {
+                let subscriber = self.group_members[g_pos][s_pos];
+                if !self.subscribers[subscriber].eligible(now) {
+                    continue;
+                }
+                if let Some((level, class_pos, class)) = self.select_class(subscriber, now) {
+                    return Some(Selection {
+                        group: g_pos,
+                        member_pos: s_pos,
+                        subscriber,
+                        level,
+                        class_pos,
+                        class,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// Dequeues the next packet which conforms to the rate limits at time `now`.
+    pub fn dequeue(&mut self, now: u64) -> Option<T> {
+        loop {
+            let Selection {
+                group,
+                member_pos,
+                subscriber,
+                level,
+                class_pos,
+                class,
+            } = self.select(now)?;
+            let leaf = subscriber * self.n_classes + class;
+            let n_dropped = self.dropped.len();
+            let packet = self.queues[leaf].dequeue(now, &mut self.dropped);
+            let n_dropped = self.dropped.len() - n_dropped;
+            self.stats[leaf].dropped += n_dropped as u64;
+            let n_removed = n_dropped + if packet.is_some() { 1 } else { 0 };
+            self.sub_backlog(group, subscriber, class, n_removed);
+            if let Some((item, len)) = packet {
+                self.stats[leaf].dequeued += 1;
+                let n_levels = self.levels.len();
+                let move_on = self.classes[leaf].charge(len);
+                let level_members = self.levels[level].len();
+                self.level_rr[subscriber * n_levels + level].served(class_pos, level_members, move_on);
+                let move_on = self.subscribers[subscriber].charge(len);
+                let n_members = self.group_members[group].len();
+                self.group_rr[group].served(member_pos, n_members, move_on);
+                let move_on = self.groups[group].charge(len);
+                let n_groups = self.groups.len();
+                self.port_rr.served(group, n_groups, move_on);
+                self.port.charge(len);
+                return Some(item);
+            }
+        }
+    }
+
+    /// Removes all packets from the scheduler and returns them to the caller.
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut items = self.take_dropped();
+        for queue in self.queues.iter_mut() {
+            queue.drain(&mut items);
+        }
+        for node in self
+            .classes
+            .iter_mut()
+            .chain(self.subscribers.iter_mut())
+            .chain(self.groups.iter_mut())
+        {
+            node.backlog = 0;
+        }
+        self.port.backlog = 0;
+        items
+    }
+}
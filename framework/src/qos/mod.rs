@@ -0,0 +1,112 @@
+//! Egress quality of service: a hierarchical scheduler with port, subscriber group, subscriber and traffic
+//! class levels, which is put in front of a `PacketTx`.
+pub use self::aqm::Aqm;
+pub use self::hqos::*;
+
+use common::errors;
+use interface::{PacketTx, Pdu};
+use native::zcsi::{mbuf_free_bulk, MBuf};
+use std::arch::x86_64::_rdtsc;
+
+mod aqm;
+mod hqos;
+
+/// Classifies a packet into (subscriber, traffic class) of the `HqosScheduler`.
+pub type QosClassifyFn = Box<dyn FnMut(&mut Pdu) -> (usize, usize)>;
+
+/// A `PacketTx` which queues packets in an `HqosScheduler` and releases them to `tx` in the order and at the
+/// rates of the scheduler. Use it as port of `Batch::send`, the `SendBatch` flushes it also when there is no
+/// input. Packets which cannot be classified or are dropped by the AQM are freed.
+pub struct HqosTx<Tx: PacketTx> {
+    tx: Tx,
+    hqos: HqosScheduler<*mut MBuf>,
+    classify: QosClassifyFn,
+    burst: usize,
+    out: Vec<*mut MBuf>,
+    drops: Vec<*mut MBuf>,
+    pub dropped: u64,
+}
+
+impl<Tx: PacketTx> HqosTx<Tx> {
+    /// `burst` is the maximum number of packets passed to `tx` at once.
+    pub fn new(tx: Tx, conf: &HqosConf, tsc_hz: u64, classify: QosClassifyFn, burst: usize) -> HqosTx<Tx> {
+        HqosTx {
+            tx,
+            hqos: HqosScheduler::new(conf, tsc_hz),
+            classify,
+            burst,
+            out: Vec::with_capacity(burst),
+            drops: Vec::with_capacity(burst),
+            dropped: 0,
+        }
+    }
+
+    #[inline]
+    pub fn scheduler(&self) -> &HqosScheduler<*mut MBuf> {
+        &self.hqos
+    }
+
+    #[inline]
+    fn free_drops(&mut self) {
+        self.drops.append(&mut self.hqos.take_dropped());
+        if !self.drops.is_empty() {
+            self.dropped += self.drops.len() as u64;
+            unsafe {
+                mbuf_free_bulk(self.drops.as_mut_ptr(), self.drops.len() as i32);
+            }
+            self.drops.clear();
+        }
+    }
+}
+
+impl<Tx: PacketTx> PacketTx for HqosTx<Tx> {
+    /// Takes over all packets, they are either queued or dropped.
+    fn send(&mut self, pkts: &mut [*mut MBuf]) -> errors::Result<u32> {
+        let now = unsafe { _rdtsc() };
+        for mbuf in pkts.iter() {
+            let mut pdu = Pdu::pdu_from_mbuf_no_increment(*mbuf);
+            let (subscriber, class) = (self.classify)(&mut pdu);
+            if let Err(mbuf) = self.hqos.enqueue(subscriber, class, *mbuf, pdu.pkt_len(), now) {
+                self.drops.push(mbuf);
+            }
+        }
+        self.free_drops();
+        self.flush()?;
+        Ok(pkts.len() as u32)
+    }
+
+    fn flush(&mut self) -> errors::Result<u32> {
+        self.tx.flush()?;
+        if self.hqos.backlog() == 0 || self.tx.backpressure() {
+            return Ok(0);
+        }
+        let now = unsafe { _rdtsc() };
+        while self.out.len() < self.burst {
+            match self.hqos.dequeue(now) {
+                Some(mbuf) => self.out.push(mbuf),
+                None => break,
+            }
+        }
+        let sent = if self.out.is_empty() {
+            0
+        } else {
+            self.tx.send(&mut self.out[..])? as usize
+        };
+        // packets not accepted by tx have already passed the scheduler, they are dropped
+        self.drops.extend_from_slice(&self.out[sent..]);
+        self.out.clear();
+        self.free_drops();
+        Ok(sent as u32)
+    }
+
+    fn pending(&self) -> usize {
+        self.hqos.backlog() + self.tx.pending()
+    }
+}
+
+impl<Tx: PacketTx> Drop for HqosTx<Tx> {
+    fn drop(&mut self) {
+        self.drops = self.hqos.drain();
+        self.free_drops();
+    }
+}
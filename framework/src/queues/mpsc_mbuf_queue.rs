@@ -279,6 +279,11 @@ impl PacketRx for MpscConsumer {
     fn queued(&self) -> usize {
         self.mpsc_queue.used_slots()
     }
+
+    #[inline]
+    fn pending(&self) -> usize {
+        self.mpsc_queue.used_slots()
+    }
 }
 
 pub fn new_mpsc_queue_pair_with_size(size: usize) -> (MpscProducer, ReceiveBatch<MpscConsumer>) {
@@ -1,17 +1,35 @@
-use allocators::CacheAligned;
+use allocators::{CacheAligned, NumaTopology};
 use common::{errors, ErrorKind};
-use config::NetbricksConfiguration;
+use config::{
+    check_numa_placement, check_port_pairing, diff_configurations, read_layered_configuration, ConfigChange,
+    NetbricksConfiguration, PipelineConfiguration,
+};
+use control::{ControlRuntime, ReloadRequest, SchedulerChannel};
 use interface::dpdk::{init_system, init_thread};
 use interface::{PmdPort, PortQueue, VirtualPort, VirtualQueue};
+use metrics::{
+    collect_performance_data, global_registry, metrics_server, register_port_collectors, task_metrics_collector,
+    MetricsRegistry, TaskLatencies,
+};
+use operators::{Batch, ReceiveBatch};
+use pipeline::{OperatorRegistry, PipelineInstance};
 use scheduler::*;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle, Thread};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often `start_metrics` refreshes the task metrics.
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
 
 type AlignedPortQueue = CacheAligned<PortQueue>;
 type AlignedVirtualQueue = CacheAligned<VirtualQueue>;
+type CoreQueues = HashMap<i32, HashSet<AlignedPortQueue>>;
+/// An instance of a declared pipeline: its configuration, core, rx queue and tx queue.
+type PipelineQueues<'a> = (&'a PipelineConfiguration, i32, AlignedPortQueue, AlignedPortQueue);
 
 /// A handle to schedulers paused on a barrier.
 pub struct BarrierHandle<'a> {
@@ -37,13 +55,23 @@ impl<'a> BarrierHandle<'a> {
 pub struct NetBricksContext {
     pub ports: HashMap<String, Arc<PmdPort>>,
     pub id_to_port: HashMap<u16, Arc<PmdPort>>,
-    pub rx_queues: HashMap<i32, HashSet<CacheAligned<PortQueue>>>,
+    pub rx_queues: CoreQueues,
     // queues running on a core
     pub active_cores: Vec<i32>,
     pub virtual_ports: HashMap<i32, Arc<VirtualPort>>,
     pub scheduler_channels: HashMap<i32, SyncSender<SchedulerCommand>>,
     pub reply_receiver: Option<Receiver<SchedulerReply>>,
     scheduler_handles: HashMap<i32, JoinHandle<()>>,
+    // the configuration the system runs with, replaced by a reload
+    configuration: NetbricksConfiguration,
+    // the instances of the configured pipelines as (core, task)
+    pipeline_tasks: HashMap<String, Vec<(i32, Uuid)>>,
+    operator_registry: Option<Arc<OperatorRegistry>>,
+    // tasks got queues or ports through `add_pipeline_to_run` or `install_pipeline_on_cores`, a reload cannot move
+    // their queues
+    undeclared_pipelines: bool,
+    // serves the metrics, see `start_metrics`
+    metrics_runtime: Option<ControlRuntime>,
 }
 
 impl NetBricksContext {
@@ -55,6 +83,9 @@ impl NetBricksContext {
         for core in &cores {
             self.init_scheduler(*core, reply_sender.clone());
         }
+        if let Err(e) = self.start_metrics() {
+            error!("metrics are not served: {}", e);
+        }
     }
 
     #[inline]
@@ -81,6 +112,7 @@ impl NetBricksContext {
     where
         T: Fn(i32, HashSet<AlignedPortQueue>, &mut StandaloneScheduler) + Send + Clone + 'static,
     {
+        self.undeclared_pipelines = true;
         for (core, channel) in &self.scheduler_channels {
             let ports = match self.rx_queues.get(core) {
                 Some(set) => set.clone(),
@@ -101,6 +133,7 @@ impl NetBricksContext {
     where
         T: Fn(i32, HashMap<String, Arc<PmdPort>>, &mut StandaloneScheduler) + Send + Clone + 'static,
     {
+        self.undeclared_pipelines = true;
         for (core, channel) in &self.scheduler_channels {
             let core_id = *core;
             let run_clone = run.clone();
@@ -110,6 +143,291 @@ impl NetBricksContext {
         }
     }
 
+    /// Install the `pipelines` declared in the configuration on their cores, each operator is created by the factory
+    /// registered for its kind in `registry`. The queues of a pipeline on a core are the queues of its ports on
+    /// that core. The registry is kept for rebuilding pipelines on a `reload`.
+    pub fn install_pipelines(
+        &mut self,
+        pipelines: &[PipelineConfiguration],
+        registry: Arc<OperatorRegistry>,
+    ) -> errors::Result<()> {
+        self.operator_registry = Some(registry.clone());
+        self.install(pipelines, &registry, false)
+    }
+
+    /// All `pipelines` are validated before any of them is installed, afterwards the operators are built on the
+    /// cores. The tasks of the pipelines are `ready` right away or wait for `execute`. Returns the first error.
+    fn install(
+        &mut self,
+        pipelines: &[PipelineConfiguration],
+        registry: &Arc<OperatorRegistry>,
+        ready: bool,
+    ) -> errors::Result<()> {
+        let instances = self.prepare(pipelines, registry, &self.rx_queues)?;
+        self.start(instances, registry, ready)
+    }
+
+    /// Validate the `pipelines` with the queues polled by the cores as in `rx_queues` and collect their instances.
+    fn prepare<'a>(
+        &self,
+        pipelines: &'a [PipelineConfiguration],
+        registry: &OperatorRegistry,
+        rx_queues: &CoreQueues,
+    ) -> errors::Result<Vec<PipelineQueues<'a>>> {
+        let mut instances = Vec::new();
+        for pipeline in pipelines {
+            let rx_port = self.port_of_pipeline(&pipeline.rx_port, &pipeline.name)?;
+            let tx_port = self.port_of_pipeline(&pipeline.tx_port, &pipeline.name)?;
+            for (i, core) in pipeline.cores.iter().enumerate() {
+                let queue_of = |port: &Arc<PmdPort>| {
+                    rx_queues
+                        .get(core)
+                        .and_then(|queues| queues.iter().find(|q| Arc::ptr_eq(&q.port, port)).cloned())
+                        .ok_or_else(|| {
+                            ErrorKind::ConfigurationError(format!(
+                                "Pipeline {}: port {} has no queue on core {}",
+                                pipeline.name,
+                                port.name(),
+                                core
+                            ))
+                        })
+                };
+                let rx = queue_of(&rx_port)?;
+                let tx = queue_of(&tx_port)?;
+                if i == 0 {
+                    registry.validate(&pipeline.name, *core, &rx, &tx, &pipeline.operators)?;
+                }
+                if !self.scheduler_channels.contains_key(core) {
+                    return Err(ErrorKind::NoRunningSchedulerOnCore(*core));
+                }
+                instances.push((pipeline, *core, rx, tx));
+            }
+        }
+        Ok(instances)
+    }
+
+    /// Build the operators of the `instances` on their cores.
+    fn start(
+        &mut self,
+        instances: Vec<PipelineQueues>,
+        registry: &Arc<OperatorRegistry>,
+        ready: bool,
+    ) -> errors::Result<()> {
+        let mut replies = Vec::with_capacity(instances.len());
+        for (pipeline, core_id, rx, tx) in instances {
+            let scheduler_channel = self
+                .scheduler_channels
+                .get(&core_id)
+                .ok_or(ErrorKind::NoRunningSchedulerOnCore(core_id))?;
+            let uuid = Uuid::new_v4();
+            let name = pipeline.name.clone();
+            let operators = pipeline.operators.clone();
+            let registry = registry.clone();
+            let (reply_sender, reply_receiver) = channel::<errors::Result<()>>();
+            let closure = Box::new(move |s: &mut StandaloneScheduler| {
+                let receive = ReceiveBatch::new(rx.clone()).compose();
+                let built = {
+                    let mut instance = PipelineInstance {
+                        name: &name,
+                        core: core_id,
+                        rx: &rx,
+                        tx: &tx,
+                        scheduler: s,
+                    };
+                    registry.build(receive, &operators, &mut instance)
+                };
+                let reply = built.map(|batch| {
+                    let runnable = Runnable::from_task(uuid, name.clone(), batch.send(tx.clone()));
+                    s.add_runnable(if ready {
+                        runnable.move_ready()
+                    } else {
+                        runnable.move_unready()
+                    });
+                });
+                // nobody waits for the reply if sending to another core failed
+                let _ = reply_sender.send(reply);
+            });
+            scheduler_channel
+                .send(SchedulerCommand::Run(closure))
+                .map_err(|_| ErrorKind::NoRunningSchedulerOnCore(core_id))?;
+            replies.push((pipeline, core_id, uuid, reply_receiver));
+        }
+
+        let mut result = Ok(());
+        for (pipeline, core_id, uuid, reply_receiver) in replies {
+            let reply = reply_receiver.recv().unwrap_or_else(|_| {
+                Err(ErrorKind::RunTimeError(format!(
+                    "scheduler on core {} did not reply",
+                    core_id
+                )))
+            });
+            match reply {
+                Ok(()) => self
+                    .pipeline_tasks
+                    .entry(pipeline.name.clone())
+                    .or_default()
+                    .push((core_id, uuid)),
+                Err(e) => {
+                    error!("pipeline {} on core {} not installed: {}", pipeline.name, core_id, e);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Remove the instances of a pipeline installed by `install_pipelines`, packets held by them are dropped.
+    fn remove_pipeline(&mut self, name: &str) -> errors::Result<()> {
+        for (core, uuid) in self.pipeline_tasks.remove(name).unwrap_or_default() {
+            let channel = self
+                .scheduler_channels
+                .get(&core)
+                .ok_or(ErrorKind::NoRunningSchedulerOnCore(core))?;
+            channel
+                .send(SchedulerCommand::Run(Box::new(move |s: &mut StandaloneScheduler| {
+                    s.remove_task(&uuid);
+                })))
+                .map_err(|_| ErrorKind::NoRunningSchedulerOnCore(core))?;
+        }
+        Ok(())
+    }
+
+    /// Let the cores in `cores` poll the queues of `port` in `rx_queues`, queue i is polled by core `cores[i]`.
+    fn move_queues(rx_queues: &mut CoreQueues, port: &Arc<PmdPort>, cores: &[i32]) {
+        for (rxq, core) in cores.iter().enumerate() {
+            let current = rx_queues.iter().find_map(|(c, queues)| {
+                queues
+                    .iter()
+                    .find(|q| Arc::ptr_eq(&q.port, port) && q.rxq() as usize == rxq)
+                    .map(|q| (*c, q.clone()))
+            });
+            if let Some((previous, queue)) = current {
+                if previous != *core {
+                    debug!(
+                        "moving queue {} of port {} from core {} to {}",
+                        rxq,
+                        port.name(),
+                        previous,
+                        core
+                    );
+                    rx_queues.get_mut(&previous).unwrap().remove(&queue);
+                    rx_queues
+                        .entry(*core)
+                        .or_insert_with(|| HashSet::with_capacity(8))
+                        .insert(queue);
+                }
+            }
+        }
+    }
+
+    /// The configuration the system runs with.
+    pub fn configuration(&self) -> &NetbricksConfiguration {
+        &self.configuration
+    }
+
+    /// Apply the changes between the running configuration and `configuration` while the schedulers keep running,
+    /// see `diff_configurations`. Pipelines are rebuilt with the operator registry of `install_pipelines`, their new
+    /// instances are ready right away. Nothing is changed if the new configuration cannot be applied live, e.g. if
+    /// a pipeline cannot be built or if queues would move away from tasks installed by `add_pipeline_to_run` or
+    /// `install_pipeline_on_cores`.
+    pub fn reload(&mut self, configuration: &NetbricksConfiguration) -> errors::Result<Vec<ConfigChange>> {
+        let changes = diff_configurations(&self.configuration, configuration)?;
+        let registry = self
+            .operator_registry
+            .clone()
+            .unwrap_or_else(|| Arc::new(OperatorRegistry::with_builtins()));
+        // the queues as polled after the reload, declared pipelines polling moved queues are rebuilt
+        let mut rx_queues = self.rx_queues.clone();
+        for change in &changes {
+            match *change {
+                ConfigChange::SetNetSpec { ref port, .. } => {
+                    self.port_of_reload(port)?;
+                }
+                ConfigChange::MoveQueues {
+                    ref port,
+                    rx_queues: ref cores,
+                    ..
+                } => {
+                    if self.undeclared_pipelines {
+                        return Err(ErrorKind::ConfigurationError(format!(
+                            "queues of port {} cannot be moved, they may be polled by pipelines which are not \
+                             declared in the configuration",
+                            port
+                        )));
+                    }
+                    let pmd_port = self.port_of_reload(port)?;
+                    NetBricksContext::move_queues(&mut rx_queues, &pmd_port, cores);
+                }
+                _ => (),
+            }
+        }
+        let pipelines: Vec<_> = changes
+            .iter()
+            .filter_map(|change| match *change {
+                ConfigChange::AddPipeline(ref pipeline) | ConfigChange::RebuildPipeline(ref pipeline) => {
+                    Some(pipeline.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        let instances = self.prepare(&pipelines, &registry, &rx_queues)?;
+
+        for change in &changes {
+            match *change {
+                ConfigChange::RemovePipeline(ref name) => self.remove_pipeline(name)?,
+                ConfigChange::RebuildPipeline(ref pipeline) => self.remove_pipeline(&pipeline.name)?,
+                ConfigChange::SetNetSpec { ref port, ref net_spec } => {
+                    self.port_of_reload(port)?.set_net_spec(net_spec.clone())
+                }
+                _ => (),
+            }
+        }
+        self.rx_queues = rx_queues;
+        self.start(instances, &registry, true)?;
+
+        self.configuration = configuration.clone();
+        for change in &changes {
+            info!("reload: {}", change);
+        }
+        Ok(changes)
+    }
+
+    /// Read the configuration file and `reload` it. The environment variables and the command line flags which
+    /// override the configuration are applied again.
+    pub fn reload_from_file(&mut self, filename: &str) -> errors::Result<Vec<ConfigChange>> {
+        let configuration = read_layered_configuration(filename, &self.configuration.flag_overrides)?;
+        self.reload(&configuration)
+    }
+
+    /// Answer the reload requests of a `ManagementServer`, without blocking. Requests without a path reload
+    /// `filename`.
+    pub fn handle_reload_requests(&mut self, requests: &Receiver<ReloadRequest>, filename: &str) {
+        while let Ok(request) = requests.try_recv() {
+            let path = request.path.clone().unwrap_or_else(|| filename.to_string());
+            let result = self.reload_from_file(&path);
+            if let Err(ref e) = result {
+                warn!("reload of {} failed: {}", path, e);
+            }
+            request.reply(result);
+        }
+    }
+
+    fn port_of_reload(&self, port: &str) -> errors::Result<Arc<PmdPort>> {
+        let name = port.split(',').next().unwrap();
+        self.ports
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ErrorKind::ConfigurationError(format!("Port {} is not initialized", name)))
+    }
+
+    fn port_of_pipeline(&self, port: &str, pipeline: &str) -> errors::Result<Arc<PmdPort>> {
+        self.ports.get(port).cloned().ok_or_else(|| {
+            ErrorKind::ConfigurationError(format!("Pipeline {} refers to unknown port {}", pipeline, port))
+        })
+    }
+
     pub fn add_test_pipeline<S>(&mut self, run: Box<S>)
     where
         S: Fn(i32, Vec<AlignedVirtualQueue>, &mut StandaloneScheduler) + Send + Clone + 'static,
@@ -141,6 +459,83 @@ impl NetBricksContext {
         }
     }
 
+    /// Serve the global metrics registry on the `metrics` address of the configuration, with the statistics of all
+    /// ports and the task metrics of all schedulers, which are refreshed every second. The agents run on a control
+    /// runtime on the primary core. Called by `start_schedulers`, does nothing if no address is configured or the
+    /// metrics are served already.
+    pub fn start_metrics(&mut self) -> errors::Result<()> {
+        let address = match self.configuration.metrics {
+            Some(address) if self.metrics_runtime.is_none() => address,
+            _ => return Ok(()),
+        };
+        let registry = global_registry();
+        self.register_port_metrics(&registry);
+        let schedulers = self.scheduler_channel();
+        let runtime = ControlRuntime::start(self.configuration.primary_core, move |handle| {
+            handle.spawn(metrics_server(address, handle)?);
+            handle.spawn(task_metrics_collector(registry, schedulers, METRICS_INTERVAL, handle)?);
+            Ok(())
+        })?;
+        info!("serving metrics on {}", address);
+        self.metrics_runtime = Some(runtime);
+        Ok(())
+    }
+
+    /// Register collectors for the statistics of all ports with `registry`.
+    pub fn register_port_metrics(&self, registry: &MetricsRegistry) {
+        for port in self.ports.values() {
+            register_port_collectors(registry, port.clone());
+        }
+    }
+
+    /// Query the performance data of all schedulers and update the task metrics in `registry`.
+    /// Must not be used concurrently with other consumers of `reply_receiver`.
+    pub fn collect_metrics(&self, registry: &MetricsRegistry) -> errors::Result<()> {
+        let receiver = match self.reply_receiver {
+            Some(ref r) => r,
+            None => return Err(ErrorKind::RunTimeError("schedulers not started".to_string())),
+        };
+        for (core, channel) in &self.scheduler_channels {
+            channel
+                .send(SchedulerCommand::GetPerformance)
+                .map_err(|_| ErrorKind::NoRunningSchedulerOnCore(*core))?;
+        }
+        for _ in 0..self.scheduler_channels.len() {
+            match receiver.recv() {
+                Ok(SchedulerReply::PerformanceData(core, data)) => collect_performance_data(registry, core, &data),
+                Ok(_) => warn!("collect_metrics: unexpected scheduler reply"),
+                Err(e) => return Err(ErrorKind::RunTimeError(format!("{}", e))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Query the latency histograms of all tasks which measure latency, keyed by core and task uuid.
+    /// If `reset` is true, the histograms in the tasks are cleared. Must not be used concurrently with other
+    /// consumers of `reply_receiver`.
+    pub fn get_latency(&self, reset: bool) -> errors::Result<HashMap<i32, TaskLatencies>> {
+        let receiver = match self.reply_receiver {
+            Some(ref r) => r,
+            None => return Err(ErrorKind::RunTimeError("schedulers not started".to_string())),
+        };
+        for (core, channel) in &self.scheduler_channels {
+            channel
+                .send(SchedulerCommand::GetLatency(reset))
+                .map_err(|_| ErrorKind::NoRunningSchedulerOnCore(*core))?;
+        }
+        let mut result = HashMap::with_capacity(self.scheduler_channels.len());
+        for _ in 0..self.scheduler_channels.len() {
+            match receiver.recv() {
+                Ok(SchedulerReply::LatencyData(core, data)) => {
+                    result.insert(core, data);
+                }
+                Ok(_) => warn!("get_latency: unexpected scheduler reply"),
+                Err(e) => return Err(ErrorKind::RunTimeError(format!("{}", e))),
+            }
+        }
+        Ok(result)
+    }
+
     /// Pause all schedulers, the returned `BarrierHandle` can be used to resume.
     pub fn barrier(&mut self) -> BarrierHandle {
         // TODO: If this becomes a problem, move this to the struct itself; but make sure to fix `stop` appropriately.
@@ -156,8 +551,15 @@ impl NetBricksContext {
         BarrierHandle::with_threads(self.scheduler_handles.values().map(|j| j.thread()).collect())
     }
 
+    /// A channel to the schedulers for agents on the control runtime.
+    pub fn scheduler_channel(&self) -> SchedulerChannel {
+        SchedulerChannel::new(self.scheduler_channels.clone())
+    }
+
     /// Stop all schedulers, safely shutting down the system.
     pub fn stop(&mut self) {
+        // the metrics agents query the schedulers
+        self.metrics_runtime = None;
         for (core, channel) in &self.scheduler_channels {
             channel.send(SchedulerCommand::Shutdown).unwrap();
             println!("Issued shutdown for core {}", core);
@@ -181,6 +583,39 @@ impl NetBricksContext {
     pub fn shutdown(&mut self) {
         self.stop()
     }
+
+    /// Stop receiving and let the executing schedulers empty the pipelines, see `drain_schedulers`. The report
+    /// includes the packets dropped by the tx buffers of all ports while draining.
+    pub fn drain(&mut self, timeout: Duration) -> DrainReport {
+        let dropped_before = self.tx_dropped();
+        let mut report = drain_schedulers(&self.scheduler_channels, timeout);
+        report.tx_dropped = self.tx_dropped().saturating_sub(dropped_before);
+        if report.drained {
+            info!("pipelines {}", report);
+        } else {
+            warn!("pipelines {}", report);
+        }
+        report
+    }
+
+    /// Packets dropped by the tx buffers of all ports since they were started.
+    fn tx_dropped(&self) -> usize {
+        self.ports
+            .values()
+            .map(|port| {
+                (0..port.txqs())
+                    .map(|queue| port.tx_queue_stats(queue).dropped())
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// Two-phase shutdown: drain the pipelines for at most `timeout`, then shutdown all schedulers.
+    pub fn shutdown_gracefully(&mut self, timeout: Duration) -> DrainReport {
+        let report = self.drain(timeout);
+        self.stop();
+        report
+    }
 }
 
 fn is_port_type_kni_or_virtio(name: &str) -> bool {
@@ -194,8 +629,17 @@ fn is_port_type_kni_or_virtio(name: &str) -> bool {
 
 /// Initialize the system from a configuration.
 pub fn initialize_system(configuration: &NetbricksConfiguration) -> errors::Result<NetBricksContext> {
+    for warning in check_port_pairing(configuration)
+        .into_iter()
+        .chain(check_numa_placement(configuration, &NumaTopology::new()))
+    {
+        warn!("{}", warning);
+    }
     init_system(configuration);
-    let mut ctx: NetBricksContext = Default::default();
+    let mut ctx = NetBricksContext {
+        configuration: configuration.clone(),
+        ..Default::default()
+    };
     let mut cores: HashSet<_> = configuration.cores.iter().cloned().collect();
     //maps kni name to port_id of associated port
     let mut kni2pci: HashMap<String, Arc<PmdPort>> = HashMap::with_capacity(configuration.ports.len());
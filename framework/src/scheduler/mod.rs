@@ -2,14 +2,30 @@
 /// Anything that implements Runnable can be polled by the scheduler. This thing can be a `Batch` (e.g., `SendBatch`) or
 /// something else (e.g., the `GroupBy` operator). Eventually this trait will have more stuff.
 pub use self::context::*;
+pub use self::shutdown::*;
 pub use self::standalone_scheduler::*;
 
+use metrics::LatencyHistogram;
+
 mod standalone_scheduler;
 
 mod context;
+mod shutdown;
 
 pub trait Executable {
     fn execute(&mut self) -> (u32, i32); // returns #packets processed, or a comparable metric
+
+    /// Latency histogram of packets processed by this task, if the task measures latency.
+    fn latency(&self) -> Option<&LatencyHistogram> {
+        None
+    }
+
+    fn reset_latency(&mut self) {}
+
+    /// Packets held by the task which still would be processed by further executions, see `drain_schedulers`.
+    fn pending(&self) -> usize {
+        0
+    }
 }
 
 impl<F> Executable for F
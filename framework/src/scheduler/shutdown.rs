@@ -0,0 +1,176 @@
+use super::{SchedulerCommand, StandaloneScheduler};
+use common::{errors, ErrorKind};
+use interface::stop_rx;
+use libc;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Set by `request_shutdown` and the signal handlers of `install_shutdown_signal_handlers`.
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Pause between two rounds of queries while draining.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Ask the main thread to shut down, e.g. from `ManagementServer::on_shutdown`.
+pub fn request_shutdown() {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[inline]
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Block until shutdown is requested, checking every `interval`.
+pub fn wait_for_shutdown_request(interval: Duration) {
+    while !shutdown_requested() {
+        thread::sleep(interval);
+    }
+}
+
+extern "C" fn on_shutdown_signal(_signal: libc::c_int) {
+    // only async-signal-safe operations are allowed here
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Let SIGTERM and SIGINT request shutdown instead of terminating the process, so that the pipelines can be drained.
+pub fn install_shutdown_signal_handlers() -> errors::Result<()> {
+    for signal in &[libc::SIGTERM, libc::SIGINT] {
+        let handler = on_shutdown_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(*signal, handler) } == libc::SIG_ERR {
+            return Err(ErrorKind::RunTimeError(format!(
+                "failed to install handler for signal {}",
+                signal
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Packets still held by a task when draining ended.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingPackets {
+    pub core: i32,
+    pub task: String,
+    pub packets: usize,
+}
+
+/// Outcome of draining the pipelines before shutdown.
+#[derive(Clone, Debug)]
+pub struct DrainReport {
+    /// All tasks reported no pending packets before the deadline.
+    pub drained: bool,
+    pub duration: Duration,
+    /// Tasks with packets left at the deadline, these mbufs are leaked.
+    pub leaked: Vec<PendingPackets>,
+    /// Packets dropped by tx buffers of the ports during the drain.
+    pub tx_dropped: usize,
+}
+
+impl DrainReport {
+    pub fn leaked_packets(&self) -> usize {
+        self.leaked.iter().map(|p| p.packets).sum()
+    }
+}
+
+impl fmt::Display for DrainReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.drained {
+            write!(f, "drained in {:?}", self.duration)?;
+        } else {
+            write!(
+                f,
+                "not drained after {:?}, {} packets leaked",
+                self.duration,
+                self.leaked_packets()
+            )?;
+            for pending in &self.leaked {
+                write!(
+                    f,
+                    ", {} in task {} on core {}",
+                    pending.packets, pending.task, pending.core
+                )?;
+            }
+        }
+        write!(f, ", {} packets dropped on tx", self.tx_dropped)
+    }
+}
+
+/// The pending packets of the ready tasks of all schedulers. A scheduler only answers between two rounds over its
+/// tasks, schedulers which do not answer before the deadline are reported with an unknown number (0) of packets.
+fn pending_packets(channels: &HashMap<i32, SyncSender<SchedulerCommand>>, deadline: Instant) -> Vec<PendingPackets> {
+    let (sender, replies) = channel();
+    let mut waiting = HashSet::new();
+    for (core, channel) in channels {
+        let core = *core;
+        let sender = sender.clone();
+        let query = SchedulerCommand::Run(Box::new(move |scheduler: &mut StandaloneScheduler| {
+            let pending: Vec<_> = scheduler
+                .tasks()
+                .iter()
+                .filter(|task| task.is_ready())
+                .map(|task| PendingPackets {
+                    core,
+                    task: task.name.clone(),
+                    packets: task.task.pending(),
+                })
+                .filter(|pending| pending.packets > 0)
+                .collect();
+            let _ = sender.send((core, pending));
+        }));
+        if channel.send(query).is_ok() {
+            waiting.insert(core);
+        } else {
+            warn!("no running scheduler on core {} while draining", core);
+        }
+    }
+    let mut pending = Vec::new();
+    while !waiting.is_empty() {
+        let now = Instant::now();
+        let timeout = if deadline > now {
+            deadline - now
+        } else {
+            Duration::from_millis(0)
+        };
+        match replies.recv_timeout(timeout) {
+            Ok((core, mut tasks)) => {
+                waiting.remove(&core);
+                pending.append(&mut tasks);
+            }
+            Err(_) => break,
+        }
+    }
+    pending.extend(waiting.into_iter().map(|core| PendingPackets {
+        core,
+        task: "scheduler".to_string(),
+        packets: 0,
+    }));
+    pending.sort_by_key(|p| p.core);
+    pending
+}
+
+/// First phase of a graceful shutdown: stop receiving on all ports and keep the schedulers running until no task
+/// holds pending packets anymore, e.g. in tx buffers or queues between pipeline stages, or until `timeout` passed.
+/// The schedulers must be executing, afterwards they can be shut down.
+pub fn drain_schedulers(channels: &HashMap<i32, SyncSender<SchedulerCommand>>, timeout: Duration) -> DrainReport {
+    let start = Instant::now();
+    let deadline = start + timeout;
+    stop_rx();
+    loop {
+        let pending = pending_packets(channels, deadline);
+        let drained = pending.is_empty();
+        if drained || Instant::now() >= deadline {
+            return DrainReport {
+                drained,
+                duration: start.elapsed(),
+                leaked: pending,
+                tx_dropped: 0,
+            };
+        }
+        thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+}
@@ -1,4 +1,5 @@
 use super::{Executable, Scheduler};
+use metrics::TaskLatencies;
 use std::arch::x86_64::_rdtsc;
 use std::cmp;
 use std::collections::HashMap;
@@ -113,10 +114,12 @@ pub enum SchedulerCommand {
     Shutdown,
     Handshake(SyncSender<bool>),
     GetPerformance,
+    GetLatency(bool), // if true, the histograms are reset after reading
 }
 
 pub enum SchedulerReply {
     PerformanceData(i32, HashMap<Uuid, (String, u64, u64, u32)>), //core id, uuid of task, task name, consumed cycles, count, queue_len
+    LatencyData(i32, TaskLatencies),                              //core id, uuid of task, task name, latency in cycles
 }
 
 const DEFAULT_Q_SIZE: usize = 256;
@@ -183,6 +186,28 @@ impl StandaloneScheduler {
         }
     }
 
+    /// Remove the task, e.g. the instance of a pipeline which was removed by a configuration reload. The packets
+    /// held by the task are freed when it is dropped.
+    pub fn remove_task(&mut self, uuid: &Uuid) -> Option<Runnable> {
+        let index = self.uuid2index.remove(uuid)?;
+        let runnable = self.run_q.remove(index);
+        for (i, r) in self.run_q.iter().enumerate().skip(index) {
+            self.uuid2index.insert(r.uuid, i);
+        }
+        if index < self.next_task {
+            self.next_task -= 1;
+        }
+        if self.next_task >= self.run_q.len() {
+            self.next_task = 0;
+        }
+        Some(runnable)
+    }
+
+    /// The installed tasks, in the order they are run.
+    pub fn tasks(&self) -> &[Runnable] {
+        &self.run_q
+    }
+
     pub fn get_ready_flag(&self, uuid: &Uuid) -> Option<Arc<AtomicBool>> {
         match self.uuid2index.get(uuid) {
             Some(index) => Some(self.run_q[*index].get_ready_atomic()),
@@ -190,6 +215,15 @@ impl StandaloneScheduler {
         }
     }
 
+    /// The name, consumed cycles, count and maximum queue length of each task, see `SchedulerReply::PerformanceData`.
+    pub fn performance_data(&self) -> HashMap<Uuid, (String, u64, u64, u32)> {
+        let mut data = HashMap::with_capacity(DEFAULT_Q_SIZE);
+        for r in &self.run_q {
+            data.insert(r.uuid, (r.name.clone(), r.cycles, r.count, r.queue_len));
+        }
+        data
+    }
+
     pub fn task_is_ready(&self, uuid: &Uuid) -> Option<bool> {
         match self.uuid2index.get(uuid) {
             Some(index) => Some(self.run_q[*index].is_ready()),
@@ -228,14 +262,22 @@ impl StandaloneScheduler {
                 );
             }
             SchedulerCommand::GetPerformance => {
-                let mut data: HashMap<Uuid, (String, u64, u64, u32)> = HashMap::with_capacity(DEFAULT_Q_SIZE);
-                for r in &self.run_q {
-                    data.insert(r.uuid, (r.name.clone(), r.cycles, r.count, r.queue_len));
-                }
                 self.sender
-                    .send(SchedulerReply::PerformanceData(self.core, data))
+                    .send(SchedulerReply::PerformanceData(self.core, self.performance_data()))
                     .unwrap();
             }
+            SchedulerCommand::GetLatency(reset) => {
+                let mut data: TaskLatencies = HashMap::with_capacity(8);
+                for r in &mut self.run_q {
+                    if let Some(histogram) = r.task.latency() {
+                        data.insert(r.uuid, (r.name.clone(), histogram.clone()));
+                    }
+                    if reset {
+                        r.task.reset_latency();
+                    }
+                }
+                self.sender.send(SchedulerReply::LatencyData(self.core, data)).unwrap();
+            }
             SchedulerCommand::Handshake(chan) => {
                 chan.send(true).unwrap(); // Inform context about reaching barrier.
                 thread::park();
@@ -300,7 +342,15 @@ impl StandaloneScheduler {
         self.execute_loop = true;
         if !self.run_q.is_empty() {
             while self.execute_loop {
-                self.execute_internal(unsafe { _rdtsc() });
+                if self.run_q.is_empty() {
+                    // all tasks were removed, wait for new ones
+                    match self.sched_channel.recv() {
+                        Ok(cmd) => self.handle_request(cmd),
+                        Err(_) => break,
+                    }
+                } else {
+                    self.execute_internal(unsafe { _rdtsc() });
+                }
             }
         }
     }
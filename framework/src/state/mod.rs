@@ -3,8 +3,10 @@ pub use self::dp_mergeable::*;
 pub use self::mergeable::*;
 pub use self::reordered_buffer::*;
 pub use self::ring_buffer::*;
+pub use self::tcp_reassembly::*;
 mod cp_mergeable;
 mod dp_mergeable;
 mod mergeable;
 pub mod reordered_buffer;
 mod ring_buffer;
+mod tcp_reassembly;
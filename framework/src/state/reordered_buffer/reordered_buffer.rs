@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 use common::*;
 use state::RingBuffer;
-use std::cmp::{max, min};
+use std::cmp::min;
 use std::u16;
 use utils::*;
 
@@ -110,11 +110,14 @@ impl SegmentList {
 
         self.storage[idx as usize].prev = prev;
 
-        self.storage[idx as usize].next = self.storage[prev as usize].next;
+        let next = self.storage[prev as usize].next;
+        self.storage[idx as usize].next = next;
         self.storage[prev as usize].next = idx;
 
-        if self.storage[idx as usize].next == -1 {
+        if next == -1 {
             self.tail = idx;
+        } else {
+            self.storage[next as usize].prev = idx;
         }
         idx
     }
@@ -142,10 +145,11 @@ impl SegmentList {
             let end = self.storage[idx as usize]
                 .seq
                 .wrapping_add(self.storage[idx as usize].length as u32);
-            if end >= self.storage[next as usize].seq {
+            if seq_ge(end, self.storage[next as usize].seq) {
                 // We have at least some overlap, and should merge.
-                let merge_len =
-                    self.storage[next as usize].length as usize - (end - self.storage[next as usize].seq) as usize;
+                let overlap = end.wrapping_sub(self.storage[next as usize].seq) as usize;
+                let merge_len = self.storage[next as usize].length as usize
+                    - min(overlap, self.storage[next as usize].length as usize);
                 let new_len = merge_len as usize + self.storage[idx as usize].length as usize;
                 if new_len <= u16::MAX as usize {
                     self.storage[idx as usize].length = new_len as u16;
@@ -154,6 +158,8 @@ impl SegmentList {
                     self.storage[idx as usize].next = next;
                     if next != -1 {
                         self.storage[next as usize].prev = idx;
+                    } else {
+                        self.tail = idx;
                     }
                     self.remove_node(to_free);
                 } else {
@@ -167,6 +173,9 @@ impl SegmentList {
                     // No more merges are possible so exit this loop.
                     break;
                 }
+            } else {
+                // A gap, no more merges are possible.
+                break;
             }
         }
     }
@@ -209,16 +218,17 @@ impl SegmentList {
                         self.insert_after_node(idx, seq_new, len_new);
                     }
                     break;
-                } else if seg_seq >= end {
+                } else if seq_ge(seg_seq, end) || seq_lt(seq, seg_seq) {
                     // println!("Adding before");
-                    // We are on to segments that are further down, insert
+                    // We are on to segments that are further down, insert. Overlaps are merged below.
                     idx = self.insert_before_node(idx, seq, len);
                     break;
-                } else if self.storage[idx as usize].seq <= seq {
+                } else if seq_le(seq, seg_end) {
                     // println!("Overlapping");
                     // Overlapping segment
-                    let new_end = max(seg_end, end);
-                    self.storage[idx as usize].length = (new_end - self.storage[idx as usize].seq) as u16;
+                    let new_end = seq_max(seg_end, end);
+                    let new_len = min(new_end.wrapping_sub(seg_seq) as usize, u16::MAX as usize);
+                    self.storage[idx as usize].length = new_len as u16;
                     break;
                 } else {
                     idx = self.storage[idx as usize].next;
@@ -246,11 +256,19 @@ impl SegmentList {
     fn remove_head(&mut self) {
         let head = self.head;
         self.head = self.storage[head as usize].next;
+        if self.head == -1 {
+            self.tail = -1;
+        } else {
+            self.storage[self.head as usize].prev = -1;
+        }
         self.remove_node(head);
     }
 
     /// Consume some amount of data from the beginning.
     pub fn consume_head_data(&mut self, seq: u32, consumed: u16) -> bool {
+        if self.head == -1 {
+            return consumed == 0;
+        }
         let idx = self.head as usize;
         // This is just an integrity check.
         if self.storage[idx].seq != seq {
@@ -431,11 +449,11 @@ impl ReorderedBuffer {
     fn slow_path_insert(&mut self, seq: u32, data: &[u8]) -> InsertionResult {
         let end = seq.wrapping_add(data.len() as u32);
 
-        if self.tail_seq > seq && end > self.tail_seq {
+        if seq_gt(self.tail_seq, seq) && seq_gt(end, self.tail_seq) {
             // Some of the data overlaps with stuff we have received before.
-            let begin = (self.tail_seq - seq) as usize;
+            let begin = self.tail_seq.wrapping_sub(seq) as usize;
             self.fast_path_insert(&data[begin..])
-        } else if end < self.tail_seq {
+        } else if seq_le(end, self.tail_seq) {
             // All the data overlaps.
             InsertionResult::Inserted {
                 written: 0,
@@ -490,8 +508,8 @@ impl ReorderedBuffer {
                 written,
                 available: self.available(),
             }
-        } else if self.tail_seq >= seq {
-            let offset = (self.tail_seq - seq) as usize;
+        } else if seq_ge(self.tail_seq, seq) {
+            let offset = self.tail_seq.wrapping_sub(seq) as usize;
             if data.len() > offset {
                 let tail_seq = self.tail_seq;
                 self.out_of_order_insert(tail_seq, &data[offset..])
@@ -504,11 +522,13 @@ impl ReorderedBuffer {
         } else {
             // self.tail_seq < seq
             // Compute offset from tail where this should be written
-            let offset = (seq - self.tail_seq) as usize;
+            let offset = seq.wrapping_sub(self.tail_seq) as usize;
             // Write stuff
             let written = self.data.write_at_offset_from_tail(offset, data);
             // Insert segment at the right place
-            self.segment_list.insert_segment(seq, written as u16);
+            if written > 0 {
+                self.segment_list.insert_segment(seq, written as u16);
+            }
             if written == data.len() {
                 InsertionResult::Inserted {
                     written: written,
@@ -135,6 +135,15 @@ impl RingBuffer {
         self.read_from_head_with_increment(data, len)
     }
 
+    /// Read data `offset` bytes behind the read head without consuming it, e.g. to retransmit it. Returns bytes
+    /// read.
+    #[inline]
+    pub fn read_at_offset_from_head(&mut self, offset: usize, data: &mut [u8]) -> usize {
+        let to_read = min(self.available().saturating_sub(offset), data.len());
+        let index = self.head.wrapping_add(offset) & self.mask;
+        self.wrapped_read(index, &mut data[..to_read])
+    }
+
     /// Seek the read head by `seek` bytes (without actually reading any data). `seek` must be less-than-or-equal to the
     /// number of available bytes.
     #[inline]
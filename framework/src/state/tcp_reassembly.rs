@@ -0,0 +1,342 @@
+use fnv::FnvHasher;
+use headers::TcpHeader;
+use state::{InsertionResult, ReorderedBuffer};
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+use utils::{round_to_power_of_2, FiveTupleV4};
+
+type FnvHash = BuildHasherDefault<FnvHasher>;
+
+/// Direction of one of the two byte streams of a TCP connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamDirection {
+    /// from the originator of the connection, i.e. the sender of the SYN, to the responder
+    ToResponder = 0,
+    /// from the responder to the originator
+    ToOriginator = 1,
+}
+
+/// Why a connection was closed, see `StreamHandler::on_close`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// both streams were delivered completely up to their FIN
+    Fin,
+    Reset,
+    IdleTimeout,
+    /// the global memory limit of the reassembler did not allow to buffer the connection
+    OutOfMemory,
+    /// data arrived beyond the reordering window of the stream buffer
+    BufferOverflow,
+    /// the connection was closed by `TcpReassembler::close_all`
+    Shutdown,
+}
+
+/// Consumer of the two reassembled byte streams of one TCP connection.
+pub trait StreamHandler {
+    /// In-order data of the stream in direction `dir`.
+    fn on_data(&mut self, dir: StreamDirection, data: &[u8]);
+
+    /// The connection is closed, no further data is delivered. Data beyond a gap in a stream is lost.
+    fn on_close(&mut self, reason: CloseReason);
+}
+
+/// Limits and timers of a `TcpReassembler`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReassemblyConf {
+    /// bytes buffered per stream, rounded up to a power of 2. This bounds the reordering window and must exceed the
+    /// largest segment.
+    pub buffer_size: usize,
+    /// limit of the bytes buffered by all connections, connections which would exceed it are closed
+    pub max_memory: usize,
+    /// limit of the tracked connections, further connections are not tracked until others are closed
+    pub max_connections: usize,
+    /// time in milliseconds after which a connection without packets is closed
+    pub idle_timeout: u64,
+    /// pick up connections without seeing their handshake, the sender of the first data is taken as originator
+    pub midstream: bool,
+}
+
+impl Default for ReassemblyConf {
+    fn default() -> ReassemblyConf {
+        ReassemblyConf {
+            buffer_size: 16384,
+            max_memory: 64 * 1024 * 1024,
+            max_connections: 65536,
+            idle_timeout: 300_000,
+            midstream: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReassemblyStats {
+    pub opened: u64,
+    pub closed: u64,
+    /// connections closed for lack of memory
+    pub out_of_memory: u64,
+    /// connections not tracked as `ReassemblyConf::max_connections` were tracked
+    pub refused: u64,
+    /// bytes delivered to the stream handlers
+    pub delivered: u64,
+}
+
+struct HalfStream {
+    buffer: Option<ReorderedBuffer>,
+    // sequence number of the next byte to deliver, valid if synced
+    next: u32,
+    synced: bool,
+    fin: Option<u32>,
+}
+
+impl HalfStream {
+    fn new() -> HalfStream {
+        HalfStream {
+            buffer: None,
+            next: 0,
+            synced: false,
+            fin: None,
+        }
+    }
+
+    fn sync(&mut self, seq: u32) {
+        if !self.synced {
+            self.next = seq;
+            self.synced = true;
+        }
+    }
+
+    #[inline]
+    fn finished(&self) -> bool {
+        self.synced && self.fin == Some(self.next)
+    }
+
+    fn memory(&self) -> usize {
+        self.buffer.as_ref().map_or(0, |b| b.buffer_size())
+    }
+}
+
+struct Connection<H> {
+    handler: H,
+    streams: [HalfStream; 2],
+    last_seen: u64,
+}
+
+/// Reassembles both byte streams of TCP connections over IPv4 from their segments, which may arrive out of order,
+/// duplicated or overlapping. A `StreamHandler` for each connection is created by `factory` from the 5-tuple of the
+/// originator, connections for which it returns None are not tracked. Connections are tracked from their SYN or
+/// SYN-ACK on, or from their first data if `ReassemblyConf::midstream` is set. Time stamps are TSC values.
+/// See `Batch::reassemble_tcp`.
+pub struct TcpReassembler<H, F>
+where
+    H: StreamHandler,
+    F: FnMut(&FiveTupleV4) -> Option<H>,
+{
+    conf: ReassemblyConf,
+    idle_timeout: u64,
+    factory: F,
+    connections: HashMap<FiveTupleV4, Connection<H>, FnvHash>,
+    memory: usize,
+    scratch: Vec<u8>,
+    last_expiry: u64,
+    stats: ReassemblyStats,
+}
+
+impl<H, F> TcpReassembler<H, F>
+where
+    H: StreamHandler,
+    F: FnMut(&FiveTupleV4) -> Option<H>,
+{
+    pub fn new(conf: ReassemblyConf, tsc_hz: u64, factory: F) -> TcpReassembler<H, F> {
+        TcpReassembler {
+            conf,
+            idle_timeout: conf.idle_timeout * tsc_hz / 1000,
+            factory,
+            connections: HashMap::with_hasher(Default::default()),
+            memory: 0,
+            scratch: vec![0; round_to_power_of_2(conf.buffer_size)],
+            last_expiry: 0,
+            stats: ReassemblyStats::default(),
+        }
+    }
+
+    #[inline]
+    pub fn conf(&self) -> &ReassemblyConf {
+        &self.conf
+    }
+
+    /// Number of tracked connections.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Bytes allocated for stream buffers.
+    #[inline]
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &ReassemblyStats {
+        &self.stats
+    }
+
+    /// Find the connection of `flow` or start tracking it. Returns the key of the connection and the direction of
+    /// `flow` in it.
+    fn connection(
+        &mut self,
+        flow: &FiveTupleV4,
+        tcp: &TcpHeader,
+        has_data: bool,
+    ) -> Option<(FiveTupleV4, StreamDirection)> {
+        if self.connections.contains_key(flow) {
+            return Some((*flow, StreamDirection::ToResponder));
+        }
+        let reverse = flow.reverse_flow();
+        if self.connections.contains_key(&reverse) {
+            return Some((reverse, StreamDirection::ToOriginator));
+        }
+        let (key, dir) = if tcp.rst_flag() {
+            return None;
+        } else if tcp.syn_flag() && !tcp.ack_flag() {
+            (*flow, StreamDirection::ToResponder)
+        } else if tcp.syn_flag() {
+            (reverse, StreamDirection::ToOriginator)
+        } else if self.conf.midstream && has_data {
+            (*flow, StreamDirection::ToResponder)
+        } else {
+            return None;
+        };
+        if self.connections.len() >= self.conf.max_connections {
+            self.stats.refused += 1;
+            return None;
+        }
+        let handler = (self.factory)(&key)?;
+        self.connections.insert(
+            key,
+            Connection {
+                handler,
+                streams: [HalfStream::new(), HalfStream::new()],
+                last_seen: 0,
+            },
+        );
+        self.stats.opened += 1;
+        Some((key, dir))
+    }
+
+    /// Process a TCP segment of `flow` with `payload` received at time `now`. Returns the direction of the segment
+    /// in its connection, or None if the connection is not tracked.
+    pub fn process(
+        &mut self,
+        flow: &FiveTupleV4,
+        tcp: &TcpHeader,
+        payload: &[u8],
+        now: u64,
+    ) -> Option<StreamDirection> {
+        let (key, dir) = self.connection(flow, tcp, !payload.is_empty())?;
+        let seq = tcp.seq_num();
+        let data_seq = if tcp.syn_flag() { seq.wrapping_add(1) } else { seq };
+        let mut close = None;
+        {
+            let conn = self.connections.get_mut(&key).unwrap();
+            conn.last_seen = now;
+            if tcp.syn_flag() {
+                conn.streams[dir as usize].sync(data_seq);
+                if tcp.ack_flag() {
+                    // the SYN of the other side was acknowledged
+                    conn.streams[1 - dir as usize].sync(tcp.ack_num());
+                }
+            }
+            if !payload.is_empty() {
+                let half = &mut conn.streams[dir as usize];
+                half.sync(data_seq);
+                if half.buffer.is_none() {
+                    let size = round_to_power_of_2(self.conf.buffer_size);
+                    if self.memory + size > self.conf.max_memory {
+                        close = Some(CloseReason::OutOfMemory);
+                    } else {
+                        match ReorderedBuffer::new(size) {
+                            Ok(mut buffer) => {
+                                buffer.seq(half.next, &[]);
+                                self.memory += buffer.buffer_size();
+                                half.buffer = Some(buffer);
+                            }
+                            Err(_) => close = Some(CloseReason::OutOfMemory),
+                        }
+                    }
+                }
+                if let Some(ref mut buffer) = half.buffer {
+                    if let InsertionResult::OutOfMemory { .. } = buffer.add_data(data_seq, payload) {
+                        close = Some(CloseReason::BufferOverflow);
+                    }
+                    loop {
+                        let read = buffer.read_data(&mut self.scratch[..]);
+                        if read == 0 {
+                            break;
+                        }
+                        half.next = half.next.wrapping_add(read as u32);
+                        self.stats.delivered += read as u64;
+                        conn.handler.on_data(dir, &self.scratch[..read]);
+                    }
+                }
+            }
+            if tcp.fin_flag() {
+                let half = &mut conn.streams[dir as usize];
+                half.sync(data_seq);
+                half.fin = Some(data_seq.wrapping_add(payload.len() as u32));
+            }
+            if tcp.rst_flag() {
+                close = close.or(Some(CloseReason::Reset));
+            } else if close.is_none() && conn.streams.iter().all(|s| s.finished()) {
+                close = Some(CloseReason::Fin);
+            }
+        }
+        if let Some(reason) = close {
+            self.close(&key, reason);
+        }
+        Some(dir)
+    }
+
+    fn close(&mut self, key: &FiveTupleV4, reason: CloseReason) {
+        if let Some(mut conn) = self.connections.remove(key) {
+            self.memory -= conn.streams.iter().map(|s| s.memory()).sum::<usize>();
+            self.stats.closed += 1;
+            if reason == CloseReason::OutOfMemory {
+                self.stats.out_of_memory += 1;
+            }
+            conn.handler.on_close(reason);
+        }
+    }
+
+    /// Close the connections which were idle for longer than the idle timeout at time `now`. The connections are
+    /// scanned at most four times per timeout, so this is cheap enough to call for each batch.
+    pub fn expire(&mut self, now: u64) {
+        if now.wrapping_sub(self.last_expiry) < self.idle_timeout / 4 {
+            return;
+        }
+        self.last_expiry = now;
+        let idle_timeout = self.idle_timeout;
+        let expired: Vec<FiveTupleV4> = self
+            .connections
+            .iter()
+            .filter(|&(_, c)| now.saturating_sub(c.last_seen) >= idle_timeout)
+            .map(|(k, _)| *k)
+            .collect();
+        for key in expired {
+            self.close(&key, CloseReason::IdleTimeout);
+        }
+    }
+
+    /// Close all connections.
+    pub fn close_all(&mut self) {
+        let keys: Vec<FiveTupleV4> = self.connections.keys().cloned().collect();
+        for key in keys {
+            self.close(&key, CloseReason::Shutdown);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! A minimal userspace TCP endpoint which terminates connections on the data plane, e.g. for split-TCP proxies
+//! and local control services. A `TcpStack` owns the connections of one IPv4 address and offers a non-blocking
+//! socket API. It does no I/O itself: received segments are passed to `TcpStack::receive` and the segments to send
+//! are collected by `TcpStack::poll`. See `Batch::tcp_endpoint` for the operator connecting a stack to a port.
+//!
+//! A `SynProxy` protects TCP services against SYN floods, see `Batch::syn_proxy`.
+pub use self::socket::*;
+pub use self::stack::*;
+pub use self::syn_proxy::*;
+
+use headers::TcpHeader;
+use interface::Pdu;
+use std::cmp;
+use std::mem;
+use utils::FiveTupleV4;
+
+mod socket;
+mod stack;
+mod syn_proxy;
+
+/// The index of the TCP header of `pdu` and the flow and TCP payload of the preceding IPv4 header. The payload
+/// excludes Ethernet padding.
+#[inline]
+pub fn tcp_segment<'a>(pdu: &'a Pdu) -> Option<(usize, FiveTupleV4, &'a [u8])> {
+    let headers = pdu.headers();
+    let tcp = (1..headers.count()).find(|i| headers.get(*i).as_tcp().is_some())?;
+    let ip = headers.get(tcp - 1).as_ip()?;
+    let flow = ip.flow()?;
+    let headers_len = ip.ihl() as usize * 4 + headers.tcp(tcp).data_offset() as usize * 4;
+    let payload = pdu.get_payload(tcp);
+    let len = cmp::min(payload.len(), (ip.length() as usize).saturating_sub(headers_len));
+    Some((tcp, flow, &payload[..len]))
+}
+
+/// The options of the TCP header at index `tcp` of `pdu`.
+#[inline]
+pub fn tcp_options<'a>(pdu: &'a Pdu, tcp: usize) -> &'a [u8] {
+    let header_len = pdu.headers().tcp(tcp).data_offset() as usize * 4;
+    let segment = pdu.get_payload(tcp - 1);
+    let end = cmp::min(header_len, segment.len());
+    &segment[cmp::min(mem::size_of::<TcpHeader>(), end)..end]
+}
@@ -0,0 +1,885 @@
+use super::Segment;
+use headers::{TcpHeader, TcpOptions, TCP_MAX_WINDOW_SCALE};
+use state::{ReorderedBuffer, RingBuffer};
+use std::cmp::{max, min};
+use utils::{round_to_power_of_2, seq_ge, seq_gt, seq_le, seq_lt, FiveTupleV4};
+
+/// The MSS assumed for peers which do not announce one (RFC 1122).
+const DEFAULT_MSS: u16 = 536;
+
+/// Connection states of RFC 793.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// Errors of the socket API of a `TcpStack`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpError {
+    /// the handle does not refer to a socket
+    InvalidHandle,
+    /// no data to receive or no space in the send buffer at the moment
+    WouldBlock,
+    /// the socket is not connected or was closed for sending
+    NotConnected,
+    AddrInUse,
+    /// the peer refused the connection
+    Refused,
+    /// the peer reset the connection
+    Reset,
+    /// retransmissions were not acknowledged
+    TimedOut,
+    /// the buffers of the connection could not be allocated
+    OutOfMemory,
+}
+
+/// Limits and timers of the connections of a `TcpStack`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TcpConf {
+    /// maximum segment size announced to peers, the segments sent are limited to the smaller of both MSS
+    pub mss: u16,
+    /// bytes of the send buffer of each connection, rounded up to a power of 2
+    pub send_buffer: usize,
+    /// bytes of the receive buffer of each connection, rounded up to a power of 2. This limits the receive window.
+    pub recv_buffer: usize,
+    /// negotiate the window scale option, without it receive windows are limited to 64 KiB
+    pub window_scaling: bool,
+    /// retransmission timeouts in milliseconds, see RFC 6298
+    pub initial_rto: u64,
+    pub min_rto: u64,
+    pub max_rto: u64,
+    /// retransmissions of a segment after which the connection is aborted
+    pub max_retries: u32,
+    /// time in milliseconds connections stay in TIME-WAIT
+    pub time_wait: u64,
+    /// connections of a listener which are not accepted yet, including those in their handshake
+    pub backlog: usize,
+}
+
+impl Default for TcpConf {
+    fn default() -> TcpConf {
+        TcpConf {
+            mss: 1460,
+            send_buffer: 65536,
+            recv_buffer: 65536,
+            window_scaling: true,
+            initial_rto: 1000,
+            min_rto: 200,
+            max_rto: 60_000,
+            max_retries: 8,
+            time_wait: 60_000,
+            backlog: 128,
+        }
+    }
+}
+
+/// The timers of `TcpConf` in TSC ticks.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Timers {
+    pub initial_rto: u64,
+    pub min_rto: u64,
+    pub max_rto: u64,
+    pub time_wait: u64,
+}
+
+impl Timers {
+    pub fn new(conf: &TcpConf, tsc_hz: u64) -> Timers {
+        let ticks = |ms: u64| ms * tsc_hz / 1000;
+        Timers {
+            initial_rto: ticks(conf.initial_rto),
+            min_rto: ticks(conf.min_rto),
+            max_rto: ticks(conf.max_rto),
+            time_wait: ticks(conf.time_wait),
+        }
+    }
+}
+
+/// One TCP connection of a `TcpStack`. Retransmissions are timed according to RFC 6298 and congestion is
+/// controlled with NewReno (RFC 5681 and RFC 6582). Segments are acknowledged once per `TcpStack::poll`.
+pub struct TcpSocket {
+    state: TcpState,
+    error: Option<TcpError>,
+    // from the local to the remote socket
+    flow: FiveTupleV4,
+    conf: TcpConf,
+
+    // send sequence space, send_seq is the sequence number of the first byte of the send buffer
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_max: u32,
+    snd_wnd: u32,
+    snd_wl1: u32,
+    snd_wl2: u32,
+    snd_wscale: u8,
+    wscale_ok: bool,
+    send_buffer: RingBuffer,
+    send_seq: u32,
+    fin_queued: bool,
+    mss: u32,
+
+    // receive sequence space, recv_seq is the sequence number of the next byte read by the application
+    rcv_wscale: u8,
+    recv_buffer: ReorderedBuffer,
+    recv_seq: u32,
+    rcv_fin: Option<u32>,
+    fin_received: bool,
+    last_adv_wnd: u32,
+
+    // congestion control
+    cwnd: u32,
+    ssthresh: u32,
+    dup_acks: u32,
+    recover: u32,
+    in_recovery: bool,
+
+    // retransmission and TIME-WAIT timer
+    srtt: Option<u64>,
+    rttvar: u64,
+    rto: u64,
+    rtt_sample: Option<(u32, u64)>,
+    timer: Option<u64>,
+    retries: u32,
+
+    ack_now: bool,
+    retransmit_first: bool,
+    probe: bool,
+    retransmits: u64,
+}
+
+impl TcpSocket {
+    fn new(flow: FiveTupleV4, iss: u32, conf: &TcpConf, timers: &Timers) -> Result<TcpSocket, TcpError> {
+        let send_buffer = RingBuffer::new(round_to_power_of_2(conf.send_buffer)).map_err(|_| TcpError::OutOfMemory)?;
+        let recv_buffer = ReorderedBuffer::new(conf.recv_buffer).map_err(|_| TcpError::OutOfMemory)?;
+        let mss = conf.mss as u32;
+        Ok(TcpSocket {
+            state: TcpState::Closed,
+            error: None,
+            flow,
+            conf: *conf,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_max: iss,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            snd_wscale: 0,
+            wscale_ok: false,
+            send_buffer,
+            send_seq: iss.wrapping_add(1),
+            fin_queued: false,
+            mss,
+            rcv_wscale: 0,
+            recv_buffer,
+            recv_seq: 0,
+            rcv_fin: None,
+            fin_received: false,
+            last_adv_wnd: 0,
+            cwnd: min(4 * mss, max(2 * mss, 4380)),
+            ssthresh: u32::MAX,
+            dup_acks: 0,
+            recover: iss,
+            in_recovery: false,
+            srtt: None,
+            rttvar: 0,
+            rto: timers.initial_rto,
+            rtt_sample: None,
+            timer: None,
+            retries: 0,
+            ack_now: false,
+            retransmit_first: false,
+            probe: false,
+            retransmits: 0,
+        })
+    }
+
+    /// A socket in SYN-SENT, the SYN is sent by the next `output`.
+    pub(crate) fn connect(flow: FiveTupleV4, iss: u32, conf: &TcpConf, timers: &Timers) -> Result<TcpSocket, TcpError> {
+        let mut socket = TcpSocket::new(flow, iss, conf, timers)?;
+        socket.state = TcpState::SynSent;
+        Ok(socket)
+    }
+
+    /// A socket in SYN-RECEIVED for the SYN `tcp` received by a listener.
+    pub(crate) fn accept(
+        flow: FiveTupleV4,
+        iss: u32,
+        conf: &TcpConf,
+        timers: &Timers,
+        tcp: &TcpHeader,
+        options: &TcpOptions,
+    ) -> Result<TcpSocket, TcpError> {
+        let mut socket = TcpSocket::new(flow, iss, conf, timers)?;
+        socket.state = TcpState::SynReceived;
+        socket.synchronize(tcp, options);
+        socket.snd_wnd = tcp.window_size() as u32;
+        socket.snd_wl1 = tcp.seq_num();
+        socket.snd_wl2 = iss;
+        Ok(socket)
+    }
+
+    #[inline]
+    pub fn state(&self) -> TcpState {
+        self.state
+    }
+
+    /// The error which closed the connection.
+    #[inline]
+    pub fn error(&self) -> Option<TcpError> {
+        self.error
+    }
+
+    /// The flow from the local to the remote socket.
+    #[inline]
+    pub fn flow(&self) -> &FiveTupleV4 {
+        &self.flow
+    }
+
+    /// Bytes which can be read.
+    #[inline]
+    pub fn recv_queue(&self) -> usize {
+        self.recv_buffer.available()
+    }
+
+    /// Bytes in the send buffer, sent or not, which are not acknowledged yet.
+    #[inline]
+    pub fn send_queue(&self) -> usize {
+        self.send_buffer.available()
+    }
+
+    /// Free space in the send buffer.
+    #[inline]
+    pub fn send_space(&self) -> usize {
+        self.send_buffer.len() - 1 - self.send_buffer.available()
+    }
+
+    /// The peer closed its side of the connection and all its data was read.
+    #[inline]
+    pub fn eof(&self) -> bool {
+        self.fin_received && self.recv_buffer.available() == 0
+    }
+
+    /// Data can still be sent on the connection.
+    #[inline]
+    pub fn may_send(&self) -> bool {
+        match self.state {
+            TcpState::SynSent | TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => !self.fin_queued,
+            _ => false,
+        }
+    }
+
+    /// The congestion window in bytes.
+    #[inline]
+    pub fn cwnd(&self) -> u32 {
+        self.cwnd
+    }
+
+    /// The send window announced by the peer in bytes.
+    #[inline]
+    pub fn snd_wnd(&self) -> u32 {
+        self.snd_wnd
+    }
+
+    /// The maximum segment size used for sending.
+    #[inline]
+    pub fn mss(&self) -> u32 {
+        self.mss
+    }
+
+    /// The shift counts of the window scale option for received and sent windows, both are 0 if the option was
+    /// not negotiated.
+    #[inline]
+    pub fn window_scale(&self) -> (u8, u8) {
+        (self.snd_wscale, self.rcv_wscale)
+    }
+
+    /// Segments retransmitted on the connection.
+    #[inline]
+    pub fn retransmits(&self) -> u64 {
+        self.retransmits
+    }
+
+    /// The current retransmission timeout in TSC ticks.
+    #[inline]
+    pub fn rto(&self) -> u64 {
+        self.rto
+    }
+
+    #[inline]
+    fn rcv_nxt(&self) -> u32 {
+        self.recv_seq
+            .wrapping_add(self.recv_buffer.available() as u32)
+            .wrapping_add(self.fin_received as u32)
+    }
+
+    #[inline]
+    fn rcv_wnd(&self) -> u32 {
+        (self.recv_buffer.buffer_size() - 1 - self.recv_buffer.available()) as u32
+    }
+
+    /// The sequence number behind the last byte of the send buffer, which is the sequence number of the FIN once
+    /// it is queued.
+    #[inline]
+    fn send_end(&self) -> u32 {
+        self.send_seq.wrapping_add(self.send_buffer.available() as u32)
+    }
+
+    #[inline]
+    fn fin_acked(&self) -> bool {
+        self.fin_queued && seq_gt(self.snd_una, self.send_end())
+    }
+
+    /// Take over the sequence number and options of the SYN of the peer.
+    fn synchronize(&mut self, tcp: &TcpHeader, options: &TcpOptions) {
+        let data_seq = tcp.seq_num().wrapping_add(1);
+        self.recv_seq = data_seq;
+        self.recv_buffer.seq(data_seq, &[]);
+        self.mss = min(self.conf.mss, options.mss.unwrap_or(DEFAULT_MSS)) as u32;
+        if let (true, Some(shift)) = (self.conf.window_scaling, options.window_scale) {
+            self.wscale_ok = true;
+            self.snd_wscale = shift;
+            self.rcv_wscale = self.own_window_scale();
+        }
+    }
+
+    /// Queue `data` for sending. Returns the bytes queued, which are limited by the free space of the send buffer.
+    pub(crate) fn send(&mut self, data: &[u8]) -> Result<usize, TcpError> {
+        if !self.may_send() {
+            return Err(self.error.unwrap_or(TcpError::NotConnected));
+        }
+        let len = min(data.len(), self.send_space());
+        if len == 0 && !data.is_empty() {
+            return Err(TcpError::WouldBlock);
+        }
+        Ok(self.send_buffer.write_at_tail(&data[..len]))
+    }
+
+    /// Read received data into `data`. Returns 0 once the peer closed the connection and all data was read.
+    pub(crate) fn recv(&mut self, data: &mut [u8]) -> Result<usize, TcpError> {
+        let read = self.recv_buffer.read_data(data);
+        if read == 0 {
+            return if self.fin_received || data.is_empty() {
+                Ok(0)
+            } else if let Some(error) = self.error {
+                Err(error)
+            } else if self.state == TcpState::Closed || self.state == TcpState::SynSent {
+                Err(TcpError::NotConnected)
+            } else {
+                Err(TcpError::WouldBlock)
+            };
+        }
+        self.recv_seq = self.recv_seq.wrapping_add(read as u32);
+        // announce the opened window if it grew considerably
+        let wnd = self.rcv_wnd();
+        let threshold = min(2 * self.mss, (self.recv_buffer.buffer_size() / 2) as u32);
+        if wnd >= self.last_adv_wnd + threshold {
+            self.ack_now = true;
+        }
+        Ok(read)
+    }
+
+    /// Close the sending side of the connection, a FIN follows the data in the send buffer.
+    pub(crate) fn close(&mut self) {
+        match self.state {
+            TcpState::SynSent => self.state = TcpState::Closed,
+            TcpState::SynReceived | TcpState::Established => {
+                self.fin_queued = true;
+                self.state = TcpState::FinWait1;
+            }
+            TcpState::CloseWait => {
+                self.fin_queued = true;
+                self.state = TcpState::LastAck;
+            }
+            _ => (),
+        }
+    }
+
+    /// Close the connection with a RST appended to `out`.
+    pub(crate) fn abort(&mut self, out: &mut Vec<Segment>) {
+        match self.state {
+            TcpState::Closed | TcpState::SynSent | TcpState::TimeWait => (),
+            _ => {
+                let mut tcp = TcpHeader::new();
+                tcp.set_seq_num(self.snd_nxt);
+                tcp.set_ack_num(self.rcv_nxt());
+                tcp.set_rst_flag();
+                tcp.set_ack_flag();
+                out.push(Segment::new(self.flow, tcp, Vec::new(), Vec::new()));
+            }
+        }
+        self.state = TcpState::Closed;
+    }
+
+    fn fail(&mut self, error: TcpError) {
+        self.state = TcpState::Closed;
+        self.error = Some(error);
+        self.timer = None;
+    }
+
+    /// Process the segment `tcp` of the connection received at time `now`. RSTs are appended to `out`, all other
+    /// segments are sent by `output`.
+    pub(crate) fn receive(
+        &mut self,
+        tcp: &TcpHeader,
+        options: &TcpOptions,
+        payload: &[u8],
+        now: u64,
+        timers: &Timers,
+        out: &mut Vec<Segment>,
+    ) {
+        match self.state {
+            TcpState::Closed => return,
+            TcpState::SynSent => return self.receive_syn_sent(tcp, options, now, timers, out),
+            _ => (),
+        }
+        let seq = tcp.seq_num();
+        let ack = tcp.ack_num();
+        let seg_len = payload.len() as u32 + tcp.syn_flag() as u32 + tcp.fin_flag() as u32;
+        let rcv_nxt = self.rcv_nxt();
+        let rcv_wnd = self.rcv_wnd();
+        let in_window = |s: u32| seq_le(rcv_nxt, s) && seq_lt(s, rcv_nxt.wrapping_add(rcv_wnd));
+        let acceptable = match (seg_len, rcv_wnd) {
+            (0, 0) => seq == rcv_nxt,
+            (0, _) => in_window(seq),
+            (_, 0) => false,
+            _ => in_window(seq) || in_window(seq.wrapping_add(seg_len - 1)),
+        };
+        if !acceptable {
+            if self.state == TcpState::SynReceived && tcp.syn_flag() && !tcp.ack_flag() {
+                // the SYN-ACK was lost
+                self.snd_nxt = self.iss;
+            } else if !tcp.rst_flag() {
+                self.ack_now = true;
+            }
+            if self.state == TcpState::TimeWait && tcp.fin_flag() {
+                self.timer = Some(now + timers.time_wait);
+            }
+            return;
+        }
+
+        if tcp.rst_flag() {
+            // RFC 5961: only a RST at the expected sequence number resets the connection
+            if seq == rcv_nxt {
+                let error = if self.state == TcpState::SynReceived {
+                    TcpError::Refused
+                } else {
+                    TcpError::Reset
+                };
+                self.fail(error);
+            } else {
+                self.ack_now = true;
+            }
+            return;
+        }
+        if tcp.syn_flag() {
+            self.ack_now = true;
+            return;
+        }
+        if !tcp.ack_flag() {
+            return;
+        }
+
+        if self.state == TcpState::SynReceived {
+            if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_max) {
+                self.state = TcpState::Established;
+                self.snd_wnd = (tcp.window_size() as u32) << self.snd_wscale;
+                self.snd_wl1 = seq;
+                self.snd_wl2 = ack;
+            } else {
+                out.push(Segment::reset(&self.flow, tcp, payload.len()));
+                return;
+            }
+        }
+        if seq_gt(ack, self.snd_max) {
+            self.ack_now = true;
+            return;
+        }
+        if seq_ge(ack, self.snd_una) {
+            let mut window_changed = false;
+            if seq_lt(self.snd_wl1, seq) || (self.snd_wl1 == seq && seq_le(self.snd_wl2, ack)) {
+                let wnd = (tcp.window_size() as u32) << self.snd_wscale;
+                window_changed = wnd != self.snd_wnd;
+                self.snd_wnd = wnd;
+                self.snd_wl1 = seq;
+                self.snd_wl2 = ack;
+            }
+            if ack != self.snd_una {
+                self.new_ack(ack, now, timers);
+            } else if payload.is_empty()
+                && !tcp.fin_flag()
+                && !window_changed
+                && self.snd_una != self.snd_max
+                && self.snd_wnd > 0
+            {
+                self.dup_ack(ack);
+            }
+            if self.fin_acked() {
+                match self.state {
+                    TcpState::FinWait1 => self.state = TcpState::FinWait2,
+                    TcpState::Closing => {
+                        self.state = TcpState::TimeWait;
+                        self.timer = Some(now + timers.time_wait);
+                    }
+                    TcpState::LastAck => {
+                        self.state = TcpState::Closed;
+                        self.timer = None;
+                        return;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let receiving = matches!(
+            self.state,
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+        );
+        if !receiving {
+            return;
+        }
+        let mut len = payload.len();
+        let window_end = rcv_nxt.wrapping_add(rcv_wnd);
+        let end = seq.wrapping_add(len as u32);
+        if seq_gt(end, window_end) {
+            len -= end.wrapping_sub(window_end) as usize;
+        }
+        if len > 0 {
+            self.recv_buffer.add_data(seq, &payload[..len]);
+            self.ack_now = true;
+        }
+        if tcp.fin_flag() && len == payload.len() {
+            self.rcv_fin = Some(end);
+        }
+        if !self.fin_received && self.rcv_fin == Some(self.rcv_nxt()) {
+            self.fin_received = true;
+            self.ack_now = true;
+            match self.state {
+                TcpState::Established => self.state = TcpState::CloseWait,
+                TcpState::FinWait1 => self.state = TcpState::Closing,
+                TcpState::FinWait2 => {
+                    self.state = TcpState::TimeWait;
+                    self.timer = Some(now + timers.time_wait);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn receive_syn_sent(
+        &mut self,
+        tcp: &TcpHeader,
+        options: &TcpOptions,
+        now: u64,
+        timers: &Timers,
+        out: &mut Vec<Segment>,
+    ) {
+        let ack = tcp.ack_num();
+        if tcp.ack_flag() && !(seq_gt(ack, self.iss) && seq_le(ack, self.snd_max)) {
+            if !tcp.rst_flag() {
+                out.push(Segment::reset(&self.flow, tcp, 0));
+            }
+            return;
+        }
+        if tcp.rst_flag() {
+            if tcp.ack_flag() {
+                self.fail(TcpError::Refused);
+            }
+            return;
+        }
+        if !tcp.syn_flag() {
+            return;
+        }
+        self.synchronize(tcp, options);
+        self.snd_wl1 = tcp.seq_num();
+        self.snd_wnd = tcp.window_size() as u32;
+        self.ack_now = true;
+        if tcp.ack_flag() {
+            self.state = TcpState::Established;
+            self.snd_wl2 = ack;
+            self.new_ack(ack, now, timers);
+        } else {
+            // simultaneous open, the SYN is repeated with an ACK
+            self.state = TcpState::SynReceived;
+            self.snd_wl2 = self.iss;
+            self.snd_nxt = self.iss;
+        }
+    }
+
+    /// `ack` acknowledges new data.
+    fn new_ack(&mut self, ack: u32, now: u64, timers: &Timers) {
+        let acked = ack.wrapping_sub(self.snd_una);
+        if seq_gt(ack, self.send_seq) {
+            let released = min(ack.wrapping_sub(self.send_seq) as usize, self.send_buffer.available());
+            self.send_buffer.seek_head(released);
+            self.send_seq = self.send_seq.wrapping_add(released as u32);
+        }
+        self.snd_una = ack;
+        if seq_lt(self.snd_nxt, ack) {
+            self.snd_nxt = ack;
+        }
+        if let Some((end, sent)) = self.rtt_sample {
+            if seq_ge(ack, end) {
+                self.update_rto(now.saturating_sub(sent), timers);
+                self.rtt_sample = None;
+            }
+        }
+        self.retries = 0;
+
+        if self.in_recovery {
+            if seq_ge(ack, self.recover) {
+                // full acknowledgment, deflate the window (RFC 6582)
+                let flight = self.snd_max.wrapping_sub(self.snd_una);
+                self.cwnd = min(self.ssthresh, max(flight, self.mss) + self.mss);
+                self.in_recovery = false;
+                self.dup_acks = 0;
+            } else {
+                // partial acknowledgment, retransmit the next hole
+                self.cwnd = self.cwnd.saturating_sub(acked);
+                if acked >= self.mss {
+                    self.cwnd += self.mss;
+                }
+                self.retransmit_first = true;
+            }
+        } else {
+            self.dup_acks = 0;
+            if self.cwnd < self.ssthresh {
+                self.cwnd = self.cwnd.saturating_add(min(acked, self.mss));
+            } else {
+                self.cwnd = self.cwnd.saturating_add(max(1, self.mss * self.mss / self.cwnd));
+            }
+        }
+        self.timer = if self.snd_una == self.snd_max {
+            None
+        } else {
+            Some(now + self.rto)
+        };
+    }
+
+    fn dup_ack(&mut self, ack: u32) {
+        self.dup_acks += 1;
+        if self.dup_acks == 3 && !self.in_recovery && seq_gt(ack, self.recover) {
+            // fast retransmit
+            let flight = self.snd_max.wrapping_sub(self.snd_una);
+            self.ssthresh = max(flight / 2, 2 * self.mss);
+            self.recover = self.snd_max;
+            self.in_recovery = true;
+            self.retransmit_first = true;
+            self.cwnd = self.ssthresh + 3 * self.mss;
+        } else if self.in_recovery {
+            self.cwnd = self.cwnd.saturating_add(self.mss);
+        }
+    }
+
+    fn update_rto(&mut self, rtt: u64, timers: &Timers) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = (3 * self.rttvar + delta) / 4;
+                (7 * srtt + rtt) / 8
+            }
+        };
+        self.srtt = Some(srtt);
+        self.rto = min(max(srtt + max(1, 4 * self.rttvar), timers.min_rto), timers.max_rto);
+    }
+
+    /// Run the timer of the connection at time `now`.
+    pub(crate) fn on_timer(&mut self, now: u64, timers: &Timers) {
+        match self.timer {
+            Some(deadline) if now >= deadline => self.timer = None,
+            _ => return,
+        }
+        if self.state == TcpState::TimeWait {
+            self.state = TcpState::Closed;
+            return;
+        }
+        self.rto = min(self.rto * 2, timers.max_rto);
+        let synchronized = self.state != TcpState::SynSent && self.state != TcpState::SynReceived;
+        if synchronized && self.snd_wnd == 0 {
+            // persist timer, probe the zero window of the peer with one byte
+            self.snd_nxt = self.snd_una;
+            self.probe = true;
+            return;
+        }
+        self.retries += 1;
+        if self.retries > self.conf.max_retries {
+            self.fail(TcpError::TimedOut);
+            return;
+        }
+        if synchronized {
+            let flight = self.snd_max.wrapping_sub(self.snd_una);
+            self.ssthresh = max(flight / 2, 2 * self.mss);
+            self.cwnd = self.mss;
+            self.recover = self.snd_max;
+            self.in_recovery = false;
+            self.dup_acks = 0;
+        }
+        // go back to the first unacknowledged byte, Karn's algorithm excludes retransmissions from RTT samples
+        self.snd_nxt = self.snd_una;
+        self.rtt_sample = None;
+        self.retransmit_first = false;
+    }
+
+    /// A segment from `seq` with `len` bytes of data and the flags of the state.
+    fn segment(&mut self, seq: u32, len: usize, fin: bool) -> Segment {
+        let mut tcp = TcpHeader::new();
+        tcp.set_seq_num(seq);
+        let mut options = TcpOptions::default();
+        let syn = seq == self.iss && (self.state == TcpState::SynSent || self.state == TcpState::SynReceived);
+        let window = if syn {
+            tcp.set_syn_flag();
+            options.mss = Some(self.conf.mss);
+            // a SYN-ACK carries the option only if the SYN did
+            if self.conf.window_scaling && (self.state == TcpState::SynSent || self.wscale_ok) {
+                options.window_scale = Some(self.own_window_scale());
+            }
+            min(self.rcv_wnd(), 65535)
+        } else {
+            min(self.rcv_wnd() >> self.rcv_wscale, 65535)
+        };
+        if self.state != TcpState::SynSent {
+            tcp.set_ack_flag();
+            tcp.set_ack_num(self.rcv_nxt());
+        }
+        tcp.set_window_size(window as u16);
+        self.last_adv_wnd = window << if syn { 0 } else { self.rcv_wscale };
+        let mut payload = vec![0; len];
+        if len > 0 {
+            let offset = seq.wrapping_sub(self.send_seq) as usize;
+            self.send_buffer.read_at_offset_from_head(offset, &mut payload);
+            if seq.wrapping_add(len as u32) == self.send_end() {
+                tcp.set_psh_flag();
+            }
+        }
+        if fin {
+            tcp.set_fin_flag();
+        }
+        self.ack_now = false;
+        Segment::new(self.flow, tcp, options.to_bytes(), payload)
+    }
+
+    /// The shift count announced for our receive window, the smallest which can express the whole receive buffer.
+    fn own_window_scale(&self) -> u8 {
+        let mut shift = 0;
+        while shift < TCP_MAX_WINDOW_SCALE && ((self.recv_buffer.buffer_size() - 1) >> shift) > 65535 {
+            shift += 1;
+        }
+        shift
+    }
+
+    /// Append the segments to send at time `now` to `out`: handshake, retransmissions, new data as far as the
+    /// windows allow, the FIN and pending acknowledgments.
+    pub(crate) fn output(&mut self, now: u64, out: &mut Vec<Segment>) {
+        match self.state {
+            TcpState::Closed => return,
+            TcpState::TimeWait => {
+                if self.ack_now {
+                    let seq = self.snd_nxt;
+                    out.push(self.segment(seq, 0, false));
+                }
+                return;
+            }
+            TcpState::SynSent | TcpState::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let iss = self.iss;
+                    out.push(self.segment(iss, 0, false));
+                    self.snd_nxt = iss.wrapping_add(1);
+                    if self.snd_max == iss {
+                        self.snd_max = self.snd_nxt;
+                        self.rtt_sample = Some((self.snd_nxt, now));
+                    } else {
+                        self.retransmits += 1;
+                    }
+                    self.timer = self.timer.or(Some(now + self.rto));
+                }
+                // no data and no bare ACK before the handshake completed
+                self.ack_now = false;
+                return;
+            }
+            _ => (),
+        }
+
+        if self.retransmit_first {
+            self.retransmit_first = false;
+            let outstanding = self.snd_max.wrapping_sub(self.snd_una);
+            let fin_end = self.send_end().wrapping_add(1);
+            let data = min(outstanding, self.send_end().wrapping_sub(self.snd_una)) as usize;
+            let len = min(data, self.mss as usize);
+            let una = self.snd_una;
+            let fin = self.fin_queued && self.snd_max == fin_end && una.wrapping_add(len as u32) == self.send_end();
+            if len > 0 || fin {
+                out.push(self.segment(una, len, fin));
+                self.retransmits += 1;
+                self.rtt_sample = None;
+                self.timer = Some(now + self.rto);
+            }
+        }
+
+        let sending = matches!(
+            self.state,
+            TcpState::Established | TcpState::CloseWait | TcpState::FinWait1 | TcpState::LastAck | TcpState::Closing
+        );
+        if sending {
+            loop {
+                let send_end = self.send_end();
+                let unsent = if seq_lt(self.snd_nxt, send_end) {
+                    send_end.wrapping_sub(self.snd_nxt) as usize
+                } else {
+                    0
+                };
+                let in_flight = self.snd_nxt.wrapping_sub(self.snd_una);
+                let window = min(self.snd_wnd, self.cwnd);
+                let usable = if self.probe && unsent > 0 {
+                    self.probe = false;
+                    max(window.saturating_sub(in_flight), 1)
+                } else {
+                    window.saturating_sub(in_flight)
+                };
+                let len = min(min(unsent, self.mss as usize), usable as usize);
+                let fin = self.fin_queued && self.snd_nxt.wrapping_add(len as u32) == send_end;
+                if len == 0 && !fin {
+                    if unsent > 0 && in_flight == 0 && self.timer.is_none() {
+                        // persist timer, probe the zero window of the peer
+                        self.timer = Some(now + self.rto);
+                    }
+                    break;
+                }
+                let seq = self.snd_nxt;
+                out.push(self.segment(seq, len, fin));
+                let new_data = seq == self.snd_max;
+                self.snd_nxt = seq.wrapping_add(len as u32 + fin as u32);
+                if seq_gt(self.snd_nxt, self.snd_max) {
+                    self.snd_max = self.snd_nxt;
+                }
+                if new_data && self.rtt_sample.is_none() {
+                    self.rtt_sample = Some((self.snd_nxt, now));
+                } else if !new_data {
+                    self.retransmits += 1;
+                }
+                if self.timer.is_none() {
+                    self.timer = Some(now + self.rto);
+                }
+                if fin {
+                    break;
+                }
+            }
+        }
+        if self.ack_now {
+            let seq = self.snd_nxt;
+            out.push(self.segment(seq, 0, false));
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use super::socket::Timers;
+use super::{TcpConf, TcpError, TcpSocket, TcpState};
+use fnv::FnvHasher;
+use headers::{IpHeader, MacHeader, TcpHeader, TcpOptions};
+use interface::PacketBuilder;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, BuildHasherDefault, Hash};
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use utils::FiveTupleV4;
+
+type FnvHash = BuildHasherDefault<FnvHasher>;
+
+/// First port used for active opens.
+const EPHEMERAL_PORTS: u16 = 49152;
+
+/// A segment sent by a `TcpStack`. The data offset and the checksum of `tcp` are filled in by the `PacketBuilder`
+/// of `Segment::packet`.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    /// from the local to the remote socket
+    pub flow: FiveTupleV4,
+    pub tcp: TcpHeader,
+    /// encoded options, see `TcpOptions::to_bytes`
+    pub options: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+impl Segment {
+    /// A segment of `flow`, the ports of `tcp` are set from the flow.
+    pub fn new(flow: FiveTupleV4, mut tcp: TcpHeader, options: Vec<u8>, payload: Vec<u8>) -> Segment {
+        tcp.set_src_port(flow.src_port);
+        tcp.set_dst_port(flow.dst_port);
+        Segment {
+            flow,
+            tcp,
+            options,
+            payload,
+        }
+    }
+
+    /// The RST answering the segment `tcp` with `len` bytes of data, which was received on `flow` reversed
+    /// (RFC 793).
+    pub fn reset(flow: &FiveTupleV4, tcp: &TcpHeader, len: usize) -> Segment {
+        let mut rst = TcpHeader::new();
+        rst.set_rst_flag();
+        if tcp.ack_flag() {
+            rst.set_seq_num(tcp.ack_num());
+        } else {
+            let seg_len = len as u32 + tcp.syn_flag() as u32 + tcp.fin_flag() as u32;
+            rst.set_ack_flag();
+            rst.set_ack_num(tcp.seq_num().wrapping_add(seg_len));
+        }
+        Segment::new(*flow, rst, Vec::new(), Vec::new())
+    }
+
+    /// A builder for the packet of the segment behind `mac`.
+    pub fn packet(&self, mac: MacHeader) -> PacketBuilder<'_> {
+        let mut ip = IpHeader::new();
+        ip.set_src(self.flow.src_ip);
+        ip.set_dst(self.flow.dst_ip);
+        PacketBuilder::new()
+            .mac(mac)
+            .ipv4(ip)
+            .tcp(self.tcp)
+            .tcp_options(&self.options)
+            .payload(&self.payload)
+    }
+}
+
+/// Refers to a socket of a `TcpStack`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SocketHandle(u64);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TcpStats {
+    pub active_opens: u64,
+    pub passive_opens: u64,
+    /// SYNs dropped because the backlog of their listener was full
+    pub backlog_drops: u64,
+    pub resets_sent: u64,
+}
+
+struct Entry {
+    socket: TcpSocket,
+    // the port of the listener, until the connection is accepted
+    listener: Option<u16>,
+    // the application closed the socket, it is removed once the connection is closed
+    released: bool,
+}
+
+#[derive(Default)]
+struct Listener {
+    accept_queue: VecDeque<SocketHandle>,
+    // connections not accepted yet, including those in their handshake
+    pending: usize,
+}
+
+/// The TCP connections of one IPv4 address. Sockets are referred to by handles, which stay valid until they are
+/// closed with `close` or `abort`, even if the connection was closed by the peer or failed. The socket API does not
+/// block: `send` and `recv` return `TcpError::WouldBlock` instead. Time stamps are TSC values.
+pub struct TcpStack {
+    addr: u32,
+    conf: TcpConf,
+    timers: Timers,
+    ticks_per_isn: u64,
+    sockets: HashMap<SocketHandle, Entry, FnvHash>,
+    flows: HashMap<FiveTupleV4, SocketHandle, FnvHash>,
+    listeners: HashMap<u16, Listener, FnvHash>,
+    next_handle: u64,
+    next_port: u16,
+    isn_key: RandomState,
+    resets: Vec<Segment>,
+    handles: Vec<SocketHandle>,
+    stats: TcpStats,
+}
+
+impl TcpStack {
+    pub fn new(addr: Ipv4Addr, conf: TcpConf, tsc_hz: u64) -> TcpStack {
+        TcpStack {
+            addr: u32::from(addr),
+            conf,
+            timers: Timers::new(&conf, tsc_hz),
+            // the ISN clock ticks every 4 microseconds (RFC 793)
+            ticks_per_isn: (tsc_hz / 250_000).max(1),
+            sockets: HashMap::with_hasher(Default::default()),
+            flows: HashMap::with_hasher(Default::default()),
+            listeners: HashMap::with_hasher(Default::default()),
+            next_handle: 0,
+            next_port: EPHEMERAL_PORTS,
+            isn_key: RandomState::new(),
+            resets: Vec::new(),
+            handles: Vec::new(),
+            stats: TcpStats::default(),
+        }
+    }
+
+    #[inline]
+    pub fn addr(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.addr)
+    }
+
+    #[inline]
+    pub fn conf(&self) -> &TcpConf {
+        &self.conf
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &TcpStats {
+        &self.stats
+    }
+
+    /// Number of sockets, including those not accepted yet.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    #[inline]
+    pub fn socket(&self, handle: SocketHandle) -> Option<&TcpSocket> {
+        self.sockets.get(&handle).map(|e| &e.socket)
+    }
+
+    /// The initial sequence number of `flow` at time `now`, a keyed hash of the flow plus a clock (RFC 6528).
+    fn isn(&self, flow: &FiveTupleV4, now: u64) -> u32 {
+        (self.isn_key.hash_one(flow) as u32).wrapping_add((now / self.ticks_per_isn) as u32)
+    }
+
+    fn insert(&mut self, socket: TcpSocket, listener: Option<u16>) -> SocketHandle {
+        let handle = SocketHandle(self.next_handle);
+        self.next_handle += 1;
+        self.flows.insert(*socket.flow(), handle);
+        self.sockets.insert(
+            handle,
+            Entry {
+                socket,
+                listener,
+                released: false,
+            },
+        );
+        handle
+    }
+
+    fn remove(&mut self, handle: SocketHandle) {
+        if let Some(entry) = self.sockets.remove(&handle) {
+            self.flows.remove(entry.socket.flow());
+            if let Some(listener) = entry.listener.and_then(|port| self.listeners.get_mut(&port)) {
+                listener.pending -= 1;
+                listener.accept_queue.retain(|h| *h != handle);
+            }
+        }
+    }
+
+    /// Queue sockets which completed their handshake for accepting and remove closed sockets which are not
+    /// referred to by the application anymore.
+    fn update(&mut self, handle: SocketHandle, before: TcpState) {
+        let (state, listener, released) = match self.sockets.get(&handle) {
+            Some(entry) => (entry.socket.state(), entry.listener, entry.released),
+            None => return,
+        };
+        if state == TcpState::Closed && (released || listener.is_some()) {
+            self.remove(handle);
+        } else if before == TcpState::SynReceived && state != TcpState::SynReceived {
+            if let Some(listener) = listener.and_then(|port| self.listeners.get_mut(&port)) {
+                listener.accept_queue.push_back(handle);
+            }
+        }
+    }
+
+    /// Accept connections on `port`.
+    pub fn listen(&mut self, port: u16) -> Result<(), TcpError> {
+        if self.listeners.contains_key(&port) {
+            return Err(TcpError::AddrInUse);
+        }
+        self.listeners.insert(port, Listener::default());
+        Ok(())
+    }
+
+    /// Stop accepting connections on `port`, connections which were not accepted yet are reset.
+    pub fn unlisten(&mut self, port: u16) {
+        if self.listeners.remove(&port).is_none() {
+            return;
+        }
+        let pending: Vec<SocketHandle> = self
+            .sockets
+            .iter()
+            .filter(|&(_, e)| e.listener == Some(port))
+            .map(|(h, _)| *h)
+            .collect();
+        for handle in pending {
+            if let Some(mut entry) = self.sockets.remove(&handle) {
+                entry.socket.abort(&mut self.resets);
+                self.flows.remove(entry.socket.flow());
+            }
+        }
+    }
+
+    /// The next established connection of the listener on `port`.
+    pub fn accept(&mut self, port: u16) -> Option<SocketHandle> {
+        let listener = self.listeners.get_mut(&port)?;
+        let handle = listener.accept_queue.pop_front()?;
+        listener.pending -= 1;
+        if let Some(entry) = self.sockets.get_mut(&handle) {
+            entry.listener = None;
+        }
+        Some(handle)
+    }
+
+    /// Open a connection to `remote` from an ephemeral port. The SYN is sent by the next `poll`.
+    pub fn connect(&mut self, remote: SocketAddrV4, now: u64) -> Result<SocketHandle, TcpError> {
+        let mut flow = FiveTupleV4 {
+            src_ip: self.addr,
+            dst_ip: u32::from(*remote.ip()),
+            src_port: 0,
+            dst_port: remote.port(),
+            proto: 6,
+        };
+        let ports = (u16::MAX - EPHEMERAL_PORTS) as usize + 1;
+        for _ in 0..ports {
+            flow.src_port = self.next_port;
+            self.next_port = self.next_port.checked_add(1).unwrap_or(EPHEMERAL_PORTS);
+            let src_port = flow.src_port;
+            if !self.flows.contains_key(&flow) && !self.listeners.contains_key(&src_port) {
+                let iss = self.isn(&flow, now);
+                let socket = TcpSocket::connect(flow, iss, &self.conf, &self.timers)?;
+                self.stats.active_opens += 1;
+                return Ok(self.insert(socket, None));
+            }
+        }
+        Err(TcpError::AddrInUse)
+    }
+
+    /// Queue `data` for sending. Returns the bytes queued, which are limited by the free space of the send buffer.
+    pub fn send(&mut self, handle: SocketHandle, data: &[u8]) -> Result<usize, TcpError> {
+        match self.sockets.get_mut(&handle) {
+            Some(entry) => entry.socket.send(data),
+            None => Err(TcpError::InvalidHandle),
+        }
+    }
+
+    /// Read received data into `data`. Returns 0 once the peer closed its side of the connection and all data was
+    /// read.
+    pub fn recv(&mut self, handle: SocketHandle, data: &mut [u8]) -> Result<usize, TcpError> {
+        match self.sockets.get_mut(&handle) {
+            Some(entry) => entry.socket.recv(data),
+            None => Err(TcpError::InvalidHandle),
+        }
+    }
+
+    /// Close the socket. The connection is closed gracefully: the data in the send buffer is still delivered,
+    /// followed by a FIN. The handle is invalid afterwards.
+    pub fn close(&mut self, handle: SocketHandle) {
+        let before = match self.sockets.get_mut(&handle) {
+            Some(entry) => {
+                entry.released = true;
+                let before = entry.socket.state();
+                entry.socket.close();
+                before
+            }
+            None => return,
+        };
+        self.update(handle, before);
+    }
+
+    /// Reset the connection of the socket. The handle is invalid afterwards.
+    pub fn abort(&mut self, handle: SocketHandle) {
+        if let Some(entry) = self.sockets.get_mut(&handle) {
+            entry.socket.abort(&mut self.resets);
+        }
+        self.remove(handle);
+    }
+
+    /// Process a TCP segment of `flow` addressed to the stack with its encoded `options` and `payload`, received
+    /// at time `now`. Segments of unknown connections are answered with a RST.
+    pub fn receive(&mut self, flow: &FiveTupleV4, tcp: &TcpHeader, options: &[u8], payload: &[u8], now: u64) {
+        if flow.dst_ip != self.addr || flow.proto != 6 {
+            return;
+        }
+        let local = flow.reverse_flow();
+        if let Some(&handle) = self.flows.get(&local) {
+            let before = {
+                let entry = self.sockets.get_mut(&handle).unwrap();
+                let before = entry.socket.state();
+                let options = if tcp.syn_flag() {
+                    TcpOptions::parse(options)
+                } else {
+                    TcpOptions::default()
+                };
+                entry
+                    .socket
+                    .receive(tcp, &options, payload, now, &self.timers, &mut self.resets);
+                before
+            };
+            self.update(handle, before);
+            return;
+        }
+        if tcp.rst_flag() {
+            return;
+        }
+        if tcp.syn_flag() && !tcp.ack_flag() {
+            let dst_port = flow.dst_port;
+            if let Some(pending) = self.listeners.get(&dst_port).map(|l| l.pending) {
+                if pending >= self.conf.backlog {
+                    self.stats.backlog_drops += 1;
+                    return;
+                }
+                let iss = self.isn(&local, now);
+                let options = TcpOptions::parse(options);
+                if let Ok(socket) = TcpSocket::accept(local, iss, &self.conf, &self.timers, tcp, &options) {
+                    self.listeners.get_mut(&dst_port).unwrap().pending += 1;
+                    self.stats.passive_opens += 1;
+                    self.insert(socket, Some(dst_port));
+                }
+                return;
+            }
+        }
+        self.resets.push(Segment::reset(&local, tcp, payload.len()));
+    }
+
+    /// Run the timers of all connections at time `now` and append the segments to send to `out`.
+    pub fn poll(&mut self, now: u64, out: &mut Vec<Segment>) {
+        self.stats.resets_sent += self.resets.iter().filter(|s| s.tcp.rst_flag()).count() as u64;
+        out.append(&mut self.resets);
+        let mut handles = mem::take(&mut self.handles);
+        handles.extend(self.sockets.keys());
+        for &handle in &handles {
+            let before = {
+                let entry = self.sockets.get_mut(&handle).unwrap();
+                let before = entry.socket.state();
+                entry.socket.on_timer(now, &self.timers);
+                entry.socket.output(now, out);
+                before
+            };
+            self.update(handle, before);
+        }
+        handles.clear();
+        self.handles = handles;
+    }
+}
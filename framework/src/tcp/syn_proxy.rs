@@ -0,0 +1,405 @@
+use fnv::FnvHasher;
+use headers::{TcpHeader, TcpOptions};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::net::SocketAddrV4;
+use utils::FiveTupleV4;
+
+type FnvHash = BuildHasherDefault<FnvHasher>;
+
+/// The MSS values a cookie can encode, the MSS of a client is rounded down to one of them. SYNs announcing a smaller
+/// MSS get no cookie.
+const COOKIE_MSS: [u16; 4] = [536, 1300, 1440, 1460];
+
+/// The cookie counter takes the upper 8 bits of a cookie, the hash plus the MSS index the lower 24 bits.
+const COUNTER_SHIFT: u32 = 24;
+const HASH_MASK: u32 = (1 << COUNTER_SHIFT) - 1;
+
+/// Limits and timers of a `SynProxy`, times are in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SynProxyConf {
+    /// MSS announced to clients, it should not exceed the MSS of the servers
+    pub mss: u16,
+    /// the counter encoded in cookies is incremented once per period
+    pub cookie_period: u64,
+    /// periods for which a cookie stays valid
+    pub cookie_lifetime: u32,
+    /// time the servers have to answer the SYN of a connection
+    pub handshake_timeout: u64,
+    /// time after which idle connections are forgotten
+    pub idle_timeout: u64,
+    /// connections whose sequence numbers are translated
+    pub max_connections: usize,
+}
+
+impl Default for SynProxyConf {
+    fn default() -> SynProxyConf {
+        SynProxyConf {
+            mss: 1460,
+            cookie_period: 64_000,
+            cookie_lifetime: 2,
+            handshake_timeout: 30_000,
+            idle_timeout: 300_000,
+            max_connections: 1 << 20,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SynProxyStats {
+    /// SYN-ACKs with cookies sent to clients
+    pub cookies_sent: u64,
+    /// SYNs dropped as their MSS is below the smallest MSS a cookie can encode
+    pub mss_too_small: u64,
+    /// ACKs with a valid cookie, each opens a connection to the server
+    pub cookies_valid: u64,
+    /// ACKs of unknown connections without a valid cookie
+    pub cookies_invalid: u64,
+    /// connections the servers accepted
+    pub connected: u64,
+    /// valid cookies rejected because `max_connections` was reached
+    pub table_full: u64,
+}
+
+/// The sequence and acknowledgment number and the window of an ACK without data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ack {
+    pub seq: u32,
+    pub ack: u32,
+    pub window: u16,
+}
+
+/// What to do with a segment passing a `SynProxy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SynProxyAction {
+    /// the segment does not belong to a protected service, forward it unchanged
+    Pass,
+    Drop,
+    /// answer the SYN with a SYN-ACK with sequence number `cookie`, which announces `mss` and a zero window
+    SynAck {
+        cookie: u32,
+        mss: u16,
+    },
+    /// the client completed the handshake with the proxy, turn its segment into the SYN to the server with
+    /// sequence number `seq`, which announces the MSS `mss` of the client
+    Syn {
+        seq: u32,
+        mss: u16,
+    },
+    /// the server answered the SYN, turn its SYN-ACK into the ACK `server` to the server and open the window of
+    /// the client with the ACK `client`
+    Connected {
+        server: Ack,
+        client: Ack,
+    },
+    /// forward the segment with `seq_delta` added to its sequence and `ack_delta` added to its acknowledgment
+    /// number
+    Translate {
+        seq_delta: u32,
+        ack_delta: u32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// the SYN was sent to the server
+    SynSent,
+    Established,
+}
+
+struct Connection {
+    state: State,
+    client_isn: u32,
+    cookie: u32,
+    server_isn: u32,
+    mss: u16,
+    window: u16,
+    opened: u64,
+    last_seen: u64,
+}
+
+impl Connection {
+    /// Added to the sequence numbers of the server to get those the client expects.
+    #[inline]
+    fn delta(&self) -> u32 {
+        self.cookie.wrapping_sub(self.server_isn)
+    }
+}
+
+/// Protects TCP services against SYN floods. The proxy answers the SYNs of clients itself with SYN cookies, which
+/// encode the MSS of the client and a coarse time stamp, so that it keeps no state for the handshakes of clients.
+/// Only an ACK returning a valid cookie opens the connection to the server, with the initial sequence number and
+/// MSS of the client. Afterwards the sequence numbers of the server are spliced into those of the cookie: the proxy
+/// has to see both directions of the protected connections.
+///
+/// The cookie handshake does not negotiate window scaling, selective acknowledgments or time stamps. Clients get a
+/// zero window until the server accepted, so that they do not send data which the proxy would have to drop. Time
+/// stamps are TSC values.
+pub struct SynProxy {
+    conf: SynProxyConf,
+    ticks_per_period: u64,
+    handshake_timeout: u64,
+    idle_timeout: u64,
+    ticks_per_expiry: u64,
+    next_expiry: u64,
+    services: HashSet<SocketAddrV4, FnvHash>,
+    connections: HashMap<FiveTupleV4, Connection, FnvHash>,
+    cookie_key: RandomState,
+    stats: SynProxyStats,
+}
+
+impl SynProxy {
+    pub fn new(conf: SynProxyConf, tsc_hz: u64) -> SynProxy {
+        let ticks = |ms: u64| (ms * tsc_hz / 1000).max(1);
+        SynProxy {
+            conf,
+            ticks_per_period: ticks(conf.cookie_period),
+            handshake_timeout: ticks(conf.handshake_timeout),
+            idle_timeout: ticks(conf.idle_timeout),
+            // idle connections are looked for once per second
+            ticks_per_expiry: tsc_hz.max(1),
+            next_expiry: 0,
+            services: HashSet::with_hasher(Default::default()),
+            connections: HashMap::with_hasher(Default::default()),
+            cookie_key: RandomState::new(),
+            stats: SynProxyStats::default(),
+        }
+    }
+
+    #[inline]
+    pub fn conf(&self) -> &SynProxyConf {
+        &self.conf
+    }
+
+    #[inline]
+    pub fn stats(&self) -> &SynProxyStats {
+        &self.stats
+    }
+
+    /// Number of connections whose sequence numbers are translated, including those waiting for the server.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Answer the SYNs for the service at `addr`.
+    pub fn protect(&mut self, addr: SocketAddrV4) {
+        self.services.insert(addr);
+    }
+
+    /// Stop protecting the service at `addr`, its connections keep being translated until they end.
+    pub fn unprotect(&mut self, addr: SocketAddrV4) {
+        self.services.remove(&addr);
+    }
+
+    /// Process the segment `tcp` of `flow` with the `options` of its header, which arrived at time `now`.
+    pub fn process(&mut self, flow: &FiveTupleV4, tcp: &TcpHeader, options: &[u8], now: u64) -> SynProxyAction {
+        if now >= self.next_expiry {
+            self.expire(now);
+            self.next_expiry = now + self.ticks_per_expiry;
+        }
+        if self.services.contains(&flow.dst_socket_addr()) {
+            self.handle_client(flow, tcp, options, now)
+        } else if self.services.contains(&flow.src_socket_addr()) {
+            self.handle_server(&flow.reverse_flow(), tcp, now)
+        } else {
+            SynProxyAction::Pass
+        }
+    }
+
+    fn handle_client(&mut self, flow: &FiveTupleV4, tcp: &TcpHeader, options: &[u8], now: u64) -> SynProxyAction {
+        if let Some(conn) = self.connections.get_mut(flow) {
+            conn.last_seen = now;
+            if tcp.rst_flag() {
+                let action = match conn.state {
+                    // the server does not know the sequence numbers of the cookie yet
+                    State::SynSent => SynProxyAction::Drop,
+                    State::Established => SynProxyAction::Translate {
+                        seq_delta: 0,
+                        ack_delta: conn.delta().wrapping_neg(),
+                    },
+                };
+                self.connections.remove(flow);
+                return action;
+            }
+            return match conn.state {
+                // the zero window probes of the client retransmit the SYN
+                State::SynSent => SynProxyAction::Syn {
+                    seq: conn.client_isn,
+                    mss: conn.mss,
+                },
+                State::Established => SynProxyAction::Translate {
+                    seq_delta: 0,
+                    ack_delta: conn.delta().wrapping_neg(),
+                },
+            };
+        }
+
+        if tcp.rst_flag() {
+            return SynProxyAction::Drop;
+        }
+        if tcp.syn_flag() {
+            if tcp.ack_flag() {
+                return SynProxyAction::Drop;
+            }
+            let mss = TcpOptions::parse(options).mss.unwrap_or(COOKIE_MSS[0]);
+            return match self.cookie(flow, tcp.seq_num(), mss, now) {
+                Some(cookie) => {
+                    self.stats.cookies_sent += 1;
+                    SynProxyAction::SynAck {
+                        cookie,
+                        mss: self.conf.mss,
+                    }
+                }
+                None => {
+                    self.stats.mss_too_small += 1;
+                    SynProxyAction::Drop
+                }
+            };
+        }
+        if !tcp.ack_flag() {
+            return SynProxyAction::Drop;
+        }
+        let client_isn = tcp.seq_num().wrapping_sub(1);
+        let cookie = tcp.ack_num().wrapping_sub(1);
+        let mss = match self.check_cookie(flow, client_isn, cookie, now) {
+            Some(mss) => mss,
+            None => {
+                self.stats.cookies_invalid += 1;
+                return SynProxyAction::Drop;
+            }
+        };
+        self.stats.cookies_valid += 1;
+        if self.connections.len() >= self.conf.max_connections {
+            self.stats.table_full += 1;
+            return SynProxyAction::Drop;
+        }
+        self.connections.insert(
+            *flow,
+            Connection {
+                state: State::SynSent,
+                client_isn,
+                cookie,
+                server_isn: 0,
+                mss,
+                window: tcp.window_size(),
+                opened: now,
+                last_seen: now,
+            },
+        );
+        SynProxyAction::Syn { seq: client_isn, mss }
+    }
+
+    fn handle_server(&mut self, flow: &FiveTupleV4, tcp: &TcpHeader, now: u64) -> SynProxyAction {
+        let (action, remove) = match self.connections.get_mut(flow) {
+            None => return SynProxyAction::Pass,
+            Some(conn) => {
+                conn.last_seen = now;
+                let answers_syn = tcp.ack_flag() && tcp.ack_num() == conn.client_isn.wrapping_add(1);
+                if tcp.rst_flag() {
+                    let action = match conn.state {
+                        // the RST answering the SYN is sent on to the client in its sequence space
+                        State::SynSent if answers_syn => SynProxyAction::Translate {
+                            seq_delta: conn.cookie.wrapping_add(1).wrapping_sub(tcp.seq_num()),
+                            ack_delta: 0,
+                        },
+                        State::SynSent => SynProxyAction::Drop,
+                        State::Established => SynProxyAction::Translate {
+                            seq_delta: conn.delta(),
+                            ack_delta: 0,
+                        },
+                    };
+                    (action, action != SynProxyAction::Drop)
+                } else if tcp.syn_flag() {
+                    if !answers_syn || (conn.state == State::Established && tcp.seq_num() != conn.server_isn) {
+                        (SynProxyAction::Drop, false)
+                    } else {
+                        // the SYN-ACK is retransmitted if the ACK of the proxy was lost
+                        if conn.state == State::SynSent {
+                            conn.state = State::Established;
+                            conn.server_isn = tcp.seq_num();
+                            self.stats.connected += 1;
+                        }
+                        let action = SynProxyAction::Connected {
+                            server: Ack {
+                                seq: conn.client_isn.wrapping_add(1),
+                                ack: conn.server_isn.wrapping_add(1),
+                                window: conn.window,
+                            },
+                            client: Ack {
+                                seq: conn.cookie.wrapping_add(1),
+                                ack: conn.client_isn.wrapping_add(1),
+                                window: tcp.window_size(),
+                            },
+                        };
+                        (action, false)
+                    }
+                } else if conn.state == State::SynSent {
+                    (SynProxyAction::Drop, false)
+                } else {
+                    let action = SynProxyAction::Translate {
+                        seq_delta: conn.delta(),
+                        ack_delta: 0,
+                    };
+                    (action, false)
+                }
+            }
+        };
+        if remove {
+            self.connections.remove(flow);
+        }
+        action
+    }
+
+    /// Forget the connections which are idle or whose server did not answer in time.
+    fn expire(&mut self, now: u64) {
+        let handshake_timeout = self.handshake_timeout;
+        let idle_timeout = self.idle_timeout;
+        self.connections.retain(|_, conn| match conn.state {
+            State::SynSent => now.saturating_sub(conn.opened) < handshake_timeout,
+            State::Established => now.saturating_sub(conn.last_seen) < idle_timeout,
+        });
+    }
+
+    #[inline]
+    fn counter(&self, now: u64) -> u32 {
+        (now / self.ticks_per_period) as u32
+    }
+
+    fn cookie_hash(&self, flow: &FiveTupleV4, isn: u32, counter: u32) -> u32 {
+        let mut hasher: DefaultHasher = self.cookie_key.build_hasher();
+        flow.hash(&mut hasher);
+        isn.hash(&mut hasher);
+        counter.hash(&mut hasher);
+        hasher.finish() as u32
+    }
+
+    /// The initial sequence number for the SYN-ACK answering the SYN with sequence number `isn` and MSS `mss` of
+    /// `flow`: the counter of the current period followed by a keyed hash of the SYN and the period, plus the index
+    /// of the MSS. None if `mss` is below the smallest MSS a cookie can encode.
+    fn cookie(&self, flow: &FiveTupleV4, isn: u32, mss: u16, now: u64) -> Option<u32> {
+        let counter = self.counter(now);
+        let index = COOKIE_MSS.iter().rposition(|m| *m <= mss)? as u32;
+        let hash = self.cookie_hash(flow, isn, counter).wrapping_add(index) & HASH_MASK;
+        Some(counter << COUNTER_SHIFT | hash)
+    }
+
+    /// The MSS encoded in `cookie`, if it was issued for the SYN with sequence number `isn` of `flow` and has not
+    /// expired.
+    fn check_cookie(&self, flow: &FiveTupleV4, isn: u32, cookie: u32, now: u64) -> Option<u16> {
+        let counter = self.counter(now);
+        let age = (counter as u8).wrapping_sub((cookie >> COUNTER_SHIFT) as u8) as u32;
+        if age >= self.conf.cookie_lifetime {
+            return None;
+        }
+        let hash = self.cookie_hash(flow, isn, counter.wrapping_sub(age));
+        let index = cookie.wrapping_sub(hash) & HASH_MASK;
+        COOKIE_MSS.get(index as usize).cloned()
+    }
+}
@@ -41,18 +41,20 @@ use std::slice;
 #[inline]
 fn sum_be_words(data: &[u8], mut skipword: usize) -> u32 {
     let len = data.len();
-    let wdata: &[u16] = unsafe { slice::from_raw_parts(data.as_ptr() as *const u16, len / 2) };
-    skipword = ::std::cmp::min(skipword, wdata.len());
+    // the slice may not be aligned for u16, therefore the words are read unaligned
+    let wdata = data.as_ptr() as *const u16;
+    let wlen = len / 2;
+    skipword = ::std::cmp::min(skipword, wlen);
 
     let mut sum = 0u32;
     let mut i = 0;
     while i < skipword {
-        sum += u16::from_be(unsafe { *wdata.get_unchecked(i) }) as u32;
+        sum += u16::from_be(unsafe { wdata.add(i).read_unaligned() }) as u32;
         i += 1;
     }
     i += 1;
-    while i < wdata.len() {
-        sum += u16::from_be(unsafe { *wdata.get_unchecked(i) }) as u32;
+    while i < wlen {
+        sum += u16::from_be(unsafe { wdata.add(i).read_unaligned() }) as u32;
         i += 1;
     }
     // If the length is odd, make sure to checksum the final byte
@@ -123,16 +125,23 @@ pub fn ipv4_checksum(
 
     sum += next_level_protocol;
 
-    let len = len + extra_data.len();
-    sum += len as u32;
+    sum += (len + extra_data.len()) as u32;
 
-    // Checksum packet header and data
+    // Checksum packet header and data, `len` must be even if there is extra data
     sum += sum_be_words_ptr(data, len, skipword);
     sum += sum_be_words(extra_data, extra_data.len() / 2);
 
     finalize_checksum(sum)
 }
 
+/// The folded but not complemented sum of the IPv4 pseudo header, which goes into the TCP or UDP checksum field
+/// when the checksum is offloaded to the NIC.
+#[inline]
+pub fn ipv4_pseudo_header_sum(src_ip: u32, dst_ip: u32, next_level_protocol: u32, len: usize) -> u16be {
+    let sum = !finalize_checksum(src_ip) as u32 + !finalize_checksum(dst_ip) as u32 + next_level_protocol + len as u32;
+    !finalize_checksum(sum)
+}
+
 // everything in host byte order:
 #[inline]
 pub fn update_checksum_incremental(old_check: u16, old_data_csum: u16, new_data_csum: u16) -> u16be {
@@ -0,0 +1,166 @@
+use native::zcsi::rte_ethdev_api::rte_get_tsc_hz;
+use std::cmp;
+
+/// TSC frequency in Hz, as determined by DPDK.
+#[inline]
+pub fn tsc_hz() -> u64 {
+    unsafe { rte_get_tsc_hz() }
+}
+
+/// A token bucket filled with `rate` bytes per second up to `size` bytes, driven by TSC time stamps.
+/// Internally tokens are kept in units of bytes * tsc_hz, so that no fractions of tokens get lost.
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+    rate: u64,
+    size: u128,
+    tokens: u128,
+    tsc_hz: u128,
+    last: u64,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket. `rate` is in bytes per second, `size` in bytes.
+    pub fn new(rate: u64, size: u64, tsc_hz: u64) -> TokenBucket {
+        let size = size as u128 * tsc_hz as u128;
+        TokenBucket {
+            rate,
+            size,
+            tokens: size,
+            tsc_hz: tsc_hz as u128,
+            last: 0,
+        }
+    }
+
+    /// Adds the tokens accumulated since the last update at time `now` (TSC). Tokens above the size of the
+    /// bucket are returned as overflow, in bytes * tsc_hz.
+    #[inline]
+    fn refill(&mut self, now: u64) -> u128 {
+        let elapsed = now.saturating_sub(self.last);
+        self.last = cmp::max(self.last, now);
+        self.tokens += elapsed as u128 * self.rate as u128;
+        if self.tokens > self.size {
+            let overflow = self.tokens - self.size;
+            self.tokens = self.size;
+            overflow
+        } else {
+            0
+        }
+    }
+
+    #[inline]
+    fn add(&mut self, tokens: u128) {
+        self.tokens = cmp::min(self.size, self.tokens + tokens);
+    }
+
+    #[inline]
+    fn contains(&self, len: usize) -> bool {
+        self.tokens >= len as u128 * self.tsc_hz
+    }
+
+    #[inline]
+    fn take(&mut self, len: usize) {
+        self.tokens -= len as u128 * self.tsc_hz;
+    }
+
+    /// Consumes `len` bytes at time `now` (TSC) if the bucket holds enough tokens.
+    #[inline]
+    pub fn consume(&mut self, len: usize, now: u64) -> bool {
+        self.refill(now);
+        if self.contains(len) {
+            self.take(len);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The number of whole bytes currently in the bucket, without refilling.
+    #[inline]
+    pub fn available(&self) -> u64 {
+        (self.tokens / self.tsc_hz) as u64
+    }
+
+    #[inline]
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+}
+
+/// Result of metering a packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Color {
+    Green,
+    Yellow,
+    Red,
+}
+
+/// A three color marker, operating in color-blind mode.
+#[derive(Clone, Copy, Debug)]
+pub enum Meter {
+    /// single rate three color marker (RFC 2697): committed and excess bucket, both filled at `cir`
+    SrTcm {
+        committed: TokenBucket,
+        excess: TokenBucket,
+    },
+    /// two rate three color marker (RFC 2698): committed bucket filled at `cir`, peak bucket filled at `pir`
+    TrTcm { committed: TokenBucket, peak: TokenBucket },
+}
+
+impl Meter {
+    /// `cir` in bytes per second, `cbs` and `ebs` in bytes.
+    pub fn srtcm(cir: u64, cbs: u64, ebs: u64, tsc_hz: u64) -> Meter {
+        Meter::SrTcm {
+            committed: TokenBucket::new(cir, cbs, tsc_hz),
+            excess: TokenBucket::new(0, ebs, tsc_hz),
+        }
+    }
+
+    /// `cir` and `pir` in bytes per second, `cbs` and `pbs` in bytes.
+    pub fn trtcm(cir: u64, cbs: u64, pir: u64, pbs: u64, tsc_hz: u64) -> Meter {
+        Meter::TrTcm {
+            committed: TokenBucket::new(cir, cbs, tsc_hz),
+            peak: TokenBucket::new(pir, pbs, tsc_hz),
+        }
+    }
+
+    /// Meters a packet of `len` bytes at time `now` (TSC).
+    #[inline]
+    pub fn color(&mut self, len: usize, now: u64) -> Color {
+        match *self {
+            Meter::SrTcm {
+                ref mut committed,
+                ref mut excess,
+            } => {
+                // tokens which do not fit into the committed bucket spill over into the excess bucket
+                let overflow = committed.refill(now);
+                excess.add(overflow);
+                if committed.contains(len) {
+                    committed.take(len);
+                    Color::Green
+                } else if excess.contains(len) {
+                    excess.take(len);
+                    Color::Yellow
+                } else {
+                    Color::Red
+                }
+            }
+            Meter::TrTcm {
+                ref mut committed,
+                ref mut peak,
+            } => {
+                committed.refill(now);
+                peak.refill(now);
+                if !peak.contains(len) {
+                    Color::Red
+                } else if !committed.contains(len) {
+                    peak.take(len);
+                    Color::Yellow
+                } else {
+                    peak.take(len);
+                    committed.take(len);
+                    Color::Green
+                }
+            }
+        }
+    }
+}
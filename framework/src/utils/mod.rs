@@ -1,8 +1,10 @@
 pub use self::check::*;
 pub use self::flow::*;
+pub use self::meter::*;
 
 mod check;
 mod flow;
+mod meter;
 
 pub const PAGE_SIZE: usize = 4096; // Page size in bytes, not using huge pages here.
 
@@ -25,3 +27,35 @@ pub fn round_to_power_of_2(mut size: usize) -> usize {
     size = size.wrapping_add(1);
     size
 }
+
+/// Compare TCP sequence numbers in serial number arithmetic (RFC 1982), i.e. modulo 2^32: `a` is before `b` if it
+/// lies less than 2^31 behind `b`.
+#[inline]
+pub fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[inline]
+pub fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+#[inline]
+pub fn seq_gt(a: u32, b: u32) -> bool {
+    seq_lt(b, a)
+}
+
+#[inline]
+pub fn seq_ge(a: u32, b: u32) -> bool {
+    !seq_lt(a, b)
+}
+
+/// The later of two sequence numbers.
+#[inline]
+pub fn seq_max(a: u32, b: u32) -> u32 {
+    if seq_lt(a, b) {
+        b
+    } else {
+        a
+    }
+}
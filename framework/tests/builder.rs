@@ -0,0 +1,134 @@
+extern crate e2d2;
+extern crate eui48;
+use e2d2::headers::*;
+use e2d2::interface::*;
+use e2d2::utils::{checksum, ipv4_checksum};
+use eui48::MacAddress;
+use std::net::Ipv4Addr;
+
+fn mac() -> MacHeader {
+    let mut mac = MacHeader::new();
+    mac.set_smac(&MacAddress::new([0x02, 0, 0, 0, 0, 1]));
+    mac.set_dmac(&MacAddress::new([0x02, 0, 0, 0, 0, 2]));
+    mac
+}
+
+fn ip() -> IpHeader {
+    let mut ip = IpHeader::new();
+    ip.set_src(u32::from(Ipv4Addr::new(10, 0, 0, 1)));
+    ip.set_dst(u32::from(Ipv4Addr::new(10, 0, 0, 2)));
+    ip
+}
+
+fn be16(bytes: &[u8], at: usize) -> u16 {
+    (bytes[at] as u16) << 8 | bytes[at + 1] as u16
+}
+
+#[test]
+fn udp_packet_fields_are_derived() {
+    let mut udp = UdpHeader::new();
+    udp.set_src_port(1234);
+    udp.set_dst_port(53);
+    let payload = [1u8, 2, 3, 4, 5];
+    let builder = PacketBuilder::new().mac(mac()).ipv4(ip()).udp(udp).payload(&payload);
+    let mut bytes = builder.to_bytes();
+    assert_eq!(bytes.len(), 14 + 20 + 8 + 5);
+    assert_eq!(builder.len(), bytes.len());
+    // Ethertype, IP version/ihl, total length, ttl and protocol
+    assert_eq!(be16(&bytes, 12), 0x0800);
+    assert_eq!(bytes[14], 0x45);
+    assert_eq!(be16(&bytes, 16), 33);
+    assert_eq!(bytes[22], 64);
+    assert_eq!(bytes[23], 17);
+    assert_eq!(be16(&bytes, 24), checksum(&bytes[14..34], 5));
+    // UDP length and checksum
+    assert_eq!(be16(&bytes, 38), 13);
+    let udp_csum = be16(&bytes, 40);
+    let l4_len = bytes.len() - 34;
+    let expected = ipv4_checksum(
+        bytes[34..].as_mut_ptr(),
+        l4_len,
+        3,
+        &[],
+        u32::from(Ipv4Addr::new(10, 0, 0, 1)),
+        u32::from(Ipv4Addr::new(10, 0, 0, 2)),
+        17,
+    );
+    assert_eq!(udp_csum, expected);
+    assert_eq!(&bytes[42..], &payload);
+}
+
+#[test]
+fn tcp_and_icmp_checksums() {
+    let payload = [0xaau8; 7];
+    let mut tcp = TcpHeader::new();
+    tcp.set_src_port(80);
+    tcp.set_syn_flag();
+    let mut bytes = PacketBuilder::new()
+        .mac(mac())
+        .ipv4(ip())
+        .tcp(tcp)
+        .payload(&payload)
+        .to_bytes();
+    assert_eq!(bytes[23], 6);
+    assert_eq!(bytes[46] >> 4, 5);
+    let csum = be16(&bytes, 50);
+    let l4_len = bytes.len() - 34;
+    let expected = ipv4_checksum(
+        bytes[34..].as_mut_ptr(),
+        l4_len,
+        8,
+        &[],
+        u32::from(Ipv4Addr::new(10, 0, 0, 1)),
+        u32::from(Ipv4Addr::new(10, 0, 0, 2)),
+        6,
+    );
+    assert_eq!(csum, expected);
+
+    let mut icmp = IcmpHeader::new();
+    icmp.set_icmp_type(ICMP_ECHO_REQUEST);
+    let bytes = PacketBuilder::new()
+        .mac(mac())
+        .ipv4(ip())
+        .icmp(icmp)
+        .payload(&payload)
+        .to_bytes();
+    assert_eq!(bytes[23], 1);
+    assert_eq!(be16(&bytes, 36), checksum(&bytes[34..], 1));
+}
+
+#[test]
+fn offloaded_checksums_hold_the_pseudo_header() {
+    let udp = UdpHeader::new();
+    let builder = PacketBuilder::new()
+        .mac(mac())
+        .ipv4(ip())
+        .udp(udp)
+        .checksum_offload(true);
+    let headers = builder.headers();
+    assert_eq!(headers.len(), 3);
+    match headers[1] {
+        Layer::Ip(ref ip) => assert_eq!(ip.csum(), 0),
+        _ => panic!("expected IP header"),
+    }
+    match headers[2] {
+        // 10.0.0.1 + 10.0.0.2 + 17 + 8
+        Layer::Udp(ref udp) => assert_eq!(udp.checksum(), 0x0a00 + 0x0001 + 0x0a00 + 0x0002 + 17 + 8),
+        _ => panic!("expected UDP header"),
+    }
+}
+
+#[test]
+fn arp_packet() {
+    let arp = ArpIpv4Header::new(
+        ARP_OP_REQUEST,
+        MacAddress::new([0x02, 0, 0, 0, 0, 1]),
+        Ipv4Addr::new(10, 0, 0, 1),
+        MacAddress::nil(),
+        Ipv4Addr::new(10, 0, 0, 2),
+    );
+    let bytes = PacketBuilder::new().mac(mac()).arp(arp).to_bytes();
+    assert_eq!(bytes.len(), 42);
+    assert_eq!(be16(&bytes, 12), 0x0806);
+    assert_eq!(be16(&bytes, 20), ARP_OP_REQUEST);
+}
@@ -0,0 +1,135 @@
+extern crate e2d2;
+extern crate toml;
+use e2d2::config::*;
+
+const CONFIG: &str = r#"
+[netbricks]
+name = "layers"
+ports = [
+    { name = "0000:01:00.0", cores = [1] },
+]
+"#;
+
+fn variables(variables: &[(&str, &str)]) -> Vec<(String, String)> {
+    variables
+        .iter()
+        .map(|&(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn environment_and_flags_override_file() {
+    let mut layers = LayeredConfiguration::from_toml(CONFIG).unwrap();
+    layers
+        .apply_variables(variables(&[
+            ("E2D2_PORTS_1_CORES", "[2]"),
+            ("E2D2_PORTS_0_RXD", "512"),
+            ("E2D2_PORTS_1_NAME", "0000:01:00.1"),
+            ("E2D2_POOL_SIZE", "4096"),
+            ("HOME", "/root"),
+        ]))
+        .unwrap();
+    layers.set("ports[0].rxd", parse_override("1024"), "--set").unwrap();
+
+    let configuration = layers.configuration().unwrap();
+    assert_eq!(configuration.name, "layers");
+    assert_eq!(configuration.pool_size, 4096);
+    assert_eq!(configuration.ports[0].rxd, 1024);
+    assert_eq!(configuration.ports[1].name, "0000:01:00.1");
+    assert_eq!(configuration.ports[1].rx_queues, vec![2]);
+
+    let paths: Vec<_> = layers.overrides().iter().map(|o| &o.path[..]).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "netbricks.pool_size",
+            "netbricks.ports[0].rxd",
+            "netbricks.ports[1].cores",
+            "netbricks.ports[1].name",
+            "netbricks.ports[0].rxd",
+        ]
+    );
+    let printed = layers.to_string();
+    assert!(
+        printed.contains("# netbricks.ports[0].rxd = 1024 from --set"),
+        "{}",
+        printed
+    );
+    assert!(printed.contains("pool_size = 4096"), "{}", printed);
+}
+
+#[test]
+fn flags_are_applied_again_on_reread() {
+    let path = std::env::temp_dir().join(format!("e2d2-layers-{}.toml", std::process::id()));
+    std::fs::write(&path, CONFIG).unwrap();
+    let flags = vec![ConfigurationOverride {
+        path: "netbricks.pool_size".to_string(),
+        value: parse_override("4096"),
+        source: "--set".to_string(),
+    }];
+    let configuration = read_layered_configuration(path.to_str().unwrap(), &flags);
+    std::fs::remove_file(&path).unwrap();
+    let configuration = configuration.unwrap();
+    assert_eq!(configuration.pool_size, 4096);
+    assert_eq!(configuration.flag_overrides, flags);
+}
+
+#[test]
+fn override_values() {
+    assert_eq!(parse_override("512"), toml::Value::Integer(512));
+    assert_eq!(parse_override("true"), toml::Value::Boolean(true));
+    assert_eq!(parse_override("kni:0"), toml::Value::String("kni:0".to_string()));
+    assert_eq!(
+        parse_override("\"0000:01:00.0\""),
+        toml::Value::String("0000:01:00.0".to_string())
+    );
+}
+
+#[test]
+fn errors_name_their_layer() {
+    let mut layers = LayeredConfiguration::from_toml(CONFIG).unwrap();
+    let errors = layers
+        .apply_variables(variables(&[("E2D2_PORTS_5_RXD", "128")]))
+        .unwrap_err();
+    assert_eq!(errors[0].path, "netbricks.ports[5]");
+    assert!(errors[0].message.contains("E2D2_PORTS_5_RXD"), "{}", errors[0]);
+    assert!(layers.set("ports[x]", parse_override("1"), "--set").is_err());
+
+    layers
+        .apply_variables(variables(&[("E2D2_PIPELINE_0_RX_PORT", "0000:02:00.0")]))
+        .unwrap();
+    layers.set("master_core", parse_override("70"), "--master").unwrap();
+    let errors = match layers.configuration() {
+        Ok(_) => panic!("configuration was accepted:\n{}", layers),
+        Err(errors) => errors,
+    };
+    let found: Vec<_> = errors.iter().map(|e| (&e.path[..], e.line)).collect();
+    assert_eq!(
+        found,
+        vec![("netbricks.master_core", None), ("pipeline[0].rx_port", None)]
+    );
+    assert!(errors[0].message.ends_with("(set by --master)"), "{}", errors[0]);
+    assert!(
+        errors[1].message.ends_with("(set by E2D2_PIPELINE_0_RX_PORT)"),
+        "{}",
+        errors[1]
+    );
+
+    let mut layers = LayeredConfiguration::from_toml(CONFIG).unwrap();
+    layers.set("cores", parse_override("[64]"), "--core").unwrap();
+    let errors = match layers.configuration() {
+        Ok(_) => panic!("configuration was accepted:\n{}", layers),
+        Err(errors) => errors,
+    };
+    assert_eq!(errors[0].path, "netbricks.cores[0]");
+
+    // errors of values from the file keep their line
+    let mut layers = LayeredConfiguration::from_toml("[netbricks]\nname = \"lines\"\nmaster_core = 64\n").unwrap();
+    layers.set("name", parse_override("env"), "E2D2_NAME").unwrap();
+    let errors = match layers.configuration() {
+        Ok(_) => panic!("configuration was accepted:\n{}", layers),
+        Err(errors) => errors,
+    };
+    assert_eq!(errors[0].path, "netbricks.master_core");
+    assert_eq!(errors[0].line, Some(3));
+}
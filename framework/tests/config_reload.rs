@@ -0,0 +1,124 @@
+extern crate e2d2;
+use e2d2::common::{errors, ErrorKind};
+use e2d2::config::*;
+
+const RUNNING: &str = r#"
+[netbricks]
+name = "reload"
+pool_size = 512
+ports = [
+    { name = "0000:01:00.0", rx_cores = [1, 2], tx_cores = [1, 2], ipnet = "10.0.0.1/24" },
+    { name = "0000:01:00.1", cores = [1, 2] },
+]
+
+[[pipeline]]
+name = "forward"
+rx_port = "0000:01:00.0"
+tx_port = "0000:01:00.1"
+
+[[pipeline.operator]]
+kind = "macswap"
+
+[[pipeline]]
+name = "reflect"
+rx_port = "0000:01:00.1"
+
+[[pipeline.operator]]
+kind = "macswap"
+
+[[pipeline]]
+name = "sink"
+rx_port = "0000:01:00.1"
+cores = [2]
+
+[[pipeline.operator]]
+kind = "drop"
+"#;
+
+fn configuration(toml: &str) -> NetbricksConfiguration {
+    read_configuration_from_str(toml, "reload.toml").unwrap()
+}
+
+fn changes(new: &str) -> errors::Result<Vec<String>> {
+    diff_configurations(&configuration(RUNNING), &configuration(new))
+        .map(|changes| changes.iter().map(|c| c.to_string()).collect())
+}
+
+#[test]
+fn unchanged_configuration() {
+    assert_eq!(changes(RUNNING).unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn pipelines_are_added_removed_and_rebuilt() {
+    let new = RUNNING.replace("name = \"sink\"", "name = \"blackhole\"").replace(
+        "kind = \"macswap\"\n\n[[pipeline]]\nname = \"reflect\"",
+        "kind = \"drop\"\n\n[[pipeline]]\nname = \"reflect\"",
+    );
+    assert_eq!(
+        changes(&new).unwrap(),
+        vec![
+            "remove pipeline sink",
+            "rebuild pipeline forward",
+            "add pipeline blackhole"
+        ]
+    );
+}
+
+#[test]
+fn port_changes_rebuild_their_pipelines() {
+    let new = RUNNING
+        .replace("ipnet = \"10.0.0.1/24\"", "ipnet = \"10.0.0.2/24\"")
+        .replace("cores = [1, 2] },\n]", "rx_cores = [2, 1], tx_cores = [2, 1] },\n]");
+    let changes = changes(&new).unwrap();
+    assert_eq!(changes.len(), 5);
+    assert!(changes[0].starts_with("set Some(NetSpec"));
+    assert!(changes[0].ends_with("on port 0000:01:00.0"));
+    assert_eq!(
+        changes[1],
+        "move queues of port 0000:01:00.1 to rx cores [2, 1] and tx cores [2, 1]"
+    );
+    assert_eq!(
+        &changes[2..],
+        &[
+            "rebuild pipeline forward",
+            "rebuild pipeline reflect",
+            "rebuild pipeline sink"
+        ]
+    );
+}
+
+#[test]
+fn changes_which_need_a_restart_are_rejected() {
+    let new = RUNNING
+        .replace("pool_size = 512", "pool_size = 1024")
+        .replace("rx_cores = [1, 2], tx_cores = [1, 2]", "rx_cores = [1], tx_cores = [1]")
+        .replace("cores = [1, 2] },\n]", "cores = [1, 3] },\n]")
+        .replace("cores = [2]", "cores = [1]");
+    match changes(&new) {
+        Err(ErrorKind::ConfigurationError(message)) => {
+            assert!(message.contains("pool_size cannot be changed"));
+            assert!(message.contains("number of queues of port 0000:01:00.0 cannot be changed"));
+            assert!(message.contains("queues of port 0000:01:00.1 cannot be moved to core 3 without a scheduler"));
+        }
+        other => panic!("reload was not rejected: {:?}", other),
+    }
+
+    // flow director rules are not reprogrammed and tx queues stay paired with their rx queues
+    let new = RUNNING
+        .replace(
+            "ipnet = \"10.0.0.1/24\"",
+            "ipnet = \"10.0.0.1/24\", flow_steering = \"Ip\"",
+        )
+        .replace("tx_cores = [1, 2]", "tx_cores = [2, 1]");
+    match changes(&new) {
+        Err(ErrorKind::ConfigurationError(message)) => {
+            assert!(message.contains("flow_steering of port 0000:01:00.0 cannot be changed"));
+            assert!(message.contains("tx queues of port 0000:01:00.0 can only move with their rx queues"));
+        }
+        other => panic!("reload was not rejected: {:?}", other),
+    }
+
+    let added_port = RUNNING.replace("ports = [", "ports = [\n    { name = \"0000:02:00.0\", cores = [1] },");
+    assert!(changes(&added_port).is_err());
+}
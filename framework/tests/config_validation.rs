@@ -0,0 +1,155 @@
+extern crate e2d2;
+use e2d2::config::*;
+use e2d2::interface::FlowSteeringMode;
+
+fn errors(configuration: &str) -> Vec<ValidationError> {
+    match validate_configuration_from_str(configuration) {
+        Ok(_) => panic!("configuration was accepted:\n{}", configuration),
+        Err(errors) => errors,
+    }
+}
+
+fn error(configuration: &str) -> ValidationError {
+    let mut errors = errors(configuration);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    errors.remove(0)
+}
+
+#[test]
+fn read_typed_sections() {
+    let configuration = validate_configuration_from_str(
+        r#"
+[netbricks]
+master_core = "3"
+metrics = "127.0.0.1:9100"
+ports = [
+    { name = "0000:01:00.0", cores = 1, kni = "kni:0", flow_steering = "Ip", mac = "02:00:00:00:00:01" },
+    { name = "kni:0", k_cores = [4] },
+]
+"#,
+    )
+    .unwrap();
+    assert_eq!(configuration.name, "zcsi");
+    assert_eq!(configuration.primary_core, 3);
+    assert_eq!(configuration.pool_size, DEFAULT_POOL_SIZE);
+    assert_eq!(configuration.metrics, Some("127.0.0.1:9100".parse().unwrap()));
+    let port = &configuration.ports[0];
+    assert_eq!(port.rx_queues, vec![1]);
+    assert_eq!(port.tx_queues, vec![1]);
+    assert_eq!(port.rxd, NUM_RXD);
+    assert_eq!(port.flow_steering, Some(FlowSteeringMode::Ip));
+    assert!(port.net_spec.as_ref().unwrap().mac.is_some());
+    assert_eq!(configuration.ports[1].k_cores, vec![4]);
+}
+
+#[test]
+fn errors_carry_path_and_line() {
+    let unknown = error(
+        r#"
+[netbricks]
+ports = [
+    { name = "0000:01:00.0", cores = [1] },
+    { name = "0000:01:00.1", cores = [1], rxdd = 512 },
+]
+"#,
+    );
+    assert!(unknown.path.starts_with("netbricks.ports[1]"), "{}", unknown);
+    assert_eq!(unknown.line, Some(5));
+    assert!(unknown.message.contains("rxdd"), "{}", unknown);
+
+    let steering = error(
+        r#"
+[netbricks]
+ports = [ { name = "0000:01:00.0", cores = [1], flow_steering = "ip" } ]
+"#,
+    );
+    assert!(steering.path.starts_with("netbricks.ports[0]"), "{}", steering);
+    assert_eq!(steering.line, Some(3));
+
+    let ipnet = error(
+        r#"
+[netbricks]
+name = "ipnet"
+
+ports = [
+    { name = "0000:01:00.0", cores = [1],
+      ipnet = "10.0.0.1" },
+]
+"#,
+    );
+    assert_eq!(ipnet.path, "netbricks.ports[0].ipnet");
+    assert_eq!(ipnet.line, Some(7));
+
+    let mac = error(
+        r#"
+[netbricks]
+ports = [ { name = "0000:01:00.0", cores = [1], mac = "02:00:00:00:01" } ]
+"#,
+    );
+    assert!(mac.message.contains("MAC address"), "{}", mac);
+
+    let section = error("[netbrick]\nname = \"typo\"\n");
+    assert_eq!(section.line, Some(1));
+}
+
+#[test]
+fn cross_check_ports_and_pipelines() {
+    let errors = errors(
+        r#"
+[netbricks]
+strict = true
+cores = [1, 2]
+ports = [
+    { name = "0000:01:00.0", cores = [1, 3], kni = "kni:0" },
+    { name = "0000:01:00.0,rx_pcap=x", cores = [2] },
+    { name = "0000:01:00.1", rx_cores = [1, 2], tx_cores = [1], kni = "kni:0" },
+    { name = "kni:0" },
+    { name = "kni:1" },
+]
+
+[[pipeline]]
+name = "forward"
+rx_port = "0000:01:00.1"
+cores = [2, 64]
+"#,
+    );
+    let found: Vec<_> = errors.iter().map(|e| (&e.path[..], e.line.unwrap())).collect();
+    assert_eq!(
+        found,
+        vec![
+            ("netbricks.ports[0].rx_cores[1]", 6),
+            ("netbricks.ports[0].tx_cores[1]", 6),
+            ("netbricks.ports[1].name", 7),
+            ("netbricks.ports[2].kni", 8),
+            ("pipeline[0].cores[1]", 16),
+        ]
+    );
+    assert!(errors[3].message.contains("already associated with netbricks.ports[0]"));
+
+    // unpaired queues and kni ports are only warned about
+    let configuration = validate_configuration_from_str(
+        r#"
+[netbricks]
+ports = [
+    { name = "0000:01:00.0", rx_cores = [1, 2] },
+    { name = "kni:1" },
+]
+"#,
+    )
+    .unwrap();
+    let warnings: Vec<_> = check_port_pairing(&configuration).into_iter().map(|w| w.path).collect();
+    assert_eq!(warnings, vec!["netbricks.ports[0].tx_cores", "netbricks.ports[1].name"]);
+
+    // the same checks apply to configurations which were not read from a file
+    let mut configuration = NetbricksConfiguration::new_with_name("flags");
+    configuration.cores = vec![64];
+    let errors = check_configuration(&configuration);
+    assert_eq!(
+        errors,
+        vec![ValidationError::new(
+            "netbricks.cores[0]",
+            "core 64 is not between 0 and 63"
+        )]
+    );
+    assert!(read_configuration_from_str("[netbricks]\ncores = [64]\n", "cores.toml").is_err());
+}
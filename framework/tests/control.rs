@@ -0,0 +1,114 @@
+extern crate e2d2;
+extern crate futures;
+#[macro_use]
+extern crate serde_derive;
+extern crate tokio_core;
+use e2d2::control::tcp::*;
+use e2d2::control::*;
+use futures::sync::oneshot;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use tokio_core::reactor::Core;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Request {
+    core: i32,
+    enable: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Reply {
+    core: i32,
+    ok: bool,
+}
+
+#[test]
+fn length_delimited_frames() {
+    let mut codec = LengthDelimited::new(16);
+    let mut buf = Vec::new();
+    codec.encode(b"hello".to_vec(), &mut buf).unwrap();
+    codec.encode(Vec::new(), &mut buf).unwrap();
+    assert_eq!(&buf[..4], &[0, 0, 0, 5]);
+
+    // messages are complete only with all their bytes
+    let mut received = buf[..6].to_vec();
+    assert_eq!(codec.decode(&mut received).unwrap(), None);
+    received.extend_from_slice(&buf[6..]);
+    assert_eq!(codec.decode(&mut received).unwrap(), Some(b"hello".to_vec()));
+    assert_eq!(codec.decode(&mut received).unwrap(), Some(Vec::new()));
+    assert!(received.is_empty());
+
+    assert!(codec.encode(vec![0; 17], &mut buf).is_err());
+    let mut too_long = vec![0, 0, 0, 17];
+    assert_eq!(codec.decode(&mut too_long).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn json_lines() {
+    let mut codec: JsonLines<Request, Reply> = JsonLines::new(64);
+    let mut buf = Vec::new();
+    codec.encode(Reply { core: 1, ok: true }, &mut buf).unwrap();
+    assert_eq!(buf, b"{\"core\":1,\"ok\":true}\n".to_vec());
+
+    let mut received = b"\n{\"core\":2,\"enable\":false}\n{\"core\"".to_vec();
+    assert_eq!(
+        codec.decode(&mut received).unwrap(),
+        Some(Request { core: 2, enable: false })
+    );
+    assert_eq!(codec.decode(&mut received).unwrap(), None);
+    assert_eq!(received, b"{\"core\"".to_vec());
+
+    let mut invalid = b"{\"core\":\"two\"}\n".to_vec();
+    assert_eq!(codec.decode(&mut invalid).unwrap_err().kind(), ErrorKind::InvalidData);
+    let mut too_long = vec![b' '; 65];
+    assert!(codec.decode(&mut too_long).is_err());
+}
+
+#[test]
+fn server_runs_agents_per_connection() {
+    let mut reactor = Core::new().unwrap();
+    let handle = reactor.handle();
+    let server = TcpControlServer::bind("127.0.0.1:0".parse().unwrap(), 16, &handle).unwrap();
+    let address = server.local_addr().unwrap();
+    let codec: JsonLines<Request, Reply> = JsonLines::default();
+    handle.spawn(server.serve(codec, |_peer: SocketAddr, connection| {
+        connection.respond(|request: Request| {
+            Ok(Reply {
+                core: request.core,
+                ok: request.enable,
+            })
+        })
+    }));
+
+    let (done, finished) = oneshot::channel();
+    let clients = thread::spawn(move || {
+        // two connections at once, each gets the answers to its own requests
+        let mut first = TcpStream::connect(address).unwrap();
+        let mut second = TcpStream::connect(address).unwrap();
+        first.write_all(b"{\"core\":1,\"enable\":true}\n").unwrap();
+        second
+            .write_all(b"{\"core\":2,\"enable\":false}\n{\"core\":3,")
+            .unwrap();
+        second.write_all(b"\"enable\":true}\n").unwrap();
+        let read_line = |reader: &mut BufReader<TcpStream>| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line
+        };
+        let mut first = BufReader::new(first);
+        let mut second = BufReader::new(second);
+        let lines = vec![read_line(&mut first), read_line(&mut second), read_line(&mut second)];
+        done.send(lines).unwrap();
+    });
+    let lines = reactor.run(finished).unwrap();
+    clients.join().unwrap();
+    assert_eq!(
+        lines,
+        vec![
+            "{\"core\":1,\"ok\":true}\n",
+            "{\"core\":2,\"ok\":false}\n",
+            "{\"core\":3,\"ok\":true}\n",
+        ]
+    );
+}
@@ -0,0 +1,301 @@
+extern crate e2d2;
+use e2d2::common::*;
+use e2d2::interface::*;
+use e2d2::native::zcsi::MBuf;
+use e2d2::operators::*;
+use e2d2::scheduler::Executable;
+use std::cell::RefCell;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// The tests replace the mempool functions of libzcsi by the heap backed ones below. Data buffers are leaked, as
+// indirect mbufs point into the buffer of the mbuf they were cloned from.
+const HEADROOM: usize = 64;
+
+// room for an mbuf followed by its metadata slots
+#[repr(C, align(64))]
+struct FakeMBuf([u8; 512]);
+
+static LIVE_MBUFS: AtomicIsize = AtomicIsize::new(0);
+static BUF_LEN: AtomicUsize = AtomicUsize::new(512);
+// the tests share the counters above
+static MEMPOOL: Mutex<()> = Mutex::new(());
+
+fn new_mbuf() -> *mut MBuf {
+    let mbuf = Box::into_raw(Box::new(FakeMBuf([0u8; 512]))) as *mut MBuf;
+    LIVE_MBUFS.fetch_add(1, Ordering::SeqCst);
+    mbuf
+}
+
+#[no_mangle]
+extern "C" fn mbuf_alloc() -> *mut MBuf {
+    let len = BUF_LEN.load(Ordering::SeqCst);
+    let buf = Box::leak(vec![0u8; len].into_boxed_slice());
+    let mbuf = new_mbuf();
+    unsafe {
+        (*mbuf).buf_addr = buf.as_mut_ptr() as *mut _;
+        (*mbuf).buf_len = len as u16;
+        (*mbuf).data_off = HEADROOM as u16;
+        (*mbuf).nb_segs = 1;
+        (*mbuf).next = ptr::null_mut();
+        (*mbuf).set_refcnt(1);
+    }
+    mbuf
+}
+
+#[no_mangle]
+unsafe extern "C" fn mbuf_clone(md: *mut MBuf) -> *mut MBuf {
+    let mut head: *mut MBuf = ptr::null_mut();
+    let mut last: *mut MBuf = ptr::null_mut();
+    let mut seg = md;
+    while !seg.is_null() {
+        let clone = new_mbuf();
+        ptr::copy_nonoverlapping(seg, clone, 1);
+        (*clone).next = ptr::null_mut();
+        (*clone).set_refcnt(1);
+        if last.is_null() {
+            head = clone;
+        } else {
+            (*last).next = clone;
+        }
+        last = clone;
+        seg = (*seg).next;
+    }
+    head
+}
+
+#[no_mangle]
+unsafe extern "C" fn mbuf_free(mbuf: *mut MBuf) {
+    let mut seg = mbuf;
+    while !seg.is_null() {
+        let next = (*seg).next;
+        if (*seg).refcnt() > 1 {
+            (*seg).dereference();
+        } else {
+            drop(Box::from_raw(seg as *mut FakeMBuf));
+            LIVE_MBUFS.fetch_sub(1, Ordering::SeqCst);
+        }
+        seg = next;
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn mbuf_free_bulk(array: *mut *mut MBuf, cnt: i32) -> i32 {
+    for i in 0..cnt as usize {
+        mbuf_free(*array.add(i));
+    }
+    0
+}
+
+// Ethernet, IPv4 10.0.0.1 > 10.0.0.2, TCP 1234 > 80 with 6 bytes payload
+const HEADERS: usize = 54;
+const IP_HEADERS: usize = 34;
+
+fn tcp_frame() -> Vec<u8> {
+    vec![
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00, // mac
+        0x45, 0, 0, 46, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, // ip
+        0x04, 0xd2, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0, // tcp
+        1, 2, 3, 4, 5, 6,
+    ]
+}
+
+/// A packet of the given segments.
+fn packet(segments: &[&[u8]]) -> *mut MBuf {
+    let mut head: *mut MBuf = ptr::null_mut();
+    for data in segments {
+        let seg = mbuf_alloc();
+        unsafe {
+            assert_eq!((*seg).add_data_end(data.len()), data.len());
+            ptr::copy_nonoverlapping(data.as_ptr(), (*seg).data_address(0), data.len());
+            if head.is_null() {
+                head = seg;
+            } else {
+                (*head).chain(&mut *seg);
+            }
+        }
+    }
+    head
+}
+
+fn bytes(mbuf: *mut MBuf) -> Vec<u8> {
+    unsafe { (*mbuf).segments().flat_map(|s| s.iter().cloned()).collect() }
+}
+
+#[test]
+fn duplicate_shares_the_data() {
+    let _mempool = MEMPOOL.lock().unwrap_or_else(|e| e.into_inner());
+    let live = LIVE_MBUFS.load(Ordering::SeqCst);
+    let frame = tcp_frame();
+    let original = Pdu::pdu_from_mbuf_no_increment(packet(&[&frame[..]]));
+    let copy = unsafe { original.duplicate() }.unwrap();
+    assert_eq!(copy.headers().count(), 3);
+    assert_eq!(copy.pkt_len(), frame.len());
+    unsafe {
+        let (o, c) = (original.get_mbuf(), copy.get_mbuf());
+        assert_ne!(o, c);
+        assert_eq!((*c).data_address(0), (*o).data_address(0));
+        assert_eq!(bytes(c), frame);
+        mbuf_free(c);
+        mbuf_free(o);
+    }
+    assert_eq!(LIVE_MBUFS.load(Ordering::SeqCst), live);
+}
+
+#[test]
+fn duplicate_with_private_headers() {
+    let _mempool = MEMPOOL.lock().unwrap_or_else(|e| e.into_inner());
+    let live = LIVE_MBUFS.load(Ordering::SeqCst);
+    let frame = tcp_frame();
+    let original = Pdu::pdu_from_mbuf_no_increment(packet(&[&frame[..]]));
+    let mut copy = original.duplicate_with_private_headers().unwrap();
+    copy.headers_mut().ip_mut(1).set_ttl(1);
+    assert_eq!(original.headers().ip(1).ttl(), 64);
+    unsafe {
+        let (o, c) = (original.get_mbuf(), copy.get_mbuf());
+        assert_eq!((*c).nb_segs(), 2);
+        assert_eq!((*c).data_len(), HEADERS);
+        // the payload is shared
+        assert_eq!((*(*c).next).data_address(0), (*o).data_address(HEADERS));
+        let mut expected = frame.clone();
+        expected[22] = 1;
+        assert_eq!(bytes(c), expected);
+        assert_eq!(bytes(o), frame);
+        mbuf_free(c);
+        mbuf_free(o);
+    }
+    assert_eq!(LIVE_MBUFS.load(Ordering::SeqCst), live);
+}
+
+#[test]
+fn duplicate_with_private_headers_of_chained_packet() {
+    let _mempool = MEMPOOL.lock().unwrap_or_else(|e| e.into_inner());
+    let live = LIVE_MBUFS.load(Ordering::SeqCst);
+    // UDP is not parsed, the first segment holds only the parsed headers and its empty clone is not part of the copy
+    let mut frame = tcp_frame();
+    frame[23] = 17;
+    let original = Pdu::pdu_from_mbuf_no_increment(packet(&[&frame[..IP_HEADERS], &frame[IP_HEADERS..]]));
+    let copy = original.duplicate_with_private_headers().unwrap();
+    unsafe {
+        let (o, c) = (original.get_mbuf(), copy.get_mbuf());
+        assert_eq!((*c).nb_segs(), 2);
+        assert_eq!((*c).pkt_len(), frame.len());
+        assert_eq!((*(*c).next).data_address(0), (*(*o).next).data_address(0));
+        assert_eq!(bytes(c), frame);
+        mbuf_free(c);
+        mbuf_free(o);
+    }
+    assert_eq!(LIVE_MBUFS.load(Ordering::SeqCst), live);
+}
+
+#[test]
+fn private_headers_must_fit_into_an_mbuf() {
+    let _mempool = MEMPOOL.lock().unwrap_or_else(|e| e.into_inner());
+    let frame = tcp_frame();
+    let original = Pdu::pdu_from_mbuf_no_increment(packet(&[&frame[..]]));
+    let live = LIVE_MBUFS.load(Ordering::SeqCst);
+    BUF_LEN.store(HEADROOM + HEADERS - 1, Ordering::SeqCst);
+    let copy = original.duplicate_with_private_headers();
+    BUF_LEN.store(512, Ordering::SeqCst);
+    match copy {
+        Err(ErrorKind::BadSize(HEADERS, _)) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("truncated copy"),
+    }
+    assert_eq!(LIVE_MBUFS.load(Ordering::SeqCst), live);
+    unsafe { mbuf_free(original.get_mbuf()) };
+}
+
+struct Source(RefCell<Vec<*mut MBuf>>);
+
+impl PacketRx for Source {
+    fn recv(&self, pkts: &mut [*mut MBuf]) -> errors::Result<(u32, i32)> {
+        let mut packets = self.0.borrow_mut();
+        let n = packets.len().min(pkts.len());
+        for (slot, mbuf) in pkts.iter_mut().zip(packets.drain(..n)) {
+            *slot = mbuf;
+        }
+        Ok((n as u32, 0))
+    }
+
+    fn queued(&self) -> usize {
+        self.0.borrow().len()
+    }
+}
+
+/// Takes up to `limit` packets per call.
+#[derive(Clone)]
+struct Sink {
+    sent: Rc<RefCell<Vec<*mut MBuf>>>,
+    limit: usize,
+}
+
+impl Sink {
+    fn new(limit: usize) -> Sink {
+        Sink {
+            sent: Rc::new(RefCell::new(Vec::new())),
+            limit,
+        }
+    }
+
+    fn free(&self) {
+        for mbuf in self.sent.borrow_mut().drain(..) {
+            unsafe { mbuf_free(mbuf) };
+        }
+    }
+}
+
+impl PacketTx for Sink {
+    fn send(&mut self, pkts: &mut [*mut MBuf]) -> errors::Result<u32> {
+        let n = self.limit.min(pkts.len());
+        self.sent.borrow_mut().extend_from_slice(&pkts[..n]);
+        Ok(n as u32)
+    }
+}
+
+#[test]
+fn tee_copies_to_each_port() {
+    let _mempool = MEMPOOL.lock().unwrap_or_else(|e| e.into_inner());
+    let live = LIVE_MBUFS.load(Ordering::SeqCst);
+    let frame = tcp_frame();
+    let source = Source(RefCell::new((0..4).map(|_| packet(&[&frame[..]])).collect()));
+    let out = Sink::new(32);
+    let mirrors = vec![Sink::new(32), Sink::new(32), Sink::new(1)];
+    let mut pipeline = ReceiveBatch::new(source).tee(mirrors.clone()).send(out.clone());
+    pipeline.execute();
+
+    assert_eq!(out.sent.borrow().len(), 4);
+    assert_eq!(mirrors[0].sent.borrow().len(), 4);
+    assert_eq!(mirrors[1].sent.borrow().len(), 4);
+    // copies which the port did not take are freed
+    assert_eq!(mirrors[2].sent.borrow().len(), 1);
+    for (i, original) in out.sent.borrow().iter().enumerate() {
+        let copies = [mirrors[0].sent.borrow()[i], mirrors[1].sent.borrow()[i]];
+        assert_ne!(copies[0], copies[1]);
+        for copy in &copies {
+            assert_eq!(bytes(*copy), frame);
+            unsafe { assert_eq!((*(**copy).next).data_address(0), (**original).data_address(HEADERS)) };
+        }
+    }
+    for sink in mirrors.iter().chain(Some(&out)) {
+        sink.free();
+    }
+    assert_eq!(LIVE_MBUFS.load(Ordering::SeqCst), live);
+}
+
+#[test]
+fn mirror_keeps_the_packets() {
+    let _mempool = MEMPOOL.lock().unwrap_or_else(|e| e.into_inner());
+    let frame = tcp_frame();
+    let source = Source(RefCell::new(vec![packet(&[&frame[..]])]));
+    let out = Sink::new(32);
+    let mirror = Sink::new(32);
+    let mut pipeline = ReceiveBatch::new(source).mirror(mirror.clone()).send(out.clone());
+    pipeline.execute();
+    assert_eq!(bytes(out.sent.borrow()[0]), frame);
+    assert_eq!(bytes(mirror.sent.borrow()[0]), frame);
+    out.free();
+    mirror.free();
+}
@@ -0,0 +1,114 @@
+extern crate e2d2;
+use e2d2::headers::*;
+use e2d2::interface::Pdu;
+use e2d2::native::zcsi::MBuf;
+use e2d2::utils::checksum;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::ptr;
+
+// Ethernet, IPv4 10.0.0.1 > 10.0.0.2, TCP 1234 > 80 with 4 bytes payload
+fn tcp_frame() -> Vec<u8> {
+    let mut frame = vec![
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00, // mac
+        0x45, 0, 0, 44, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, // ip
+        0x04, 0xd2, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0, // tcp
+        0xde, 0xad, 0xbe, 0xef,
+    ];
+    let ip_csum = checksum(&frame[14..34], 5);
+    frame[24] = (ip_csum >> 8) as u8;
+    frame[25] = ip_csum as u8;
+    frame
+}
+
+/// `frame` placed into a buffer of 128 bytes behind `headroom` bytes
+fn mbuf(buf: &mut Vec<u8>, frame: &[u8], headroom: usize) -> MBuf {
+    buf[headroom..headroom + frame.len()].copy_from_slice(frame);
+    let mut mbuf: MBuf = unsafe { mem::zeroed() };
+    mbuf.buf_addr = buf.as_mut_ptr() as *mut _;
+    mbuf.buf_len = buf.len() as u16;
+    mbuf.data_off = headroom as u16;
+    mbuf.data_len = frame.len() as u16;
+    mbuf.pkt_len = frame.len() as u32;
+    mbuf.nb_segs = 1;
+    mbuf.next = ptr::null_mut();
+    mbuf
+}
+
+fn outer_ip() -> IpHeader {
+    let mut ip = IpHeader::new();
+    ip.set_version(4);
+    ip.set_ihl(5);
+    ip.set_ttl(32);
+    ip.set_src(u32::from(Ipv4Addr::new(192, 168, 0, 1)));
+    ip.set_dst(u32::from(Ipv4Addr::new(192, 168, 0, 2)));
+    ip.update_checksum();
+    ip
+}
+
+fn check_encapsulated(pdu: &Pdu) {
+    let headers = pdu.headers();
+    assert_eq!(headers.count(), 4);
+    assert_eq!(headers.mac(0).etype(), 0x0800);
+    let outer = headers.ip(1);
+    assert_eq!(outer.protocol(), 4);
+    assert_eq!(Ipv4Addr::from(outer.src()), Ipv4Addr::new(192, 168, 0, 1));
+    let bytes = unsafe { std::slice::from_raw_parts(headers.get(1).as_ptr_u8().unwrap(), 20) };
+    assert_eq!(outer.csum_host(), checksum(bytes, 5));
+    assert_eq!(Ipv4Addr::from(headers.ip(2).src()), Ipv4Addr::new(10, 0, 0, 1));
+    assert_eq!(headers.tcp(3).dst_port(), 80);
+    assert_eq!(pdu.get_payload(3), &[0xde, 0xad, 0xbe, 0xef]);
+}
+
+#[test]
+fn insert_and_remove_using_headroom() {
+    let frame = tcp_frame();
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame, 32);
+    {
+        let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+        assert_eq!(pdu.headers().count(), 3);
+        pdu.insert_header(1, &outer_ip()).unwrap();
+        assert_eq!(pdu.data_len(), frame.len() + 20);
+        check_encapsulated(&pdu);
+        pdu.remove_header(1).unwrap();
+        assert_eq!(pdu.headers().count(), 3);
+        assert_eq!(pdu.headers().ip(1).dst(), u32::from(Ipv4Addr::new(10, 0, 0, 2)));
+    }
+    assert_eq!(m.data_off, 32);
+    assert_eq!(&buf[32..32 + frame.len()], &frame[..]);
+}
+
+#[test]
+fn insert_without_headroom_moves_the_tail() {
+    let frame = tcp_frame();
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame, 0);
+    let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    pdu.insert_header(1, &outer_ip()).unwrap();
+    check_encapsulated(&pdu);
+    // the MAC header stays in place
+    assert_eq!(pdu.headers().get(0).as_ptr_u8().unwrap(), buf.as_ptr());
+}
+
+#[test]
+fn removing_a_header_patches_the_next_protocol() {
+    let frame = tcp_frame();
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame, 32);
+    let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    // IP-in-IP with the outer header announcing TCP
+    pdu.insert_header(2, &outer_ip()).unwrap();
+    assert_eq!(pdu.headers().ip(1).protocol(), 4);
+    assert_eq!(pdu.headers().ip(2).protocol(), 6);
+    pdu.remove_header(2).unwrap();
+    assert_eq!(pdu.headers().ip(1).protocol(), 6);
+    let bytes = unsafe { std::slice::from_raw_parts(pdu.headers().get(1).as_ptr_u8().unwrap(), 20) };
+    assert_eq!(pdu.headers().ip(1).csum_host(), checksum(bytes, 5));
+    assert_eq!(&pdu.headers().get(0).as_ptr_u8().unwrap(), &unsafe {
+        buf.as_ptr().offset(32)
+    });
+
+    assert!(pdu.remove_header(3).is_err());
+    assert!(pdu.insert_header(4, &outer_ip()).is_err());
+}
@@ -0,0 +1,128 @@
+extern crate e2d2;
+use e2d2::qos::*;
+
+const HZ: u64 = 1_000_000;
+
+fn class(priority: u8, weight: u32, queue_len: usize, aqm: Aqm) -> ClassConf {
+    ClassConf {
+        priority,
+        weight,
+        queue_len,
+        aqm,
+        rate: None,
+    }
+}
+
+fn subscriber(weight: u32, rate: Option<Rate>) -> SubscriberConf {
+    SubscriberConf { group: 0, weight, rate }
+}
+
+fn conf(subscribers: Vec<SubscriberConf>, classes: Vec<ClassConf>) -> HqosConf {
+    HqosConf {
+        groups: vec![GroupConf { weight: 1, rate: None }],
+        subscribers,
+        classes,
+        quantum: 1000,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn strict_priority_among_classes() {
+    let conf = conf(
+        vec![subscriber(1, None)],
+        vec![class(1, 1, 16, Aqm::TailDrop), class(0, 1, 16, Aqm::TailDrop)],
+    );
+    let mut hqos = HqosScheduler::new(&conf, HZ);
+    for i in 0..3 {
+        hqos.enqueue(0, 0, i, 100, 0).unwrap();
+    }
+    for i in 10..13 {
+        hqos.enqueue(0, 1, i, 100, 0).unwrap();
+    }
+    assert_eq!(hqos.backlog(), 6);
+    let order: Vec<usize> = (0..6).filter_map(|_| hqos.dequeue(1)).collect();
+    assert_eq!(order, vec![10, 11, 12, 0, 1, 2]);
+    assert_eq!(hqos.dequeue(1), None);
+}
+
+#[test]
+fn weighted_round_robin_among_subscribers() {
+    let conf = conf(
+        vec![subscriber(1, None), subscriber(3, None)],
+        vec![class(0, 1, 64, Aqm::TailDrop)],
+    );
+    let mut hqos = HqosScheduler::new(&conf, HZ);
+    for i in 0..20 {
+        hqos.enqueue(0, 0, i, 1000, 0).unwrap();
+        hqos.enqueue(1, 0, 100 + i, 1000, 0).unwrap();
+    }
+    let served: Vec<usize> = (0..8).filter_map(|_| hqos.dequeue(1)).collect();
+    assert_eq!(served.iter().filter(|i| **i < 100).count(), 2);
+    assert_eq!(served.iter().filter(|i| **i >= 100).count(), 6);
+}
+
+#[test]
+fn rate_limited_subscriber() {
+    let conf = conf(
+        vec![subscriber(
+            1,
+            Some(Rate {
+                rate: 1000,
+                burst: 1000,
+            }),
+        )],
+        vec![class(0, 1, 16, Aqm::TailDrop)],
+    );
+    let mut hqos = HqosScheduler::new(&conf, HZ);
+    for i in 0..3 {
+        hqos.enqueue(0, 0, i, 1000, 0).unwrap();
+    }
+    assert_eq!(hqos.dequeue(1), Some(0));
+    assert_eq!(hqos.dequeue(1), None);
+    assert_eq!(hqos.dequeue(1 + HZ / 2), Some(1));
+    assert_eq!(hqos.dequeue(1 + HZ), None);
+    assert_eq!(hqos.dequeue(2 + 3 * HZ / 2), Some(2));
+}
+
+#[test]
+fn tail_drop_and_unknown_subscribers() {
+    let conf = conf(vec![subscriber(1, None)], vec![class(0, 1, 2, Aqm::TailDrop)]);
+    let mut hqos = HqosScheduler::new(&conf, HZ);
+    assert!(hqos.enqueue(0, 0, 1, 100, 0).is_ok());
+    assert!(hqos.enqueue(0, 0, 2, 100, 0).is_ok());
+    assert_eq!(hqos.enqueue(0, 0, 3, 100, 0), Err(3));
+    assert_eq!(hqos.enqueue(1, 0, 4, 100, 0), Err(4));
+    assert_eq!(hqos.enqueue(0, 1, 5, 100, 0), Err(5));
+    let stats = hqos.queue_stats(0, 0).unwrap();
+    assert_eq!(stats.enqueued, 2);
+    assert_eq!(stats.dropped, 1);
+    assert_eq!(stats.len, 2);
+}
+
+#[test]
+fn codel_drops_after_interval_above_target() {
+    // one TSC tick is one microsecond
+    let conf = conf(
+        vec![subscriber(1, None)],
+        vec![class(
+            0,
+            1,
+            64,
+            Aqm::CoDel {
+                target: 5,
+                interval: 100,
+            },
+        )],
+    );
+    let mut hqos = HqosScheduler::new(&conf, HZ);
+    for i in 0..10 {
+        hqos.enqueue(0, 0, i, 100, 0).unwrap();
+    }
+    assert_eq!(hqos.dequeue(10), Some(0));
+    assert!(hqos.take_dropped().is_empty());
+    assert_eq!(hqos.dequeue(120), Some(2));
+    assert_eq!(hqos.take_dropped(), vec![1]);
+    assert_eq!(hqos.backlog(), 7);
+    assert_eq!(hqos.queue_stats(0, 0).unwrap().dropped, 1);
+}
@@ -0,0 +1,102 @@
+extern crate e2d2;
+use e2d2::headers::*;
+use e2d2::icmp::*;
+use e2d2::interface::Pdu;
+use e2d2::native::zcsi::MBuf;
+use e2d2::utils::checksum;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::ptr;
+
+// Ethernet, IPv4 10.0.0.1 > 10.0.0.2, ICMP echo request id 0x1234 seq 1 with 4 bytes payload
+fn echo_request() -> Vec<u8> {
+    let mut frame = vec![
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00, // mac
+        0x45, 0, 0, 32, 0, 1, 0x40, 0, 64, 1, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, // ip
+        8, 0, 0, 0, 0x12, 0x34, 0, 1, // icmp
+        0xde, 0xad, 0xbe, 0xef,
+    ];
+    let ip_csum = checksum(&frame[14..34], 5);
+    frame[24] = (ip_csum >> 8) as u8;
+    frame[25] = ip_csum as u8;
+    let icmp_csum = checksum(&frame[34..], 1);
+    frame[36] = (icmp_csum >> 8) as u8;
+    frame[37] = icmp_csum as u8;
+    frame
+}
+
+fn mbuf(buf: &mut Vec<u8>) -> MBuf {
+    let mut mbuf: MBuf = unsafe { mem::zeroed() };
+    mbuf.buf_addr = buf.as_mut_ptr() as *mut _;
+    mbuf.buf_len = buf.len() as u16;
+    mbuf.data_len = buf.len() as u16;
+    mbuf.pkt_len = buf.len() as u32;
+    mbuf.nb_segs = 1;
+    mbuf.next = ptr::null_mut();
+    mbuf
+}
+
+#[test]
+fn parse_icmp_header() {
+    let mut frame = echo_request();
+    let mut m = mbuf(&mut frame);
+    let pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    assert_eq!(pdu.headers().count(), 3);
+    let icmp = pdu.headers().icmp(2);
+    assert_eq!(icmp.icmp_type(), ICMP_ECHO_REQUEST);
+    assert_eq!(icmp.identifier(), 0x1234);
+    assert_eq!(icmp.sequence(), 1);
+    assert!(may_send_error(&pdu, 1));
+}
+
+#[test]
+fn echo_request_is_answered_in_place() {
+    let mut frame = echo_request();
+    let mut m = mbuf(&mut frame);
+    {
+        let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+        assert!(!echo_reply(&mut pdu, Ipv4Addr::new(10, 0, 0, 3)));
+        assert!(echo_reply(&mut pdu, Ipv4Addr::new(10, 0, 0, 2)));
+        let ip = pdu.headers().ip(1);
+        assert_eq!(Ipv4Addr::from(ip.src()), Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(Ipv4Addr::from(ip.dst()), Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(pdu.headers().icmp(2).icmp_type(), ICMP_ECHO_REPLY);
+    }
+    assert_eq!(&frame[0..6], &[0x02, 0, 0, 0, 0, 0x01]);
+    // both checksums still verify
+    assert_eq!(checksum(&frame[14..34], 5), (frame[24] as u16) << 8 | frame[25] as u16);
+    assert_eq!(checksum(&frame[34..], 1), (frame[36] as u16) << 8 | frame[37] as u16);
+}
+
+#[test]
+fn ttl_and_mtu_checks() {
+    let mut frame = echo_request();
+    let mut m = mbuf(&mut frame);
+    let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    let mut ttl = ttl_check();
+    assert_eq!(ttl(&mut pdu), None);
+    assert_eq!(pdu.headers().ip(1).ttl(), 63);
+    pdu.headers_mut().ip_mut(1).set_ttl(1);
+    assert_eq!(ttl(&mut pdu), Some(IcmpError::TtlExceeded));
+    // the request has DF set and 32 bytes
+    assert_eq!(mtu_check(32)(&mut pdu), None);
+    assert_eq!(mtu_check(31)(&mut pdu), Some(IcmpError::FragmentationNeeded(31)));
+}
+
+#[test]
+fn no_errors_about_errors_and_fragments() {
+    let mut frame = echo_request();
+    frame[34] = ICMP_DEST_UNREACH;
+    let mut m = mbuf(&mut frame);
+    {
+        let pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+        assert!(!may_send_error(&pdu, 1));
+    }
+    let mut frame = echo_request();
+    // non-initial fragment
+    frame[21] = 0x10;
+    let mut m = mbuf(&mut frame);
+    let pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    assert_eq!(pdu.headers().ip(1).fragment_offset(), 0x10);
+    assert!(!may_send_error(&pdu, 1));
+}
@@ -0,0 +1,188 @@
+extern crate e2d2;
+extern crate futures;
+extern crate serde_json;
+extern crate tokio_core;
+extern crate uuid;
+use e2d2::common::ErrorKind;
+use e2d2::control::tcp::TcpControlServer;
+use e2d2::control::*;
+use e2d2::scheduler::{SchedulerCommand, StandaloneScheduler};
+use e2d2::utils::FiveTupleV4;
+use futures::sync::oneshot;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel};
+use std::sync::Arc;
+use std::thread;
+use tokio_core::reactor::Core;
+use uuid::Uuid;
+
+/// Starts a scheduler thread with one disabled task.
+fn start_scheduler(
+    core: i32,
+    schedulers: &mut HashMap<i32, std::sync::mpsc::SyncSender<SchedulerCommand>>,
+) -> (thread::JoinHandle<()>, Uuid) {
+    let (sender, receiver) = sync_channel(0);
+    let (task_sender, task) = channel();
+    let thread = thread::spawn(move || {
+        let (reply_sender, _replies) = channel();
+        let mut scheduler = StandaloneScheduler::new_with_channel(core, receiver, reply_sender);
+        task_sender
+            .send(scheduler.install_task(&format!("task-{}", core), || (1, 0)))
+            .unwrap();
+        scheduler.handle_requests();
+    });
+    schedulers.insert(core, sender);
+    (thread, task.recv().unwrap())
+}
+
+#[test]
+fn management_commands() {
+    let mut channels = HashMap::new();
+    let (first, first_task) = start_scheduler(1, &mut channels);
+    let (second, _) = start_scheduler(2, &mut channels);
+    let schedulers = SchedulerChannel::new(channels);
+
+    let flow = FiveTupleV4 {
+        src_ip: 0x0a00_0001,
+        dst_ip: 0x0a00_0002,
+        src_port: 1000,
+        dst_port: 80,
+        proto: 6,
+    };
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let requested = shutdown.clone();
+    let (reload_sender, reload_requests) = channel::<ReloadRequest>();
+    let reloader = thread::spawn(move || {
+        for request in reload_requests.iter() {
+            let message = format!("pool_size of {:?} cannot be changed", request.path);
+            request.reply(Err(ErrorKind::ConfigurationError(message)));
+        }
+    });
+    let server = ManagementServer::with_schedulers(schedulers.clone())
+        .flow_table("counters", move || {
+            let mut table = HashMap::new();
+            table.insert(flow, 42u64);
+            flow_entries(&table)
+        })
+        .on_reload(reload_sender)
+        .on_shutdown(move || requested.store(true, Ordering::SeqCst));
+
+    let mut reactor = Core::new().unwrap();
+    let handle = reactor.handle();
+    let listener = TcpControlServer::bind("127.0.0.1:0".parse().unwrap(), 16, &handle).unwrap();
+    let address = listener.local_addr().unwrap();
+    handle.spawn(server.serve(listener));
+
+    let requests = vec![
+        r#"{"command":"list_cores"}"#.to_string(),
+        r#"{"command":"list_tasks"}"#.to_string(),
+        format!(
+            r#"{{"command":"set_task_state","core":1,"task":"{}","enabled":true}}"#,
+            first_task
+        ),
+        r#"{"command":"set_task_state","core":2,"task":"nonsense","enabled":true}"#.to_string(),
+        r#"{"command":"get_performance"}"#.to_string(),
+        r#"{"command":"list_flow_tables"}"#.to_string(),
+        r#"{"command":"dump_flow_table","table":"counters"}"#.to_string(),
+        r#"{"command":"get_port_stats","port":"0000:01:00.0"}"#.to_string(),
+        r#"{"command":"reload","path":"new.toml"}"#.to_string(),
+        r#"{"command":"shutdown"}"#.to_string(),
+    ];
+    let (done, finished) = oneshot::channel();
+    let client = thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut responses = Vec::new();
+        for request in requests {
+            stream.write_all(request.as_bytes()).unwrap();
+            stream.write_all(b"\n").unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            responses.push(serde_json::from_str::<ManagementResponse>(&line).unwrap());
+        }
+        done.send(responses).unwrap();
+    });
+    let responses = reactor.run(finished).unwrap();
+    client.join().unwrap();
+
+    assert_eq!(responses[0], ManagementResponse::Cores { cores: vec![1, 2] });
+    match responses[1] {
+        ManagementResponse::Tasks { ref tasks } => {
+            assert_eq!(tasks.len(), 2);
+            assert_eq!(tasks[0].core, 1);
+            assert_eq!(tasks[0].uuid, first_task.to_string());
+            assert_eq!(tasks[0].name, "task-1");
+            assert!(!tasks[0].enabled);
+        }
+        ref response => panic!("list_tasks was answered with {:?}", response),
+    }
+    assert_eq!(
+        responses[2],
+        ManagementResponse::TaskState {
+            core: 1,
+            task: first_task.to_string(),
+            enabled: true,
+            previous: false,
+        }
+    );
+    match responses[3] {
+        ManagementResponse::Error { .. } => (),
+        ref response => panic!("invalid task was answered with {:?}", response),
+    }
+    match responses[4] {
+        ManagementResponse::Performance { ref tasks } => {
+            assert_eq!(tasks.len(), 2);
+            assert_eq!(tasks[1].name, "task-2");
+        }
+        ref response => panic!("get_performance was answered with {:?}", response),
+    }
+    assert_eq!(
+        responses[5],
+        ManagementResponse::FlowTables {
+            tables: vec!["counters".to_string()],
+        }
+    );
+    assert_eq!(
+        responses[6],
+        ManagementResponse::FlowTable {
+            table: "counters".to_string(),
+            flows: vec![FlowEntry {
+                src: "10.0.0.1:1000".to_string(),
+                dst: "10.0.0.2:80".to_string(),
+                proto: 6,
+                value: "42".to_string(),
+            }],
+        }
+    );
+    assert_eq!(
+        responses[7],
+        ManagementResponse::Error {
+            message: "no port 0000:01:00.0".to_string(),
+        }
+    );
+    match responses[8] {
+        ManagementResponse::Error { ref message } => {
+            assert!(message.contains(r#"pool_size of Some(\"new.toml\") cannot be changed"#))
+        }
+        ref response => panic!("reload was answered with {:?}", response),
+    }
+    assert_eq!(responses[9], ManagementResponse::ShuttingDown);
+    assert!(shutdown.load(Ordering::SeqCst));
+
+    // the state change reached the scheduler
+    let enabled = reactor
+        .run(schedulers.query(1, move |scheduler| scheduler.task_is_ready(&first_task)))
+        .unwrap();
+    assert_eq!(enabled, Some(true));
+
+    for core in schedulers.cores() {
+        schedulers.send(core, SchedulerCommand::Shutdown).unwrap();
+    }
+    first.join().unwrap();
+    second.join().unwrap();
+    drop(reactor);
+    reloader.join().unwrap();
+}
@@ -0,0 +1,151 @@
+extern crate e2d2;
+use e2d2::native::zcsi::MBuf;
+use std::cell::Cell;
+use std::mem;
+use std::ptr;
+
+const BUF_LEN: usize = 512;
+const HEADROOM: usize = 128;
+
+thread_local! {
+    static FREED: Cell<usize> = Cell::new(0);
+}
+
+// replace the mbuf functions of libzcsi, segments are allocated like by a mempool with the default headroom
+#[no_mangle]
+extern "C" fn mbuf_alloc() -> *mut MBuf {
+    let buf = Box::leak(vec![0u8; BUF_LEN].into_boxed_slice());
+    let mut mbuf: Box<MBuf> = Box::new(unsafe { mem::zeroed() });
+    mbuf.buf_addr = buf.as_mut_ptr() as *mut _;
+    mbuf.buf_len = BUF_LEN as u16;
+    mbuf.data_off = HEADROOM as u16;
+    mbuf.nb_segs = 1;
+    mbuf.next = ptr::null_mut();
+    Box::into_raw(mbuf)
+}
+
+#[no_mangle]
+extern "C" fn mbuf_free(_mbuf: *mut MBuf) {
+    FREED.with(|freed| freed.set(freed.get() + 1));
+}
+
+fn packet(data: &[u8]) -> &'static mut MBuf {
+    let mbuf = unsafe { &mut *mbuf_alloc() };
+    assert_eq!(mbuf.append_bytes(data), data.len());
+    mbuf
+}
+
+fn content(mbuf: &MBuf) -> Vec<u8> {
+    mbuf.segments().flat_map(|s| s.iter().cloned()).collect()
+}
+
+fn segment(buf: &mut Vec<u8>, data: &[u8]) -> MBuf {
+    let mut mbuf: MBuf = unsafe { mem::zeroed() };
+    mbuf.buf_addr = buf.as_mut_ptr() as *mut _;
+    mbuf.buf_len = buf.len() as u16;
+    mbuf.data_off = 0;
+    mbuf.data_len = data.len() as u16;
+    mbuf.pkt_len = data.len() as u32;
+    mbuf.nb_segs = 1;
+    mbuf.next = ptr::null_mut();
+    buf[..data.len()].copy_from_slice(data);
+    mbuf
+}
+
+#[test]
+fn chained_segments() {
+    let mut b0 = vec![0u8; 16];
+    let mut b1 = vec![0u8; 16];
+    let mut b2 = vec![0u8; 16];
+    let mut s0 = segment(&mut b0, &[0, 1, 2, 3]);
+    let mut s1 = segment(&mut b1, &[4, 5, 6, 7, 8]);
+    let mut s2 = segment(&mut b2, &[9, 10]);
+    s1.chain(&mut s2);
+    s0.chain(&mut s1);
+    assert_eq!(s0.nb_segs(), 3);
+    assert_eq!(s0.pkt_len(), 11);
+    assert!(!s0.is_contiguous());
+
+    let data: Vec<u8> = s0.segments().flat_map(|s| s.iter().cloned()).collect();
+    assert_eq!(data, (0..11).collect::<Vec<u8>>());
+    let lens: Vec<usize> = s0.segments_from(5).map(|s| s.len()).collect();
+    assert_eq!(lens, vec![4, 2]);
+
+    let mut out = [0u8; 4];
+    assert_eq!(s0.read_bytes(3, &mut out), 4);
+    assert_eq!(out, [3, 4, 5, 6]);
+    assert_eq!(s0.write_bytes(7, &[70, 80, 90]), 3);
+    assert_eq!(s0.read_bytes(6, &mut out), 4);
+    assert_eq!(out, [6, 70, 80, 90]);
+
+    // tail operations work on the last segment
+    assert_eq!(s0.add_data_end(4), 4);
+    assert_eq!(s0.pkt_len(), 15);
+    assert_eq!(s0.last_segment().data_len(), 6);
+    assert_eq!(s0.add_data_end(11), 0);
+    assert_eq!(s0.remove_data_end(5), 5);
+    assert_eq!(s0.pkt_len(), 10);
+    assert_eq!(s0.nb_segs(), 3);
+    assert_eq!(s0.last_segment().data_len(), 1);
+    assert_eq!(s0.data_len(), 4);
+}
+
+#[test]
+fn prepend_into_headroom() {
+    let mbuf = packet(&[1, 2, 3, 4]);
+    assert!(mbuf.prepend(20));
+    assert_eq!(mbuf.nb_segs(), 1);
+    assert_eq!(mbuf.pkt_len(), 24);
+    assert_eq!(mbuf.data_len(), 24);
+    assert_eq!(content(mbuf)[20..], [1, 2, 3, 4]);
+}
+
+#[test]
+fn prepend_beyond_headroom_moves_data_to_a_new_segment() {
+    let mbuf = packet(&[1, 2, 3, 4]);
+    assert!(!mbuf.prepend(BUF_LEN + 1));
+    assert!(mbuf.prepend(200));
+    assert_eq!(mbuf.nb_segs(), 2);
+    assert_eq!(mbuf.pkt_len(), 204);
+    assert_eq!(mbuf.data_len(), 200);
+    assert_eq!(content(mbuf)[200..], [1, 2, 3, 4]);
+    // the first segment keeps the default headroom and has tailroom left
+    assert_eq!(mbuf.pkt_tailroom(), BUF_LEN - HEADROOM - 200);
+    assert!(mbuf.prepend(HEADROOM));
+    assert_eq!(mbuf.add_data_end_first_segment(10), 10);
+    assert_eq!(mbuf.pkt_len(), 342);
+
+    assert_eq!(mbuf.append_bytes(&[5, 6]), 2);
+    assert_eq!(mbuf.nb_segs(), 2);
+    assert_eq!(content(mbuf)[338..], [1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn append_allocates_segments() {
+    let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    let mbuf = packet(&data);
+    assert_eq!(mbuf.nb_segs(), 3);
+    assert_eq!(mbuf.pkt_len(), 1000);
+    assert_eq!(mbuf.data_len(), BUF_LEN - HEADROOM);
+    assert_eq!(mbuf.last_segment().data_len(), 1000 - 2 * (BUF_LEN - HEADROOM));
+    assert_eq!(content(mbuf), data);
+}
+
+#[test]
+fn linearize_copies_segments_into_the_first() {
+    let mbuf = packet(&[1, 2, 3, 4]);
+    assert!(mbuf.prepend(200));
+    assert!(mbuf.linearize());
+    assert!(mbuf.is_contiguous());
+    assert_eq!(mbuf.nb_segs(), 1);
+    assert_eq!(mbuf.data_len(), 204);
+    assert_eq!(content(mbuf)[200..], [1, 2, 3, 4]);
+    assert_eq!(FREED.with(|freed| freed.get()), 1);
+
+    // the data of further segments does not fit into the first one
+    let data = vec![7u8; 2 * BUF_LEN];
+    let mbuf = packet(&data);
+    assert!(!mbuf.linearize());
+    assert_eq!(mbuf.nb_segs(), 3);
+    assert_eq!(content(mbuf), data);
+}
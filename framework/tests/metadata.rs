@@ -0,0 +1,39 @@
+extern crate e2d2;
+use e2d2::common::ErrorKind;
+use e2d2::interface::dpdk::METADATA_SLOTS;
+use e2d2::interface::*;
+use e2d2::native::zcsi::MBuf;
+use std::mem;
+
+// room for an mbuf followed by its metadata slots
+#[repr(C, align(64))]
+struct FakeMBuf([u8; 512]);
+
+#[test]
+fn allocate_read_and_write_metadata() {
+    assert!(mem::size_of::<MBuf>() + METADATA_SLOTS as usize * METADATA_SLOT_SIZE <= 512);
+    let mut fake = FakeMBuf([0u8; 512]);
+    let mbuf = &mut fake as *mut FakeMBuf as *mut MBuf;
+
+    let flow_id = MetadataKey::<u32>::allocate("flow_id").unwrap();
+    let tuple = MetadataKey::<[u64; 3]>::allocate("tuple").unwrap();
+    assert_eq!(MetadataKey::<[u64; 3]>::slots(), 3);
+    assert_eq!(tuple.slot(), flow_id.slot() + 1);
+
+    unsafe {
+        flow_id.write(mbuf, 4711);
+        tuple.write(mbuf, [1, 2, 3]);
+        assert_eq!(flow_id.read(mbuf), 4711);
+        assert_eq!(tuple.read(mbuf), [1, 2, 3]);
+    }
+
+    let remaining = METADATA_SLOTS as usize - 4;
+    for i in 0..remaining {
+        MetadataKey::<u64>::allocate(&format!("field{}", i)).unwrap();
+    }
+    match MetadataKey::<u8>::allocate("one_too_many") {
+        Err(ErrorKind::MetadataTooLarge) => {}
+        _ => panic!("expected MetadataTooLarge"),
+    }
+    assert_eq!(metadata_layout().len(), remaining + 2);
+}
@@ -0,0 +1,47 @@
+extern crate e2d2;
+use e2d2::utils::*;
+
+const HZ: u64 = 1_000_000_000;
+
+#[test]
+fn token_bucket_refills_at_rate() {
+    // 1000 bytes per second, burst of 1500 bytes
+    let mut bucket = TokenBucket::new(1000, 1500, HZ);
+    assert!(bucket.consume(1500, 0));
+    assert!(!bucket.consume(1, 0));
+    // half a second later 500 bytes are available
+    assert!(!bucket.consume(501, HZ / 2));
+    assert!(bucket.consume(500, HZ / 2));
+    // the bucket never holds more than its size
+    assert!(!bucket.consume(1501, 100 * HZ));
+    assert!(bucket.consume(1500, 100 * HZ));
+}
+
+#[test]
+fn srtcm_colors() {
+    let mut meter = Meter::srtcm(1000, 1000, 500, HZ);
+    let now = 10 * HZ;
+    assert_eq!(meter.color(1000, now), Color::Green);
+    // excess bucket starts full
+    assert_eq!(meter.color(500, now), Color::Yellow);
+    assert_eq!(meter.color(1, now), Color::Red);
+    // committed bucket refills first, then the excess bucket gets the overflow
+    assert_eq!(meter.color(1000, now + HZ), Color::Green);
+    assert_eq!(meter.color(200, now + HZ), Color::Red);
+    assert_eq!(meter.color(1000, now + 5 * HZ / 2), Color::Green);
+    assert_eq!(meter.color(500, now + 5 * HZ / 2), Color::Yellow);
+    assert_eq!(meter.color(1, now + 5 * HZ / 2), Color::Red);
+}
+
+#[test]
+fn trtcm_colors() {
+    let mut meter = Meter::trtcm(1000, 1000, 2000, 2000, HZ);
+    let now = 10 * HZ;
+    assert_eq!(meter.color(1000, now), Color::Green);
+    assert_eq!(meter.color(1000, now), Color::Yellow);
+    assert_eq!(meter.color(1, now), Color::Red);
+    // after one second, the peak bucket holds 2000 bytes, the committed 1000 bytes
+    assert_eq!(meter.color(1500, now + HZ), Color::Yellow);
+    assert_eq!(meter.color(500, now + HZ), Color::Green);
+    assert_eq!(meter.color(1, now + HZ), Color::Red);
+}
@@ -0,0 +1,70 @@
+extern crate e2d2;
+use e2d2::metrics::*;
+
+#[test]
+fn render_counter_and_gauge() {
+    let registry = MetricsRegistry::new();
+    let c = registry.counter(
+        "rx_packets_total",
+        "Received packets",
+        labels(&[(LABEL_PORT, "eth0"), (LABEL_QUEUE, "0")]),
+    );
+    c.inc_by(41);
+    c.inc();
+    registry.gauge("q_len", "Queue length", labels(&[])).set(-3);
+    let text = registry.render();
+    assert!(text.contains("# TYPE rx_packets_total counter\n"));
+    assert!(text.contains("rx_packets_total{port=\"eth0\",queue=\"0\"} 42\n"));
+    assert!(text.contains("# HELP q_len Queue length\n"));
+    assert!(text.contains("q_len -3\n"));
+}
+
+#[test]
+fn histogram_buckets_are_cumulative() {
+    let registry = MetricsRegistry::new();
+    let h = registry.histogram("latency", "Latency", labels(&[(LABEL_CORE, "1")]), &[10.0, 100.0]);
+    h.observe(5.0);
+    h.observe(50.0);
+    h.observe(500.0);
+    let text = registry.render();
+    assert!(text.contains("latency_bucket{core=\"1\",le=\"10\"} 1\n"));
+    assert!(text.contains("latency_bucket{core=\"1\",le=\"100\"} 2\n"));
+    assert!(text.contains("latency_bucket{core=\"1\",le=\"+Inf\"} 3\n"));
+    assert!(text.contains("latency_sum{core=\"1\"} 555\n"));
+    assert!(text.contains("latency_count{core=\"1\"} 3\n"));
+}
+
+#[test]
+fn histogram_with_nan_bound() {
+    let h = Histogram::with_buckets(&[100.0, f64::NAN, 10.0]);
+    h.observe(50.0);
+    assert_eq!(h.count(), 1);
+}
+
+#[test]
+fn collectors_run_before_render() {
+    let registry = MetricsRegistry::new();
+    registry.register_collector(|r| r.gauge("collected", "Set by collector", labels(&[])).set(7));
+    assert!(registry.render().contains("collected 7\n"));
+}
+
+#[test]
+fn latency_percentiles() {
+    let mut h = LatencyHistogram::new();
+    for v in 1..1001u64 {
+        h.record(v * 100);
+    }
+    assert_eq!(h.count(), 1000);
+    assert_eq!(h.min(), 100);
+    assert_eq!(h.max(), 100000);
+    let (p50, p99, p999) = h.percentiles();
+    // relative error is bounded by the sub bucket resolution
+    assert!(p50 >= 50000 && p50 <= 50000 + 50000 / 64, "p50= {}", p50);
+    assert!(p99 >= 99000 && p99 <= 99000 + 99000 / 64, "p99= {}", p99);
+    assert!(p999 >= 99900 && p999 <= 100000, "p99.9= {}", p999);
+    let mut other = LatencyHistogram::new();
+    other.record(7);
+    h.merge(&other);
+    assert_eq!(h.min(), 7);
+    assert_eq!(h.value_at_percentile(0.0), 7);
+}
@@ -0,0 +1,164 @@
+extern crate e2d2;
+use e2d2::headers::*;
+use e2d2::interface::{PacketBuilder, Pdu};
+use e2d2::mpls::*;
+use e2d2::native::zcsi::MBuf;
+use std::mem;
+use std::ptr;
+
+// Ethernet, label 100, label 200 (bottom of stack), IPv4 10.0.0.1 > 10.0.0.2, TCP 1234 > 80
+fn labeled_frame(ttl: u8) -> Vec<u8> {
+    vec![
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x88, 0x47, // mac
+        0x00, 0x06, 0x40, ttl, // label 100
+        0x00, 0x0c, 0x81, 64, // label 200, S
+        0x45, 0, 0, 40, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, // ip
+        0x04, 0xd2, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0, // tcp
+    ]
+}
+
+fn mbuf(buf: &mut Vec<u8>, frame: &[u8]) -> MBuf {
+    buf[32..32 + frame.len()].copy_from_slice(frame);
+    let mut mbuf: MBuf = unsafe { mem::zeroed() };
+    mbuf.buf_addr = buf.as_mut_ptr() as *mut _;
+    mbuf.buf_len = buf.len() as u16;
+    mbuf.data_off = 32;
+    mbuf.data_len = frame.len() as u16;
+    mbuf.pkt_len = frame.len() as u32;
+    mbuf.nb_segs = 1;
+    mbuf.next = ptr::null_mut();
+    mbuf
+}
+
+fn labels(pdu: &Pdu) -> Vec<(u32, bool, u8)> {
+    let headers = pdu.headers();
+    (0..headers.count())
+        .filter_map(|i| headers.get(i).as_mpls())
+        .map(|mpls| (mpls.label(), mpls.bottom_of_stack(), mpls.ttl()))
+        .collect()
+}
+
+fn ilm() -> Ilm {
+    let mut ilm = Ilm::new(None);
+    ilm.insert(
+        100,
+        Nhlfe {
+            op: LabelOp::Swap(101),
+            next_hop: 1,
+        },
+    );
+    ilm.insert(
+        101,
+        Nhlfe {
+            op: LabelOp::Pop,
+            next_hop: 2,
+        },
+    );
+    ilm.insert(
+        102,
+        Nhlfe {
+            op: LabelOp::SwapPush(103, 300),
+            next_hop: 3,
+        },
+    );
+    ilm
+}
+
+#[test]
+fn parse_label_stack() {
+    let frame = labeled_frame(64);
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame);
+    let pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    assert_eq!(pdu.headers().count(), 5);
+    assert_eq!(labels(&pdu), vec![(100, false, 64), (200, true, 64)]);
+    assert_eq!(top_label(&pdu), Some(1));
+    assert_eq!(pdu.headers().ip(3).protocol(), 6);
+    assert_eq!(pdu.headers().tcp(4).dst_port(), 80);
+}
+
+#[test]
+fn parse_frame_ending_with_the_label_stack() {
+    let frame = &labeled_frame(64)[..22];
+    // the buffer ends with the frame
+    let mut buf = vec![0u8; 32 + frame.len()];
+    let mut m = mbuf(&mut buf, frame);
+    let pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    assert_eq!(pdu.headers().count(), 3);
+    assert_eq!(labels(&pdu), vec![(100, false, 64), (200, true, 64)]);
+}
+
+#[test]
+fn swap_pop_and_push() {
+    let ilm = ilm();
+    let frame = labeled_frame(64);
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame);
+    let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+
+    assert_eq!(ilm.switch(&mut pdu), Some(1));
+    assert_eq!(labels(&pdu), vec![(101, false, 63), (200, true, 64)]);
+
+    assert_eq!(ilm.switch(&mut pdu), Some(2));
+    assert_eq!(labels(&pdu), vec![(200, true, 62)]);
+    assert_eq!(pdu.headers().mac(0).etype(), ETYPE_MPLS_UNICAST);
+
+    pop_label(&mut pdu).unwrap();
+    assert_eq!(pdu.headers().count(), 3);
+    assert_eq!(pdu.headers().mac(0).etype(), 0x0800);
+    assert_eq!(pdu.headers().ip(1).ttl(), 64);
+    assert!(pop_label(&mut pdu).is_err());
+
+    push_label(&mut pdu, 400, 5, 32).unwrap();
+    assert_eq!(pdu.headers().mac(0).etype(), ETYPE_MPLS_UNICAST);
+    assert_eq!(labels(&pdu), vec![(400, true, 32)]);
+    assert_eq!(pdu.headers().mpls(1).tc(), 5);
+    push_label(&mut pdu, 102, 0, 16).unwrap();
+    assert_eq!(ilm.switch(&mut pdu), Some(3));
+    assert_eq!(labels(&pdu), vec![(300, false, 15), (103, false, 15), (400, true, 32)]);
+
+    // the packet as seen by the next operator
+    pdu.reparse();
+    assert_eq!(labels(&pdu), vec![(300, false, 15), (103, false, 15), (400, true, 32)]);
+    assert_eq!(pdu.headers().tcp(5).dst_port(), 80);
+}
+
+#[test]
+fn drop_unknown_labels_and_expiring_ttl() {
+    let ilm = ilm();
+    let mut frame = labeled_frame(1);
+    let mut buf = vec![0u8; 128];
+    {
+        let mut m = mbuf(&mut buf, &frame);
+        let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+        assert_eq!(ilm.switch(&mut pdu), None);
+    }
+    // label 200 on top
+    frame[14] = 0x00;
+    frame[15] = 0x0c;
+    frame[17] = 64;
+    let mut m = mbuf(&mut buf, &frame);
+    let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    assert_eq!(ilm.switch(&mut pdu), None);
+}
+
+#[test]
+fn build_labeled_packet() {
+    let mut outer = MplsHeader::new();
+    outer.set_label(100);
+    outer.set_ttl(64);
+    let mut inner = MplsHeader::new();
+    inner.set_label(200);
+    inner.set_ttl(64);
+    let bytes = PacketBuilder::new()
+        .mac(MacHeader::new())
+        .mpls(outer)
+        .mpls(inner)
+        .ipv4(IpHeader::new())
+        .to_bytes();
+    assert_eq!(
+        &bytes[12..22],
+        &[0x88, 0x47, 0x00, 0x06, 0x40, 64, 0x00, 0x0c, 0x81, 64]
+    );
+    assert_eq!(bytes[22], 0x45);
+}
@@ -0,0 +1,114 @@
+extern crate e2d2;
+extern crate eui48;
+use e2d2::native::zcsi::MBuf;
+use e2d2::neighbor::*;
+use eui48::MacAddress;
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+
+const HZ: u64 = 1000;
+
+fn conf() -> NeighborConf {
+    NeighborConf {
+        reachable_time: 10_000,
+        retrans_time: 1_000,
+        max_requests: 3,
+        max_pending: 2,
+    }
+}
+
+// The tests replace the mbuf free function of libzcsi by the one below, the fake mbufs are never dereferenced.
+static FREED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+#[no_mangle]
+extern "C" fn mbuf_free(mbuf: *mut MBuf) {
+    FREED.lock().unwrap().push(mbuf as usize);
+}
+
+fn mac(last: u8) -> MacAddress {
+    MacAddress::new([0x02, 0, 0, 0, 0, last])
+}
+
+#[test]
+fn learned_neighbors_age_out() {
+    let mut cache: NeighborCache<Ipv4Addr, u32> = NeighborCache::new(conf(), HZ);
+    let ip = Ipv4Addr::new(10, 0, 0, 1);
+    let mut released = Vec::new();
+    // not created unless we are the target
+    assert!(!cache.learn(ip, mac(1), 0, false, &mut released));
+    assert_eq!(cache.lookup(&ip, 0), None);
+    assert!(cache.learn(ip, mac(1), 0, true, &mut released));
+    assert_eq!(cache.lookup(&ip, 9_999), Some(mac(1)));
+    assert_eq!(cache.lookup(&ip, 10_000), None);
+    // updates refresh the entry
+    assert!(cache.learn(ip, mac(2), 5_000, false, &mut released));
+    assert_eq!(cache.lookup(&ip, 10_000), Some(mac(2)));
+    let (mut requests, mut dropped) = (Vec::new(), Vec::new());
+    cache.expire(15_000, &mut requests, &mut dropped);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn pending_packets_are_released_on_resolution() {
+    let mut cache: NeighborCache<Ipv4Addr, u32> = NeighborCache::new(conf(), HZ);
+    let ip = Ipv4Addr::new(10, 0, 0, 2);
+    assert_eq!(cache.hold(ip, 1, 0), Ok(true));
+    assert_eq!(cache.hold(ip, 2, 10), Ok(false));
+    assert_eq!(cache.hold(ip, 3, 20), Err(3));
+    assert_eq!(cache.state(&ip), Some(NeighborState::Incomplete));
+    let mut released = Vec::new();
+    assert!(cache.learn(ip, mac(2), 100, false, &mut released));
+    assert_eq!(released, vec![1, 2]);
+    assert_eq!(cache.lookup(&ip, 100), Some(mac(2)));
+    assert_eq!(cache.pending(&ip), 0);
+}
+
+#[test]
+fn unresolved_neighbors_are_requested_and_given_up() {
+    let mut cache: NeighborCache<Ipv4Addr, u32> = NeighborCache::new(conf(), HZ);
+    let ip = Ipv4Addr::new(10, 0, 0, 3);
+    assert_eq!(cache.hold(ip, 1, 0), Ok(true));
+    let (mut requests, mut dropped) = (Vec::new(), Vec::new());
+    cache.expire(999, &mut requests, &mut dropped);
+    assert!(requests.is_empty());
+    cache.expire(1_000, &mut requests, &mut dropped);
+    cache.expire(2_000, &mut requests, &mut dropped);
+    assert_eq!(requests, vec![ip, ip]);
+    assert!(dropped.is_empty());
+    cache.expire(3_000, &mut requests, &mut dropped);
+    assert_eq!(dropped, vec![1]);
+    assert_eq!(cache.state(&ip), None);
+}
+
+#[test]
+fn static_neighbors_do_not_age() {
+    let mut cache: NeighborCache<Ipv4Addr, u32> = NeighborCache::new(conf(), HZ);
+    let ip = Ipv4Addr::new(10, 0, 0, 4);
+    let mut released = Vec::new();
+    assert_eq!(cache.hold(ip, 7, 0), Ok(true));
+    cache.insert_static(ip, mac(4), &mut released);
+    assert_eq!(released, vec![7]);
+    assert!(cache.learn(ip, mac(5), 0, false, &mut released));
+    let (mut requests, mut dropped) = (Vec::new(), Vec::new());
+    cache.expire(1_000_000, &mut requests, &mut dropped);
+    assert_eq!(cache.lookup(&ip, 1_000_000), Some(mac(4)));
+    assert_eq!(cache.state(&ip), Some(NeighborState::Static));
+}
+
+#[test]
+fn pending_packets_are_freed_with_the_cache() {
+    let mut cache: ArpCache = NeighborCache::new(conf(), HZ);
+    let (resolved, unresolved) = (Ipv4Addr::new(10, 0, 0, 5), Ipv4Addr::new(10, 0, 0, 6));
+    assert!(cache.hold(resolved, PendingPacket::new(0x1000 as *mut MBuf), 0).is_ok());
+    assert!(cache
+        .hold(unresolved, PendingPacket::new(0x2000 as *mut MBuf), 0)
+        .is_ok());
+    let mut released = Vec::new();
+    assert!(cache.learn(resolved, mac(5), 0, false, &mut released));
+    // released packets are handed over without being freed
+    let mbufs: Vec<_> = released.into_iter().map(PendingPacket::into_mbuf).collect();
+    assert_eq!(mbufs, vec![0x1000 as *mut MBuf]);
+    assert!(FREED.lock().unwrap().is_empty());
+    drop(cache);
+    assert_eq!(*FREED.lock().unwrap(), vec![0x2000]);
+}
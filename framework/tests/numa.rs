@@ -0,0 +1,76 @@
+extern crate e2d2;
+use e2d2::allocators::*;
+use e2d2::config::*;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+/// A sysfs tree of two nodes with cores 0, 1 on node 0 and cores 2, 3 on node 1.
+fn two_sockets(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("e2d2-numa-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for node in 0..2 {
+        fs::create_dir_all(root.join(format!("devices/system/node/node{}", node))).unwrap();
+    }
+    for core in 0..4 {
+        fs::create_dir_all(root.join(format!("devices/system/cpu/cpu{}/node{}", core, core / 2))).unwrap();
+    }
+    for &(device, node) in &[("0000:01:00.0", "0"), ("0000:81:00.0", "1"), ("0000:82:00.0", "-1")] {
+        let dir = root.join("bus/pci/devices").join(device);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("numa_node"), format!("{}\n", node)).unwrap();
+    }
+    root
+}
+
+#[test]
+fn topology_from_sysfs() {
+    let root = two_sockets("topology");
+    let topology = NumaTopology::from_sysfs(&root);
+    assert_eq!(topology.nodes(), vec![0, 1]);
+    assert_eq!(topology.node_of_core(1), Some(0));
+    assert_eq!(topology.node_of_core(2), Some(1));
+    assert_eq!(topology.node_of_core(8), None);
+    assert_eq!(topology.node_of_device("0000:81:00.0"), Some(1));
+    assert_eq!(topology.node_of_device("0000:82:00.0"), None);
+    assert_eq!(topology.node_of_device("kni:0"), None);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn warn_about_remote_cores() {
+    let root = two_sockets("placement");
+    let topology = NumaTopology::from_sysfs(&root);
+    let mut configuration = NetbricksConfiguration::new_with_name("numa");
+    configuration.ports = vec![
+        PortConfiguration::new_with_queues("0000:01:00.0", &[0, 2], &[0, 2]),
+        PortConfiguration::new_with_queues("0000:81:00.0,rx_pcap=x", &[3], &[1]),
+        PortConfiguration::new_with_queues("0000:82:00.0", &[0, 2], &[0, 2]),
+    ];
+    let warnings: Vec<_> = check_numa_placement(&configuration, &topology)
+        .into_iter()
+        .map(|w| w.path)
+        .collect();
+    assert_eq!(
+        warnings,
+        vec!["netbricks.ports[0].rx_cores[1]", "netbricks.ports[1].tx_cores[0]"]
+    );
+    // remote cores are no errors
+    assert!(check_configuration(&configuration).is_empty());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn allocate_on_node() {
+    let value = CacheAligned::allocate_on_node([7u64; 4], 0);
+    assert_eq!(*value, [7u64; 4]);
+    assert_eq!(&*value as *const _ as usize % 64, 0);
+    let copy = value.clone();
+    assert_eq!(copy.node(), value.node());
+    assert_eq!(*copy, [7u64; 4]);
+    if !numa_available() {
+        assert_eq!(value.node(), None);
+    }
+    assert_eq!(CacheAligned::allocate(1u8).node(), None);
+}
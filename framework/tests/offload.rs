@@ -0,0 +1,241 @@
+extern crate e2d2;
+use e2d2::headers::*;
+use e2d2::interface::*;
+use e2d2::native::zcsi::rte_ethdev_api::{
+    DEV_RX_OFFLOAD_IPV4_CKSUM, DEV_RX_OFFLOAD_VLAN_STRIP, DEV_TX_OFFLOAD_IPV4_CKSUM, DEV_TX_OFFLOAD_TCP_CKSUM,
+    DEV_TX_OFFLOAD_TCP_TSO, DEV_TX_OFFLOAD_UDP_CKSUM, DEV_TX_OFFLOAD_VLAN_INSERT, DEV_TX_OFFLOAD_VXLAN_TNL_TSO,
+    PKT_TX_IPV4, PKT_TX_IP_CKSUM, PKT_TX_OUTER_IP_CKSUM, PKT_TX_TCP_CKSUM, PKT_TX_TCP_SEG, PKT_TX_TUNNEL_VXLAN,
+    PKT_TX_UDP_CKSUM,
+};
+use e2d2::native::zcsi::MBuf;
+use e2d2::utils::{checksum, ipv4_checksum};
+use std::mem;
+use std::net::Ipv4Addr;
+use std::ptr;
+
+// Ethernet, IPv4 10.0.0.1 > 10.0.0.2, TCP 1234 > 80 with 6 bytes payload, all checksums zero
+fn tcp_frame() -> Vec<u8> {
+    vec![
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00, // mac
+        0x45, 0, 0, 46, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, // ip
+        0x04, 0xd2, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0, // tcp
+        1, 2, 3, 4, 5, 6,
+    ]
+}
+
+// Ethernet, IPv6 fe80::1 > fe80::2, UDP 1234 > 53 with 3 bytes payload, padded to 72 bytes
+fn udp6_frame() -> Vec<u8> {
+    let mut frame = vec![
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x86, 0xdd, // mac
+        0x60, 0, 0, 0, 0, 11, 17, 64, // ip
+    ];
+    frame.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    frame.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+    frame.extend_from_slice(&[0x04, 0xd2, 0, 53, 0, 11, 0, 0, 0xaa, 0xbb, 0xcc]);
+    frame.resize(72, 0xff);
+    frame
+}
+
+fn mbuf(buf: &mut Vec<u8>, frame: &[u8], headroom: usize) -> MBuf {
+    buf[headroom..headroom + frame.len()].copy_from_slice(frame);
+    let mut mbuf: MBuf = unsafe { mem::zeroed() };
+    mbuf.buf_addr = buf.as_mut_ptr() as *mut _;
+    mbuf.buf_len = buf.len() as u16;
+    mbuf.data_off = headroom as u16;
+    mbuf.data_len = frame.len() as u16;
+    mbuf.pkt_len = frame.len() as u32;
+    mbuf.nb_segs = 1;
+    mbuf.next = ptr::null_mut();
+    mbuf
+}
+
+fn be16(bytes: &[u8], at: usize) -> u16 {
+    (bytes[at] as u16) << 8 | bytes[at + 1] as u16
+}
+
+fn tcp_checksum(frame: &mut [u8]) -> u16 {
+    let len = frame.len() - 34;
+    ipv4_checksum(
+        frame[34..].as_mut_ptr(),
+        len,
+        8,
+        &[],
+        u32::from(Ipv4Addr::new(10, 0, 0, 1)),
+        u32::from(Ipv4Addr::new(10, 0, 0, 2)),
+        6,
+    )
+}
+
+#[test]
+fn negotiate_requested_offloads() {
+    let requested = Offloads::requested(true, true, false);
+    assert!(requested.has_tx(DEV_TX_OFFLOAD_TCP_TSO | DEV_TX_OFFLOAD_TCP_CKSUM));
+    assert!(!requested.has_tx(DEV_TX_OFFLOAD_VLAN_INSERT));
+    let capabilities = Offloads {
+        rx: (DEV_RX_OFFLOAD_IPV4_CKSUM | DEV_RX_OFFLOAD_VLAN_STRIP) as u64,
+        tx: (DEV_TX_OFFLOAD_IPV4_CKSUM | DEV_TX_OFFLOAD_TCP_CKSUM | DEV_TX_OFFLOAD_VLAN_INSERT) as u64,
+    };
+    let enabled = requested.negotiate(&capabilities);
+    assert_eq!(enabled.rx, DEV_RX_OFFLOAD_IPV4_CKSUM as u64);
+    assert_eq!(
+        enabled.tx,
+        (DEV_TX_OFFLOAD_IPV4_CKSUM | DEV_TX_OFFLOAD_TCP_CKSUM) as u64
+    );
+    let flags = PKT_TX_IPV4 | PKT_TX_IP_CKSUM | PKT_TX_UDP_CKSUM;
+    assert_eq!(
+        tx_offloads_for(flags),
+        (DEV_TX_OFFLOAD_IPV4_CKSUM | DEV_TX_OFFLOAD_UDP_CKSUM) as u64
+    );
+    assert_eq!(
+        tx_offloads_for(PKT_TX_TCP_SEG | PKT_TX_TCP_CKSUM | PKT_TX_TUNNEL_VXLAN),
+        (DEV_TX_OFFLOAD_VXLAN_TNL_TSO | DEV_TX_OFFLOAD_TCP_CKSUM) as u64
+    );
+}
+
+#[test]
+fn offload_request_from_header_stack() {
+    let frame = tcp_frame();
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame, 32);
+    let mut pdu = Pdu::pdu_from_mbuf_no_increment(&mut m);
+    let offload = pdu.tx_offload_from_headers().unwrap();
+    assert_eq!((offload.l2_len, offload.l3_len, offload.l4_len), (14, 20, 20));
+    assert_eq!(offload.l4_checksum, L4Checksum::Tcp);
+    assert_eq!(offload.tunnel, None);
+    pdu.set_tx_offload(&offload);
+    assert_eq!(pdu.ol_flags(), PKT_TX_IPV4 | PKT_TX_IP_CKSUM | PKT_TX_TCP_CKSUM);
+    assert_eq!((pdu.l2_len(), pdu.l3_len(), pdu.l4_len()), (14, 20, 20));
+
+    let mut outer = IpHeader::new();
+    outer.set_version(4);
+    outer.set_ihl(5);
+    pdu.insert_header(1, &outer).unwrap();
+    let offload = pdu.tx_offload_from_headers().unwrap();
+    assert_eq!((offload.l2_len, offload.l3_len, offload.l4_len), (0, 20, 20));
+    let tunnel = offload.tunnel.unwrap();
+    assert_eq!(tunnel.tunnel, Tunnel::IpIp);
+    assert_eq!((tunnel.outer_l2_len, tunnel.outer_l3_len), (14, 20));
+    assert_ne!(offload.ol_flags() & PKT_TX_OUTER_IP_CKSUM, 0);
+}
+
+#[test]
+fn checksums_in_software() {
+    let mut frame = tcp_frame();
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame, 32);
+    let offload = Pdu::pdu_from_mbuf_no_increment(&mut m)
+        .tx_offload_from_headers()
+        .unwrap();
+    offload.write_to(&mut m);
+    assert!(needs_tx_fallback(&m, 0));
+    let mut out = Vec::new();
+    // the port offloads the IP checksum only
+    unsafe { tx_fallback(&mut m, DEV_TX_OFFLOAD_IPV4_CKSUM as u64, &mut out) }.unwrap();
+    assert_eq!(out, vec![&mut m as *mut MBuf]);
+    assert_eq!(m.ol_flags, PKT_TX_IPV4 | PKT_TX_IP_CKSUM);
+    assert!(!needs_tx_fallback(&m, DEV_TX_OFFLOAD_IPV4_CKSUM as u64));
+    let sent = buf[32..32 + frame.len()].to_vec();
+    assert_eq!(be16(&sent, 24), 0);
+    assert_eq!(be16(&sent, 50), tcp_checksum(&mut frame));
+
+    // nothing offloaded
+    let mut m = mbuf(&mut buf, &tcp_frame(), 32);
+    offload.write_to(&mut m);
+    out.clear();
+    unsafe { tx_fallback(&mut m, 0, &mut out) }.unwrap();
+    assert_eq!(m.ol_flags, 0);
+    let sent = buf[32..32 + frame.len()].to_vec();
+    assert_eq!(be16(&sent, 24), checksum(&sent[14..34], 5));
+    assert_eq!(be16(&sent, 50), tcp_checksum(&mut frame));
+}
+
+#[test]
+fn udp_over_ipv6_checksum_ignores_padding() {
+    let frame = udp6_frame();
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame, 32);
+    let mut offload = TxOffload::new(IpVersion::V6, 14, 40);
+    offload.l4_len = 8;
+    offload.l4_checksum = L4Checksum::Udp;
+    offload.write_to(&mut m);
+    let mut out = Vec::new();
+    unsafe { tx_fallback(&mut m, 0, &mut out) }.unwrap();
+    // pseudo header: addresses, length and next header, then the UDP header and the payload
+    let sum: u32 = 0xfe80 + 1 + 0xfe80 + 2 + 11 + 17 + 0x04d2 + 53 + 11 + 0xaabb + 0xcc00;
+    let expected = !((sum & 0xffff) + (sum >> 16)) as u16;
+    assert_eq!(be16(&buf, 32 + 60), expected);
+}
+
+#[test]
+fn vlan_tag_in_software() {
+    let frame = tcp_frame();
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame, 32);
+    let mut offload = TxOffload::new(IpVersion::V4, 14, 20);
+    offload.vlan_tci = Some(0x2064);
+    offload.ip_checksum = true;
+    offload.write_to(&mut m);
+    let mut out = Vec::new();
+    unsafe { tx_fallback(&mut m, DEV_TX_OFFLOAD_IPV4_CKSUM as u64, &mut out) }.unwrap();
+    assert_eq!(m.data_off, 28);
+    assert_eq!(m.pkt_len as usize, frame.len() + 4);
+    assert_eq!(m.l2_len(), 18);
+    assert_eq!(m.ol_flags, PKT_TX_IPV4 | PKT_TX_IP_CKSUM);
+    assert_eq!(&buf[28..40], &frame[..12]);
+    assert_eq!(&buf[40..46], &[0x81, 0x00, 0x20, 0x64, 0x08, 0x00]);
+
+    // no headroom
+    let mut m = mbuf(&mut buf, &frame, 0);
+    offload.write_to(&mut m);
+    assert!(unsafe { tx_fallback(&mut m, 0, &mut out) }.is_err());
+}
+
+#[test]
+fn segment_headers() {
+    let mut headers = tcp_frame()[..54].to_vec();
+    headers[47] = 0x89; // CWR, PSH, FIN
+    let mut first = headers.clone();
+    tcp_segment_headers(&mut first, 14, 20, false, 0, 0..4, false);
+    assert_eq!(be16(&first, 16), 44);
+    assert_eq!(be16(&first, 18), 1);
+    assert_eq!(be16(&first, 24), checksum(&first[14..34], 5));
+    assert_eq!(&first[38..42], &[0, 0, 0, 1]);
+    assert_eq!(first[47], 0x80);
+
+    let mut last = headers.clone();
+    tcp_segment_headers(&mut last, 14, 20, false, 1, 4..6, true);
+    assert_eq!(be16(&last, 16), 42);
+    assert_eq!(be16(&last, 18), 2);
+    assert_eq!(&last[38..42], &[0, 0, 0, 5]);
+    assert_eq!(last[47], 0x09);
+}
+
+#[test]
+fn tunnel_segmentation_is_not_done_in_software() {
+    let frame = tcp_frame();
+    let mut buf = vec![0u8; 128];
+    let mut m = mbuf(&mut buf, &frame, 32);
+    let mut offload = TxOffload::new(IpVersion::V4, 0, 20);
+    offload.tso_segsz = 2;
+    offload.l4_len = 20;
+    offload.tunnel = Some(TunnelOffload {
+        tunnel: Tunnel::Gre,
+        outer_l2_len: 14,
+        outer_l3_len: 20,
+        outer_ip: IpVersion::V4,
+        outer_ip_checksum: false,
+    });
+    offload.write_to(&mut m);
+    let mut out = Vec::new();
+    assert!(unsafe { tx_fallback(&mut m, 0, &mut out) }.is_err());
+    assert!(out.is_empty());
+    // a packet which fits into one segment is sent as it is
+    let mut m = mbuf(&mut buf, &frame, 32);
+    let mut offload = TxOffload::new(IpVersion::V4, 14, 20);
+    offload.tso_segsz = 1460;
+    offload.l4_len = 20;
+    offload.write_to(&mut m);
+    unsafe { tx_fallback(&mut m, 0, &mut out) }.unwrap();
+    assert_eq!(out.len(), 1);
+    assert_eq!(m.ol_flags, 0);
+}
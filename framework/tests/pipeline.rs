@@ -0,0 +1,234 @@
+extern crate e2d2;
+use e2d2::allocators::CacheAligned;
+use e2d2::config::{read_configuration_from_str, PortConfiguration};
+use e2d2::interface::{PmdPort, PortQueue};
+use e2d2::operators::{Batch, ReceiveBatch};
+use e2d2::pipeline::*;
+use e2d2::scheduler::StandaloneScheduler;
+use e2d2::utils::{FiveTupleV4, Ipv4Prefix};
+use std::sync::mpsc::channel;
+
+const CONFIG: &str = r#"
+[netbricks]
+name = "pipeline"
+ports = [
+    { name = "0000:01:00.0", cores = [1, 2] },
+    { name = "0000:01:00.1", cores = [1, 2] },
+]
+
+[[pipeline]]
+name = "firewall"
+rx_port = "0000:01:00.0"
+tx_port = "0000:01:00.1"
+cores = [2]
+
+[[pipeline.operator]]
+kind = "acl"
+rules = [
+    { src_ip = "10.0.0.0/8", dst_port = 80 },
+    { established = true },
+    { drop = true },
+]
+
+[[pipeline.operator]]
+kind = "lpm"
+routes = [
+    { prefix = "192.168.0.0/16", mac = "02:00:00:00:00:01" },
+    { prefix = "192.168.1.0/24", mac = "02:00:00:00:00:02" },
+]
+
+[[pipeline]]
+rx_port = "0000:01:00.1"
+
+[[pipeline.operator]]
+kind = "macswap"
+"#;
+
+fn flow(src_ip: u32, dst_ip: u32, dst_port: u16) -> FiveTupleV4 {
+    FiveTupleV4 {
+        src_ip,
+        dst_ip,
+        src_port: 1234,
+        dst_port,
+        proto: 6,
+    }
+}
+
+#[test]
+fn read_pipelines() {
+    let configuration = read_configuration_from_str(CONFIG, "pipeline.toml").unwrap();
+    assert_eq!(configuration.pipelines.len(), 2);
+    let firewall = &configuration.pipelines[0];
+    assert_eq!(firewall.name, "firewall");
+    assert_eq!(firewall.rx_port, "0000:01:00.0");
+    assert_eq!(firewall.tx_port, "0000:01:00.1");
+    assert_eq!(firewall.cores, vec![2]);
+    let kinds: Vec<_> = firewall.operators.iter().map(|o| &o.kind[..]).collect();
+    assert_eq!(kinds, vec!["acl", "lpm"]);
+    assert!(firewall.operators[0].params.get("kind").is_none());
+
+    let reflector = &configuration.pipelines[1];
+    assert_eq!(reflector.name, "pipeline-1");
+    assert_eq!(reflector.tx_port, "0000:01:00.1");
+    assert_eq!(reflector.cores, vec![1, 2]);
+
+    let registry = OperatorRegistry::with_builtins();
+    assert!(configuration
+        .pipelines
+        .iter()
+        .all(|p| p.operators.iter().all(|o| registry.contains(&o.kind))));
+    assert!(!OperatorRegistry::new().contains("acl"));
+}
+
+#[test]
+fn acl_rules_and_routes_from_parameters() {
+    let configuration = read_configuration_from_str(CONFIG, "pipeline.toml").unwrap();
+    let operators = &configuration.pipelines[0].operators;
+
+    let rules = read_acl_rules(&operators[0].params).unwrap();
+    assert_eq!(rules.len(), 3);
+    assert_eq!(rules[0].src_ip, Some(Ipv4Prefix::new(0x0a000000, 8)));
+    assert_eq!(rules[0].dst_port, Some(80));
+    assert!(rules[2].drop);
+    let mut connections = AclConnections::new(1000, 16, 1000);
+    let web = flow(0x0a010203, 0x08080808, 80);
+    assert!(rules[0].matches(&web, &connections));
+    assert!(!rules[0].matches(&flow(0x0b010203, 0x08080808, 80), &connections));
+    assert!(!rules[1].matches(&web.reverse_flow(), &connections));
+    connections.insert(web, 0);
+    assert!(rules[1].matches(&web.reverse_flow(), &connections));
+
+    let routes = read_lpm_routes(&operators[1].params).unwrap();
+    assert_eq!(routes.lookup(0xc0a80101).unwrap().to_hex_string(), "02:00:00:00:00:02");
+    assert_eq!(routes.lookup(0xc0a80201).unwrap().to_hex_string(), "02:00:00:00:00:01");
+    assert!(routes.lookup(0x0a000001).is_none());
+}
+
+#[test]
+fn reject_invalid_pipelines() {
+    let ports = r#"
+[netbricks]
+ports = [ { name = "0000:01:00.0", cores = [1] } ]
+"#;
+    let unknown_port = format!("{}{}", ports, "[[pipeline]]\nrx_port = \"0000:02:00.0\"\n");
+    assert!(read_configuration_from_str(&unknown_port, "port.toml").is_err());
+    let unknown_core = format!("{}{}", ports, "[[pipeline]]\nrx_port = \"0000:01:00.0\"\ncores = [3]\n");
+    assert!(read_configuration_from_str(&unknown_core, "core.toml").is_err());
+    let without_kind = format!(
+        "{}{}",
+        ports, "[[pipeline]]\nrx_port = \"0000:01:00.0\"\n[[pipeline.operator]]\nip = \"10.0.0.1\"\n"
+    );
+    assert!(read_configuration_from_str(&without_kind, "kind.toml").is_err());
+    let duplicate = format!(
+        "{}{}",
+        ports, "[[pipeline]]\nname = \"a\"\nrx_port = \"0000:01:00.0\"\n[[pipeline]]\nname = \"a\"\nrx_port = \"0000:01:00.0\"\n"
+    );
+    assert!(read_configuration_from_str(&duplicate, "duplicate.toml").is_err());
+}
+
+fn null_queue() -> CacheAligned<PortQueue> {
+    let config = PortConfiguration {
+        name: String::from("null:0"),
+        rx_queues: vec![0],
+        tx_queues: vec![0],
+        ..Default::default()
+    };
+    let port = PmdPort::new_port_from_configuration(&config, None).unwrap();
+    PmdPort::new_queue_pair(&port, 0, 0).unwrap()
+}
+
+#[test]
+fn build_rejects_unknown_operators_and_bad_parameters() {
+    let config = r#"
+[netbricks]
+ports = [ { name = "0000:01:00.0", cores = [1] } ]
+
+[[pipeline]]
+name = "unknown"
+rx_port = "0000:01:00.0"
+operator = [ { kind = "macswap" }, { kind = "police" } ]
+
+[[pipeline]]
+name = "bad"
+rx_port = "0000:01:00.0"
+operator = [ { kind = "macswap" }, { kind = "nat", ip = "10.0.0" } ]
+
+[[pipeline]]
+name = "good"
+rx_port = "0000:01:00.0"
+operator = [ { kind = "macswap" }, { kind = "nat", ip = "10.0.0.1" } ]
+"#;
+    let configuration = read_configuration_from_str(config, "build.toml").unwrap();
+    let registry = OperatorRegistry::with_builtins();
+    let queue = null_queue();
+    let (_, receiver) = channel();
+    let (sender, _) = channel();
+    let mut scheduler = StandaloneScheduler::new_with_channel(1, receiver, sender);
+    for pipeline in &configuration.pipelines {
+        let built = {
+            let mut instance = PipelineInstance {
+                name: &pipeline.name,
+                core: 1,
+                rx: &queue,
+                tx: &queue,
+                scheduler: &mut scheduler,
+            };
+            let receive = ReceiveBatch::new(queue.clone()).compose();
+            registry.build(receive, &pipeline.operators, &mut instance).is_ok()
+        };
+        let valid = registry
+            .validate(&pipeline.name, 1, &queue, &queue, &pipeline.operators)
+            .is_ok();
+        assert_eq!(built, pipeline.name == "good", "pipeline {}", pipeline.name);
+        assert_eq!(valid, built, "pipeline {}", pipeline.name);
+    }
+}
+
+#[test]
+fn acl_connections_expire_and_are_bounded() {
+    // 1000 TSC ticks per ms
+    let mut connections = AclConnections::new(10, 2, 1_000_000);
+    let web = flow(0x0a010203, 0x08080808, 80);
+    connections.insert(web, 1_000);
+    connections.insert(web.reverse_flow(), 2_000);
+    assert_eq!(connections.len(), 1);
+    connections.insert(flow(0x0a010204, 0x08080808, 80), 3_000);
+    connections.insert(flow(0x0a010205, 0x08080808, 80), 4_000);
+    assert_eq!(connections.len(), 2);
+    assert!(!connections.contains(&flow(0x0a010205, 0x08080808, 80)));
+
+    connections.insert(web, 11_000);
+    connections.expire(13_500);
+    assert!(connections.contains(&web));
+    assert!(!connections.contains(&flow(0x0a010204, 0x08080808, 80)));
+    connections.expire(21_000);
+    assert!(connections.is_empty());
+}
+
+#[test]
+fn nat_table_recycles_ports_of_idle_mappings() {
+    let mut table = NatTable::new(0xc0a80001, 10, 1_000_000);
+    let web = flow(0x0a010203, 0x08080808, 80);
+    let outgoing = table.translate(&web, 1_000).unwrap();
+    assert_eq!({ outgoing.src_ip }, 0xc0a80001);
+    assert_eq!(table.lookup(&outgoing.reverse_flow()), Some(web.reverse_flow()));
+    assert_eq!(
+        table.translate(&outgoing.reverse_flow(), 2_000),
+        Some(web.reverse_flow())
+    );
+
+    let mut src = 0x0a020000;
+    while table.translate(&flow(src, 0x08080808, 80), 5_000).is_some() {
+        src += 1;
+    }
+    assert_eq!(table.len(), 65535 - 1024 + 1);
+    assert!(table.lookup(&flow(src, 0x08080808, 80)).is_none());
+
+    // only the reverse flow of the first mapping was used later
+    assert!(table.translate(&flow(src, 0x08080808, 80), 11_500).is_none());
+    let recycled = table.translate(&flow(src, 0x08080808, 80), 14_000).unwrap();
+    assert_eq!({ recycled.src_port }, { outgoing.src_port });
+    assert!(table.lookup(&web).is_none());
+    table.expire(30_000);
+    assert!(table.is_empty());
+}
@@ -0,0 +1,147 @@
+extern crate e2d2;
+use e2d2::common::*;
+use e2d2::interface::*;
+use e2d2::native::zcsi::MBuf;
+use e2d2::operators::*;
+use e2d2::scheduler::Executable;
+use e2d2::utils::*;
+use std::arch::x86_64::_rdtsc;
+use std::cell::RefCell;
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+use std::sync::Mutex;
+
+// room for an mbuf followed by its metadata slots
+#[repr(C, align(64))]
+struct FakeMBuf([u8; 512]);
+
+const HEADROOM: usize = 64;
+
+// The tests replace the mbuf free function of libzcsi by the one below, which only records the freed mbufs.
+static FREED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+// the tests share `FREED`
+static MEMPOOL: Mutex<()> = Mutex::new(());
+
+#[no_mangle]
+unsafe extern "C" fn mbuf_free_bulk(array: *mut *mut MBuf, cnt: i32) -> i32 {
+    FREED
+        .lock()
+        .unwrap()
+        .extend(slice::from_raw_parts(array, cnt as usize).iter().map(|p| *p as usize));
+    0
+}
+
+/// A 60 byte TCP packet to 10.0.0.`class`.
+fn packet(class: u8) -> *mut MBuf {
+    let frame = [
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00, // mac
+        0x45, 0, 0, 46, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, class, // ip
+        0x04, 0xd2, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0, // tcp
+        1, 2, 3, 4, 5, 6,
+    ];
+    let buf = Box::leak(vec![0u8; 256].into_boxed_slice());
+    let mbuf = Box::into_raw(Box::new(FakeMBuf([0u8; 512]))) as *mut MBuf;
+    unsafe {
+        (*mbuf).buf_addr = buf.as_mut_ptr() as *mut _;
+        (*mbuf).buf_len = buf.len() as u16;
+        (*mbuf).data_off = HEADROOM as u16;
+        (*mbuf).nb_segs = 1;
+        (*mbuf).next = ptr::null_mut();
+        (*mbuf).set_refcnt(1);
+        assert_eq!((*mbuf).add_data_end(frame.len()), frame.len());
+        ptr::copy_nonoverlapping(frame.as_ptr(), (*mbuf).data_address(0), frame.len());
+    }
+    mbuf
+}
+
+fn class_of(pdu: &mut Pdu) -> usize {
+    (pdu.headers().ip(1).dst() & 0xff) as usize
+}
+
+struct Source(Rc<RefCell<Vec<*mut MBuf>>>);
+
+impl PacketRx for Source {
+    fn recv(&self, pkts: &mut [*mut MBuf]) -> errors::Result<(u32, i32)> {
+        let mut packets = self.0.borrow_mut();
+        let n = packets.len().min(pkts.len());
+        for (slot, mbuf) in pkts.iter_mut().zip(packets.drain(..n)) {
+            *slot = mbuf;
+        }
+        Ok((n as u32, 0))
+    }
+
+    fn queued(&self) -> usize {
+        self.0.borrow().len()
+    }
+}
+
+#[derive(Clone)]
+struct Sink(Rc<RefCell<Vec<*mut MBuf>>>);
+
+impl PacketTx for Sink {
+    fn send(&mut self, pkts: &mut [*mut MBuf]) -> errors::Result<u32> {
+        self.0.borrow_mut().extend_from_slice(pkts);
+        Ok(pkts.len() as u32)
+    }
+}
+
+fn dscp(mbuf: *mut MBuf) -> u8 {
+    unsafe { *(*mbuf).data_address(15) >> 2 }
+}
+
+#[test]
+fn police_marks_and_drops_over_rate_packets() {
+    let _mempool = MEMPOOL.lock().unwrap_or_else(|e| e.into_inner());
+    FREED.lock().unwrap().clear();
+    // nothing is refilled, the committed bucket takes two packets and the excess bucket one more
+    let meters = vec![Meter::srtcm(0, 120, 60, 1_000_000)];
+    let actions = ColorActions {
+        green: PoliceAction::Pass,
+        yellow: PoliceAction::Mark(10),
+        red: PoliceAction::Drop,
+    };
+    let pkts = vec![packet(0), packet(0), packet(9), packet(0), packet(0), packet(0)];
+    let out = Sink(Rc::new(RefCell::new(Vec::new())));
+    let mut pipeline = ReceiveBatch::new(Source(Rc::new(RefCell::new(pkts.clone()))))
+        .police(Box::new(class_of), meters, actions)
+        .send(out.clone());
+    pipeline.execute();
+
+    // packets of classes without meter pass unmetered
+    assert_eq!(*out.0.borrow(), pkts[..4].to_vec());
+    let marks: Vec<_> = out.0.borrow().iter().map(|p| dscp(*p)).collect();
+    assert_eq!(marks, vec![0, 0, 0, 10]);
+    assert_eq!(*FREED.lock().unwrap(), vec![pkts[4] as usize, pkts[5] as usize]);
+}
+
+#[test]
+fn shape_releases_at_bucket_rate() {
+    let _mempool = MEMPOOL.lock().unwrap_or_else(|e| e.into_inner());
+    FREED.lock().unwrap().clear();
+    // one packet per second of 2^26 TSC cycles, with a burst of one packet
+    let second: u64 = 1 << 26;
+    let buckets = vec![TokenBucket::new(60, 60, second)];
+    let pkts = vec![packet(0), packet(0), packet(0), packet(0), packet(0), packet(9)];
+    let out = Sink(Rc::new(RefCell::new(Vec::new())));
+    let mut pipeline = ReceiveBatch::new(Source(Rc::new(RefCell::new(pkts.clone()))))
+        .shape(Box::new(class_of), buckets, 3)
+        .send(out.clone());
+    let start = unsafe { _rdtsc() };
+    pipeline.execute();
+
+    // the packet beyond the buckets passes unshaped, the class queue holds three packets and drops the rest
+    assert_eq!(*out.0.borrow(), vec![pkts[5], pkts[0]]);
+    assert_eq!(*FREED.lock().unwrap(), vec![pkts[3] as usize, pkts[4] as usize]);
+
+    while out.0.borrow().len() < 4 {
+        assert!(
+            unsafe { _rdtsc() } - start < 100 * second,
+            "shaped packets were not released"
+        );
+        pipeline.execute();
+    }
+    assert!(unsafe { _rdtsc() } - start >= 2 * second);
+    assert_eq!(*out.0.borrow(), vec![pkts[5], pkts[0], pkts[1], pkts[2]]);
+    assert_eq!(FREED.lock().unwrap().len(), 2);
+}
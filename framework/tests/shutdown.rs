@@ -0,0 +1,86 @@
+extern crate e2d2;
+extern crate uuid;
+use e2d2::scheduler::*;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, sync_channel};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A task holding `pending` packets, of which it processes up to `per_round` in each execution.
+struct Backlog {
+    pending: usize,
+    per_round: usize,
+}
+
+impl Executable for Backlog {
+    fn execute(&mut self) -> (u32, i32) {
+        let processed = std::cmp::min(self.pending, self.per_round);
+        self.pending -= processed;
+        (processed as u32, self.pending as i32)
+    }
+
+    fn pending(&self) -> usize {
+        self.pending
+    }
+}
+
+#[test]
+fn drain_until_empty_or_deadline() {
+    let (sender, receiver) = sync_channel(0);
+    let scheduler = thread::spawn(move || {
+        let (reply_sender, _replies) = channel();
+        let mut scheduler = StandaloneScheduler::new_with_channel(1, receiver, reply_sender);
+        scheduler.handle_requests();
+    });
+    let mut channels = HashMap::new();
+    channels.insert(1, sender.clone());
+    let add = |name: &str, per_round: usize| {
+        let task = Backlog {
+            pending: 1000,
+            per_round,
+        };
+        let uuid = Uuid::new_v4();
+        sender
+            .send(SchedulerCommand::Add((
+                uuid,
+                name.to_string(),
+                Box::new(task) as Box<dyn Executable + Send>,
+            )))
+            .unwrap();
+        uuid
+    };
+    add("draining", 1);
+    let stuck = add("stuck", 0);
+    sender.send(SchedulerCommand::SetTaskStateAll(true)).unwrap();
+    sender.send(SchedulerCommand::Execute).unwrap();
+
+    let report = drain_schedulers(&channels, Duration::from_millis(200));
+    assert!(!report.drained);
+    assert_eq!(
+        report.leaked,
+        vec![PendingPackets {
+            core: 1,
+            task: "stuck".to_string(),
+            packets: 1000,
+        }]
+    );
+    assert_eq!(report.leaked_packets(), 1000);
+
+    // disabled tasks are not waited for
+    sender.send(SchedulerCommand::SetTaskState(stuck, false)).unwrap();
+    let report = drain_schedulers(&channels, Duration::from_millis(200));
+    assert!(report.drained);
+    assert!(report.leaked.is_empty());
+
+    sender.send(SchedulerCommand::Shutdown).unwrap();
+    scheduler.join().unwrap();
+}
+
+#[test]
+fn shutdown_request() {
+    assert!(!shutdown_requested());
+    request_shutdown();
+    assert!(shutdown_requested());
+    wait_for_shutdown_request(Duration::from_millis(1));
+}
@@ -0,0 +1,255 @@
+extern crate e2d2;
+use e2d2::headers::*;
+use e2d2::tcp::*;
+use e2d2::utils::FiveTupleV4;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+fn client_flow(port: u16) -> FiveTupleV4 {
+    FiveTupleV4 {
+        src_ip: u32::from(CLIENT),
+        dst_ip: u32::from(SERVER),
+        src_port: port,
+        dst_port: 80,
+        proto: 6,
+    }
+}
+
+fn segment(syn: bool, ack: Option<u32>, seq: u32, window: u16) -> TcpHeader {
+    let mut tcp = TcpHeader::new();
+    if syn {
+        tcp.set_syn_flag();
+    }
+    if let Some(ack) = ack {
+        tcp.set_ack_flag();
+        tcp.set_ack_num(ack);
+    }
+    tcp.set_seq_num(seq);
+    tcp.set_window_size(window);
+    tcp
+}
+
+// tsc_hz of 1000 makes time stamps milliseconds
+fn proxy(conf: SynProxyConf) -> SynProxy {
+    let mut proxy = SynProxy::new(conf, 1000);
+    proxy.protect(SocketAddrV4::new(SERVER, 80));
+    proxy
+}
+
+/// Sends the SYN of `flow` with sequence number 1000 and returns the cookie.
+fn syn(proxy: &mut SynProxy, flow: &FiveTupleV4, mss: u16, now: u64) -> u32 {
+    let options = TcpOptions {
+        mss: Some(mss),
+        window_scale: Some(7),
+    }
+    .to_bytes();
+    match proxy.process(flow, &segment(true, None, 1000, 65535), &options, now) {
+        SynProxyAction::SynAck { cookie, mss } => {
+            assert_eq!(mss, 1460);
+            cookie
+        }
+        action => panic!("SYN was answered with {:?}", action),
+    }
+}
+
+#[test]
+fn handshake_is_spliced() {
+    let mut proxy = proxy(SynProxyConf::default());
+    let flow = client_flow(40000);
+    let cookie = syn(&mut proxy, &flow, 1400, 0);
+    // no state for the handshake with the client
+    assert!(proxy.is_empty());
+
+    let ack = segment(false, Some(cookie.wrapping_add(1)), 1001, 29200);
+    assert_eq!(
+        proxy.process(&flow, &ack, &[], 10),
+        SynProxyAction::Syn { seq: 1000, mss: 1300 }
+    );
+    assert_eq!(proxy.len(), 1);
+    // client segments before the server answered retransmit the SYN
+    assert_eq!(
+        proxy.process(&flow, &ack, &[], 500),
+        SynProxyAction::Syn { seq: 1000, mss: 1300 }
+    );
+
+    let reverse = flow.reverse_flow();
+    let syn_ack = segment(true, Some(1001), 5000, 65535);
+    let connected = SynProxyAction::Connected {
+        server: Ack {
+            seq: 1001,
+            ack: 5001,
+            window: 29200,
+        },
+        client: Ack {
+            seq: cookie.wrapping_add(1),
+            ack: 1001,
+            window: 65535,
+        },
+    };
+    assert_eq!(proxy.process(&reverse, &syn_ack, &[], 600), connected);
+    // a retransmitted SYN-ACK is acknowledged again
+    assert_eq!(proxy.process(&reverse, &syn_ack, &[], 700), connected);
+
+    let data = segment(false, Some(cookie.wrapping_add(1)), 1001, 29200);
+    match proxy.process(&flow, &data, &[], 800) {
+        SynProxyAction::Translate { seq_delta, ack_delta } => {
+            assert_eq!(seq_delta, 0);
+            assert_eq!(cookie.wrapping_add(1).wrapping_add(ack_delta), 5001);
+        }
+        action => panic!("data was handled with {:?}", action),
+    }
+    let reply = segment(false, Some(1101), 5001, 65535);
+    match proxy.process(&reverse, &reply, &[], 900) {
+        SynProxyAction::Translate { seq_delta, ack_delta } => {
+            assert_eq!(5001u32.wrapping_add(seq_delta), cookie.wrapping_add(1));
+            assert_eq!(ack_delta, 0);
+        }
+        action => panic!("data was handled with {:?}", action),
+    }
+
+    let stats = proxy.stats();
+    assert_eq!(stats.cookies_sent, 1);
+    assert_eq!(stats.cookies_valid, 1);
+    assert_eq!(stats.connected, 1);
+}
+
+#[test]
+fn invalid_and_expired_cookies_are_dropped() {
+    let conf = SynProxyConf::default();
+    let mut proxy = proxy(conf);
+    let flow = client_flow(40000);
+    let cookie = syn(&mut proxy, &flow, 536, 0);
+
+    // guessed cookies, a cookie of another SYN and one of another flow
+    for ack in &[
+        segment(false, Some(cookie), 1001, 1000),
+        segment(false, Some(cookie.wrapping_add(1)), 2001, 1000),
+    ] {
+        assert_eq!(proxy.process(&flow, ack, &[], 10), SynProxyAction::Drop);
+    }
+    let ack = segment(false, Some(cookie.wrapping_add(1)), 1001, 1000);
+    assert_eq!(proxy.process(&client_flow(40001), &ack, &[], 10), SynProxyAction::Drop);
+    assert_eq!(proxy.stats().cookies_invalid, 3);
+    assert_eq!(
+        proxy.process(&flow, &segment(true, Some(1), 1000, 1000), &[], 10),
+        SynProxyAction::Drop
+    );
+
+    // cookies expire after `cookie_lifetime` periods
+    let expired = conf.cookie_period * conf.cookie_lifetime as u64;
+    assert_eq!(proxy.process(&flow, &ack, &[], expired), SynProxyAction::Drop);
+    assert_eq!(
+        proxy.process(&flow, &ack, &[], expired - 1),
+        SynProxyAction::Syn { seq: 1000, mss: 536 }
+    );
+    assert_eq!(proxy.len(), 1);
+
+    // other services and unknown connections of the servers pass
+    let mut other = client_flow(40002);
+    other.dst_port = 22;
+    assert_eq!(
+        proxy.process(&other, &segment(true, None, 1, 1000), &[], 10),
+        SynProxyAction::Pass
+    );
+    let reverse = client_flow(40003).reverse_flow();
+    assert_eq!(
+        proxy.process(&reverse, &segment(false, Some(1), 1, 1000), &[], 10),
+        SynProxyAction::Pass
+    );
+}
+
+#[test]
+fn resets_and_timeouts_end_connections() {
+    let conf = SynProxyConf {
+        max_connections: 2,
+        ..Default::default()
+    };
+    let mut proxy = proxy(conf);
+    let open = |proxy: &mut SynProxy, port: u16, now: u64| {
+        let flow = client_flow(port);
+        let cookie = syn(proxy, &flow, 1460, now);
+        let ack = segment(false, Some(cookie.wrapping_add(1)), 1001, 1000);
+        (proxy.process(&flow, &ack, &[], now), cookie)
+    };
+
+    // the RST of the server is sent on to the client in the sequence space of the cookie
+    let (action, cookie) = open(&mut proxy, 40000, 0);
+    assert_eq!(action, SynProxyAction::Syn { seq: 1000, mss: 1460 });
+    let mut rst = segment(false, Some(1001), 0, 0);
+    rst.set_rst_flag();
+    assert_eq!(
+        proxy.process(&client_flow(40000).reverse_flow(), &rst, &[], 1),
+        SynProxyAction::Translate {
+            seq_delta: cookie.wrapping_add(1),
+            ack_delta: 0,
+        }
+    );
+    assert!(proxy.is_empty());
+
+    // the table is full
+    assert_eq!(
+        open(&mut proxy, 40001, 0).0,
+        SynProxyAction::Syn { seq: 1000, mss: 1460 }
+    );
+    assert_eq!(
+        open(&mut proxy, 40002, 0).0,
+        SynProxyAction::Syn { seq: 1000, mss: 1460 }
+    );
+    assert_eq!(open(&mut proxy, 40003, 0).0, SynProxyAction::Drop);
+    assert_eq!(proxy.stats().table_full, 1);
+
+    // the server answers only one of them in time
+    let syn_ack = segment(true, Some(1001), 5000, 1000);
+    match proxy.process(&client_flow(40001).reverse_flow(), &syn_ack, &[], 1000) {
+        SynProxyAction::Connected { .. } => (),
+        action => panic!("SYN-ACK was handled with {:?}", action),
+    }
+    proxy.process(
+        &client_flow(40004),
+        &segment(true, None, 1, 1000),
+        &[],
+        conf.handshake_timeout,
+    );
+    assert_eq!(proxy.len(), 1);
+    let mut rst = segment(false, Some(1), 1001, 0);
+    rst.set_rst_flag();
+    match proxy.process(&client_flow(40001), &rst, &[], conf.handshake_timeout) {
+        SynProxyAction::Translate { .. } => (),
+        action => panic!("RST was handled with {:?}", action),
+    }
+    assert!(proxy.is_empty());
+
+    // idle connections are forgotten
+    open(&mut proxy, 40005, 0);
+    proxy.process(&client_flow(40005).reverse_flow(), &syn_ack, &[], 0);
+    proxy.process(
+        &client_flow(40006),
+        &segment(true, None, 1, 1000),
+        &[],
+        conf.idle_timeout - 1,
+    );
+    assert_eq!(proxy.len(), 1);
+    proxy.process(
+        &client_flow(40006),
+        &segment(true, None, 1, 1000),
+        &[],
+        conf.idle_timeout + 1000,
+    );
+    assert!(proxy.is_empty());
+}
+
+#[test]
+fn syn_with_small_mss_gets_no_cookie() {
+    let mut proxy = proxy(SynProxyConf::default());
+    let options = TcpOptions {
+        mss: Some(500),
+        window_scale: None,
+    }
+    .to_bytes();
+    let action = proxy.process(&client_flow(40000), &segment(true, None, 1000, 65535), &options, 0);
+    assert_eq!(action, SynProxyAction::Drop);
+    assert_eq!(proxy.stats().mss_too_small, 1);
+    assert_eq!(proxy.stats().cookies_sent, 0);
+}
@@ -0,0 +1,303 @@
+extern crate e2d2;
+use e2d2::headers::*;
+use e2d2::tcp::*;
+use e2d2::utils::FiveTupleV4;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ptr;
+
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+/// Sends the segments of the stack as packets to `peer`, except those `lose` drops.
+fn transfer(from: &mut TcpStack, to: &mut TcpStack, now: u64, lose: &mut dyn FnMut(&Segment) -> bool) -> usize {
+    let mut segments = Vec::new();
+    from.poll(now, &mut segments);
+    for segment in &segments {
+        if lose(segment) {
+            continue;
+        }
+        // the segment is sent through the packet builder and parsed again
+        let bytes = segment.packet(MacHeader::new()).to_bytes();
+        let ip: IpHeader = unsafe { ptr::read_unaligned(bytes[14..].as_ptr() as *const IpHeader) };
+        let tcp: TcpHeader = unsafe { ptr::read_unaligned(bytes[34..].as_ptr() as *const TcpHeader) };
+        let payload_start = 34 + tcp.data_offset() as usize * 4;
+        let flow = FiveTupleV4 {
+            src_ip: ip.src(),
+            dst_ip: ip.dst(),
+            src_port: tcp.src_port(),
+            dst_port: tcp.dst_port(),
+            proto: ip.protocol(),
+        };
+        to.receive(&flow, &tcp, &bytes[54..payload_start], &bytes[payload_start..], now);
+    }
+    segments.len()
+}
+
+struct Loopback {
+    client: TcpStack,
+    server: TcpStack,
+    now: u64,
+}
+
+impl Loopback {
+    // tsc_hz of 1000 makes time stamps milliseconds
+    fn new(conf: TcpConf) -> Loopback {
+        let mut server = TcpStack::new(SERVER, conf, 1000);
+        server.listen(80).unwrap();
+        Loopback {
+            client: TcpStack::new(CLIENT, conf, 1000),
+            server,
+            now: 0,
+        }
+    }
+
+    /// One round trip, each millisecond long.
+    fn step(&mut self, lose: &mut dyn FnMut(&Segment) -> bool) -> usize {
+        self.now += 1;
+        let sent = transfer(&mut self.client, &mut self.server, self.now, lose);
+        sent + transfer(&mut self.server, &mut self.client, self.now, lose)
+    }
+
+    fn settle(&mut self) {
+        for _ in 0..1000 {
+            if self.step(&mut |_| false) == 0 {
+                return;
+            }
+        }
+        panic!("segments are sent forever");
+    }
+
+    fn connect(&mut self) -> (SocketHandle, SocketHandle) {
+        let client = self.client.connect(SocketAddrV4::new(SERVER, 80), self.now).unwrap();
+        self.settle();
+        let server = self.server.accept(80).expect("connection was not accepted");
+        assert_eq!(self.client.socket(client).unwrap().state(), TcpState::Established);
+        assert_eq!(self.server.socket(server).unwrap().state(), TcpState::Established);
+        (client, server)
+    }
+}
+
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+/// Sends `data` from `client` to `server`, reading as it arrives, and returns what was received.
+fn send_all(
+    link: &mut Loopback,
+    client: SocketHandle,
+    server: SocketHandle,
+    data: &[u8],
+    lose: &mut dyn FnMut(&Segment) -> bool,
+) -> Vec<u8> {
+    let mut sent = 0;
+    let mut received = Vec::new();
+    let mut buf = [0u8; 4096];
+    for _ in 0..100_000 {
+        if sent < data.len() {
+            sent += link.client.send(client, &data[sent..]).unwrap_or(0);
+        }
+        if link.step(lose) == 0 {
+            // a retransmission timer has to expire
+            link.now += 100;
+        }
+        while let Ok(n) = link.server.recv(server, &mut buf) {
+            received.extend_from_slice(&buf[..n]);
+            if n == 0 {
+                break;
+            }
+        }
+        if received.len() == data.len() {
+            return received;
+        }
+    }
+    panic!("only {} of {} bytes were received", received.len(), data.len());
+}
+
+#[test]
+fn handshake_and_transfer_with_window_scaling() {
+    let conf = TcpConf {
+        recv_buffer: 256 * 1024,
+        send_buffer: 256 * 1024,
+        ..Default::default()
+    };
+    let mut link = Loopback::new(conf);
+    let (client, server) = link.connect();
+    let socket = link.client.socket(client).unwrap();
+    assert_eq!(socket.window_scale(), (2, 2));
+    assert_eq!(socket.mss(), 1460);
+
+    let request = data(300_000);
+    assert_eq!(send_all(&mut link, client, server, &request, &mut |_| false), request);
+    // the window of the SYN-ACK is not scaled, the later ones are
+    assert!(link.client.socket(client).unwrap().snd_wnd() > 65535);
+    let reply = data(5000);
+    let mut buf = vec![0u8; 8192];
+    link.server.send(server, &reply).unwrap();
+    link.settle();
+    assert_eq!(link.client.recv(client, &mut buf), Ok(5000));
+    assert_eq!(&buf[..5000], &reply[..]);
+    assert_eq!(link.client.recv(client, &mut buf), Err(TcpError::WouldBlock));
+    assert_eq!(link.client.socket(client).unwrap().retransmits(), 0);
+}
+
+#[test]
+fn lost_segments_are_retransmitted() {
+    let mut link = Loopback::new(TcpConf::default());
+    let (client, server) = link.connect();
+    let request = data(200_000);
+    let mut count = 0;
+    let mut lost = HashSet::new();
+    // every 20th data segment is lost, and then most of a window, but retransmissions arrive
+    let mut lose = |segment: &Segment| {
+        if segment.payload.is_empty() || lost.contains(&segment.tcp.seq_num()) {
+            return false;
+        }
+        count += 1;
+        let lose = count % 20 == 0 || (count > 100 && count < 120);
+        if lose {
+            lost.insert(segment.tcp.seq_num());
+        }
+        lose
+    };
+    assert_eq!(send_all(&mut link, client, server, &request, &mut lose), request);
+    let socket = link.client.socket(client).unwrap();
+    assert!(socket.retransmits() > 0);
+    assert!(socket.rto() >= 200);
+}
+
+#[test]
+fn connections_close_gracefully() {
+    let conf = TcpConf {
+        time_wait: 1000,
+        ..Default::default()
+    };
+    let mut link = Loopback::new(conf);
+    let (client, server) = link.connect();
+    link.client.send(client, b"bye").unwrap();
+    link.client.close(client);
+    assert!(link.client.send(client, b"more").is_err());
+    link.settle();
+
+    let mut buf = [0u8; 16];
+    assert_eq!(link.server.socket(server).unwrap().state(), TcpState::CloseWait);
+    assert_eq!(link.server.recv(server, &mut buf), Ok(3));
+    assert_eq!(link.server.recv(server, &mut buf), Ok(0));
+    assert!(link.server.socket(server).unwrap().eof());
+    link.server.send(server, b"ok").unwrap();
+    link.server.close(server);
+    link.settle();
+    // the server forgets the connection after the last ACK, the client waits in TIME-WAIT
+    assert!(link.server.is_empty());
+    assert_eq!(link.client.socket(client).unwrap().state(), TcpState::TimeWait);
+    link.now += 1000;
+    link.settle();
+    assert!(link.client.is_empty());
+}
+
+#[test]
+fn refused_reset_and_timed_out_connections() {
+    let conf = TcpConf {
+        max_retries: 3,
+        ..Default::default()
+    };
+    let mut link = Loopback::new(conf);
+    let refused = link.client.connect(SocketAddrV4::new(SERVER, 81), 0).unwrap();
+    link.settle();
+    let socket = link.client.socket(refused).unwrap();
+    assert_eq!(socket.state(), TcpState::Closed);
+    assert_eq!(socket.error(), Some(TcpError::Refused));
+    assert_eq!(link.client.send(refused, b"x"), Err(TcpError::Refused));
+    link.client.close(refused);
+    assert!(link.client.is_empty());
+    assert_eq!(link.server.stats().resets_sent, 1);
+
+    let (client, server) = link.connect();
+    link.client.abort(client);
+    link.settle();
+    assert_eq!(link.server.socket(server).unwrap().error(), Some(TcpError::Reset));
+    assert_eq!(link.client.send(client, b"x"), Err(TcpError::InvalidHandle));
+
+    // the peer vanishes
+    let (client, _) = link.connect();
+    link.client.send(client, b"hello").unwrap();
+    for _ in 0..100 {
+        link.step(&mut |_| true);
+        link.now += 1000;
+    }
+    let socket = link.client.socket(client).unwrap();
+    assert_eq!(socket.error(), Some(TcpError::TimedOut));
+    assert_eq!(socket.retransmits(), 3);
+}
+
+#[test]
+fn zero_window_is_probed() {
+    let conf = TcpConf {
+        recv_buffer: 4096,
+        ..Default::default()
+    };
+    let mut link = Loopback::new(conf);
+    let (client, server) = link.connect();
+    // the server does not read until its buffer is full
+    let request = data(10_000);
+    let mut sent = link.client.send(client, &request).unwrap();
+    link.settle();
+    assert_eq!(link.server.socket(server).unwrap().recv_queue(), 4095);
+    assert_eq!(link.client.socket(client).unwrap().snd_wnd(), 0);
+    // probes do not abort the connection
+    for _ in 0..20 {
+        link.now += 60_000;
+        link.settle();
+    }
+    assert_eq!(link.client.socket(client).unwrap().state(), TcpState::Established);
+
+    let mut received = Vec::new();
+    let mut buf = [0u8; 1024];
+    while received.len() < request.len() {
+        while let Ok(n) = link.server.recv(server, &mut buf) {
+            received.extend_from_slice(&buf[..n]);
+        }
+        sent += link.client.send(client, &request[sent..]).unwrap_or(0);
+        link.step(&mut |_| false);
+    }
+    assert_eq!(received, request);
+}
+
+#[test]
+fn backlog_limits_pending_connections() {
+    let conf = TcpConf {
+        backlog: 2,
+        ..Default::default()
+    };
+    let mut link = Loopback::new(conf);
+    for _ in 0..3 {
+        link.client.connect(SocketAddrV4::new(SERVER, 80), 0).unwrap();
+    }
+    link.step(&mut |_| false);
+    assert_eq!(link.server.stats().backlog_drops, 1);
+    link.settle();
+    assert!(link.server.accept(80).is_some());
+    assert!(link.server.accept(80).is_some());
+    assert!(link.server.accept(80).is_none());
+    assert_eq!(link.server.listen(80), Err(TcpError::AddrInUse));
+}
+
+#[test]
+fn options_round_trip() {
+    let options = TcpOptions {
+        mss: Some(1460),
+        window_scale: Some(7),
+    };
+    let bytes = options.to_bytes();
+    assert_eq!(bytes.len() % 4, 0);
+    assert_eq!(TcpOptions::parse(&bytes), options);
+    // SACK permitted and a truncated option
+    assert_eq!(
+        TcpOptions::parse(&[4, 2, 2, 4, 0x05, 0xb4, 3, 3]),
+        TcpOptions {
+            mss: Some(1460),
+            window_scale: None,
+        }
+    );
+    assert_eq!(TcpOptions::parse(&[3, 3, 20]).window_scale, Some(14));
+}
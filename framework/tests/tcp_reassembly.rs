@@ -0,0 +1,221 @@
+extern crate e2d2;
+use e2d2::headers::TcpHeader;
+use e2d2::state::*;
+use e2d2::utils::FiveTupleV4;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Default)]
+struct Log {
+    to_responder: Vec<u8>,
+    to_originator: Vec<u8>,
+    closed: Option<CloseReason>,
+}
+
+struct Recorder(Rc<RefCell<Log>>);
+
+impl StreamHandler for Recorder {
+    fn on_data(&mut self, dir: StreamDirection, data: &[u8]) {
+        let mut log = self.0.borrow_mut();
+        match dir {
+            StreamDirection::ToResponder => log.to_responder.extend_from_slice(data),
+            StreamDirection::ToOriginator => log.to_originator.extend_from_slice(data),
+        }
+    }
+
+    fn on_close(&mut self, reason: CloseReason) {
+        self.0.borrow_mut().closed = Some(reason);
+    }
+}
+
+const CLIENT: FiveTupleV4 = FiveTupleV4 {
+    src_ip: 0x0a000001,
+    dst_ip: 0x0a000002,
+    src_port: 40000,
+    dst_port: 80,
+    proto: 6,
+};
+
+// tsc_hz of 1000 makes time stamps milliseconds
+fn reassembler(
+    conf: ReassemblyConf,
+) -> (
+    TcpReassembler<Recorder, impl FnMut(&FiveTupleV4) -> Option<Recorder>>,
+    Rc<RefCell<Log>>,
+) {
+    let log = Rc::new(RefCell::new(Log::default()));
+    let handler_log = log.clone();
+    let reassembler = TcpReassembler::new(conf, 1000, move |flow: &FiveTupleV4| {
+        if flow.dst_port == 80 {
+            Some(Recorder(handler_log.clone()))
+        } else {
+            None
+        }
+    });
+    (reassembler, log)
+}
+
+fn tcp(seq: u32, flags: &str) -> TcpHeader {
+    let mut tcp = TcpHeader::new();
+    tcp.set_seq_num(seq);
+    tcp.set_data_offset(5);
+    for flag in flags.chars() {
+        match flag {
+            'S' => tcp.set_syn_flag(),
+            'A' => tcp.set_ack_flag(),
+            'F' => tcp.set_fin_flag(),
+            'R' => tcp.set_rst_flag(),
+            _ => panic!("unknown flag"),
+        }
+    }
+    tcp
+}
+
+fn syn_ack(seq: u32, ack: u32) -> TcpHeader {
+    let mut tcp = tcp(seq, "SA");
+    tcp.set_ack_num(ack);
+    tcp
+}
+
+fn handshake<H, F>(r: &mut TcpReassembler<H, F>, client_isn: u32, server_isn: u32)
+where
+    H: StreamHandler,
+    F: FnMut(&FiveTupleV4) -> Option<H>,
+{
+    let server = CLIENT.reverse_flow();
+    assert_eq!(
+        r.process(&CLIENT, &tcp(client_isn, "S"), &[], 0),
+        Some(StreamDirection::ToResponder)
+    );
+    assert_eq!(
+        r.process(&server, &syn_ack(server_isn, client_isn.wrapping_add(1)), &[], 0),
+        Some(StreamDirection::ToOriginator)
+    );
+}
+
+#[test]
+fn reassemble_out_of_order_segments_until_fin() {
+    let (mut r, log) = reassembler(ReassemblyConf::default());
+    let server = CLIENT.reverse_flow();
+    handshake(&mut r, 1000, 5000);
+    assert_eq!(r.len(), 1);
+
+    // "hello world" in three segments: out of order, duplicated and overlapping
+    r.process(&CLIENT, &tcp(1007, "A"), b"world", 1);
+    assert!(log.borrow().to_responder.is_empty());
+    r.process(&CLIENT, &tcp(1001, "A"), b"hel", 2);
+    assert_eq!(&log.borrow().to_responder[..], b"hel");
+    r.process(&CLIENT, &tcp(1001, "A"), b"hel", 3);
+    r.process(&CLIENT, &tcp(1003, "A"), b"llo ", 4);
+    assert_eq!(&log.borrow().to_responder[..], b"hello world");
+
+    r.process(&server, &tcp(5001, "A"), b"HTTP/1.1 200 OK", 5);
+    r.process(&CLIENT, &tcp(1012, "FA"), &[], 6);
+    assert!(log.borrow().closed.is_none());
+    r.process(&server, &tcp(5016, "FA"), &[], 7);
+    assert_eq!(&log.borrow().to_originator[..], b"HTTP/1.1 200 OK");
+    assert_eq!(log.borrow().closed, Some(CloseReason::Fin));
+    assert!(r.is_empty());
+    assert_eq!(r.memory(), 0);
+    assert_eq!(r.stats().delivered, 26);
+}
+
+#[test]
+fn sequence_numbers_wrap_around() {
+    let (mut r, log) = reassembler(ReassemblyConf::default());
+    let isn = u32::max_value() - 4;
+    handshake(&mut r, isn, 7);
+    let data: Vec<u8> = (0..64u8).collect();
+    // segments of 8 bytes in reverse order, the stream crosses 2^32 after 4 bytes
+    for i in (0..8).rev() {
+        let seq = isn.wrapping_add(1).wrapping_add(i * 8);
+        r.process(&CLIENT, &tcp(seq, "A"), &data[i as usize * 8..(i as usize + 1) * 8], 1);
+    }
+    assert_eq!(log.borrow().to_responder, data);
+    r.process(&CLIENT, &tcp(isn.wrapping_add(65), "RA"), &[], 2);
+    assert_eq!(log.borrow().closed, Some(CloseReason::Reset));
+}
+
+#[test]
+fn idle_timeout_and_untracked_connections() {
+    let conf = ReassemblyConf {
+        idle_timeout: 100,
+        ..Default::default()
+    };
+    let (mut r, log) = reassembler(conf);
+    handshake(&mut r, 1, 1);
+    r.expire(50);
+    assert_eq!(r.len(), 1);
+    r.expire(150);
+    assert!(r.is_empty());
+    assert_eq!(log.borrow().closed, Some(CloseReason::IdleTimeout));
+
+    // data without handshake
+    assert_eq!(r.process(&CLIENT, &tcp(10, "A"), b"data", 200), None);
+    // the factory declines other ports
+    let mut ssh = CLIENT;
+    ssh.dst_port = 22;
+    assert_eq!(r.process(&ssh, &tcp(10, "S"), &[], 200), None);
+    assert!(r.is_empty());
+
+    let (mut r, log) = reassembler(ReassemblyConf {
+        midstream: true,
+        ..Default::default()
+    });
+    assert_eq!(
+        r.process(&CLIENT, &tcp(10, "A"), b"data", 0),
+        Some(StreamDirection::ToResponder)
+    );
+    assert_eq!(&log.borrow().to_responder[..], b"data");
+}
+
+#[test]
+fn connection_limit() {
+    let (mut r, _) = reassembler(ReassemblyConf {
+        max_connections: 1,
+        ..Default::default()
+    });
+    handshake(&mut r, 1, 1);
+    let mut other = CLIENT;
+    other.src_port += 1;
+    assert_eq!(r.process(&other, &tcp(0, "S"), &[], 0), None);
+    assert_eq!(r.len(), 1);
+    assert_eq!(r.stats().refused, 1);
+
+    r.close_all();
+    assert_eq!(
+        r.process(&other, &tcp(0, "S"), &[], 0),
+        Some(StreamDirection::ToResponder)
+    );
+}
+
+#[test]
+fn memory_limit_and_reordering_window() {
+    let conf = ReassemblyConf {
+        buffer_size: 1024,
+        max_memory: 2048,
+        ..Default::default()
+    };
+    let (mut r, log) = reassembler(conf);
+    let server = CLIENT.reverse_flow();
+    handshake(&mut r, 0, 0);
+    r.process(&CLIENT, &tcp(1, "A"), b"a", 0);
+    r.process(&server, &tcp(1, "A"), b"b", 0);
+    assert_eq!(r.memory(), 2048);
+
+    // a second connection does not fit
+    let mut other = CLIENT;
+    other.src_port += 1;
+    r.process(&other, &tcp(0, "S"), &[], 0);
+    assert_eq!(r.len(), 2);
+    r.process(&other, &tcp(1, "A"), b"c", 0);
+    assert_eq!(r.len(), 1);
+    assert_eq!(r.stats().out_of_memory, 1);
+    assert_eq!(log.borrow().closed, Some(CloseReason::OutOfMemory));
+
+    // data beyond the buffer
+    r.process(&CLIENT, &tcp(4000, "A"), b"far ahead", 0);
+    assert_eq!(log.borrow().closed, Some(CloseReason::BufferOverflow));
+    assert!(r.is_empty());
+    assert_eq!(r.memory(), 0);
+}
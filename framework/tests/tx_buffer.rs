@@ -0,0 +1,290 @@
+extern crate e2d2;
+use e2d2::allocators::CacheAligned;
+use e2d2::common::*;
+use e2d2::config::{read_configuration_from_str, PortConfiguration};
+use e2d2::interface::*;
+use e2d2::native::zcsi::rte_ethdev_api::{PKT_TX_IPV4, PKT_TX_TCP_CKSUM, PKT_TX_TCP_SEG};
+use e2d2::native::zcsi::MBuf;
+use e2d2::operators::*;
+use e2d2::scheduler::Executable;
+use std::cell::RefCell;
+use std::mem;
+use std::ops::Range;
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const CONFIG: &str = r#"
+[netbricks]
+name = "tx_buffer"
+ports = [
+    { name = "0000:01:00.0", cores = [1], tx_buffer_packets = 8192, tx_drop_policy = "head" },
+    { name = "0000:01:00.1", cores = [1], tx_buffer_bytes = 1048576 },
+    { name = "0000:01:00.2", cores = [1] },
+]
+"#;
+
+#[test]
+fn read_tx_buffer_configuration() {
+    let configuration = read_configuration_from_str(CONFIG, "tx_buffer.toml").unwrap();
+    assert_eq!(
+        configuration.ports[0].tx_buffer,
+        TxBufferConf {
+            limit: TxBufferLimit::Packets(8192),
+            policy: TxDropPolicy::HeadDrop,
+        }
+    );
+    assert_eq!(
+        configuration.ports[1].tx_buffer,
+        TxBufferConf {
+            limit: TxBufferLimit::Bytes(1048576),
+            policy: TxDropPolicy::TailDrop,
+        }
+    );
+    assert_eq!(configuration.ports[2].tx_buffer, TxBufferConf::default());
+}
+
+#[test]
+fn reject_invalid_tx_buffer_configuration() {
+    let both = r#"
+[netbricks]
+ports = [ { name = "0000:01:00.0", cores = [1], tx_buffer_packets = 64, tx_buffer_bytes = 4096 } ]
+"#;
+    assert!(read_configuration_from_str(both, "both.toml").is_err());
+    let policy = r#"
+[netbricks]
+ports = [ { name = "0000:01:00.0", cores = [1], tx_buffer_packets = 64, tx_drop_policy = "random" } ]
+"#;
+    assert!(read_configuration_from_str(policy, "policy.toml").is_err());
+}
+
+// The tests replace the tx burst and mbuf functions of libzcsi by the ones below. The fake mbufs carry a number in
+// `pkt_len`, a tx burst accepts no packet with a number of at least `BLOCKED`, as long as `BLOCKING` is set, and at
+// most `BURST` packets.
+const BLOCKED: usize = 1000;
+
+static BLOCKING: AtomicBool = AtomicBool::new(true);
+static BURST: AtomicUsize = AtomicUsize::new(usize::MAX);
+static SENT: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+static FREED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+// the tests share the globals above
+static NIC: Mutex<()> = Mutex::new(());
+
+#[no_mangle]
+unsafe extern "C" fn eth_tx_burst(_port: u16, _qid: u16, pkts: *mut *mut MBuf, len: u16) -> u16 {
+    let pkts = slice::from_raw_parts(pkts, len as usize);
+    let n = pkts
+        .iter()
+        .take_while(|p| !BLOCKING.load(Ordering::SeqCst) || number(**p) < BLOCKED)
+        .take(BURST.load(Ordering::SeqCst))
+        .count();
+    SENT.lock().unwrap().extend(pkts[..n].iter().map(|p| number(*p)));
+    n as u16
+}
+
+#[no_mangle]
+unsafe extern "C" fn mbuf_free_bulk(array: *mut *mut MBuf, cnt: i32) -> i32 {
+    FREED
+        .lock()
+        .unwrap()
+        .extend(slice::from_raw_parts(array, cnt as usize).iter().map(|p| number(*p)));
+    0
+}
+
+#[no_mangle]
+extern "C" fn mbuf_free(mbuf: *mut MBuf) {
+    FREED.lock().unwrap().push(number(mbuf));
+}
+
+#[no_mangle]
+extern "C" fn mbuf_alloc() -> *mut MBuf {
+    let buf = Box::leak(vec![0u8; 256].into_boxed_slice());
+    let mut mbuf: Box<MBuf> = Box::new(unsafe { mem::zeroed() });
+    mbuf.buf_addr = buf.as_mut_ptr() as *mut _;
+    mbuf.buf_len = buf.len() as u16;
+    mbuf.data_off = 64;
+    mbuf.nb_segs = 1;
+    mbuf.next = ptr::null_mut();
+    mbuf.set_refcnt(1);
+    Box::into_raw(mbuf)
+}
+
+fn reset() -> MutexGuard<'static, ()> {
+    let guard = NIC.lock().unwrap_or_else(|e| e.into_inner());
+    BLOCKING.store(true, Ordering::SeqCst);
+    BURST.store(usize::MAX, Ordering::SeqCst);
+    SENT.lock().unwrap().clear();
+    FREED.lock().unwrap().clear();
+    guard
+}
+
+fn null_port(limit: TxBufferLimit, policy: TxDropPolicy) -> Arc<PmdPort> {
+    let config = PortConfiguration {
+        name: String::from("null:0"),
+        rx_queues: vec![0],
+        tx_queues: vec![0],
+        tx_buffer: TxBufferConf { limit, policy },
+        ..Default::default()
+    };
+    PmdPort::new_port_from_configuration(&config, None).unwrap()
+}
+
+fn mbufs(numbers: Range<usize>) -> Vec<*mut MBuf> {
+    numbers
+        .map(|n| {
+            let mut mbuf: Box<MBuf> = Box::new(unsafe { mem::zeroed() });
+            mbuf.pkt_len = n as u32;
+            Box::into_raw(mbuf)
+        })
+        .collect()
+}
+
+fn number(mbuf: *mut MBuf) -> usize {
+    unsafe { (*mbuf).pkt_len() }
+}
+
+fn send(queue: &mut CacheAligned<PortQueueTxBuffered>, numbers: Range<usize>) {
+    let len = numbers.len();
+    assert_eq!(queue.send(&mut mbufs(numbers)).unwrap(), len as u32);
+}
+
+#[test]
+fn tail_drop_keeps_the_oldest_packets() {
+    let _nic = reset();
+    let port = null_port(TxBufferLimit::Packets(4), TxDropPolicy::TailDrop);
+    let mut queue = PmdPort::new_tx_buffered_queue_pair(&port, 0, 0).unwrap();
+    send(&mut queue, BLOCKED..BLOCKED + 3);
+    send(&mut queue, BLOCKED + 3..BLOCKED + 6);
+    assert!(queue.backpressure());
+    assert_eq!(*FREED.lock().unwrap(), vec![BLOCKED + 4, BLOCKED + 5]);
+    let stats = port.tx_queue_stats(0);
+    assert_eq!(stats.dropped(), 2);
+    assert_eq!(stats.queued.load(Ordering::Relaxed), 4);
+
+    BLOCKING.store(false, Ordering::SeqCst);
+    send(&mut queue, 0..0);
+    assert_eq!(*SENT.lock().unwrap(), (BLOCKED..BLOCKED + 4).collect::<Vec<_>>());
+    assert!(!queue.backpressure());
+}
+
+#[test]
+fn head_drop_keeps_the_newest_packets() {
+    let _nic = reset();
+    let port = null_port(TxBufferLimit::Packets(4), TxDropPolicy::HeadDrop);
+    let mut queue = PmdPort::new_tx_buffered_queue_pair(&port, 0, 0).unwrap();
+    send(&mut queue, BLOCKED..BLOCKED + 3);
+    send(&mut queue, BLOCKED + 3..BLOCKED + 6);
+    assert_eq!(*FREED.lock().unwrap(), vec![BLOCKED, BLOCKED + 1]);
+    let stats = port.tx_queue_stats(0);
+    assert_eq!(stats.queued.load(Ordering::Relaxed), 6);
+    // more fresh packets than fit into the buffer, only the newest ones are kept
+    send(&mut queue, BLOCKED + 6..BLOCKED + 12);
+    assert_eq!(*FREED.lock().unwrap(), (BLOCKED..BLOCKED + 8).collect::<Vec<_>>());
+    assert_eq!(stats.dropped(), 8);
+    assert_eq!(stats.queued.load(Ordering::Relaxed), 10);
+
+    BLOCKING.store(false, Ordering::SeqCst);
+    send(&mut queue, 0..0);
+    assert_eq!(*SENT.lock().unwrap(), (BLOCKED + 8..BLOCKED + 12).collect::<Vec<_>>());
+}
+
+/// A 60 byte TCP packet, which requests segmentation into three segments of 56 bytes.
+fn tso_packet() -> *mut MBuf {
+    let frame = [
+        0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00, // mac
+        0x45, 0, 0, 46, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, // ip
+        0x04, 0xd2, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0, // tcp
+        1, 2, 3, 4, 5, 6,
+    ];
+    let mbuf = unsafe { &mut *mbuf_alloc() };
+    assert_eq!(mbuf.append_bytes(&frame), frame.len());
+    mbuf.ol_flags = PKT_TX_IPV4 | PKT_TX_TCP_CKSUM | PKT_TX_TCP_SEG;
+    mbuf.set_l2_len(14);
+    mbuf.set_l3_len(20);
+    mbuf.set_l4_len(20);
+    mbuf.set_tso_segsz(2);
+    mbuf
+}
+
+#[test]
+fn unsent_software_segments_are_buffered() {
+    let _nic = reset();
+    let port = null_port(TxBufferLimit::Packets(8), TxDropPolicy::TailDrop);
+    let mut queue = PmdPort::new_tx_buffered_queue_pair(&port, 0, 0).unwrap();
+    BURST.store(1, Ordering::SeqCst);
+    assert_eq!(queue.send(&mut [tso_packet()]).unwrap(), 1);
+    // the original packet is freed, only the segment on the wire counts as sent
+    assert_eq!(*SENT.lock().unwrap(), vec![56]);
+    assert_eq!(*FREED.lock().unwrap(), vec![60]);
+    let stats = port.tx_queue_stats(0);
+    assert_eq!(stats.stats.load(Ordering::Relaxed), 1);
+    assert_eq!(stats.queued.load(Ordering::Relaxed), 2);
+
+    BURST.store(usize::MAX, Ordering::SeqCst);
+    queue.flush().unwrap();
+    assert_eq!(*SENT.lock().unwrap(), vec![56, 56, 56]);
+    assert_eq!(stats.stats.load(Ordering::Relaxed), 3);
+    assert!(!queue.backpressure());
+}
+
+struct Source(Rc<RefCell<Vec<*mut MBuf>>>);
+
+impl PacketRx for Source {
+    fn recv(&self, pkts: &mut [*mut MBuf]) -> errors::Result<(u32, i32)> {
+        let mut packets = self.0.borrow_mut();
+        let n = packets.len().min(pkts.len());
+        for (slot, mbuf) in pkts.iter_mut().zip(packets.drain(..n)) {
+            *slot = mbuf;
+        }
+        Ok((n as u32, 0))
+    }
+
+    fn queued(&self) -> usize {
+        self.0.borrow().len()
+    }
+}
+
+#[test]
+fn merge_skips_congested_parents() {
+    let _nic = reset();
+    let blocked_port = null_port(TxBufferLimit::Packets(8), TxDropPolicy::TailDrop);
+    let blocked_queue = PmdPort::new_tx_buffered_queue_pair(&blocked_port, 0, 0).unwrap();
+    let port = null_port(TxBufferLimit::Packets(8), TxDropPolicy::TailDrop);
+    let queue = PmdPort::new_tx_buffered_queue_pair(&port, 0, 0).unwrap();
+    let blocked_rx = Rc::new(RefCell::new(mbufs(BLOCKED..BLOCKED + 64)));
+    let rx = Rc::new(RefCell::new(mbufs(0..64)));
+    let mut merged = merge_auto(
+        vec![
+            Box::new(ReceiveBatch::new(Source(blocked_rx.clone())).send(blocked_queue.clone())),
+            Box::new(ReceiveBatch::new(Source(rx.clone())).send(queue.clone())),
+        ],
+        SchedulingPolicy::LongestQueue,
+    );
+
+    // the first batch fills the buffer of the blocked port, afterwards only the other parent is served
+    for _ in 0..4 {
+        merged.execute();
+    }
+    assert!(blocked_queue.backpressure());
+    assert_eq!(blocked_rx.borrow().len(), 32);
+    assert!(rx.borrow().is_empty());
+    assert_eq!(*SENT.lock().unwrap(), (0..64).collect::<Vec<_>>());
+    assert_eq!(blocked_port.tx_queue_stats(0).dropped(), 24);
+
+    // the buffer of the skipped parent drains when the merge polls its backpressure
+    BLOCKING.store(false, Ordering::SeqCst);
+    for _ in 0..2 {
+        merged.execute();
+    }
+    assert!(!blocked_queue.backpressure());
+    assert!(blocked_rx.borrow().is_empty());
+    let sent: Vec<_> = SENT.lock().unwrap().drain(64..).collect();
+    assert_eq!(
+        sent,
+        (BLOCKED..BLOCKED + 8)
+            .chain(BLOCKED + 32..BLOCKED + 64)
+            .collect::<Vec<_>>()
+    );
+}
@@ -18,6 +18,8 @@ int find_secondary_mempool();
 unsigned int mbuf_avail_count();
 struct rte_mbuf* mbuf_alloc();
 void mbuf_free(struct rte_mbuf* buf);
+/// returns an indirect mbuf (chain) attached to the data of md, or NULL
+struct rte_mbuf* mbuf_clone(struct rte_mbuf* md);
 int mbuf_alloc_bulk(struct rte_mbuf **array, unsigned int cnt);
 /// returns 0 if it used fast path (assambler), returns 1 if it used rte_pktmbuf_free on each array element (slow path)
 int mbuf_free_bulk(mbuf_array_t array, int cnt);
@@ -231,6 +231,10 @@ void mbuf_free(struct rte_mbuf *buf) {
     rte_pktmbuf_free(buf);
 }
 
+struct rte_mbuf *mbuf_clone(struct rte_mbuf *md) {
+    return rte_pktmbuf_clone(md, current_pframe_pool());
+}
+
 
 
 #define RTE_MBUF_FROM_BADDR(ba) (((struct rte_mbuf *)(ba)) - 1)
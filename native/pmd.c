@@ -231,7 +231,8 @@ assert_link_status(int port_id) {
 
 
 int init_pmd_port(uint16_t port, uint16_t rxqs, uint16_t txqs, int rxq_core[], int txq_core[], uint16_t nrxd, uint16_t ntxd,
-                  int loopback, int tso, int csumoffload, enum rte_eth_rx_mq_mode rx_mq_mode, struct rte_fdir_conf const *p_fdir_conf) {
+                  int loopback, uint64_t rx_offloads, uint64_t tx_offloads, enum rte_eth_rx_mq_mode rx_mq_mode,
+                  struct rte_fdir_conf const *p_fdir_conf) {
     struct rte_eth_dev_info dev_info = {};
     struct rte_eth_conf eth_conf;
     struct rte_eth_rxconf eth_rxconf;
@@ -255,10 +256,9 @@ int init_pmd_port(uint16_t port, uint16_t rxqs, uint16_t txqs, int rxq_core[], i
      * with minor tweaks */
     rte_eth_dev_info_get(port, &dev_info);
     eth_conf.rx_adv_conf.rss_conf.rss_hf = dev_info.flow_type_rss_offloads;
-    if (csumoffload) {
-        eth_conf.txmode.offloads = DEV_TX_OFFLOAD_IPV4_CKSUM | DEV_TX_OFFLOAD_UDP_CKSUM | DEV_TX_OFFLOAD_TCP_CKSUM;
-        eth_conf.rxmode.offloads = DEV_RX_OFFLOAD_IPV4_CKSUM | DEV_RX_OFFLOAD_UDP_CKSUM | DEV_RX_OFFLOAD_TCP_CKSUM;
-    }
+    /* offloads are negotiated by the caller against dev_info.rx/tx_offload_capa */
+    eth_conf.txmode.offloads = tx_offloads;
+    eth_conf.rxmode.offloads = rx_offloads;
     eth_rxconf = dev_info.default_rxconf;
     /* Drop packets when no descriptors are available */
     //eth_rxconf.rx_drop_en = 0; // changed that to 0, because 82574L seems not supporting this
@@ -269,8 +269,6 @@ int init_pmd_port(uint16_t port, uint16_t rxqs, uint16_t txqs, int rxq_core[], i
     //eth_rxconf.rx_free_thresh=RX_FREE_THRESH;
 
     eth_txconf = dev_info.default_txconf;
-    tso = !(!tso);
-    csumoffload = !(!csumoffload);
 
     /* removed in 18.08
     eth_txconf.txq_flags = ETH_TXQ_FLAGS_NOVLANOFFL | ETH_TXQ_FLAGS_NOMULTSEGS * (1 - tso) |
@@ -23,6 +23,13 @@ fn main() {
     let cfg = matches
         .opt_str("config")
         .expect("No configuration supplied, rendering this meaningless");
-    let sched_cfg = read_configuration(&cfg[..]).expect("Could not parse configuration");
-    println!("Read configuration {}", sched_cfg)
+    match validate_configuration(&cfg[..]) {
+        Ok(sched_cfg) => println!("Read configuration {}", sched_cfg),
+        Err(errors) => {
+            for error in &errors {
+                println!("{}: {}", cfg, error);
+            }
+            process::exit(1)
+        }
+    }
 }
@@ -57,6 +57,7 @@ fn main() {
         Err(f) => panic!("{}", f.to_string()),
     };
     let mut configuration = read_matches(&matches, &opts);
+    let configuration_file = matches.opt_str("f");
 
     let delay_arg = matches
         .opt_str("d")
@@ -66,6 +67,8 @@ fn main() {
 
     match initialize_system(&mut configuration) {
         Ok(mut context) => {
+            install_shutdown_signal_handlers().expect("Could not install signal handlers");
+            install_reload_signal_handler().expect("Could not install signal handler");
             context.start_schedulers();
 
             context.add_pipeline_to_run(Box::new(
@@ -80,6 +83,7 @@ fn main() {
             const MAX_PRINT_INTERVAL: f64 = 30.;
             const PRINT_DELAY: f64 = 15.;
             const RUN_TIME: f64 = 60.;
+            const DRAIN_TIME: u64 = 1;
             let sleep_delay = (PRINT_DELAY / 2.) as u64;
             let mut start = OffsetDateTime::now_utc().unix_timestamp_nanos() as f64 / CONVERSION_FACTOR;
             let system_boot = start;
@@ -111,8 +115,19 @@ fn main() {
                         start = now;
                         pkts_so_far = pkts;
                     }
-                } else if now - system_boot > RUN_TIME {
-                    context.stop();
+                }
+                if take_reload_request() {
+                    match configuration_file {
+                        Some(ref file) => match context.reload_from_file(file) {
+                            Ok(changes) => println!("Reloaded {}: {} changes", file, changes.len()),
+                            Err(ref e) => println!("Reload of {} failed: {}", file, e),
+                        },
+                        None => println!("No configuration file to reload"),
+                    }
+                }
+                if shutdown_requested() || now - system_boot > RUN_TIME {
+                    let report = context.shutdown_gracefully(Duration::from_secs(DRAIN_TIME));
+                    println!("Shutdown: {}", report);
                     break;
                 }
             }